@@ -0,0 +1,46 @@
+//! Deps Module - Finding the external programs n01d runs
+
+use std::process::{Command, Stdio};
+
+/// The first of `programs` found on PATH, and the version it reports
+pub fn find_program<'a>(programs: &[&'a str]) -> Option<(&'a str, Option<String>)> {
+    let (name, path) = programs.iter().find_map(|name| which::which(name).ok().map(|path| (*name, path)))?;
+    
+    // openvpn exits non-zero after printing its version, so the status is ignored
+    let version = Command::new(path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()
+        .and_then(|output| {
+            let text = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+            parse_version(text.lines().next()?)
+        });
+    Some((name, version))
+}
+
+/// The first version-looking word of `--version` output, like `8.2.2` from
+/// "QEMU emulator version 8.2.2 (Debian ...)" or `1.0.20210914` from
+/// "wireguard-tools v1.0.20210914 - ..."
+pub fn parse_version(output: &str) -> Option<String> {
+    output
+        .split_whitespace()
+        .map(|word| word.trim_start_matches('v').trim_end_matches(|c: char| !c.is_ascii_alphanumeric()))
+        .find(|word| word.starts_with(|c: char| c.is_ascii_digit()) && word.contains('.'))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1)").as_deref(), Some("8.2.2"));
+        assert_eq!(parse_version("Tor version 0.4.8.10.").as_deref(), Some("0.4.8.10"));
+        assert_eq!(parse_version("wireguard-tools v1.0.20210914 - https://git.zx2c4.com/wireguard-tools/").as_deref(), Some("1.0.20210914"));
+        assert_eq!(parse_version("TPM emulator version 0.8.0, Copyright (c) 2014-2022 IBM Corp.").as_deref(), Some("0.8.0"));
+        assert_eq!(parse_version("iptables v1.8.10 (nf_tables)").as_deref(), Some("1.8.10"));
+        assert_eq!(parse_version("usage: wg <cmd>"), None);
+    }
+}
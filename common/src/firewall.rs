@@ -0,0 +1,401 @@
+//! Firewall Module - Writing n01d's rules as `iptables-restore` and `nft -f`
+//! input, and reading its chains and tables back out of `iptables-save` and
+//! `nft list` output
+
+/// Prefix of every iptables chain and nftables table n01d creates
+pub const FIREWALL_PREFIX: &str = "n01d-";
+
+/// A loaded `n01d-*` iptables chain or nftables table
+#[derive(Debug, Clone, PartialEq)]
+pub struct FirewallChain {
+    /// `iptables` or `nft`
+    pub backend: &'static str,
+    /// iptables table or nftables family
+    pub table: String,
+    pub name: String,
+}
+
+impl std::fmt::Display for FirewallChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.backend, self.table, self.name)
+    }
+}
+
+/// One iptables rule to add, or a chain to create
+#[derive(Debug, Clone, PartialEq)]
+pub struct IptablesRule {
+    pub table: &'static str,
+    /// `-A` to append to the chain, `-I` to insert at its head, `-N` to
+    /// create it
+    pub action: &'static str,
+    pub chain: String,
+    pub spec: Vec<String>,
+}
+
+impl IptablesRule {
+    pub fn new(table: &'static str, action: &'static str, chain: &str, spec: &[&str]) -> Self {
+        Self {
+            table,
+            action,
+            chain: chain.to_string(),
+            spec: spec.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+    
+    /// Create `chain`, or empty it if it's already there
+    pub fn chain(table: &'static str, chain: &str) -> Self {
+        Self::new(table, "-N", chain, &[])
+    }
+    
+    fn creates_chain(&self) -> bool {
+        self.action == "-N"
+    }
+}
+
+/// An argument as `iptables-restore` reads it: it splits on whitespace and
+/// honours double quotes with `\"` escapes, but expands nothing
+fn restore_quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\')) {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `iptables-restore --noflush` input adding `rules`: a `*table ... COMMIT`
+/// section per table, in the order the tables first appear. Declaring a
+/// chain creates it, or empties it if it's already there
+pub fn iptables_restore_input(rules: &[IptablesRule]) -> String {
+    let mut tables: Vec<&str> = Vec::new();
+    for rule in rules {
+        if !tables.contains(&rule.table) {
+            tables.push(rule.table);
+        }
+    }
+    
+    let mut input = String::new();
+    for table in tables {
+        input.push_str(&format!("*{}\n", table));
+        for rule in rules.iter().filter(|rule| rule.table == table && rule.creates_chain()) {
+            input.push_str(&format!(":{} - [0:0]\n", rule.chain));
+        }
+        for rule in rules.iter().filter(|rule| rule.table == table && !rule.creates_chain()) {
+            let mut line = format!("{} {}", rule.action, restore_quote(&rule.chain));
+            for arg in &rule.spec {
+                line.push(' ');
+                line.push_str(&restore_quote(arg));
+            }
+            input.push_str(&line);
+            input.push('\n');
+        }
+        input.push_str("COMMIT\n");
+    }
+    input
+}
+
+/// The same `rules` as `nft -f` input: an `inet` table `n01d-<name>` that is
+/// deleted and recreated in one transaction. Built-in chains become base
+/// chains of the table hooked where the iptables ones are, `n01d-*` chains
+/// become regular chains. An inet table also sees IPv6, so its drops cover
+/// what ip6tables would otherwise have to. Fails on matches and targets
+/// n01d doesn't generate
+pub fn nft_ruleset(name: &str, rules: &[IptablesRule]) -> Result<String, String> {
+    let table = format!("{}{}", FIREWALL_PREFIX, name);
+    if !is_nft_word(&table) {
+        return Err(format!("Invalid nftables table name '{}'", table));
+    }
+    
+    // (chain, base chain header, rules), in order of first appearance
+    let mut chains: Vec<(String, Option<String>, Vec<String>)> = Vec::new();
+    for rule in rules {
+        let (chain, header) = match base_chain_hook(rule.table, &rule.chain) {
+            Some(header) => (format!("{}-{}", rule.table, rule.chain.to_lowercase()), Some(header)),
+            None if rule.chain.starts_with(FIREWALL_PREFIX) && is_nft_word(&rule.chain) => (rule.chain.clone(), None),
+            None => return Err(format!("Can't express chain '{}' in nftables", rule.chain)),
+        };
+        let index = match chains.iter().position(|(name, _, _)| *name == chain) {
+            Some(index) => index,
+            None => {
+                chains.push((chain, header, Vec::new()));
+                chains.len() - 1
+            }
+        };
+        match rule.action {
+            "-N" => {}
+            "-A" => chains[index].2.push(nft_rule(&rule.spec)?),
+            "-I" => chains[index].2.insert(0, nft_rule(&rule.spec)?),
+            action => return Err(format!("Can't express iptables {} in nftables", action)),
+        }
+    }
+    
+    let mut ruleset = format!("table inet {table}\ndelete table inet {table}\ntable inet {table} {{\n", table = table);
+    for (chain, header, lines) in chains {
+        ruleset.push_str(&format!("    chain {} {{\n", chain));
+        if let Some(header) = header {
+            ruleset.push_str(&format!("        {}\n", header));
+        }
+        for line in lines {
+            ruleset.push_str(&format!("        {}\n", line));
+        }
+        ruleset.push_str("    }\n");
+    }
+    ruleset.push_str("}\n");
+    Ok(ruleset)
+}
+
+/// The base chain declaration matching an iptables built-in chain
+fn base_chain_hook(table: &str, chain: &str) -> Option<String> {
+    let priority = match (table, chain) {
+        ("filter", "INPUT" | "FORWARD" | "OUTPUT") => 0,
+        ("nat", "PREROUTING" | "OUTPUT") => -100,
+        ("nat", "POSTROUTING" | "INPUT") => 100,
+        _ => return None,
+    };
+    Some(format!(
+        "type {} hook {} priority {}; policy accept;",
+        table,
+        chain.to_lowercase(),
+        priority
+    ))
+}
+
+/// A word nft takes as-is: a name, address, CIDR, port or port range
+fn is_nft_word(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':' | '/'))
+}
+
+/// One iptables rule spec as an nft rule
+fn nft_rule(spec: &[String]) -> Result<String, String> {
+    let mut args = spec.iter().map(String::as_str);
+    let mut matches = Vec::new();
+    let mut protocol = None;
+    let mut verdict = None;
+    while let Some(option) = args.next() {
+        let mut value = |option: &str| match args.next() {
+            Some(word) if is_nft_word(word) => Ok(word),
+            Some(word) => Err(format!("Can't express '{} {}' in nftables", option, word)),
+            None => Err(format!("{} without a value", option)),
+        };
+        match option {
+            "-i" => matches.push(format!("iifname \"{}\"", value(option)?)),
+            "-o" => matches.push(format!("oifname \"{}\"", value(option)?)),
+            "-s" | "-d" => {
+                let address = value(option)?;
+                let family = if address.contains(':') { "ip6" } else { "ip" };
+                let field = if option == "-s" { "saddr" } else { "daddr" };
+                matches.push(format!("{} {} {}", family, field, address));
+            }
+            "-p" => {
+                let name = value(option)?;
+                if !matches!(name, "tcp" | "udp") {
+                    return Err(format!("Can't express protocol '{}' in nftables", name));
+                }
+                protocol = Some(name);
+                matches.push(format!("meta l4proto {}", name));
+            }
+            "--dport" => {
+                let protocol = protocol.ok_or("--dport without -p")?;
+                matches.push(format!("{} dport {}", protocol, value(option)?.replace(':', "-")));
+            }
+            "--syn" if protocol == Some("tcp") => matches.push("tcp flags & (fin|syn|rst|ack) == syn".to_string()),
+            "-j" => {
+                verdict = Some(match value(option)? {
+                    "ACCEPT" => "accept".to_string(),
+                    "DROP" => "drop".to_string(),
+                    "REJECT" => "reject".to_string(),
+                    "MASQUERADE" => "masquerade".to_string(),
+                    "DNAT" => match (value("DNAT")?, value("DNAT")?) {
+                        ("--to-destination", to) => format!("dnat ip to {}", to),
+                        (flag, _) => return Err(format!("Can't express DNAT {} in nftables", flag)),
+                    },
+                    chain if chain.starts_with(FIREWALL_PREFIX) => format!("jump {}", chain),
+                    target => return Err(format!("Can't express target '{}' in nftables", target)),
+                });
+            }
+            other => return Err(format!("Can't express '{}' in nftables", other)),
+        }
+    }
+    
+    matches.push(verdict.ok_or("Rule has no target")?);
+    Ok(matches.join(" "))
+}
+
+/// The `n01d-*` chains declared in `iptables-save` output
+pub fn iptables_chains(save: &str) -> Vec<FirewallChain> {
+    let mut chains = Vec::new();
+    let mut table = "";
+    for line in save.lines() {
+        if let Some(name) = line.strip_prefix('*') {
+            table = name;
+        } else if let Some(chain) = line.strip_prefix(':').filter(|chain| chain.starts_with(FIREWALL_PREFIX)) {
+            let name = chain.split_whitespace().next().unwrap_or(chain).to_string();
+            chains.push(FirewallChain { backend: "iptables", table: table.to_string(), name });
+        }
+    }
+    chains
+}
+
+/// The `n01d-*` tables in `nft list tables` output
+pub fn nft_tables(list: &str) -> Vec<FirewallChain> {
+    list.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("table"), Some(family), Some(name)) if name.starts_with(FIREWALL_PREFIX) => {
+                    Some(FirewallChain { backend: "nft", table: family.to_string(), name: name.to_string() })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The n01d parts of `iptables-save` output, as `iptables-restore --noflush`
+/// input: the `n01d-*` chains, their rules and any jumps into them, per
+/// table. Empty when there are none
+pub fn n01d_iptables_section(save: &str) -> String {
+    let mut section = String::new();
+    let mut table = String::new();
+    
+    for line in save.lines() {
+        if let Some(name) = line.strip_prefix('*') {
+            table = format!("*{}\n", name);
+        } else if line == "COMMIT" {
+            if table.lines().count() > 1 {
+                section.push_str(&table);
+                section.push_str("COMMIT\n");
+            }
+            table.clear();
+        } else {
+            let ours = line.starts_with(&format!(":{}", FIREWALL_PREFIX))
+                || line.starts_with(&format!("-A {}", FIREWALL_PREFIX))
+                || line.contains(&format!(" -j {}", FIREWALL_PREFIX));
+            if ours && !table.is_empty() {
+                table.push_str(line);
+                table.push('\n');
+            }
+        }
+    }
+    section
+}
+
+/// `section` (from `n01d_iptables_section`) minus the jumps from built-in
+/// chains that `current` (live `iptables-save` output) already has. Under
+/// `--noflush` declaring an `n01d-*` chain resets it, but a jump is simply
+/// appended again, so restoring twice would leave two
+pub fn without_loaded_jumps(section: &str, current: &str) -> String {
+    let mut loaded = std::collections::HashSet::new();
+    let mut table = "";
+    for line in current.lines() {
+        if let Some(name) = line.strip_prefix('*') {
+            table = name;
+        } else if line.starts_with("-A ") {
+            loaded.insert((table, line));
+        }
+    }
+    
+    let mut kept = String::new();
+    let mut table = "";
+    for line in section.lines() {
+        if let Some(name) = line.strip_prefix('*') {
+            table = name;
+        }
+        let jump = line.starts_with("-A ") && !line.starts_with(&format!("-A {}", FIREWALL_PREFIX));
+        if !(jump && loaded.contains(&(table, line))) {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const SAVE: &str = "# Generated by iptables-save\n\
+                        *nat\n\
+                        :PREROUTING ACCEPT [0:0]\n\
+                        -A POSTROUTING -s 10.0.0.0/24 -j MASQUERADE\n\
+                        COMMIT\n\
+                        *filter\n\
+                        :FORWARD ACCEPT [0:0]\n\
+                        :n01d-lab - [0:0]\n\
+                        -A FORWARD -i nullsec-lab -j n01d-lab\n\
+                        -A FORWARD -i docker0 -j ACCEPT\n\
+                        -A n01d-lab -j DROP\n\
+                        COMMIT\n";
+    
+    fn isolation_rules() -> Vec<IptablesRule> {
+        vec![
+            IptablesRule::chain("filter", "n01d-lab"),
+            IptablesRule::new("filter", "-A", "n01d-lab", &["-j", "DROP"]),
+            IptablesRule::new("filter", "-I", "FORWARD", &["-i", "nullsec-lab", "-j", "n01d-lab"]),
+            IptablesRule::new("nat", "-A", "PREROUTING", &["-i", "tap0", "-p", "tcp", "--syn", "-j", "DNAT", "--to-destination", "127.0.0.1:9040"]),
+        ]
+    }
+    
+    #[test]
+    fn test_restore_input_declares_chains_per_table() {
+        assert_eq!(
+            iptables_restore_input(&isolation_rules()),
+            "*filter\n:n01d-lab - [0:0]\n-A n01d-lab -j DROP\n-I FORWARD -i nullsec-lab -j n01d-lab\nCOMMIT\n\
+             *nat\n-A PREROUTING -i tap0 -p tcp --syn -j DNAT --to-destination 127.0.0.1:9040\nCOMMIT\n"
+        );
+        assert_eq!(restore_quote("a b"), "\"a b\"");
+        assert_eq!(restore_quote("x\"y"), "\"x\\\"y\"");
+        assert_eq!(restore_quote(""), "\"\"");
+    }
+    
+    #[test]
+    fn test_nft_ruleset_mirrors_iptables_rules() {
+        let ruleset = nft_ruleset("lab", &isolation_rules()).unwrap();
+        assert_eq!(
+            ruleset,
+            "table inet n01d-lab\n\
+             delete table inet n01d-lab\n\
+             table inet n01d-lab {\n\
+             \x20   chain n01d-lab {\n\
+             \x20       drop\n\
+             \x20   }\n\
+             \x20   chain filter-forward {\n\
+             \x20       type filter hook forward priority 0; policy accept;\n\
+             \x20       iifname \"nullsec-lab\" jump n01d-lab\n\
+             \x20   }\n\
+             \x20   chain nat-prerouting {\n\
+             \x20       type nat hook prerouting priority -100; policy accept;\n\
+             \x20       iifname \"tap0\" meta l4proto tcp tcp flags & (fin|syn|rst|ack) == syn dnat ip to 127.0.0.1:9040\n\
+             \x20   }\n\
+             }\n"
+        );
+        
+        let rule = |spec: &[&str]| nft_ruleset("lab", &[IptablesRule::new("filter", "-A", "INPUT", spec)]);
+        assert!(rule(&["-i", "tap0\"; flush ruleset", "-j", "DROP"]).is_err());
+        assert!(rule(&["-m", "state", "-j", "DROP"]).is_err());
+        assert!(rule(&["-j", "LOG"]).is_err());
+        assert!(rule(&["-i", "tap0"]).is_err());
+        assert!(nft_ruleset("lab", &[IptablesRule::new("mangle", "-A", "INPUT", &["-j", "DROP"])]).is_err());
+    }
+    
+    #[test]
+    fn test_saved_ruleset_keeps_only_n01d() {
+        let section = n01d_iptables_section(SAVE);
+        assert_eq!(section, "*filter\n:n01d-lab - [0:0]\n-A FORWARD -i nullsec-lab -j n01d-lab\n-A n01d-lab -j DROP\nCOMMIT\n");
+        assert_eq!(n01d_iptables_section("*filter\n:INPUT ACCEPT [0:0]\nCOMMIT\n"), "");
+        
+        assert_eq!(
+            iptables_chains(SAVE),
+            [FirewallChain { backend: "iptables", table: "filter".into(), name: "n01d-lab".into() }]
+        );
+        let tables = nft_tables("table inet filter\ntable inet n01d-stealth\ntable ip nat\n");
+        assert_eq!(tables.iter().map(ToString::to_string).collect::<Vec<_>>(), ["nft inet n01d-stealth"]);
+    }
+    
+    #[test]
+    fn test_restore_skips_loaded_jumps() {
+        let section = n01d_iptables_section(SAVE);
+        assert_eq!(without_loaded_jumps(&section, SAVE), "*filter\n:n01d-lab - [0:0]\n-A n01d-lab -j DROP\nCOMMIT\n");
+        
+        // After a reboot nothing is loaded, so the jump goes back in
+        assert_eq!(without_loaded_jumps(&section, "*filter\n:FORWARD ACCEPT [0:0]\nCOMMIT\n"), section);
+    }
+}
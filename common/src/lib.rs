@@ -0,0 +1,10 @@
+//! n01d Common - Code the CLI and the cross-platform app both need, so the
+//! two don't drift apart
+
+pub mod deps;
+pub mod firewall;
+pub mod openvpn;
+pub mod ports;
+pub mod process;
+pub mod snapshot;
+pub mod usb;
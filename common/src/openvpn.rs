@@ -0,0 +1,146 @@
+//! OpenVPN Module - Checking client configs before openvpn gets them
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Problems `validate_openvpn_config` finds in an OpenVPN config
+#[derive(Debug, thiserror::Error)]
+pub enum OpenVpnConfigError {
+    #[error("OpenVPN config {0} not found")]
+    NotFound(PathBuf),
+    
+    #[error("Can't read OpenVPN config {path}: {source}")]
+    Unreadable { path: PathBuf, source: std::io::Error },
+    
+    #[error("OpenVPN config has no 'remote' directive")]
+    NoRemote,
+    
+    #[error("OpenVPN config has no 'ca' (or 'pkcs12'/'secret')")]
+    NoCa,
+    
+    #[error("OpenVPN config has no client credentials: needs 'cert' and 'key', 'pkcs12' or 'auth-user-pass'")]
+    NoCredentials,
+    
+    #[error("'{directive}' points at {path}, which doesn't exist")]
+    MissingFile { directive: String, path: PathBuf },
+}
+
+/// Directives whose first argument is a file OpenVPN has to read
+const OPENVPN_FILE_DIRECTIVES: &[&str] = &[
+    "ca", "cert", "key", "pkcs12", "tls-auth", "tls-crypt", "secret", "auth-user-pass", "dh", "crl-verify",
+];
+
+/// Catch the config problems that would otherwise only show up as an
+/// `openvpn --daemon` that quietly dies. Referenced files are resolved
+/// relative to the config's directory. Returns warnings on success
+pub fn validate_openvpn_config(path: &Path) -> Result<Vec<String>, OpenVpnConfigError> {
+    if !path.exists() {
+        return Err(OpenVpnConfigError::NotFound(path.to_path_buf()));
+    }
+    let config = fs::read_to_string(path)
+        .map_err(|source| OpenVpnConfigError::Unreadable { path: path.to_path_buf(), source })?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    
+    let mut seen = HashSet::new();
+    let mut remotes = Vec::new();
+    let mut files = Vec::new();
+    let mut warnings = Vec::new();
+    
+    for line in config.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        // Inline blocks like <ca>...</ca> stand in for the directive
+        if let Some(tag) = line.strip_prefix('<').and_then(|l| l.strip_suffix('>')) {
+            if !tag.starts_with('/') {
+                seen.insert(tag.to_string());
+            }
+            continue;
+        }
+        
+        let mut parts = line.split_whitespace();
+        let Some(directive) = parts.next() else { continue };
+        let arg = parts.next();
+        seen.insert(directive.to_string());
+        
+        if directive == "remote" {
+            remotes.push(arg.unwrap_or_default().to_string());
+        }
+        // [inline] means the file's contents are embedded in the config
+        if let Some(file) = arg.filter(|f| OPENVPN_FILE_DIRECTIVES.contains(&directive) && *f != "[inline]") {
+            files.push((directive.to_string(), base.join(file.trim_matches('"'))));
+        }
+    }
+    
+    if remotes.is_empty() {
+        return Err(OpenVpnConfigError::NoRemote);
+    }
+    for remote in &remotes {
+        if remote.is_empty() || remote == "0.0.0.0" || remote == "::" {
+            warnings.push(format!("'remote {}' doesn't name a real server", remote));
+        }
+    }
+    
+    let has = |directive: &str| seen.contains(directive);
+    if !has("secret") {
+        if !has("ca") && !has("pkcs12") {
+            return Err(OpenVpnConfigError::NoCa);
+        }
+        let has_credentials = (has("cert") && has("key")) || has("pkcs12") || has("auth-user-pass");
+        if !has_credentials {
+            return Err(OpenVpnConfigError::NoCredentials);
+        }
+    }
+    
+    for (directive, file) in files {
+        if !file.exists() {
+            return Err(OpenVpnConfigError::MissingFile { directive, path: file });
+        }
+        
+        #[cfg(unix)]
+        if matches!(directive.as_str(), "key" | "secret" | "pkcs12" | "auth-user-pass") {
+            use std::os::unix::fs::PermissionsExt;
+            
+            let mode = fs::metadata(&file)
+                .map_err(|source| OpenVpnConfigError::Unreadable { path: file.clone(), source })?
+                .permissions()
+                .mode();
+            if mode & 0o077 != 0 {
+                warnings.push(format!("{} is readable by other users (mode {:o}); chmod 600 it", file.display(), mode & 0o777));
+            }
+        }
+    }
+    
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_validate_openvpn_config() {
+        let dir = std::env::temp_dir().join(format!("n01d-ovpn-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("client.ovpn");
+        fs::write(dir.join("ca.crt"), "").unwrap();
+        
+        fs::write(&config, "client\nca ca.crt\nauth-user-pass\n").unwrap();
+        assert!(matches!(validate_openvpn_config(&config), Err(OpenVpnConfigError::NoRemote)));
+        
+        fs::write(&config, "client\nremote vpn.example.org 1194\nca ca.crt\n").unwrap();
+        assert!(matches!(validate_openvpn_config(&config), Err(OpenVpnConfigError::NoCredentials)));
+        
+        fs::write(&config, "client\nremote vpn.example.org 1194\nca ca.crt\ncert client.crt\nkey client.key\n").unwrap();
+        let err = validate_openvpn_config(&config).unwrap_err();
+        assert!(err.to_string().contains("client.crt"));
+        
+        fs::write(&config, "client\nremote 0.0.0.0 1194\nca ca.crt\n<cert>\n...\n</cert>\n<key>\n...\n</key>\n").unwrap();
+        let warnings = validate_openvpn_config(&config).unwrap();
+        assert!(warnings.iter().any(|w| w.contains("0.0.0.0")));
+        
+        assert!(matches!(validate_openvpn_config(&dir.join("missing.ovpn")), Err(OpenVpnConfigError::NotFound(_))));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,56 @@
+//! Ports Module - Host ports forwarded to guest SSH
+
+use std::collections::HashSet;
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+
+/// Host port forwarded to guest SSH when a VM doesn't have its own
+pub const DEFAULT_SSH_PORT: u16 = 2222;
+
+/// Host ports handed out for guest SSH
+pub const SSH_PORT_RANGE: RangeInclusive<u16> = DEFAULT_SSH_PORT..=2999;
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is the same on every run and build
+pub fn fnv1a(s: &str) -> u64 {
+    s.bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3))
+}
+
+/// Where a VM's name hashes to in `SSH_PORT_RANGE`, or the first port after
+/// it (wrapping around) that isn't claimed and is `free`. The same name
+/// always starts from the same port
+pub fn pick_ssh_port(name: &str, claimed: &HashSet<u16>, free: impl Fn(u16) -> bool) -> Option<u16> {
+    let (start, len) = (*SSH_PORT_RANGE.start(), SSH_PORT_RANGE.len() as u64);
+    let offset = fnv1a(name) % len;
+    
+    (0..len)
+        .map(|i| start + ((offset + i) % len) as u16)
+        .find(|port| !claimed.contains(port) && free(*port))
+}
+
+/// Whether nothing is listening on `port` on the loopback address, which is
+/// the only place SSH forwards are bound
+pub fn loopback_port_free(port: u16) -> bool {
+    TcpListener::bind(("127.0.0.1", port)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_ssh_port_is_stable_and_skips_taken_ports() {
+        let none = HashSet::new();
+        let web = pick_ssh_port("web-01", &none, |_| true).unwrap();
+        assert!(SSH_PORT_RANGE.contains(&web));
+        assert_eq!(pick_ssh_port("web-01", &none, |_| true), Some(web));
+        
+        // Claimed by another VM, or something else listening on it
+        let claimed = HashSet::from([web]);
+        let next = pick_ssh_port("web-01", &claimed, |_| true).unwrap();
+        assert_ne!(next, web);
+        assert_eq!(pick_ssh_port("web-01", &none, |port| port != web), Some(next));
+        
+        assert_eq!(pick_ssh_port("web-01", &none, |_| false), None);
+    }
+}
@@ -0,0 +1,85 @@
+//! Process Module - Pidfiles and liveness checks
+
+use std::fs;
+use std::path::Path;
+
+/// The pid in a pidfile, if that process still exists
+pub fn live_pid(pid_path: &Path) -> Option<u32> {
+    let pid: u32 = fs::read_to_string(pid_path).ok()?.trim().parse().ok()?;
+    pid_alive(pid).then_some(pid)
+}
+
+/// Whether a process with this pid exists
+pub fn pid_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        use nix::unistd::Pid;
+        
+        let Ok(pid) = i32::try_from(pid) else {
+            return false;
+        };
+        // Signal 0 only checks that the process exists
+        nix::sys::signal::kill(Pid::from_raw(pid), None).is_ok()
+    }
+    
+    #[cfg(windows)]
+    {
+        use std::process::Command;
+        
+        Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+            .unwrap_or(false)
+    }
+}
+
+/// Ask a process to exit, returning whether the request was delivered
+pub fn terminate(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{kill, Signal};
+        use nix::unistd::Pid;
+        
+        let Ok(pid) = i32::try_from(pid) else {
+            return false;
+        };
+        kill(Pid::from_raw(pid), Signal::SIGTERM).is_ok()
+    }
+    
+    #[cfg(windows)]
+    {
+        use std::process::Command;
+        
+        Command::new("taskkill")
+            .args(["/PID", &pid.to_string()])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    
+    #[test]
+    #[cfg(unix)]
+    fn test_stale_pidfile_is_not_running() {
+        let dir = std::env::temp_dir().join(format!("n01d-pid-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pid_path = dir.join("vm.pid");
+        
+        // A reaped child's pid belongs to no process
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        fs::write(&pid_path, child.id().to_string()).unwrap();
+        assert_eq!(live_pid(&pid_path), None);
+        
+        fs::write(&pid_path, std::process::id().to_string()).unwrap();
+        assert_eq!(live_pid(&pid_path), Some(std::process::id()));
+        
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,72 @@
+//! Snapshot Module - The rules for naming a new internal snapshot, and the
+//! snapshots a qcow2 disk already has
+
+use std::path::Path;
+use std::process::Command;
+
+/// Prefix of the snapshots the CLI takes before each boot; users can't pick it
+pub const AUTOSTART_PREFIX: &str = "autostart-";
+
+/// What's wrong with `name` as the name of a new snapshot, if anything. It's
+/// passed to qemu-img and shown in listings, so only a plain set of
+/// characters is allowed
+pub fn snapshot_name_problem(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("name is empty")
+    } else if name.starts_with('-') {
+        Some("name cannot start with '-'")
+    } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        Some("only letters, digits, '.', '_' and '-' are allowed")
+    } else if name.starts_with(AUTOSTART_PREFIX) {
+        Some("the 'autostart-' prefix is reserved for automatic snapshots")
+    } else {
+        None
+    }
+}
+
+/// The snapshot tags on `disk`, read with `qemu-img snapshot -l`. `-U` lets
+/// it read a disk a running VM has locked. None if qemu-img can't read it
+pub fn disk_snapshots(disk: &Path) -> Option<Vec<String>> {
+    let output = Command::new("qemu-img")
+        .args(["snapshot", "-U", "-l"])
+        .arg(disk)
+        .output()
+        .ok()?;
+    
+    if !output.status.success() {
+        return None;
+    }
+    Some(parse_snapshot_list(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Snapshot tags from `qemu-img snapshot -l`, which prints a header row
+/// starting with `ID` and then one `<id> <tag> ...` row per snapshot
+pub fn parse_snapshot_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("ID"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_snapshot_names() {
+        assert_eq!(snapshot_name_problem("pre-update_1.0"), None);
+        for name in ["", "my snap", "a/b", "-a", "autostart-20240101-000000", "snap;rm"] {
+            assert!(snapshot_name_problem(name).is_some(), "{}", name);
+        }
+        
+        let listing = "Snapshot list:\n\
+                       ID        TAG               VM SIZE                DATE     VM CLOCK     ICOUNT\n\
+                       1         clean                 0 B 2024-01-01 12:00:00 00:00:00.000          0\n\
+                       2         pre-update            0 B 2024-01-02 12:00:00 00:00:00.000          0\n";
+        assert_eq!(parse_snapshot_list(listing), ["clean", "pre-update"]);
+        assert!(parse_snapshot_list("").is_empty());
+    }
+}
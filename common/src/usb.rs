@@ -0,0 +1,49 @@
+//! USB Module - Host USB device ids
+
+/// A USB device as `vendorid:productid` in hex
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UsbId {
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl std::str::FromStr for UsbId {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = |part: &str| {
+            let part = part.trim_start_matches("0x");
+            if part.is_empty() || part.len() > 4 {
+                return None;
+            }
+            u16::from_str_radix(part, 16).ok()
+        };
+        
+        s.split_once(':')
+            .and_then(|(vendor, product)| Some(UsbId { vendor: hex(vendor)?, product: hex(product)? }))
+            .ok_or_else(|| format!("'{}' is not vendorid:productid in hex (like 046d:c52b)", s))
+    }
+}
+
+impl std::fmt::Display for UsbId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor, self.product)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_usb_ids() {
+        let id: UsbId = "046D:c52b".parse().unwrap();
+        assert_eq!(id, UsbId { vendor: 0x046d, product: 0xc52b });
+        assert_eq!(id.to_string(), "046d:c52b");
+        assert_eq!("0x1d6b:0x2".parse::<UsbId>().unwrap().to_string(), "1d6b:0002");
+        
+        for bad in ["", "046d", "046d:", "12345:1", "zz:01", "1:2:3"] {
+            assert!(bad.parse::<UsbId>().is_err(), "{}", bad);
+        }
+    }
+}
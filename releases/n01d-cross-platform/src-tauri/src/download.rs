@@ -0,0 +1,139 @@
+// ISO downloads
+// Streams into `<name>.part` next to the destination, resuming with a Range
+// request when a partial file is already there, and only renames to the final
+// name once the optional SHA-256 matches
+
+use crate::error::AppError;
+use crate::progress::percent;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Pick a local file name from the last path segment of the URL
+pub fn file_name_from_url(url: &str) -> Result<String, AppError> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+
+    if name.is_empty() || name.starts_with('.') || name.contains('\\') {
+        return Err(AppError::Invalid(format!("Can't derive a file name from '{}'", url)));
+    }
+
+    Ok(name.to_string())
+}
+
+/// Download `url` into `dest_dir`, reporting (stage, percent) to `on_progress`
+pub async fn download_file<F>(
+    url: &str,
+    dest_dir: &Path,
+    sha256: Option<&str>,
+    mut on_progress: F,
+) -> Result<PathBuf, AppError>
+where
+    F: FnMut(&str, Option<u8>),
+{
+    let name = file_name_from_url(url)?;
+    let dest = dest_dir.join(&name);
+    let part = dest_dir.join(format!("{}.part", name));
+
+    if dest.exists() {
+        return Err(AppError::AlreadyExists(format!("'{}'", dest.display())));
+    }
+    fs::create_dir_all(dest_dir)?;
+
+    // Resume from whatever a previous attempt left behind
+    let resume_from = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+
+    let client = reqwest::Client::new();
+    let mut request = client.get(url);
+    if resume_from > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", resume_from));
+    }
+
+    let mut response = request.send().await.map_err(download_error)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(AppError::Other(format!("Download failed: HTTP {}", status)));
+    }
+
+    // A server that ignores Range sends the whole file again
+    let resumed = status == reqwest::StatusCode::PARTIAL_CONTENT;
+    let mut downloaded = if resumed { resume_from } else { 0 };
+    let total = response.content_length().map(|len| len + downloaded);
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)?;
+
+    while let Some(chunk) = response.chunk().await.map_err(download_error)? {
+        file.write_all(&chunk)?;
+        downloaded += chunk.len() as u64;
+        on_progress("downloading", total.map(|total| percent(downloaded, total)));
+    }
+    file.sync_all()?;
+    drop(file);
+
+    if let Some(expected) = sha256 {
+        on_progress("verifying", None);
+        let actual = sha256_file(&part)?;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            let _ = fs::remove_file(&part);
+            return Err(AppError::Invalid(format!(
+                "Checksum mismatch for '{}': expected {}, got {}",
+                name, expected, actual
+            )));
+        }
+    }
+
+    fs::rename(&part, &dest)?;
+    Ok(dest)
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, AppError> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn download_error(err: reqwest::Error) -> AppError {
+    AppError::Other(format!("Download failed: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name_from_url() {
+        assert_eq!(
+            file_name_from_url("https://example.org/isos/nullsec-1.0-amd64.iso?mirror=1").unwrap(),
+            "nullsec-1.0-amd64.iso"
+        );
+        assert!(file_name_from_url("https://example.org/isos/").is_err());
+        assert!(file_name_from_url("https://example.org/..").is_err());
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let path = std::env::temp_dir().join(format!("n01d-sha-test-{}", std::process::id()));
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,129 @@
+// Error type returned by Tauri commands
+// Serializes to { code, message } so the frontend can branch on `code`
+// and fall back to showing `message`
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// A VM, profile or file that was asked for doesn't exist
+    NotFound(String),
+    /// Creating something whose name is already taken
+    AlreadyExists(String),
+    /// A required external program isn't installed
+    DependencyMissing(String),
+    /// An external program failed to launch or exited with an error
+    SpawnFailed(String),
+    /// A VM's disk or ISO isn't where its config says; the path is sent
+    /// along so the UI can offer to relocate it
+    MissingFile { kind: &'static str, path: PathBuf },
+    /// Input that was rejected by validation
+    Invalid(String),
+    /// Custom security profiles are encrypted and the passphrase hasn't
+    /// been entered yet
+    Locked,
+    Io(std::io::Error),
+    Other(String),
+}
+
+impl AppError {
+    /// Stable machine-readable identifier for the error kind
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::AlreadyExists(_) => "already_exists",
+            AppError::DependencyMissing(_) => "dependency_missing",
+            AppError::SpawnFailed(_) => "spawn_failed",
+            AppError::MissingFile { .. } => "missing_file",
+            AppError::Invalid(_) => "invalid",
+            AppError::Locked => "locked",
+            AppError::Io(e) if e.kind() == std::io::ErrorKind::PermissionDenied => "permission_denied",
+            AppError::Io(_) => "io",
+            AppError::Other(_) => "error",
+        }
+    }
+
+    /// Map a failed `Command::spawn`/`output` of `program`, reporting a
+    /// missing binary as `DependencyMissing`
+    pub fn spawn(program: &str, err: std::io::Error) -> Self {
+        if err.kind() == std::io::ErrorKind::NotFound {
+            AppError::DependencyMissing(program.to_string())
+        } else {
+            AppError::SpawnFailed(format!("Failed to run {}: {}", program, err))
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(what) => write!(f, "{} not found", what),
+            AppError::AlreadyExists(what) => write!(f, "{} already exists", what),
+            AppError::DependencyMissing(program) => write!(f, "{} is not installed", program),
+            AppError::MissingFile { kind, path } => write!(f, "{} not found at {}", kind, path.display()),
+            AppError::Locked => write!(f, "Security profiles are encrypted; enter the passphrase to unlock them"),
+            AppError::SpawnFailed(msg) | AppError::Invalid(msg) | AppError::Other(msg) => {
+                write!(f, "{}", msg)
+            }
+            AppError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let path = match self {
+            AppError::MissingFile { path, .. } => Some(path),
+            _ => None,
+        };
+        let mut state = serializer.serialize_struct("AppError", 2 + path.is_some() as usize)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        if let Some(path) = path {
+            state.serialize_field("path", path)?;
+        }
+        state.end()
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        AppError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        AppError::Other(err.to_string())
+    }
+}
+
+impl From<String> for AppError {
+    fn from(msg: String) -> Self {
+        AppError::Other(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_code_and_message() {
+        let err = AppError::NotFound("VM 'test'".to_string());
+        let value = serde_json::to_value(&err).unwrap();
+        assert_eq!(value["code"], "not_found");
+        assert_eq!(value["message"], "VM 'test' not found");
+    }
+
+    #[test]
+    fn test_missing_binary_is_dependency_error() {
+        let io = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let err = AppError::spawn("qemu-img", io);
+        assert_eq!(err.code(), "dependency_missing");
+    }
+}
@@ -0,0 +1,185 @@
+// Live QEMU log streaming for the console pane
+// Each tailed VM gets a thread following its qemu.log that emits every
+// complete line on `n01d://vm-log/<name>`. Event names can't contain dots,
+// so any in the VM name are sent as ':', which VM names never contain
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const LOG_EVENT_PREFIX: &str = "n01d://vm-log/";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub fn log_event(name: &str) -> String {
+    format!("{}{}", LOG_EVENT_PREFIX, name.replace('.', ":"))
+}
+
+/// Stop flags of the running tails, by VM name
+#[derive(Default, Clone)]
+pub struct LogTails(Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>);
+
+impl LogTails {
+    /// Follow `path` until stopped, the window closes or `running` says the
+    /// VM is gone; whatever it logged up to then is still sent. Replaces any
+    /// tail already running for the VM
+    pub fn start<F>(&self, name: &str, path: PathBuf, window: tauri::Window, running: F)
+    where
+        F: Fn() -> bool + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        if let Some(previous) = self.0.lock().unwrap().insert(name.to_string(), stop.clone()) {
+            previous.store(true, Ordering::Relaxed);
+        }
+
+        let on_close = stop.clone();
+        window.on_window_event(move |event| {
+            if matches!(event, tauri::WindowEvent::Destroyed) {
+                on_close.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let tails = self.clone();
+        let (name, event) = (name.to_string(), log_event(name));
+        std::thread::spawn(move || {
+            let mut follower = LogFollower::new(path);
+            while !stop.load(Ordering::Relaxed) {
+                let lines = follower.poll();
+                if lines.iter().any(|line| window.emit(&event, line).is_err()) {
+                    break;
+                }
+                if lines.is_empty() {
+                    if !running() {
+                        break;
+                    }
+                    std::thread::sleep(POLL_INTERVAL);
+                }
+            }
+
+            // A newer tail for the same VM may have taken the slot
+            let mut tails = tails.0.lock().unwrap();
+            if tails.get(&name).is_some_and(|current| Arc::ptr_eq(current, &stop)) {
+                tails.remove(&name);
+            }
+        });
+    }
+
+    /// Returns whether a tail was running
+    pub fn stop(&self, name: &str) -> bool {
+        match self.0.lock().unwrap().remove(name) {
+            Some(stop) => {
+                stop.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Reads the lines appended to a file since the last poll. A restart moves
+/// qemu.log aside and starts a new one, and a truncated file starts over, so
+/// either way it's read again from the top
+struct LogFollower {
+    path: PathBuf,
+    reader: Option<(BufReader<fs::File>, fs::Metadata)>,
+    position: u64,
+    partial: String,
+}
+
+impl LogFollower {
+    fn new(path: PathBuf) -> Self {
+        LogFollower { path, reader: None, position: 0, partial: String::new() }
+    }
+
+    fn poll(&mut self) -> Vec<String> {
+        let Ok(current) = fs::metadata(&self.path) else {
+            return Vec::new();
+        };
+        let replaced = match &self.reader {
+            Some((_, opened)) => !same_file(opened, &current) || current.len() < self.position,
+            None => true,
+        };
+        if replaced {
+            let Ok(file) = fs::File::open(&self.path) else {
+                return Vec::new();
+            };
+            self.reader = Some((BufReader::new(file), current));
+            self.position = 0;
+            self.partial.clear();
+        }
+
+        let mut lines = Vec::new();
+        let Some((reader, _)) = self.reader.as_mut() else {
+            return lines;
+        };
+        loop {
+            let mut chunk = Vec::new();
+            match reader.read_until(b'\n', &mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => self.position += n as u64,
+            }
+            // QEMU's output isn't guaranteed to be UTF-8
+            self.partial.push_str(&String::from_utf8_lossy(&chunk));
+            // Hold on to a line QEMU is still writing
+            if self.partial.ends_with('\n') {
+                lines.push(self.partial.trim_end_matches(['\r', '\n']).to_string());
+                self.partial.clear();
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+/// Windows can't rename a file QEMU still has open, so only truncation
+/// needs noticing there
+#[cfg(not(unix))]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_follower_survives_rotation() {
+        let dir = std::env::temp_dir().join(format!("n01d-logtail-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("qemu.log");
+        let append = |text: &str| {
+            fs::OpenOptions::new().create(true).append(true).open(&path).unwrap().write_all(text.as_bytes()).unwrap();
+        };
+
+        let mut follower = LogFollower::new(path.clone());
+        assert!(follower.poll().is_empty());
+
+        append("qemu: starting\nqemu: half a li");
+        assert_eq!(follower.poll(), ["qemu: starting"]);
+        append("ne\n");
+        assert_eq!(follower.poll(), ["qemu: half a line"]);
+
+        // A restart keeps the old log as qemu.log.1 and starts a fresh one
+        fs::rename(&path, dir.join("qemu.log.1")).unwrap();
+        append("qemu: restarted with a longer first line\n");
+        let rotated = follower.poll();
+
+        fs::write(&path, "after truncation\n").unwrap();
+        let truncated = follower.poll();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(rotated, ["qemu: restarted with a longer first line"]);
+        assert_eq!(truncated, ["after truncation"]);
+        assert_eq!(log_event("web.01"), "n01d://vm-log/web:01");
+    }
+}
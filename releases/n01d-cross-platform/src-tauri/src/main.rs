@@ -3,33 +3,102 @@
     windows_subsystem = "windows"
 )]
 
+mod download;
+mod error;
+mod logtail;
+mod progress;
 mod security;
+mod tray;
+mod vault;
 
-use security::{SecurityManager, SecurityProfile, IsolationMode, TorConfig, VpnConfig, ProxyConfig};
+use error::AppError;
+use logtail::LogTails;
+use progress::Progress;
+use security::{SecurityManager, SecurityProfile, IsolationMode, TorConfig, VpnConfig, VpnProvider, ProxyConfig, VirtualDeviceType};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ffi::OsString;
+use fs2::FileExt;
+use n01d_common::process::{live_pid, pid_alive};
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tauri::Manager;
+use vault::Vault;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct VmConfig {
     disk: String,
+    #[serde(default = "default_disk_format")]
+    disk_format: String,
     iso: Option<String>,
     ram: u32,
     cpus: u32,
     security_profile: Option<String>,
+    /// Tor SocksPort of its own, assigned the first time it runs TorOnly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tor_socks_port: Option<u16>,
+    /// Host port forwarded to its SSH, assigned the first time it runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ssh_port: Option<u16>,
+    /// Host USB devices passed through, as `vendorid:productid`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    usb_passthrough: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+fn default_disk_format() -> String {
+    "qcow2".to_string()
+}
+
+/// Current `AppConfig` schema version, bumped whenever `migrate` gains a step
+const CONFIG_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 struct AppConfig {
+    version: u32,
     vms: HashMap<String, VmConfig>,
     default_ram: u32,
     default_cpus: u32,
     security_profiles: HashMap<String, SecurityProfile>,
+    /// When set, custom profiles (and the VPN and proxy credentials in them)
+    /// are kept encrypted in `sealed_profiles` instead of `security_profiles`
+    encrypt_profiles: bool,
+    sealed_profiles: Option<vault::Sealed>,
     tor_enabled: bool,
     default_security_profile: Option<String>,
+    iso_search_paths: Vec<PathBuf>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            version: CONFIG_VERSION,
+            vms: HashMap::new(),
+            default_ram: 4096,
+            default_cpus: 4,
+            security_profiles: HashMap::new(),
+            encrypt_profiles: false,
+            sealed_profiles: None,
+            tor_enabled: false,
+            default_security_profile: None,
+            iso_search_paths: default_iso_search_paths(),
+        }
+    }
+}
+
+/// The app's own ISO directory plus the usual places people keep ISOs
+fn default_iso_search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![get_config_dir().join("isos")];
+    if let Some(home) = dirs::home_dir() {
+        paths.push(home.join("Downloads"));
+        paths.push(home.join("ISOs"));
+        paths.push(home.join("projects"));
+    }
+    paths
 }
 
 fn get_config_dir() -> PathBuf {
@@ -37,39 +106,196 @@ fn get_config_dir() -> PathBuf {
     home.join("n01d-machine")
 }
 
+fn get_vm_dir(name: &str) -> PathBuf {
+    get_config_dir().join("vms").join(name)
+}
+
 fn get_config_path() -> PathBuf {
     get_config_dir().join("config.json")
 }
 
 fn load_config() -> AppConfig {
     let path = get_config_path();
-    if path.exists() {
-        let content = fs::read_to_string(&path).unwrap_or_default();
-        serde_json::from_str(&content).unwrap_or_else(|_| AppConfig {
-            vms: HashMap::new(),
-            default_ram: 4096,
-            default_cpus: 4,
-            security_profiles: HashMap::new(),
-            tor_enabled: false,
-            default_security_profile: None,
-        })
-    } else {
-        AppConfig {
-            vms: HashMap::new(),
-            default_ram: 4096,
-            default_cpus: 4,
-            security_profiles: HashMap::new(),
-            tor_enabled: false,
-            default_security_profile: None,
+    if !path.exists() {
+        return AppConfig::default();
+    }
+    
+    let content = fs::read_to_string(&path).unwrap_or_default();
+    let raw: serde_json::Value = match serde_json::from_str(&content) {
+        Ok(raw) => raw,
+        Err(_) => return AppConfig::default(),
+    };
+    
+    let version = raw.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    let config = migrate(raw);
+    
+    if version < CONFIG_VERSION as u64 {
+        // Keep the pre-migration file around in case the upgrade loses something
+        let _ = fs::copy(&path, path.with_extension("json.bak"));
+        let _ = save_config(&config);
+    }
+    
+    config
+}
+
+/// Upgrade a raw config of any older schema version to the current `AppConfig`
+fn migrate(mut raw: serde_json::Value) -> AppConfig {
+    upgrade(&mut raw);
+    serde_json::from_value(raw.clone()).unwrap_or_else(|_| salvage_config(&raw))
+}
+
+/// Whether a config file deserializes completely, so loading and saving it
+/// again loses nothing
+fn reads_cleanly(content: &str) -> bool {
+    serde_json::from_str(content).is_ok_and(|mut raw| {
+        upgrade(&mut raw);
+        serde_json::from_value::<AppConfig>(raw).is_ok()
+    })
+}
+
+/// Rewrite a raw config of an older schema version into the current shape
+fn upgrade(raw: &mut serde_json::Value) {
+    let version = raw.get("version").and_then(|v| v.as_u64()).unwrap_or(0);
+    
+    if let Some(obj) = raw.as_object_mut() {
+        // v0 -> v1: VMs carried a `created` flag and there were no security
+        // fields; missing fields are filled in by `#[serde(default)]`
+        if version < 1 {
+            if let Some(vms) = obj.get_mut("vms").and_then(|v| v.as_object_mut()) {
+                for vm in vms.values_mut().filter_map(|v| v.as_object_mut()) {
+                    vm.remove("created");
+                }
+            }
         }
+        
+        obj.insert("version".to_string(), CONFIG_VERSION.into());
+    }
+}
+
+/// Recover what we can from a config that doesn't deserialize as a whole,
+/// keeping every VM entry that is individually valid
+fn salvage_config(raw: &serde_json::Value) -> AppConfig {
+    let mut config = AppConfig::default();
+    let field = |name: &str| raw.get(name).cloned().unwrap_or(serde_json::Value::Null);
+    
+    if let Some(vms) = raw.get("vms").and_then(|v| v.as_object()) {
+        config.vms = vms
+            .iter()
+            .filter_map(|(name, vm)| Some((name.clone(), serde_json::from_value(vm.clone()).ok()?)))
+            .collect();
+    }
+    if let Ok(profiles) = serde_json::from_value(field("security_profiles")) {
+        config.security_profiles = profiles;
+    }
+    if let Ok(encrypt) = serde_json::from_value(field("encrypt_profiles")) {
+        config.encrypt_profiles = encrypt;
+    }
+    if let Ok(sealed) = serde_json::from_value(field("sealed_profiles")) {
+        config.sealed_profiles = sealed;
+    }
+    if let Ok(ram) = serde_json::from_value(field("default_ram")) {
+        config.default_ram = ram;
     }
+    if let Ok(cpus) = serde_json::from_value(field("default_cpus")) {
+        config.default_cpus = cpus;
+    }
+    if let Ok(tor) = serde_json::from_value(field("tor_enabled")) {
+        config.tor_enabled = tor;
+    }
+    if let Ok(profile) = serde_json::from_value(field("default_security_profile")) {
+        config.default_security_profile = profile;
+    }
+    
+    config
 }
 
-fn save_config(config: &AppConfig) -> Result<(), String> {
+fn save_config(config: &AppConfig) -> Result<(), AppError> {
     let path = get_config_path();
-    fs::create_dir_all(get_config_dir()).map_err(|e| e.to_string())?;
-    let content = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
-    fs::write(path, content).map_err(|e| e.to_string())
+    fs::create_dir_all(get_config_dir())?;
+    // Whatever load_config had to skip in a damaged file, sealed profiles
+    // included, would be gone after this save, so set the file aside first
+    if let Ok(existing) = fs::read_to_string(&path) {
+        if !reads_cleanly(&existing) {
+            let stamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            fs::copy(&path, path.with_extension(format!("json.corrupt-{}", stamp)))?;
+        }
+    }
+    let content = serde_json::to_string_pretty(config)?;
+    write_atomic(&path, content.as_bytes())?;
+    Ok(())
+}
+
+/// Write a file by renaming a fully-written temp file over it, so readers
+/// never observe a half-written file. Commands run on several threads, so
+/// the temp name carries a counter as well as the pid
+fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(
+        ".{}.tmp-{}-{}", file_name, std::process::id(), WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    
+    let written = fs::File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&tmp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Exclusive advisory lock, released when dropped
+struct FileLock(fs::File);
+
+impl FileLock {
+    fn acquire(path: &Path) -> std::io::Result<Self> {
+        let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+        file.lock_exclusive()?;
+        Ok(FileLock(file))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.0);
+    }
+}
+
+/// Lock the config for a load-modify-save cycle
+fn lock_config() -> Result<FileLock, AppError> {
+    fs::create_dir_all(get_config_dir())?;
+    Ok(FileLock::acquire(&get_config_dir().join("config.lock"))?)
+}
+
+/// Check that a VM name is safe to join onto the VMs directory: only
+/// `[A-Za-z0-9._-]`, no `.`/`..`, no leading dash, at most 64 characters
+fn validate_vm_name(name: &str) -> Result<(), AppError> {
+    let invalid = |reason: &str| Err(AppError::Invalid(format!("Invalid VM name '{}': {}", name, reason)));
+    
+    if name.is_empty() {
+        return invalid("name is empty");
+    }
+    if name.len() > 64 {
+        return invalid("name is longer than 64 characters");
+    }
+    if name == "." || name == ".." {
+        return invalid("name cannot be '.' or '..'");
+    }
+    if name.starts_with('-') {
+        return invalid("name cannot start with '-'");
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        return invalid("only letters, digits, '.', '_' and '-' are allowed");
+    }
+    
+    Ok(())
 }
 
 #[tauri::command]
@@ -84,37 +310,33 @@ fn get_config() -> AppConfig {
 
 #[tauri::command]
 fn list_isos() -> Vec<String> {
+    let config = load_config();
     let mut isos = Vec::new();
-    let iso_dir = get_config_dir().join("isos");
+    let mut seen_dirs = Vec::new();
     
-    // Check n01d-machine/isos directory
-    if iso_dir.exists() {
-        if let Ok(entries) = fs::read_dir(&iso_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.extension().map(|e| e == "iso").unwrap_or(false) {
-                    isos.push(path.to_string_lossy().to_string());
-                }
-            }
+    for dir in &config.iso_search_paths {
+        // The same directory can be listed twice, or via a symlink
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.clone());
+        if seen_dirs.contains(&dir) {
+            continue;
         }
-    }
-    
-    // Also check common ISO locations
-    let common_paths = vec![
-        dirs::home_dir().map(|h| h.join("Downloads")),
-        dirs::home_dir().map(|h| h.join("ISOs")),
-        dirs::home_dir().map(|h| h.join("projects")),
-    ];
-    
-    for dir_opt in common_paths.into_iter().flatten() {
-        if let Ok(entries) = fs::read_dir(&dir_opt) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.extension().map(|e| e == "iso").unwrap_or(false) {
-                    let path_str = path.to_string_lossy().to_string();
-                    if !isos.contains(&path_str) {
-                        isos.push(path_str);
-                    }
+        
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        seen_dirs.push(dir);
+        
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_iso = path
+                .extension()
+                .map(|e| e.eq_ignore_ascii_case("iso"))
+                .unwrap_or(false);
+            
+            if is_iso {
+                let path_str = path.to_string_lossy().to_string();
+                if !isos.contains(&path_str) {
+                    isos.push(path_str);
                 }
             }
         }
@@ -124,94 +346,716 @@ fn list_isos() -> Vec<String> {
 }
 
 #[tauri::command]
-fn create_vm(name: String, iso: Option<String>, ram: u32, cpus: u32, disk_size: u32) -> Result<String, String> {
+fn set_iso_search_paths(paths: Vec<String>) -> Result<String, AppError> {
+    let paths: Vec<PathBuf> = paths.into_iter().map(PathBuf::from).collect();
+    if let Some(relative) = paths.iter().find(|p| !p.is_absolute()) {
+        return Err(AppError::Invalid(format!("ISO search path '{}' must be absolute", relative.display())));
+    }
+    
+    let _lock = lock_config()?;
+    let mut config = load_config();
+    config.iso_search_paths = paths;
+    save_config(&config)?;
+    
+    Ok("ISO search paths saved".to_string())
+}
+
+#[tauri::command]
+async fn download_iso(window: tauri::Window, url: String, sha256: Option<String>) -> Result<String, AppError> {
+    let iso_dir = get_config_dir().join("isos");
+    let mut progress = Progress::new(&window, "download_iso");
+    
+    progress.emit("starting", Some(0));
+    let path = download::download_file(&url, &iso_dir, sha256.as_deref(), |stage, percent| {
+        progress.emit(stage, percent);
+    })
+    .await?;
+    progress.emit("done", Some(100));
+    
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[tauri::command(async)]
+fn create_vm(
+    window: tauri::Window,
+    name: String,
+    iso: Option<String>,
+    ram: u32,
+    cpus: u32,
+    disk_size: u32,
+    preallocate: Option<bool>,
+) -> Result<String, AppError> {
+    validate_vm_name(&name)?;
+    let _lock = lock_config()?;
     let mut config = load_config();
     
     if config.vms.contains_key(&name) {
-        return Err(format!("VM '{}' already exists", name));
+        return Err(AppError::AlreadyExists(format!("VM '{}'", name)));
     }
     
-    let vm_dir = get_config_dir().join("vms").join(&name);
-    fs::create_dir_all(&vm_dir).map_err(|e| e.to_string())?;
+    let vm_dir = get_vm_dir(&name);
+    fs::create_dir_all(&vm_dir)?;
     
     let disk_path = vm_dir.join(format!("{}.qcow2", name));
+    let preallocate = preallocate.unwrap_or(false);
+    let mut progress = Progress::new(&window, "create_vm");
+    progress.emit("creating_disk", Some(0));
     
     // Create disk using qemu-img
-    let output = Command::new("qemu-img")
-        .args(["create", "-f", "qcow2", disk_path.to_str().unwrap(), &format!("{}G", disk_size)])
-        .output()
-        .map_err(|e| format!("Failed to create disk: {}", e))?;
+    let mut cmd = Command::new("qemu-img");
+    cmd.args(["create", "-f", "qcow2"]);
+    if preallocate {
+        cmd.args(["-o", "preallocation=full"]);
+    }
+    let mut child = cmd
+        .arg(&disk_path)
+        .arg(format!("{}G", disk_size))
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::spawn("qemu-img", e))?;
+    
+    // Full preallocation writes every byte, so the file size tracks progress
+    if preallocate {
+        let target = u64::from(disk_size) * 1024 * 1024 * 1024;
+        while child.try_wait()?.is_none() {
+            let written = fs::metadata(&disk_path).map(|m| m.len()).unwrap_or(0);
+            progress.emit("creating_disk", Some(progress::percent(written, target)));
+            std::thread::sleep(std::time::Duration::from_millis(500));
+        }
+    }
     
+    let output = child.wait_with_output()?;
     if !output.status.success() {
-        return Err(format!("qemu-img failed: {}", String::from_utf8_lossy(&output.stderr)));
+        return Err(AppError::SpawnFailed(format!("qemu-img failed: {}", String::from_utf8_lossy(&output.stderr))));
     }
     
+    // The config is JSON, so a non-UTF-8 path can only be stored lossily
     config.vms.insert(name.clone(), VmConfig {
         disk: disk_path.to_string_lossy().to_string(),
+        disk_format: default_disk_format(),
         iso,
         ram,
         cpus,
         security_profile: None,
+        tor_socks_port: None,
+        ssh_port: None,
+        usb_passthrough: Vec::new(),
     });
     
     save_config(&config)?;
+    tray::refresh(&window.app_handle());
+    progress.emit("done", Some(100));
     Ok(format!("VM '{}' created successfully", name))
 }
 
 #[tauri::command]
-fn delete_vm(name: String) -> Result<String, String> {
+fn import_disk(app: tauri::AppHandle, name: String, path: String, ram: u32, cpus: u32, copy: bool) -> Result<String, AppError> {
+    validate_vm_name(&name)?;
+    let _lock = lock_config()?;
+    let mut config = load_config();
+    
+    if config.vms.contains_key(&name) {
+        return Err(AppError::AlreadyExists(format!("VM '{}'", name)));
+    }
+    
+    let source = PathBuf::from(&path);
+    fs::File::open(&source).map_err(|_| AppError::NotFound(format!("Disk image '{}'", path)))?;
+    
+    // Let qemu-img tell us whether this is a disk image and which format
+    let output = Command::new("qemu-img")
+        .args(["info", "--output=json", &path])
+        .output()
+        .map_err(|e| AppError::spawn("qemu-img", e))?;
+    
+    if !output.status.success() {
+        return Err(AppError::Invalid(format!("'{}' is not a disk image: {}", path, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let disk_format = info["format"]
+        .as_str()
+        .ok_or_else(|| AppError::Invalid(format!("qemu-img reported no format for '{}'", path)))?
+        .to_string();
+    
+    // The original is only ever read; with `copy` the VM gets its own image
+    let disk_path = if copy {
+        let vm_dir = get_vm_dir(&name);
+        fs::create_dir_all(&vm_dir)?;
+        let file_name = source.file_name().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(&name));
+        let target = vm_dir.join(file_name);
+        fs::copy(&source, &target)?;
+        target
+    } else {
+        source.canonicalize()?
+    };
+    
+    config.vms.insert(name.clone(), VmConfig {
+        disk: disk_path.to_string_lossy().to_string(),
+        disk_format,
+        iso: None,
+        ram,
+        cpus,
+        security_profile: None,
+        tor_socks_port: None,
+        ssh_port: None,
+        usb_passthrough: Vec::new(),
+    });
+    
+    save_config(&config)?;
+    tray::refresh(&app);
+    Ok(format!("Disk imported as VM '{}'", name))
+}
+
+#[tauri::command]
+fn delete_vm(app: tauri::AppHandle, name: String) -> Result<String, AppError> {
+    validate_vm_name(&name)?;
+    let _lock = lock_config()?;
     let mut config = load_config();
     
     if !config.vms.contains_key(&name) {
-        return Err(format!("VM '{}' not found", name));
+        return Err(AppError::NotFound(format!("VM '{}'", name)));
     }
     
-    let vm_dir = get_config_dir().join("vms").join(&name);
+    let vm_dir = get_vm_dir(&name);
+    security::clean_swtpm_state(&vm_dir.join("tpm"))?;
     if vm_dir.exists() {
-        fs::remove_dir_all(&vm_dir).map_err(|e| e.to_string())?;
+        fs::remove_dir_all(&vm_dir)?;
     }
     
     config.vms.remove(&name);
     save_config(&config)?;
+    tray::refresh(&app);
     
     Ok(format!("VM '{}' deleted", name))
 }
 
-#[tauri::command]
-fn run_vm(name: String, live: bool, install: bool) -> Result<String, String> {
+/// Show `path` in the platform's file manager
+fn open_in_file_manager(path: &Path) -> Result<(), AppError> {
+    #[cfg(target_os = "linux")]
+    let opener = "xdg-open";
+    #[cfg(target_os = "macos")]
+    let opener = "open";
+    #[cfg(target_os = "windows")]
+    let opener = "explorer";
+    
+    let mut child = Command::new(opener)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| AppError::spawn(opener, e))?;
+    
+    // explorer exits 1 even when it worked, so just reap it
+    std::thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+#[tauri::command]
+fn open_vm_folder(name: String) -> Result<String, AppError> {
+    validate_vm_name(&name)?;
+    if !load_config().vms.contains_key(&name) {
+        return Err(AppError::NotFound(format!("VM '{}'", name)));
+    }
+    
+    // Disks imported in place live elsewhere, but logs and pid files still go here
+    let vm_dir = get_vm_dir(&name);
+    fs::create_dir_all(&vm_dir)?;
+    open_in_file_manager(&vm_dir)?;
+    Ok(format!("Opened {}", vm_dir.display()))
+}
+
+/// Stream a VM's qemu.log to the window as `n01d://vm-log/<name>` events,
+/// starting from the top, until the VM stops or `stop_tailing` is called
+#[tauri::command]
+fn tail_vm_log(
+    window: tauri::Window,
+    running: tauri::State<'_, RunningVms>,
+    tails: tauri::State<'_, LogTails>,
+    name: String,
+) -> Result<(), AppError> {
+    validate_vm_name(&name)?;
+    if !load_config().vms.contains_key(&name) {
+        return Err(AppError::NotFound(format!("VM '{}'", name)));
+    }
+    
+    let running = running.inner().clone();
+    let vm = name.clone();
+    tails.start(&name, get_vm_dir(&name).join("qemu.log"), window, move || {
+        running.0.lock().unwrap().get(&vm).is_some_and(|pid| pid_alive(*pid))
+    });
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_tailing(tails: tauri::State<'_, LogTails>, name: String) -> bool {
+    tails.stop(&name)
+}
+
+#[tauri::command]
+fn open_config_dir() -> Result<String, AppError> {
+    let dir = get_config_dir();
+    fs::create_dir_all(&dir)?;
+    open_in_file_manager(&dir)?;
+    Ok(format!("Opened {}", dir.display()))
+}
+
+/// Record the QEMU pid in the VM directory, like the CLI does
+fn write_pid(name: &str, pid: u32) -> Result<(), AppError> {
+    let vm_dir = get_vm_dir(name);
+    fs::create_dir_all(&vm_dir)?;
+    fs::write(vm_dir.join("vm.pid"), pid.to_string())?;
+    Ok(())
+}
+
+/// Send QEMU's stdout/stderr to `<vm_dir>/qemu.log`, keeping the previous
+/// run's log as `qemu.log.1`
+fn redirect_qemu_output(cmd: &mut Command, name: &str) -> Result<(), AppError> {
+    let vm_dir = get_vm_dir(name);
+    fs::create_dir_all(&vm_dir)?;
+    
+    let log_path = vm_dir.join("qemu.log");
+    if log_path.exists() {
+        fs::rename(&log_path, vm_dir.join("qemu.log.1"))?;
+    }
+    
+    let log = fs::OpenOptions::new().create(true).append(true).open(&log_path)?;
+    cmd.stdout(Stdio::from(log.try_clone()?));
+    cmd.stderr(Stdio::from(log));
+    Ok(())
+}
+
+/// Give QEMU a moment to fail on bad options, missing KVM or a locked disk,
+/// and turn an early exit into an error carrying the end of its log
+fn wait_for_launch(child: &mut Child, name: &str) -> Result<(), AppError> {
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    
+    if let Some(status) = child.try_wait()? {
+        let log = fs::read_to_string(get_vm_dir(name).join("qemu.log")).unwrap_or_default();
+        let tail: Vec<&str> = log.lines().rev().take(10).collect();
+        let output = tail.into_iter().rev().collect::<Vec<_>>().join("\n");
+        
+        return Err(AppError::SpawnFailed(format!("QEMU exited during startup ({}): {}", status, output)));
+    }
+    
+    Ok(())
+}
+
+/// Helpers started for a VM that live exactly as long as its QEMU process
+#[derive(Default)]
+struct VmCompanions {
+    rotator: Option<security::IdentityRotator>,
+    proxy: Option<security::LocalProxy>,
+    tor: Option<security::VmTor>,
+    tor_network: Option<security::TorNetwork>,
+    tpm: Option<security::Swtpm>,
+}
+
+/// VM name -> QEMU pid for every VM this app knows to be running
+#[derive(Default, Clone)]
+struct RunningVms(Arc<Mutex<HashMap<String, u32>>>);
+
+impl RunningVms {
+    /// Pick up VMs still running from a previous session via their pid files
+    fn recover(config: &AppConfig) -> Self {
+        let running = RunningVms::default();
+        {
+            let mut vms = running.0.lock().unwrap();
+            for name in config.vms.keys() {
+                if let Some(pid) = running_pid(name) {
+                    vms.insert(name.clone(), pid);
+                }
+            }
+        }
+        running
+    }
+    
+    /// Record a VM we just spawned and forget it again once QEMU exits,
+    /// stopping its companions with it
+    fn track(&self, name: &str, mut child: Child, companions: VmCompanions) -> Result<(), AppError> {
+        let pid = child.id();
+        write_pid(name, pid)?;
+        self.0.lock().unwrap().insert(name.to_string(), pid);
+        
+        let vms = self.clone();
+        let name = name.to_string();
+        std::thread::spawn(move || {
+            let _ = child.wait();
+            if let Some(rotator) = companions.rotator {
+                rotator.stop();
+            }
+            drop(companions.proxy);
+            drop(companions.tor);
+            drop(companions.tor_network);
+            drop(companions.tpm);
+            
+            // A restart may have replaced the entry with a newer pid
+            let mut vms = vms.0.lock().unwrap();
+            if vms.get(&name) == Some(&pid) {
+                vms.remove(&name);
+                let _ = fs::remove_file(get_vm_dir(&name).join("vm.pid"));
+            }
+        });
+        
+        Ok(())
+    }
+}
+
+#[tauri::command]
+fn get_running_vms(running: tauri::State<'_, RunningVms>) -> Vec<String> {
+    let mut vms = running.0.lock().unwrap();
+    
+    // Recovered VMs aren't our children, so nothing reaps them for us
+    vms.retain(|_, pid| pid_alive(*pid));
+    
+    let mut names: Vec<String> = vms.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// The pid from the VM's pid file, if that process is still alive
+fn running_pid(name: &str) -> Option<u32> {
+    live_pid(&get_vm_dir(name).join("vm.pid"))
+}
+
+/// Refuse to launch a VM whose disk, or the ISO it's about to boot, has
+/// been moved or deleted; QEMU would fail opaquely or drop to the firmware
+/// shell
+fn check_vm_files(vm: &VmConfig, boot_iso: bool) -> Result<(), AppError> {
+    if !Path::new(&vm.disk).exists() {
+        return Err(AppError::MissingFile { kind: "Disk", path: PathBuf::from(&vm.disk) });
+    }
+    match &vm.iso {
+        Some(iso) if boot_iso && !Path::new(iso).exists() => {
+            Err(AppError::MissingFile { kind: "ISO", path: PathBuf::from(iso) })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Refuse to launch a second QEMU on a VM's disk, which would corrupt it.
+/// A pid file left behind by a VM that died doesn't count
+fn ensure_not_running(running: &RunningVms, name: &str, disk: &Path) -> Result<(), AppError> {
+    let tracked = running.0.lock().unwrap().get(name).copied().filter(|pid| pid_alive(*pid));
+    if let Some(pid) = tracked.or_else(|| running_pid(name)) {
+        return Err(AppError::Invalid(format!("VM '{}' is already running (pid {})", name, pid)));
+    }
+    
+    // Catches QEMUs started outside n01d: `qemu-img info` asks for the same
+    // image locks QEMU holds, and fails the same way the launch would
+    if disk.exists() {
+        let output = Command::new("qemu-img")
+            .arg("info")
+            .arg(disk)
+            .output()
+            .map_err(|e| AppError::spawn("qemu-img", e))?;
+        if !output.status.success() && String::from_utf8_lossy(&output.stderr).contains("lock") {
+            return Err(AppError::Invalid(format!("Disk of VM '{}' is in use by another QEMU process", name)));
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_vm(running: tauri::State<'_, RunningVms>, name: String, force: bool) -> Result<String, AppError> {
+    validate_vm_name(&name)?;
+    let pid = running_pid(&name).ok_or_else(|| AppError::Invalid(format!("VM '{}' is not running", name)))?;
+    
+    #[cfg(unix)]
+    {
+        let signal = if force { "-KILL" } else { "-TERM" };
+        let output = Command::new("kill")
+            .args([signal, &pid.to_string()])
+            .output()
+            .map_err(|e| AppError::spawn("kill", e))?;
+        if !output.status.success() {
+            return Err(AppError::SpawnFailed(format!("kill failed: {}", String::from_utf8_lossy(&output.stderr))));
+        }
+    }
+    
+    #[cfg(windows)]
+    {
+        let pid = pid.to_string();
+        let mut args = vec!["/PID", pid.as_str()];
+        if force {
+            args.push("/F");
+        }
+        let output = Command::new("taskkill")
+            .args(&args)
+            .output()
+            .map_err(|e| AppError::spawn("taskkill", e))?;
+        if !output.status.success() {
+            return Err(AppError::SpawnFailed(format!("taskkill failed: {}", String::from_utf8_lossy(&output.stderr))));
+        }
+    }
+    
+    running.0.lock().unwrap().remove(&name);
+    let _ = fs::remove_file(get_vm_dir(&name).join("vm.pid"));
+    Ok(format!("VM '{}' stopped", name))
+}
+
+#[derive(Debug, Serialize)]
+struct SnapshotInfo {
+    id: String,
+    name: String,
+    date_sec: u64,
+    vm_state_size: u64,
+}
+
+/// Look up a VM for a snapshot operation: it has to exist, be qcow2, and be stopped
+fn snapshot_target(vm_name: &str) -> Result<VmConfig, AppError> {
+    validate_vm_name(vm_name)?;
+    let mut config = load_config();
+    let vm = config.vms.remove(vm_name).ok_or_else(|| AppError::NotFound(format!("VM '{}'", vm_name)))?;
+    
+    if vm.disk_format != "qcow2" {
+        return Err(AppError::Invalid(format!("Snapshots need a qcow2 disk, '{}' is {}", vm_name, vm.disk_format)));
+    }
+    
+    Ok(vm)
+}
+
+/// Check the name for a new snapshot, by the same rules as the CLI
+fn validate_snapshot_name(name: &str) -> Result<(), AppError> {
+    match n01d_common::snapshot::snapshot_name_problem(name) {
+        Some(reason) => Err(AppError::Invalid(format!("Invalid snapshot name '{}': {}", name, reason))),
+        None => Ok(()),
+    }
+}
+
+/// Check the name of a snapshot to restore or delete. It may have been made
+/// by hand or by the CLI's autostart, so only what qemu-img would misread
+/// is refused
+fn validate_existing_snapshot_name(name: &str) -> Result<(), AppError> {
+    // qemu-img would read a leading dash as an option
+    if name.trim().is_empty() || name.starts_with('-') {
+        return Err(AppError::Invalid(format!("Invalid snapshot name '{}'", name)));
+    }
+    Ok(())
+}
+
+fn ensure_stopped(vm_name: &str) -> Result<(), AppError> {
+    match running_pid(vm_name) {
+        Some(pid) => Err(AppError::Invalid(format!("VM '{}' is running (pid {}), stop it first", vm_name, pid))),
+        None => Ok(()),
+    }
+}
+
+fn run_qemu_img_snapshot(flag: &str, snapshot: &str, disk: &str) -> Result<(), AppError> {
+    let output = Command::new("qemu-img")
+        .args(["snapshot", flag, snapshot, disk])
+        .output()
+        .map_err(|e| AppError::spawn("qemu-img", e))?;
+    
+    if !output.status.success() {
+        return Err(AppError::SpawnFailed(format!("qemu-img failed: {}", String::from_utf8_lossy(&output.stderr))));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn create_snapshot(vm: String, name: String) -> Result<String, AppError> {
+    let config = snapshot_target(&vm)?;
+    validate_snapshot_name(&name)?;
+    ensure_stopped(&vm)?;
+    
+    // The disk is the authority, whoever made its snapshots
+    let existing = n01d_common::snapshot::disk_snapshots(Path::new(&config.disk))
+        .ok_or_else(|| AppError::Other(format!("Couldn't read the snapshots on {}", config.disk)))?;
+    if existing.contains(&name) {
+        return Err(AppError::AlreadyExists(format!("Snapshot '{}' on VM '{}'", name, vm)));
+    }
+    
+    run_qemu_img_snapshot("-c", &name, &config.disk)
+        .map_err(|_| AppError::Other(format!("Couldn't create snapshot '{}' on VM '{}'", name, vm)))?;
+    Ok(format!("Snapshot '{}' created for VM '{}'", name, vm))
+}
+
+#[tauri::command]
+fn restore_snapshot(vm: String, snapshot: String) -> Result<String, AppError> {
+    let config = snapshot_target(&vm)?;
+    validate_existing_snapshot_name(&snapshot)?;
+    ensure_stopped(&vm)?;
+    
+    run_qemu_img_snapshot("-a", &snapshot, &config.disk)?;
+    Ok(format!("VM '{}' restored to snapshot '{}'", vm, snapshot))
+}
+
+#[tauri::command]
+fn delete_snapshot(vm: String, name: String) -> Result<String, AppError> {
+    let config = snapshot_target(&vm)?;
+    validate_existing_snapshot_name(&name)?;
+    ensure_stopped(&vm)?;
+    
+    run_qemu_img_snapshot("-d", &name, &config.disk)?;
+    Ok(format!("Snapshot '{}' deleted from VM '{}'", name, vm))
+}
+
+#[tauri::command]
+fn list_snapshots(vm: String) -> Result<Vec<SnapshotInfo>, AppError> {
+    let config = snapshot_target(&vm)?;
+    
+    // -U lets us read the snapshot table while the VM has the image locked
+    let output = Command::new("qemu-img")
+        .args(["info", "-U", "--output=json", &config.disk])
+        .output()
+        .map_err(|e| AppError::spawn("qemu-img", e))?;
+    
+    if !output.status.success() {
+        return Err(AppError::SpawnFailed(format!("qemu-img failed: {}", String::from_utf8_lossy(&output.stderr))));
+    }
+    
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    Ok(parse_snapshots(&info))
+}
+
+fn parse_snapshots(info: &serde_json::Value) -> Vec<SnapshotInfo> {
+    info["snapshots"]
+        .as_array()
+        .map(|snapshots| {
+            snapshots
+                .iter()
+                .map(|s| SnapshotInfo {
+                    id: s["id"].as_str().unwrap_or_default().to_string(),
+                    name: s["name"].as_str().unwrap_or_default().to_string(),
+                    date_sec: s["date-sec"].as_u64().unwrap_or(0),
+                    vm_state_size: s["vm-state-size"].as_u64().unwrap_or(0),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether `/dev/kvm` exists and we can open it
+#[cfg(target_os = "linux")]
+fn kvm_available() -> bool {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .is_ok()
+}
+
+/// The host's hardware accelerator, matching what `add_accel_args` asks for
+fn accelerator() -> Option<&'static str> {
+    if cfg!(target_os = "linux") {
+        kvm_available().then_some("kvm")
+    } else if cfg!(target_os = "macos") {
+        let output = Command::new("sysctl").args(["-n", "kern.hv_support"]).output().ok()?;
+        (String::from_utf8_lossy(&output.stdout).trim() == "1").then_some("hvf")
+    } else if cfg!(target_os = "windows") {
+        // Only tells that QEMU was built with WHPX; whether the Hypervisor
+        // Platform feature is on shows up at launch
+        let output = Command::new("qemu-system-x86_64").args(["-accel", "help"]).output().ok()?;
+        String::from_utf8_lossy(&output.stdout).lines().any(|line| line.trim() == "whpx").then_some("whpx")
+    } else {
+        None
+    }
+}
+
+/// Add the platform's hardware accelerator to `cmd`, returning false when
+/// QEMU has to fall back to TCG software emulation
+fn add_accel_args(cmd: &mut Command) -> bool {
+    #[cfg(target_os = "linux")]
+    let accelerated = if kvm_available() {
+        cmd.arg("-enable-kvm");
+        true
+    } else {
+        cmd.args(["-accel", "tcg"]);
+        false
+    };
+    
+    #[cfg(target_os = "macos")]
+    let accelerated = {
+        cmd.args(["-accel", "hvf"]);
+        true
+    };
+    
+    #[cfg(target_os = "windows")]
+    let accelerated = {
+        cmd.args(["-accel", "whpx"]);
+        true
+    };
+    
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    let accelerated = {
+        let _ = cmd;
+        false
+    };
+    
+    accelerated
+}
+
+/// Build a `-drive` value without going through `str`, so non-UTF-8 paths
+/// survive. QEMU splits options on commas, so commas in the path are doubled
+fn drive_arg(disk: &Path, options: &str) -> OsString {
+    let mut arg = OsString::from("file=");
+    match disk.to_str() {
+        Some(path) => arg.push(path.replace(',', ",,")),
+        None => arg.push(disk),
+    }
+    arg.push(",");
+    arg.push(options);
+    arg
+}
+
+fn with_tcg_warning(message: String, accelerated: bool) -> String {
+    if accelerated {
+        message
+    } else {
+        format!("{} (KVM is not available, using TCG emulation; performance will be degraded)", message)
+    }
+}
+
+#[tauri::command(async)]
+fn run_vm(
+    running: tauri::State<'_, RunningVms>,
+    vault: tauri::State<'_, Vault>,
+    name: String,
+    live: bool,
+    install: bool,
+    rotate_identity: Option<u64>,
+) -> Result<String, AppError> {
+    validate_vm_name(&name)?;
     let config = load_config();
-    let vm = config.vms.get(&name).ok_or(format!("VM '{}' not found", name))?;
+    let vm = config.vms.get(&name).ok_or_else(|| AppError::NotFound(format!("VM '{}'", name)))?;
+    check_vm_files(vm, live || install)?;
+    
+    // A VM's own profile wins, otherwise the configured default applies
+    if let Some(profile) = vm.security_profile.as_ref().or(config.default_security_profile.as_ref()) {
+        return run_vm_secure(running, vault, name.clone(), profile.clone(), live, install, rotate_identity);
+    }
+    if rotate_identity.is_some() {
+        return Err(AppError::Invalid(format!("VM '{}' has no Tor security profile to rotate identities for", name)));
+    }
+    ensure_not_running(&running, &name, Path::new(&vm.disk))?;
+    let ssh_port = assign_ssh_port(&name)?;
     
     let mut cmd = Command::new("qemu-system-x86_64");
     cmd.args(["-name", &format!("n01d-{}", name)]);
     
-    // Try to use KVM acceleration on Linux
-    #[cfg(target_os = "linux")]
-    cmd.arg("-enable-kvm");
-    
-    // Try to use HVF acceleration on macOS
-    #[cfg(target_os = "macos")]
-    cmd.args(["-accel", "hvf"]);
-    
-    // Try to use WHPX acceleration on Windows
-    #[cfg(target_os = "windows")]
-    cmd.args(["-accel", "whpx"]);
+    let accelerated = add_accel_args(&mut cmd);
     
     cmd.args([
         "-m", &vm.ram.to_string(),
         "-smp", &vm.cpus.to_string(),
         "-cpu", "max",
-        "-drive", &format!("file={},format=qcow2,if=virtio", vm.disk),
-        "-netdev", "user,id=net0,hostfwd=tcp::2222-:22",
+        "-netdev", &format!("user,id=net0,hostfwd=tcp:127.0.0.1:{}-:22", ssh_port),
         "-device", "virtio-net-pci,netdev=net0",
         "-vga", "virtio",
         "-usb", "-device", "usb-tablet",
         "-display", "gtk",
     ]);
     
+    cmd.arg("-drive").arg(drive_arg(Path::new(&vm.disk), &format!("format={},if=virtio", vm.disk_format)));
+    
     if let Some(iso) = &vm.iso {
         if live || install {
-            cmd.args(["-cdrom", iso, "-boot", "d"]);
+            cmd.arg("-cdrom").arg(Path::new(iso));
+            cmd.args(["-boot", "d"]);
         }
     }
     
@@ -219,26 +1063,26 @@ fn run_vm(name: String, live: bool, install: bool) -> Result<String, String> {
         cmd.args(["-boot", "c"]);
     }
     
-    cmd.spawn().map_err(|e| format!("Failed to start VM: {}", e))?;
+    redirect_qemu_output(&mut cmd, &name)?;
+    let mut child = cmd.spawn().map_err(|e| AppError::spawn("qemu-system-x86_64", e))?;
+    wait_for_launch(&mut child, &name)?;
+    running.track(&name, child, VmCompanions::default())?;
     
-    Ok(format!("VM '{}' started", name))
+    Ok(with_tcg_warning(format!("VM '{}' started, SSH on port {}", name, ssh_port), accelerated))
 }
 
 #[tauri::command]
-fn quick_boot_iso(iso_path: String) -> Result<String, String> {
+fn quick_boot_iso(iso_path: String) -> Result<String, AppError> {
     let config = load_config();
+    // Not a saved VM, so it only borrows a port no VM has for this boot
+    let ssh_port = SecurityManager::new(get_config_dir())
+        .with_ssh_ports(assigned_ssh_ports(&config))
+        .allocate_ssh_port("quickboot")?;
     
     let mut cmd = Command::new("qemu-system-x86_64");
     cmd.args(["-name", "n01d-quickboot"]);
     
-    #[cfg(target_os = "linux")]
-    cmd.arg("-enable-kvm");
-    
-    #[cfg(target_os = "macos")]
-    cmd.args(["-accel", "hvf"]);
-    
-    #[cfg(target_os = "windows")]
-    cmd.args(["-accel", "whpx"]);
+    let accelerated = add_accel_args(&mut cmd);
     
     cmd.args([
         "-m", &config.default_ram.to_string(),
@@ -246,20 +1090,21 @@ fn quick_boot_iso(iso_path: String) -> Result<String, String> {
         "-cpu", "max",
         "-cdrom", &iso_path,
         "-boot", "d",
-        "-netdev", "user,id=net0,hostfwd=tcp::2222-:22",
+        "-netdev", &format!("user,id=net0,hostfwd=tcp:127.0.0.1:{}-:22", ssh_port),
         "-device", "virtio-net-pci,netdev=net0",
         "-vga", "virtio",
         "-usb", "-device", "usb-tablet",
         "-display", "gtk",
     ]);
     
-    cmd.spawn().map_err(|e| format!("Failed to boot ISO: {}", e))?;
+    cmd.spawn().map_err(|e| AppError::spawn("qemu-system-x86_64", e))?;
     
-    Ok("ISO booted".to_string())
+    Ok(with_tcg_warning(format!("ISO booted, SSH on port {}", ssh_port), accelerated))
 }
 
 #[tauri::command]
-fn save_settings(default_ram: u32, default_cpus: u32) -> Result<String, String> {
+fn save_settings(default_ram: u32, default_cpus: u32) -> Result<String, AppError> {
+    let _lock = lock_config()?;
     let mut config = load_config();
     config.default_ram = default_ram;
     config.default_cpus = default_cpus;
@@ -272,6 +1117,32 @@ fn check_qemu_installed() -> bool {
     which::which("qemu-system-x86_64").is_ok()
 }
 
+/// Guest architectures there may be a `qemu-system-<arch>` for
+const QEMU_ARCHES: &[&str] = &["x86_64", "aarch64", "riscv64"];
+
+#[derive(Debug, Clone, Serialize)]
+struct QemuBinary {
+    arch: &'static str,
+    installed: bool,
+    /// Only guests of the host's own arch get hardware acceleration; the
+    /// rest are emulated with TCG
+    accelerated: bool,
+}
+
+/// Which emulators are installed and which of them would run accelerated,
+/// so the UI can point ARM hosts away from a slow x86_64 emulator
+#[tauri::command]
+fn list_qemu_binaries() -> Vec<QemuBinary> {
+    let accelerator = accelerator().is_some();
+    QEMU_ARCHES
+        .iter()
+        .map(|arch| {
+            let installed = which::which(format!("qemu-system-{}", arch)).is_ok();
+            QemuBinary { arch, installed, accelerated: installed && accelerator && *arch == std::env::consts::ARCH }
+        })
+        .collect()
+}
+
 // ==================== SECURITY COMMANDS ====================
 
 #[tauri::command]
@@ -282,37 +1153,297 @@ fn get_security_profiles() -> Vec<(String, String, SecurityProfile)> {
         .collect()
 }
 
+/// The custom security profiles, decrypting them if they're stored encrypted
+fn custom_profiles(vault: &Vault, config: &AppConfig) -> Result<HashMap<String, SecurityProfile>, AppError> {
+    if !config.encrypt_profiles {
+        return Ok(config.security_profiles.clone());
+    }
+    
+    let mut profiles = match &config.sealed_profiles {
+        Some(sealed) => vault.open(sealed)?,
+        None if vault.is_unlocked() => HashMap::new(),
+        None => return Err(AppError::Locked),
+    };
+    // Plaintext profiles from before encryption was turned on are sealed
+    // along with the rest on the next save
+    profiles.extend(config.security_profiles.clone());
+    Ok(profiles)
+}
+
+/// Replace the custom security profiles, sealing them if encryption is on
+fn store_profiles(vault: &Vault, config: &mut AppConfig, profiles: HashMap<String, SecurityProfile>) -> Result<(), AppError> {
+    if config.encrypt_profiles {
+        config.sealed_profiles = Some(vault.seal(&profiles)?);
+        config.security_profiles.clear();
+    } else {
+        config.security_profiles = profiles;
+    }
+    Ok(())
+}
+
 #[tauri::command]
-fn get_custom_security_profiles() -> HashMap<String, SecurityProfile> {
-    load_config().security_profiles
+fn get_custom_security_profiles(vault: tauri::State<'_, Vault>) -> Result<HashMap<String, SecurityProfile>, AppError> {
+    let profiles = custom_profiles(&vault, &load_config())?;
+    let security_manager = SecurityManager::new(get_config_dir())
+        .with_custom_profiles(profiles.clone());
+    
+    profiles
+        .keys()
+        .map(|name| Ok((name.clone(), security_manager.resolve_profile(name)?)))
+        .collect()
 }
 
 #[tauri::command]
-fn create_security_profile(name: String, profile: SecurityProfile) -> Result<String, String> {
+fn create_security_profile(vault: tauri::State<'_, Vault>, name: String, profile: SecurityProfile) -> Result<String, AppError> {
+    let _lock = lock_config()?;
     let mut config = load_config();
-    config.security_profiles.insert(name.clone(), profile);
+    let mut profiles = custom_profiles(&vault, &config)?;
+    // The profile goes by the name it's saved under, firewall chain included
+    profiles.insert(name.clone(), SecurityProfile { name: name.clone(), ..profile });
+    
+    // Reject profiles whose parent is missing, that form a cycle, or
+    // whose resolved settings contradict each other
+    let resolved = SecurityManager::new(get_config_dir())
+        .with_custom_profiles(profiles.clone())
+        .resolve_profile(&name)?;
+    resolved.validate().map_err(|problems| {
+        AppError::Invalid(format!("Invalid security profile '{}': {}", name, problems.join("; ")))
+    })?;
+    
+    store_profiles(&vault, &mut config, profiles)?;
     save_config(&config)?;
     Ok(format!("Security profile '{}' created", name))
 }
 
 #[tauri::command]
-fn delete_security_profile(name: String) -> Result<String, String> {
+fn delete_security_profile(vault: tauri::State<'_, Vault>, name: String) -> Result<String, AppError> {
+    let _lock = lock_config()?;
     let mut config = load_config();
-    config.security_profiles.remove(&name);
+    let mut profiles = custom_profiles(&vault, &config)?;
+    profiles.remove(&name);
+    store_profiles(&vault, &mut config, profiles)?;
     save_config(&config)?;
     Ok(format!("Security profile '{}' deleted", name))
 }
 
 #[tauri::command]
-fn apply_security_profile(vm_name: String, profile_name: String) -> Result<String, String> {
+fn export_security_profile(vault: tauri::State<'_, Vault>, name: String, path: String) -> Result<String, AppError> {
+    let is_preset = SecurityManager::get_preset_profiles().iter().any(|(n, _, _)| *n == name);
+    let custom = if is_preset { HashMap::new() } else { custom_profiles(&vault, &load_config())? };
+    
+    let profile = if let Some(profile) = custom.get(&name) {
+        profile.clone()
+    } else {
+        SecurityManager::get_preset_profiles()
+            .into_iter()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, _, p)| p)
+            .ok_or_else(|| AppError::NotFound(format!("Security profile '{}'", name)))?
+    };
+    
+    profile.export_to_file(&PathBuf::from(&path))?;
+    Ok(format!("Security profile '{}' exported to {}", name, path))
+}
+
+#[tauri::command]
+fn import_security_profile(vault: tauri::State<'_, Vault>, path: String, overwrite: bool) -> Result<String, AppError> {
+    let profile = SecurityProfile::import_from_file(&PathBuf::from(&path))?;
+    let name = profile.name.clone();
+    
+    let _lock = lock_config()?;
+    let mut config = load_config();
+    let is_preset = SecurityManager::get_preset_profiles()
+        .iter()
+        .any(|(n, _, _)| *n == name);
+    
+    if is_preset {
+        return Err(AppError::AlreadyExists(format!("Built-in security profile '{}'", name)));
+    }
+    
+    let mut profiles = custom_profiles(&vault, &config)?;
+    if profiles.contains_key(&name) && !overwrite {
+        return Err(AppError::AlreadyExists(format!("Security profile '{}'", name)));
+    }
+    
+    profiles.insert(name.clone(), profile);
+    
+    let resolved = SecurityManager::new(get_config_dir())
+        .with_custom_profiles(profiles.clone())
+        .resolve_profile(&name)?;
+    resolved.validate().map_err(|problems| {
+        AppError::Invalid(format!("Invalid security profile '{}': {}", name, problems.join("; ")))
+    })?;
+    
+    store_profiles(&vault, &mut config, profiles)?;
+    save_config(&config)?;
+    Ok(format!("Security profile '{}' imported", name))
+}
+
+#[tauri::command]
+fn apply_security_profile(vm_name: String, profile_name: String) -> Result<String, AppError> {
+    validate_vm_name(&vm_name)?;
+    let _lock = lock_config()?;
     let mut config = load_config();
     if let Some(vm) = config.vms.get_mut(&vm_name) {
         vm.security_profile = Some(profile_name.clone());
         save_config(&config)?;
         Ok(format!("Applied '{}' profile to VM '{}'", profile_name, vm_name))
     } else {
-        Err(format!("VM '{}' not found", vm_name))
+        Err(AppError::NotFound(format!("VM '{}'", vm_name)))
+    }
+}
+
+#[tauri::command]
+fn set_default_security_profile(vault: tauri::State<'_, Vault>, name: Option<String>) -> Result<String, AppError> {
+    let _lock = lock_config()?;
+    let mut config = load_config();
+    
+    if let Some(name) = &name {
+        SecurityManager::new(get_config_dir())
+            .with_custom_profiles(custom_profiles(&vault, &config)?)
+            .resolve_profile(name)?;
+    }
+    
+    let message = match &name {
+        Some(name) => format!("Default security profile set to '{}'", name),
+        None => "Default security profile cleared".to_string(),
+    };
+    
+    config.default_security_profile = name;
+    save_config(&config)?;
+    Ok(message)
+}
+
+#[derive(Debug, Serialize)]
+struct ProfileEncryption {
+    enabled: bool,
+    unlocked: bool,
+}
+
+#[tauri::command]
+fn get_profile_encryption(vault: tauri::State<'_, Vault>) -> ProfileEncryption {
+    let enabled = load_config().encrypt_profiles;
+    ProfileEncryption { enabled, unlocked: !enabled || vault.is_unlocked() }
+}
+
+#[tauri::command]
+fn unlock_security_profiles(vault: tauri::State<'_, Vault>, passphrase: String) -> Result<String, AppError> {
+    let config = load_config();
+    match &config.sealed_profiles {
+        _ if !config.encrypt_profiles => return Err(AppError::Invalid("Security profiles aren't encrypted".to_string())),
+        Some(sealed) => vault.unlock(&passphrase, sealed)?,
+        // Encryption was switched on in the config by hand, so the first
+        // passphrase entered becomes the passphrase
+        None => vault.set_passphrase(&passphrase)?,
+    }
+    Ok("Security profiles unlocked".to_string())
+}
+
+#[tauri::command]
+fn lock_security_profiles(vault: tauri::State<'_, Vault>) -> String {
+    vault.lock();
+    "Security profiles locked".to_string()
+}
+
+/// Encrypt the custom security profiles under `passphrase`, or change the
+/// passphrase if they already are. Profiles left in the old plaintext
+/// security_profiles.json are moved in, and plaintext copies removed
+#[tauri::command]
+fn encrypt_security_profiles(vault: tauri::State<'_, Vault>, passphrase: String) -> Result<String, AppError> {
+    let _lock = lock_config()?;
+    let mut config = load_config();
+    let mut profiles = custom_profiles(&vault, &config)?;
+    
+    let legacy_path = get_config_dir().join("security_profiles.json");
+    if let Ok(content) = fs::read_to_string(&legacy_path) {
+        let legacy: HashMap<String, SecurityProfile> = serde_json::from_str(&content)
+            .map_err(|e| AppError::Invalid(format!("{} is not valid: {}", legacy_path.display(), e)))?;
+        for (name, profile) in legacy {
+            profiles.entry(name).or_insert(profile);
+        }
+    }
+    
+    vault.set_passphrase(&passphrase)?;
+    config.encrypt_profiles = true;
+    store_profiles(&vault, &mut config, profiles)?;
+    save_config(&config)?;
+    
+    // The backup `load_config` keeps from before a schema upgrade, and the
+    // damaged configs `save_config` sets aside, would still hold the
+    // profiles in plaintext
+    let mut plaintext = vec![legacy_path, get_config_path().with_extension("json.bak")];
+    for entry in fs::read_dir(get_config_dir())?.flatten() {
+        if entry.file_name().to_string_lossy().starts_with("config.json.corrupt-") {
+            plaintext.push(entry.path());
+        }
+    }
+    for path in plaintext {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok("Security profiles are now encrypted".to_string())
+}
+
+#[tauri::command]
+fn decrypt_security_profiles(vault: tauri::State<'_, Vault>) -> Result<String, AppError> {
+    let _lock = lock_config()?;
+    let mut config = load_config();
+    let profiles = custom_profiles(&vault, &config)?;
+    
+    config.encrypt_profiles = false;
+    config.sealed_profiles = None;
+    store_profiles(&vault, &mut config, profiles)?;
+    save_config(&config)?;
+    vault.lock();
+    Ok("Security profiles are stored unencrypted again".to_string())
+}
+
+/// Where the applied firewall rules are saved for restoring after a reboot
+fn firewall_ruleset_path() -> PathBuf {
+    get_config_dir().join("security").join("firewall.rules")
+}
+
+#[tauri::command]
+fn get_firewall_status() -> Vec<String> {
+    security::firewall_status()
+}
+
+#[tauri::command]
+fn save_firewall_rules() -> Result<String, AppError> {
+    let path = firewall_ruleset_path();
+    let saved = security::export_ruleset(&path)?;
+    // The boot unit loads its own root-owned copy, which would go stale
+    if security::firewall_restore_enabled() {
+        security::set_firewall_restore(&path, true)?;
     }
+    Ok(format!("Saved {} firewall chain(s) to {}", saved, path.display()))
+}
+
+#[tauri::command]
+fn restore_firewall_rules() -> Result<String, AppError> {
+    let path = firewall_ruleset_path();
+    if !path.exists() {
+        return Err(AppError::NotFound(format!("Saved firewall rules at {}", path.display())));
+    }
+    security::import_ruleset(&path)?;
+    Ok("Firewall rules restored".to_string())
+}
+
+/// Opt in to (or out of) reloading the saved rules at boot, so isolation
+/// doesn't lapse when the host reboots under a VM meant to keep running
+#[tauri::command]
+fn set_firewall_restore_on_boot(enable: bool) -> Result<String, AppError> {
+    let path = firewall_ruleset_path();
+    if enable && !path.exists() {
+        return Err(AppError::Invalid("Save the firewall rules before restoring them at boot".to_string()));
+    }
+    security::set_firewall_restore(&path, enable)?;
+    Ok(if enable {
+        format!("Firewall rules will be restored at boot by {}", security::FIREWALL_RESTORE_UNIT)
+    } else {
+        "Firewall rules will no longer be restored at boot".to_string()
+    })
 }
 
 #[tauri::command]
@@ -326,13 +1457,13 @@ fn check_tor_running() -> bool {
 }
 
 #[tauri::command]
-fn start_tor_service() -> Result<String, String> {
+fn start_tor_service() -> Result<String, AppError> {
     #[cfg(target_os = "linux")]
     {
         Command::new("systemctl")
             .args(["start", "tor"])
             .output()
-            .map_err(|e| format!("Failed to start Tor: {}", e))?;
+            .map_err(|e| AppError::spawn("systemctl", e))?;
     }
     
     #[cfg(target_os = "macos")]
@@ -340,27 +1471,27 @@ fn start_tor_service() -> Result<String, String> {
         Command::new("brew")
             .args(["services", "start", "tor"])
             .output()
-            .map_err(|e| format!("Failed to start Tor: {}", e))?;
+            .map_err(|e| AppError::spawn("brew", e))?;
     }
     
     #[cfg(target_os = "windows")]
     {
         Command::new("tor")
             .spawn()
-            .map_err(|e| format!("Failed to start Tor: {}", e))?;
+            .map_err(|e| AppError::spawn("tor", e))?;
     }
     
     Ok("Tor service started".to_string())
 }
 
 #[tauri::command]
-fn stop_tor_service() -> Result<String, String> {
+fn stop_tor_service() -> Result<String, AppError> {
     #[cfg(target_os = "linux")]
     {
         Command::new("systemctl")
             .args(["stop", "tor"])
             .output()
-            .map_err(|e| format!("Failed to stop Tor: {}", e))?;
+            .map_err(|e| AppError::spawn("systemctl", e))?;
     }
     
     #[cfg(target_os = "macos")]
@@ -368,7 +1499,7 @@ fn stop_tor_service() -> Result<String, String> {
         Command::new("brew")
             .args(["services", "stop", "tor"])
             .output()
-            .map_err(|e| format!("Failed to stop Tor: {}", e))?;
+            .map_err(|e| AppError::spawn("brew", e))?;
     }
     
     #[cfg(target_os = "windows")]
@@ -376,18 +1507,32 @@ fn stop_tor_service() -> Result<String, String> {
         Command::new("taskkill")
             .args(["/IM", "tor.exe", "/F"])
             .output()
-            .map_err(|e| format!("Failed to stop Tor: {}", e))?;
+            .map_err(|e| AppError::spawn("taskkill", e))?;
     }
     
     Ok("Tor service stopped".to_string())
 }
 
 #[tauri::command]
-fn new_tor_identity() -> Result<String, String> {
-    security::new_tor_circuit(9051)?;
+fn check_obfs4_installed() -> bool {
+    security::find_obfs4_transport().is_some()
+}
+
+#[tauri::command]
+fn new_tor_identity() -> Result<String, AppError> {
+    security::new_tor_circuit(&security::TorControlAddr { port: 9051, cookie: None })?;
     Ok("New Tor identity requested".to_string())
 }
 
+/// A WireGuard client config as a QR code for mobile clients. It's read
+/// from a file rather than generated from the VPN settings, whose template
+/// only has placeholder keys
+#[tauri::command]
+fn wireguard_config_qr(config_file: String) -> Result<String, AppError> {
+    let config = fs::read_to_string(&config_file)?;
+    Ok(security::wireguard_config_qr(&config)?)
+}
+
 #[tauri::command]
 fn check_vpn_installed() -> HashMap<String, bool> {
     let mut results = HashMap::new();
@@ -396,56 +1541,249 @@ fn check_vpn_installed() -> HashMap<String, bool> {
     results
 }
 
+/// An external program: whether it's on PATH and the version it reports
+#[derive(Debug, Clone, Serialize)]
+struct Dependency {
+    installed: bool,
+    version: Option<String>,
+}
+
+/// Everything the app shells out to, for one readiness panel
+#[derive(Debug, Clone, Serialize)]
+struct DependencyReport {
+    qemu_system: Dependency,
+    qemu_img: Dependency,
+    tor: Dependency,
+    tor_running: bool,
+    openvpn: Dependency,
+    wireguard: Dependency,
+    swtpm: Dependency,
+    iptables: Dependency,
+    nftables: Dependency,
+    /// `/dev/kvm` can be opened; always false off Linux
+    kvm: bool,
+}
+
+/// Look for the first of `programs` on PATH and ask it for its version
+fn find_dependency(programs: &[&str]) -> Dependency {
+    match n01d_common::deps::find_program(programs) {
+        Some((_, version)) => Dependency { installed: true, version },
+        None => Dependency { installed: false, version: None },
+    }
+}
+
+#[tauri::command]
+fn check_dependencies() -> DependencyReport {
+    #[cfg(target_os = "linux")]
+    let kvm = kvm_available();
+    #[cfg(not(target_os = "linux"))]
+    let kvm = false;
+    
+    DependencyReport {
+        qemu_system: find_dependency(&["qemu-system-x86_64"]),
+        qemu_img: find_dependency(&["qemu-img"]),
+        tor: find_dependency(&["tor"]),
+        tor_running: security::check_tor_status(),
+        openvpn: find_dependency(&["openvpn"]),
+        wireguard: find_dependency(&["wg", "wg-quick"]),
+        swtpm: find_dependency(&["swtpm"]),
+        iptables: find_dependency(&["iptables"]),
+        nftables: find_dependency(&["nft"]),
+        kvm,
+    }
+}
+
+/// Everything about the host worth pasting into a bug report
+#[derive(Debug, Serialize)]
+struct AppInfo {
+    version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    /// kvm, hvf or whpx
+    accelerator: Option<&'static str>,
+    dependencies: DependencyReport,
+    config_dir: PathBuf,
+    vms: usize,
+    /// None while encrypted profiles are locked
+    custom_profiles: Option<usize>,
+}
+
 #[tauri::command]
-fn run_vm_secure(name: String, profile_name: String, live: bool, install: bool) -> Result<String, String> {
+fn app_info(vault: tauri::State<'_, Vault>) -> AppInfo {
     let config = load_config();
-    let vm = config.vms.get(&name).ok_or(format!("VM '{}' not found", name))?;
+    AppInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        accelerator: accelerator(),
+        dependencies: check_dependencies(),
+        config_dir: get_config_dir(),
+        vms: config.vms.len(),
+        custom_profiles: custom_profiles(&vault, &config).ok().map(|profiles| profiles.len()),
+    }
+}
+
+/// The VM's own Tor SocksPort, picking and saving a free one the first time
+/// so it stays the same across restarts
+fn assign_socks_port(name: &str) -> Result<u16, AppError> {
+    let _lock = lock_config()?;
+    let mut config = load_config();
     
-    // Get security profile
-    let security_profile = if let Some(profile) = config.security_profiles.get(&profile_name) {
-        profile.clone()
+    let assigned: HashMap<String, u16> = config.vms
+        .iter()
+        .filter_map(|(vm, cfg)| Some((vm.clone(), cfg.tor_socks_port?)))
+        .collect();
+    let vm = config.vms.get_mut(name).ok_or_else(|| AppError::NotFound(format!("VM '{}'", name)))?;
+    if let Some(port) = vm.tor_socks_port {
+        return Ok(port);
+    }
+    
+    let port = SecurityManager::new(get_config_dir())
+        .with_socks_ports(assigned)
+        .allocate_socks_port()?;
+    vm.tor_socks_port = Some(port);
+    save_config(&config)?;
+    Ok(port)
+}
+
+/// Host SSH ports the saved VMs already have
+fn assigned_ssh_ports(config: &AppConfig) -> HashMap<String, u16> {
+    config.vms
+        .iter()
+        .filter_map(|(vm, cfg)| Some((vm.clone(), cfg.ssh_port?)))
+        .collect()
+}
+
+/// The VM's own host SSH port, picking and saving a free one the first time
+/// so several VMs can have SSH forwarded at once
+fn assign_ssh_port(name: &str) -> Result<u16, AppError> {
+    let _lock = lock_config()?;
+    let mut config = load_config();
+    
+    let assigned = assigned_ssh_ports(&config);
+    let vm = config.vms.get_mut(name).ok_or_else(|| AppError::NotFound(format!("VM '{}'", name)))?;
+    if let Some(port) = vm.ssh_port {
+        return Ok(port);
+    }
+    
+    let port = SecurityManager::new(get_config_dir())
+        .with_ssh_ports(assigned)
+        .allocate_ssh_port(name)?;
+    vm.ssh_port = Some(port);
+    save_config(&config)?;
+    Ok(port)
+}
+
+#[tauri::command(async)]
+fn run_vm_secure(
+    running: tauri::State<'_, RunningVms>,
+    vault: tauri::State<'_, Vault>,
+    name: String,
+    profile_name: String,
+    live: bool,
+    install: bool,
+    rotate_identity: Option<u64>,
+) -> Result<String, AppError> {
+    validate_vm_name(&name)?;
+    let config = load_config();
+    let vm = config.vms.get(&name).ok_or_else(|| AppError::NotFound(format!("VM '{}'", name)))?;
+    ensure_not_running(&running, &name, Path::new(&vm.disk))?;
+    
+    // Get security profile, flattening any inheritance
+    let security_manager = SecurityManager::new(get_config_dir())
+        .with_custom_profiles(custom_profiles(&vault, &config)?);
+    let security_profile = security_manager.resolve_profile(&profile_name)?;
+    
+    // Tor-only guests go through the TransPort on their tap, any other
+    // guest using Tor gets a SocksPort of its own
+    let tor_only = security_profile.network_isolation.mode() == IsolationMode::TorOnly;
+    let (security_manager, socks_port) = if security_profile.uses_tor() && !tor_only {
+        let port = assign_socks_port(&name)?;
+        (security_manager.with_socks_ports(HashMap::from([(name.clone(), port)])), Some(port))
     } else {
-        // Check preset profiles
-        SecurityManager::get_preset_profiles()
-            .into_iter()
-            .find(|(n, _, _)| *n == profile_name)
-            .map(|(_, _, p)| p)
-            .ok_or(format!("Security profile '{}' not found", profile_name))?
+        (security_manager, None)
     };
     
-    let security_manager = SecurityManager::new(get_config_dir());
-    let security_args = security_manager.generate_qemu_security_args(&security_profile);
+    let mut security_args = security_manager.generate_qemu_security_args(&security_profile, &name)?;
+    security_args.extend(security::usb_passthrough_args(&security_profile, &vm.usb_passthrough)?);
     
-    let mut cmd = Command::new("qemu-system-x86_64");
-    cmd.args(["-name", &format!("n01d-{}", name)]);
+    // Tor refuses NEWNYM more often than every 10 seconds
+    let rotate_identity = match rotate_identity {
+        Some(_) if !security_profile.uses_tor() => {
+            return Err(AppError::Invalid(format!("Security profile '{}' doesn't use Tor", profile_name)));
+        }
+        Some(secs) if secs < 10 => {
+            return Err(AppError::Invalid("Tor identity rotation needs an interval of at least 10 seconds".to_string()));
+        }
+        other => other,
+    };
     
-    #[cfg(target_os = "linux")]
-    cmd.arg("-enable-kvm");
+    // Catch a broken OpenVPN config now rather than as a silently dead daemon
+    let mut vpn_warnings = Vec::new();
+    if let Some(vpn) = &security_profile.vpn_config {
+        if let (VpnProvider::OpenVPN, Some(config_file)) = (&vpn.provider, &vpn.config_file) {
+            vpn_warnings = n01d_common::openvpn::validate_openvpn_config(Path::new(config_file)).map_err(|e| e.to_string())?;
+        }
+    }
     
-    #[cfg(target_os = "macos")]
-    cmd.args(["-accel", "hvf"]);
+    // Host-side confinement of QEMU itself, on top of the guest isolation
+    let vm_dir = get_vm_dir(&name);
+    let mut unconfined_warning = None;
+    let mut cmd = if security_profile.host_confined() && security::apparmor_available() {
+        let mut paths = vec![(Path::new(&vm.disk), "rwk")];
+        if let Some(iso) = &vm.iso {
+            paths.push((Path::new(iso), "r"));
+        }
+        // usb-host finds devices by id, so it scans the whole bus. The sysfs
+        // entries are symlinks, and AppArmor checks where they point
+        if !vm.usb_passthrough.is_empty() {
+            paths.push((Path::new("/dev/bus/usb/**"), "rw"));
+            paths.push((Path::new("/sys/bus/usb/devices/"), "r"));
+            paths.push((Path::new("/sys/devices/**/usb[0-9]*/**"), "r"));
+        }
+        if tor_only {
+            paths.push((Path::new("/dev/net/tun"), "rw"));
+        }
+        let policy = security::apparmor_allow(&security::generate_apparmor_profile(&security_profile, &vm_dir), &paths);
+        security::load_apparmor_profile(&vm_dir, &policy)?;
+        
+        let mut cmd = Command::new("aa-exec");
+        cmd.args(["-p", &security::apparmor_profile_name(&vm_dir), "--", "qemu-system-x86_64"]);
+        cmd
+    } else {
+        if security_profile.host_confined() {
+            unconfined_warning = Some("AppArmor is not available, QEMU is running unconfined");
+        }
+        Command::new("qemu-system-x86_64")
+    };
+    cmd.args(["-name", &format!("n01d-{}", name)]);
     
-    #[cfg(target_os = "windows")]
-    cmd.args(["-accel", "whpx"]);
+    let accelerated = add_accel_args(&mut cmd);
     
     cmd.args([
         "-m", &vm.ram.to_string(),
         "-smp", &vm.cpus.to_string(),
         "-cpu", "max",
-        "-drive", &format!("file={},format=qcow2,if=virtio", vm.disk),
         "-vga", "virtio",
-        "-usb", "-device", "usb-tablet",
         "-display", "gtk",
     ]);
     
+    // The profile's USB controller replaces the default tablet setup
+    if !security_profile.has_device(VirtualDeviceType::UsbController) {
+        cmd.args(["-usb", "-device", "usb-tablet"]);
+    }
+    
     // Add security arguments
     for arg in security_args {
         cmd.arg(arg);
     }
     
+    cmd.arg("-drive").arg(drive_arg(Path::new(&vm.disk), &format!("format={},if=virtio", vm.disk_format)));
+    
     if let Some(iso) = &vm.iso {
         if live || install {
-            cmd.args(["-cdrom", iso, "-boot", "d"]);
+            cmd.arg("-cdrom").arg(Path::new(iso));
+            cmd.args(["-boot", "d"]);
         }
     }
     
@@ -453,9 +1791,85 @@ fn run_vm_secure(name: String, profile_name: String, live: bool, install: bool)
         cmd.args(["-boot", "c"]);
     }
     
-    cmd.spawn().map_err(|e| format!("Failed to start VM: {}", e))?;
+    // Bring the proxy's local endpoint up before the VM starts using it.
+    // Without one (e.g. ss-local missing) the VM still starts, with a warning
+    let mut proxy_warning = None;
+    let proxy = match &security_profile.proxy_config {
+        Some(proxy) => {
+            fs::create_dir_all(&vm_dir)?;
+            let local = security::start_local_proxy(proxy, &vm_dir).unwrap_or_else(|e| {
+                proxy_warning = Some(format!("proxy not started: {}", e));
+                None
+            });
+            // Without its local endpoint there's no proxy to point at
+            let conf_path = vm_dir.join("proxychains.conf");
+            match security::generate_proxychains_conf(proxy, local.as_ref().map(|local| local.port)) {
+                Ok(conf) => fs::write(&conf_path, conf)?,
+                Err(_) if conf_path.exists() => fs::remove_file(&conf_path)?,
+                Err(_) => {}
+            }
+            local
+        }
+        None => None,
+    };
+    let proxy_port = proxy.as_ref().map(|proxy| proxy.port);
+    
+    // The tap has to exist, already routed through Tor, before QEMU opens it
+    let tor_network = if tor_only {
+        Some(security_manager.setup_tor_network(&name)?)
+    } else {
+        None
+    };
+    let guest_address = tor_network.as_ref().map(|network| network.tap.guest);
+    
+    // The VM's own Tor, listening on the tap it has to come after
+    let tor = if security_profile.uses_tor() {
+        let torrc = security_manager.generate_torrc(&security_profile, &name)?;
+        Some(security::start_vm_tor(&security_manager.tor_dir(&name), &torrc)?)
+    } else {
+        None
+    };
+    
+    // swtpm has to be listening before QEMU connects to it. Until then
+    // dropping the guard is what stops it, so it's started last
+    let tpm = if security_profile.wants_tpm() {
+        Some(security::start_swtpm(&security_manager.tpm_dir(&name))?)
+    } else {
+        None
+    };
+    
+    redirect_qemu_output(&mut cmd, &name)?;
+    let mut child = cmd.spawn().map_err(|e| AppError::spawn("qemu-system-x86_64", e))?;
+    wait_for_launch(&mut child, &name)?;
+    
+    let rotator = rotate_identity.zip(tor.as_ref()).map(|(secs, tor)| {
+        security::start_identity_rotation(tor.control.clone(), std::time::Duration::from_secs(secs))
+    });
+    running.track(&name, child, VmCompanions { rotator, proxy, tor, tor_network, tpm })?;
     
-    Ok(format!("VM '{}' started with '{}' security profile", name, profile_name))
+    let mut message = format!("VM '{}' started with '{}' security profile", name, profile_name);
+    if let Some(address) = guest_address {
+        message = format!("{}, guest at {} behind Tor", message, address);
+    }
+    if let Some(port) = socks_port {
+        message = format!("{}, Tor SOCKS on host port {}", message, port);
+    }
+    if let Some(port) = proxy_port {
+        message = format!("{}, Shadowsocks on 127.0.0.1:{}", message, port);
+    }
+    if let Some(warning) = proxy_warning {
+        message = format!("{} ({})", message, warning);
+    }
+    for warning in vpn_warnings {
+        message = format!("{} ({})", message, warning);
+    }
+    if let Some(secs) = rotate_identity {
+        message = format!("{}, new Tor identity every {}s", message, secs);
+    }
+    if let Some(warning) = unconfined_warning {
+        message = format!("{} ({})", message, warning);
+    }
+    Ok(with_tcg_warning(message, accelerated))
 }
 
 fn main() {
@@ -468,30 +1882,303 @@ fn main() {
     let _ = fs::create_dir_all(config_dir.join("vpn"));
     
     tauri::Builder::default()
+        .manage(RunningVms::recover(&load_config()))
+        .manage(Vault::default())
+        .manage(LogTails::default())
+        .system_tray(tray::tray(&load_config()))
+        .on_system_tray_event(tray::handle_event)
         .invoke_handler(tauri::generate_handler![
             get_vms,
             get_config,
             list_isos,
+            set_iso_search_paths,
+            download_iso,
             create_vm,
+            import_disk,
             delete_vm,
             run_vm,
+            stop_vm,
+            get_running_vms,
+            create_snapshot,
+            restore_snapshot,
+            list_snapshots,
+            delete_snapshot,
             quick_boot_iso,
             save_settings,
             check_qemu_installed,
+            list_qemu_binaries,
             // Security commands
             get_security_profiles,
             get_custom_security_profiles,
             create_security_profile,
             delete_security_profile,
+            export_security_profile,
+            import_security_profile,
             apply_security_profile,
+            set_default_security_profile,
+            get_profile_encryption,
+            unlock_security_profiles,
+            lock_security_profiles,
+            encrypt_security_profiles,
+            decrypt_security_profiles,
+            get_firewall_status,
+            save_firewall_rules,
+            restore_firewall_rules,
+            set_firewall_restore_on_boot,
             check_tor_installed,
             check_tor_running,
             start_tor_service,
             stop_tor_service,
             new_tor_identity,
+            check_obfs4_installed,
             check_vpn_installed,
+            wireguard_config_qr,
+            check_dependencies,
+            app_info,
+            open_vm_folder,
+            tail_vm_log,
+            stop_tailing,
+            open_config_dir,
             run_vm_secure,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locked_writers_do_not_clobber() {
+        let dir = std::env::temp_dir().join(format!("n01d-lock-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data = dir.join("counter.json");
+        let lock = dir.join("counter.lock");
+        write_atomic(&data, b"0").unwrap();
+
+        let writers: Vec<_> = (0..2)
+            .map(|_| {
+                let (data, lock) = (data.clone(), lock.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        let _guard = FileLock::acquire(&lock).unwrap();
+                        let value: u32 = fs::read_to_string(&data).unwrap().parse().unwrap();
+                        std::thread::yield_now();
+                        write_atomic(&data, (value + 1).to_string().as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let total = fs::read_to_string(&data).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(total, "100");
+    }
+
+    #[test]
+    fn test_concurrent_atomic_writes_use_their_own_temp_files() {
+        let dir = std::env::temp_dir().join(format!("n01d-atomic-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || (0..50).all(|_| write_atomic(&path, i.to_string().as_bytes()).is_ok()))
+            })
+            .collect();
+        let all_ok = writers.into_iter().all(|writer| writer.join().unwrap());
+
+        let leftovers = fs::read_dir(&dir).unwrap().count();
+        let _ = fs::remove_dir_all(&dir);
+        assert!(all_ok);
+        assert_eq!(leftovers, 1);
+    }
+
+    #[test]
+    fn test_migrate_v0_config() {
+        let v0 = serde_json::json!({
+            "vms": {
+                "nullsec-linux": {
+                    "disk": "/home/user/n01d-machine/vms/nullsec-linux/nullsec-linux.qcow2",
+                    "iso": "/home/user/isos/nullsec-linux-1.0-amd64.iso",
+                    "ram": 4096,
+                    "cpus": 4,
+                    "created": true
+                }
+            },
+            "default_ram": 8192,
+            "default_cpus": 2
+        });
+
+        let config = migrate(v0);
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.default_ram, 8192);
+        assert_eq!(config.default_cpus, 2);
+        assert!(config.security_profiles.is_empty());
+
+        let vm = &config.vms["nullsec-linux"];
+        assert_eq!(vm.ram, 4096);
+        assert_eq!(vm.iso.as_deref(), Some("/home/user/isos/nullsec-linux-1.0-amd64.iso"));
+        assert!(vm.security_profile.is_none());
+    }
+
+    #[test]
+    fn test_migrate_keeps_valid_vms_when_one_is_broken() {
+        let raw = serde_json::json!({
+            "version": 1,
+            "vms": {
+                "good": { "disk": "/vms/good.qcow2", "iso": null, "ram": 2048, "cpus": 2 },
+                "broken": { "disk": 42 }
+            }
+        });
+
+        assert!(!reads_cleanly(&raw.to_string()));
+        let config = migrate(raw);
+        assert!(config.vms.contains_key("good"));
+        assert!(!config.vms.contains_key("broken"));
+        assert!(reads_cleanly(&serde_json::to_string(&config).unwrap()));
+        assert!(!reads_cleanly("{ \"vms\": "));
+    }
+
+    #[test]
+    fn test_validate_vm_name_rejects_traversal() {
+        for name in ["..", ".", "../../.ssh", "vms/other", "C:\\Windows"] {
+            let err = validate_vm_name(name).unwrap_err();
+            assert_eq!(err.code(), "invalid");
+        }
+    }
+
+    #[test]
+    fn test_validate_vm_name_rejects_empty_and_long() {
+        assert!(validate_vm_name("").is_err());
+        assert!(validate_vm_name("-debug").is_err());
+        assert!(validate_vm_name(&"x".repeat(65)).is_err());
+        assert!(validate_vm_name(&"x".repeat(64)).is_ok());
+        assert!(validate_vm_name("nullsec-linux_1.0").is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_names() {
+        assert!(validate_snapshot_name("pre-update_1.0").is_ok());
+        for name in ["my snap", "autostart-20240101-000000", "$(id)"] {
+            assert!(matches!(validate_snapshot_name(name), Err(AppError::Invalid(_))), "{}", name);
+        }
+        // Whatever is already on the disk can still be restored or deleted
+        assert!(validate_existing_snapshot_name("autostart-20240101-000000").is_ok());
+        assert!(validate_existing_snapshot_name("my snap").is_ok());
+        assert!(validate_existing_snapshot_name("-l").is_err());
+    }
+
+    #[test]
+    fn test_parse_snapshots() {
+        let info = serde_json::json!({
+            "format": "qcow2",
+            "snapshots": [
+                { "id": "1", "name": "clean-install", "vm-state-size": 0, "date-sec": 1700000000, "date-nsec": 0 },
+                { "id": "2", "name": "pre-update", "vm-state-size": 1048576, "date-sec": 1700003600, "date-nsec": 0 }
+            ]
+        });
+
+        let snapshots = parse_snapshots(&info);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].name, "pre-update");
+        assert_eq!(snapshots[1].vm_state_size, 1048576);
+        assert!(parse_snapshots(&serde_json::json!({ "format": "qcow2" })).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_encrypted_profiles() {
+        let vault = Vault::default();
+        let mut config = AppConfig { encrypt_profiles: true, ..Default::default() };
+        let mut profile = SecurityManager::get_preset_profiles().remove(0).2;
+        profile.proxy_config = Some(ProxyConfig {
+            proxy_type: security::ProxyType::Socks5,
+            host: "proxy.example".to_string(),
+            port: 1080,
+            username: Some("me".to_string()),
+            password: Some("hunter2".to_string()),
+            chain: Vec::new(),
+            cipher: None,
+            local_port: None,
+        });
+
+        vault.set_passphrase("correct horse").unwrap();
+        store_profiles(&vault, &mut config, HashMap::from([("work".to_string(), profile)])).unwrap();
+        let saved = serde_json::to_string(&config).unwrap();
+        assert!(config.security_profiles.is_empty());
+        assert!(!saved.contains("hunter2"));
+
+        // Locked until the right passphrase is entered again
+        vault.lock();
+        assert!(matches!(custom_profiles(&vault, &config), Err(AppError::Locked)));
+        let sealed = config.sealed_profiles.clone().unwrap();
+        assert!(vault.unlock("wrong horse", &sealed).is_err());
+        vault.unlock("correct horse", &sealed).unwrap();
+        let profiles = custom_profiles(&vault, &config).unwrap();
+        assert_eq!(profiles["work"].proxy_config.as_ref().unwrap().password.as_deref(), Some("hunter2"));
+    }
+
+    #[test]
+    fn test_drive_arg_keeps_odd_paths_intact() {
+        let disk = Path::new("/home/user/My VMs/ünïcode,lab.qcow2");
+        assert_eq!(
+            drive_arg(disk, "format=qcow2,if=virtio"),
+            OsString::from("file=/home/user/My VMs/ünïcode,,lab.qcow2,format=qcow2,if=virtio")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_drive_arg_non_utf8_path() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let disk = Path::new(std::ffi::OsStr::from_bytes(b"/vms/disk-\xff.qcow2"));
+        let arg = drive_arg(disk, "format=raw").into_vec();
+        assert!(arg.starts_with(b"file=/vms/disk-\xff"));
+        assert!(arg.ends_with(b",format=raw"));
+    }
+
+    #[test]
+    fn test_missing_disk_or_iso_refuses_to_start() {
+        let dir = std::env::temp_dir().join(format!("n01d-files-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let disk = dir.join("lab.qcow2");
+        let iso = dir.join("debian.iso");
+        let mut vm = VmConfig {
+            disk: disk.to_string_lossy().to_string(),
+            disk_format: default_disk_format(),
+            iso: Some(iso.to_string_lossy().to_string()),
+            ram: 2048,
+            cpus: 2,
+            security_profile: None,
+            tor_socks_port: None,
+            ssh_port: None,
+            usb_passthrough: Vec::new(),
+        };
+
+        let missing_disk = check_vm_files(&vm, false).unwrap_err();
+        fs::write(&disk, b"").unwrap();
+        // The ISO only matters when booting from it
+        let disk_boot = check_vm_files(&vm, false);
+        let missing_iso = check_vm_files(&vm, true).unwrap_err();
+        fs::write(&iso, b"").unwrap();
+        let iso_boot = check_vm_files(&vm, true);
+        vm.iso = None;
+        let no_iso = check_vm_files(&vm, true);
+        fs::remove_dir_all(&dir).ok();
+
+        let value = serde_json::to_value(&missing_disk).unwrap();
+        assert_eq!(value["code"], "missing_file");
+        assert_eq!(value["path"], disk.to_string_lossy().as_ref());
+        assert_eq!(missing_disk.to_string(), format!("Disk not found at {}", disk.display()));
+        assert!(disk_boot.is_ok());
+        assert_eq!(missing_iso.to_string(), format!("ISO not found at {}", iso.display()));
+        assert!(iso_boot.is_ok() && no_iso.is_ok());
+    }
+}
@@ -0,0 +1,64 @@
+// Progress events for long-running commands
+// Emitted on `n01d://progress`; the frontend subscribes with `listen` and
+// matches on `op` to find the operation it started
+
+use serde::Serialize;
+
+pub const PROGRESS_EVENT: &str = "n01d://progress";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub op: String,
+    pub stage: String,
+    /// None while the total size isn't known
+    pub percent: Option<u8>,
+}
+
+/// Emits progress for one operation, skipping repeats of the same stage/percent
+pub struct Progress<'a> {
+    window: &'a tauri::Window,
+    op: String,
+    last: Option<(String, Option<u8>)>,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(window: &'a tauri::Window, op: impl Into<String>) -> Self {
+        Progress { window, op: op.into(), last: None }
+    }
+
+    pub fn emit(&mut self, stage: &str, percent: Option<u8>) {
+        let current = (stage.to_string(), percent);
+        if self.last.as_ref() == Some(&current) {
+            return;
+        }
+
+        let event = ProgressEvent {
+            op: self.op.clone(),
+            stage: stage.to_string(),
+            percent,
+        };
+        let _ = self.window.emit(PROGRESS_EVENT, event);
+        self.last = Some(current);
+    }
+}
+
+/// Whole-number percentage of `done` out of `total`, capped at 100
+pub fn percent(done: u64, total: u64) -> u8 {
+    if total == 0 {
+        return 0;
+    }
+    (done.saturating_mul(100) / total).min(100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(percent(0, 200), 0);
+        assert_eq!(percent(50, 200), 25);
+        assert_eq!(percent(300, 200), 100);
+        assert_eq!(percent(10, 0), 0);
+    }
+}
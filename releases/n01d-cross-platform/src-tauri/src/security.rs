@@ -1,35 +1,254 @@
 // Security Module for n01d Machine
 // Provides sandboxing, network isolation, VPN, Tor, and proxy support
 
+use n01d_common::firewall;
+use n01d_common::ports::{self, fnv1a};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 /// Security profile for a VM
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SecurityProfile {
     pub name: String,
-    pub sandbox_enabled: bool,
+    /// Unset in a profile that `extends` another means inherit the
+    /// parent's setting; `Some(false)` turns it off
+    #[serde(default)]
+    pub sandbox_enabled: Option<bool>,
+    #[serde(default)]
     pub network_isolation: NetworkIsolation,
-    pub tor_enabled: bool,
+    #[serde(default)]
+    pub tor_enabled: Option<bool>,
     pub vpn_config: Option<VpnConfig>,
     pub proxy_config: Option<ProxyConfig>,
     pub firewall_rules: Vec<FirewallRule>,
     pub virtual_devices: Vec<VirtualDevice>,
+    /// Name of a parent profile this one overrides
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Confine the host's QEMU process with AppArmor
+    #[serde(default)]
+    pub host_confinement: Option<bool>,
 }
 
-/// Network isolation modes
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+impl SecurityProfile {
+    pub fn sandboxed(&self) -> bool {
+        self.sandbox_enabled.unwrap_or(false)
+    }
+
+    pub fn uses_tor(&self) -> bool {
+        self.tor_enabled.unwrap_or(false)
+    }
+
+    pub fn host_confined(&self) -> bool {
+        self.host_confinement.unwrap_or(false)
+    }
+
+    /// Whether the profile declares a device of this type (enabled or not)
+    pub fn has_device(&self, device_type: VirtualDeviceType) -> bool {
+        self.virtual_devices.iter().any(|d| d.device_type == device_type)
+    }
+
+    /// Whether the VM gets an emulated TPM, which needs swtpm running
+    pub fn wants_tpm(&self) -> bool {
+        self.virtual_devices
+            .iter()
+            .any(|d| d.enabled && matches!(d.device_type, VirtualDeviceType::Tpm))
+    }
+
+    /// Check for contradictory settings, returning every problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        let isolation = &self.network_isolation;
+
+        if !is_profile_name(&self.name) {
+            problems.push(format!(
+                "Profile name '{}' must be 1-{} letters, digits, '-' or '_'",
+                self.name.escape_debug(),
+                MAX_PROFILE_NAME_LEN
+            ));
+        }
+
+        if isolation.mode() == IsolationMode::Full {
+            if self.uses_tor() {
+                problems.push("Tor is enabled but the network is fully isolated".to_string());
+            }
+            if self.vpn_config.is_some() {
+                problems.push("VPN is configured but the network is fully isolated".to_string());
+            }
+            if self.proxy_config.is_some() {
+                problems.push("Proxy is configured but the network is fully isolated".to_string());
+            }
+        }
+
+        if isolation.mode() == IsolationMode::TorOnly && !self.uses_tor() {
+            problems.push("Isolation mode is TorOnly but Tor is disabled".to_string());
+        }
+
+        if isolation.mode() == IsolationMode::VpnOnly && self.vpn_config.is_none() {
+            problems.push("Isolation mode is VpnOnly but no VPN is configured".to_string());
+        }
+
+        if let Some(vpn) = &self.vpn_config {
+            for route in &vpn.split_tunnel_routes {
+                if parse_cidr(route).is_none() {
+                    problems.push(format!("VPN split-tunnel route '{}' is not a valid CIDR", route));
+                }
+            }
+        }
+
+        if let Some(proxy) = &self.proxy_config {
+            if proxy.host.trim().is_empty() && proxy.chain.is_empty() {
+                problems.push("Proxy has no host and an empty chain".to_string());
+            }
+            if matches!(proxy.proxy_type, ProxyType::Shadowsocks) {
+                match proxy.cipher.as_deref() {
+                    Some(cipher) if SHADOWSOCKS_CIPHERS.contains(&cipher) => {}
+                    Some(cipher) => problems.push(format!("Unknown Shadowsocks cipher '{}'", cipher)),
+                    None => problems.push("Shadowsocks proxy has no cipher".to_string()),
+                }
+                if proxy.password.as_deref().unwrap_or_default().is_empty() {
+                    problems.push("Shadowsocks proxy has no password".to_string());
+                }
+            }
+            if proxy.chain.iter().any(|entry| matches!(entry.proxy_type, ProxyType::Shadowsocks)) {
+                problems.push("Shadowsocks can only be the profile's main proxy, not a chain entry".to_string());
+            }
+        }
+
+        for device in &self.virtual_devices {
+            if let (VirtualDeviceType::SerialPort, Some(target)) = (&device.device_type, &device.target) {
+                match SerialTarget::parse(target) {
+                    Err(problem) => problems.push(format!("Serial port '{}': {}", device.name, problem)),
+                    // The name becomes the chardev's id
+                    Ok(SerialTarget::File(_)) if !is_qemu_id(&device.name) => problems.push(format!(
+                        "Serial port '{}' logging to a file needs a name of letters, digits, '-', '.' or '_', starting with a letter",
+                        device.name
+                    )),
+                    Ok(_) => {}
+                }
+            }
+        }
+
+        // Rules end up in rulesets loaded as root, one setting per token
+        for rule in &self.firewall_rules {
+            if rule.description.chars().any(char::is_control) {
+                problems.push(format!(
+                    "Firewall rule '{}' has control characters in its description",
+                    rule.description.escape_debug()
+                ));
+            }
+            if let Some(protocol) = rule.protocol.as_deref().filter(|p| !FIREWALL_PROTOCOLS.contains(p)) {
+                problems.push(format!(
+                    "Firewall rule '{}' has unknown protocol '{}' (expected one of {})",
+                    rule.description.escape_debug(),
+                    protocol.escape_debug(),
+                    FIREWALL_PROTOCOLS.join(", ")
+                ));
+            }
+            for address in [&rule.source, &rule.destination].into_iter().flatten() {
+                if !is_address(address) {
+                    problems.push(format!(
+                        "Firewall rule '{}' has '{}', which is not an IP address or CIDR",
+                        rule.description.escape_debug(),
+                        address.escape_debug()
+                    ));
+                }
+            }
+
+            if let Some((start, end)) = rule.port_range {
+                if start > end {
+                    problems.push(format!(
+                        "Firewall rule '{}' has port range {}-{} with start after end",
+                        rule.description, start, end
+                    ));
+                }
+            }
+
+            if rule.port.is_some() && rule.port_range.is_some() {
+                problems.push(format!(
+                    "Firewall rule '{}' sets both a port and a port range",
+                    rule.description
+                ));
+            }
+
+            let allows_outbound = matches!(rule.action, FirewallAction::Allow)
+                && matches!(rule.direction, TrafficDirection::Outbound | TrafficDirection::Both);
+            if allows_outbound && !isolation.allows_internet() {
+                problems.push(format!(
+                    "Firewall rule '{}' allows outbound traffic but internet access is disabled",
+                    rule.description
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Write the profile to a standalone JSON file
+    pub fn export_to_file(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Load a profile previously written with `export_to_file`
+    pub fn import_from_file(path: &Path) -> Result<SecurityProfile, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let profile: SecurityProfile = serde_json::from_str(&content)
+            .map_err(|e| format!("Invalid security profile file: {}", e))?;
+
+        if profile.name.trim().is_empty() {
+            return Err("Invalid security profile file: name is empty".to_string());
+        }
+
+        Ok(profile)
+    }
+}
+
+/// Network isolation modes. Every setting is optional so a profile that
+/// `extends` another can override some and inherit the rest
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct NetworkIsolation {
-    pub mode: IsolationMode,
-    pub allow_host_access: bool,
-    pub allow_internet: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<IsolationMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_host_access: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_internet: Option<bool>,
+    /// Segment for `Internal` mode; only VMs with the same id see each
+    /// other (see `internal_mcast_endpoint`)
     pub isolated_network_id: Option<String>,
     pub mac_address: Option<String>,
 }
 
+impl NetworkIsolation {
+    pub fn mode(&self) -> IsolationMode {
+        self.mode.clone().unwrap_or_default()
+    }
+
+    pub fn allows_internet(&self) -> bool {
+        self.allow_internet.unwrap_or(false)
+    }
+
+    /// Overlay a child profile's settings on its parent's, one by one
+    fn merge(parent: NetworkIsolation, child: NetworkIsolation) -> NetworkIsolation {
+        NetworkIsolation {
+            mode: child.mode.or(parent.mode),
+            allow_host_access: child.allow_host_access.or(parent.allow_host_access),
+            allow_internet: child.allow_internet.or(parent.allow_internet),
+            isolated_network_id: child.isolated_network_id.or(parent.isolated_network_id),
+            mac_address: child.mac_address.or(parent.mac_address),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub enum IsolationMode {
     #[default]
@@ -59,6 +278,9 @@ pub struct VpnConfig {
     pub username: Option<String>,
     pub kill_switch: bool,
     pub dns_leak_protection: bool,
+    /// CIDRs to send through the VPN; empty means everything (full tunnel)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub split_tunnel_routes: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -85,6 +307,12 @@ pub struct ProxyConfig {
     pub username: Option<String>,
     pub password: Option<String>,
     pub chain: Vec<ProxyChainEntry>,
+    /// Shadowsocks encryption method, one of `SHADOWSOCKS_CIPHERS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<String>,
+    /// Local SOCKS5 port `ss-local` listens on, a free one if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_port: Option<u16>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -101,8 +329,17 @@ pub enum ProxyType {
     Socks4,
     Http,
     Https,
+    /// Reached through a local `ss-local` SOCKS5 endpoint
+    Shadowsocks,
 }
 
+/// Encryption methods shadowsocks-libev's `ss-local` accepts
+pub const SHADOWSOCKS_CIPHERS: &[&str] = &[
+    "aes-128-gcm", "aes-192-gcm", "aes-256-gcm", "chacha20-ietf-poly1305", "xchacha20-ietf-poly1305",
+    "aes-128-cfb", "aes-192-cfb", "aes-256-cfb", "aes-128-ctr", "aes-192-ctr", "aes-256-ctr",
+    "camellia-128-cfb", "camellia-192-cfb", "camellia-256-cfb", "chacha20-ietf", "salsa20", "chacha20",
+];
+
 /// Firewall rule
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FirewallRule {
@@ -139,9 +376,108 @@ pub struct VirtualDevice {
     pub enabled: bool,
     pub passthrough: bool,
     pub isolated: bool,
+    /// Where a serial port is redirected: `pty` (the default), `file:<path>`
+    /// or `tcp:<host>:<port>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Whether QEMU accepts `name` as an object id
+fn is_qemu_id(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+}
+
+/// iptables refuses chain names longer than this
+const IPTABLES_MAX_CHAIN_LEN: usize = 28;
+
+/// Longest profile name whose `n01d-<name>` firewall chain iptables accepts
+pub const MAX_PROFILE_NAME_LEN: usize = IPTABLES_MAX_CHAIN_LEN - firewall::FIREWALL_PREFIX.len();
+
+/// Profile names become firewall chain names (see `firewall_chain`)
+fn is_profile_name(name: &str) -> bool {
+    (1..=MAX_PROFILE_NAME_LEN).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+/// Protocols a firewall rule can match, by the name iptables and nft share
+pub const FIREWALL_PROTOCOLS: &[&str] = &["tcp", "udp", "icmp", "icmpv6", "sctp"];
+
+/// A bare IP address or a CIDR, exactly as written
+fn is_address(address: &str) -> bool {
+    address.parse::<std::net::IpAddr>().is_ok() || (address.trim() == address && parse_cidr(address).is_some())
+}
+
+/// Parsed redirect target of a serial port
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerialTarget {
+    Pty,
+    /// Guest console output appended to a log file
+    File(PathBuf),
+    /// A TCP listener a console client can attach to
+    Tcp { host: String, port: u16 },
+}
+
+impl SerialTarget {
+    /// Parse `pty`, `file:<path>` or `tcp:<host>:<port>`. A file's parent
+    /// directory has to exist already
+    pub fn parse(target: &str) -> Result<Self, String> {
+        if target == "pty" {
+            return Ok(SerialTarget::Pty);
+        }
+
+        if let Some(path) = target.strip_prefix("file:") {
+            let path = PathBuf::from(path);
+            if !path.is_absolute() {
+                return Err(format!("Serial log '{}' must be an absolute path", path.display()));
+            }
+            match path.parent() {
+                Some(dir) if dir.is_dir() => return Ok(SerialTarget::File(path)),
+                _ => return Err(format!("Directory for serial log '{}' doesn't exist", path.display())),
+            }
+        }
+
+        if let Some(address) = target.strip_prefix("tcp:") {
+            // rsplit so bracketed IPv6 hosts keep their colons
+            let (host, port) = address
+                .rsplit_once(':')
+                .ok_or_else(|| format!("Serial target '{}' needs tcp:<host>:<port>", target))?;
+            let port = port
+                .parse::<u16>()
+                .ok()
+                .filter(|&port| port > 0)
+                .ok_or_else(|| format!("Invalid port in serial target '{}'", target))?;
+            if host.is_empty() || host.contains(',') {
+                return Err(format!("Invalid host in serial target '{}'", target));
+            }
+            return Ok(SerialTarget::Tcp { host: host.to_string(), port });
+        }
+
+        Err(format!("Unknown serial target '{}' (expected pty, file:<path> or tcp:<host>:<port>)", target))
+    }
+
+    /// QEMU arguments connecting a serial port to the target. A file goes
+    /// through a `-chardev` named `id`, since the `-serial file:` shorthand
+    /// can't take a path containing commas
+    pub fn qemu_args(&self, id: &str) -> Vec<String> {
+        match self {
+            SerialTarget::Pty => vec!["-serial".to_string(), "pty".to_string()],
+            // QEMU splits chardev options on commas, so any in the path are doubled
+            SerialTarget::File(path) => vec![
+                "-chardev".to_string(),
+                format!("file,id={},path={}", id, path.display().to_string().replace(',', ",,")),
+                "-serial".to_string(),
+                format!("chardev:{}", id),
+            ],
+            // Listen without waiting, so the guest boots whether or not anyone attaches
+            SerialTarget::Tcp { host, port } => {
+                vec!["-serial".to_string(), format!("tcp:{}:{},server=on,wait=off", host, port)]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum VirtualDeviceType {
     NetworkAdapter,
     UsbController,
@@ -158,6 +494,8 @@ pub struct TorConfig {
     pub control_port: u16,
     pub dns_port: u16,
     pub transparent_proxy: bool,
+    #[serde(default = "default_trans_port")]
+    pub trans_port: u16,
     pub bridge_enabled: bool,
     pub bridges: Vec<String>,
     pub exit_nodes: Option<Vec<String>>,
@@ -173,6 +511,7 @@ impl Default for TorConfig {
             control_port: 9051,
             dns_port: 5353,
             transparent_proxy: true,
+            trans_port: default_trans_port(),
             bridge_enabled: false,
             bridges: Vec::new(),
             exit_nodes: None,
@@ -183,11 +522,21 @@ impl Default for TorConfig {
     }
 }
 
+fn default_trans_port() -> u16 {
+    9040
+}
+
+pub use n01d_common::ports::{DEFAULT_SSH_PORT, SSH_PORT_RANGE};
+
 /// Security Manager
 pub struct SecurityManager {
     config_dir: PathBuf,
     profiles: HashMap<String, SecurityProfile>,
     tor_config: TorConfig,
+    /// VM name -> that VM's own Tor SocksPort
+    socks_ports: HashMap<String, u16>,
+    /// VM name -> host port forwarded to that VM's SSH
+    ssh_ports: HashMap<String, u16>,
 }
 
 impl SecurityManager {
@@ -204,9 +553,74 @@ impl SecurityManager {
             config_dir,
             profiles,
             tor_config: TorConfig::default(),
+            socks_ports: HashMap::new(),
+            ssh_ports: HashMap::new(),
         }
     }
 
+    /// Make additional custom profiles (e.g. from the app config) resolvable
+    pub fn with_custom_profiles(mut self, profiles: HashMap<String, SecurityProfile>) -> Self {
+        self.profiles.extend(profiles);
+        self
+    }
+
+    /// Give VMs their own Tor SocksPort (see `allocate_socks_port`)
+    pub fn with_socks_ports(mut self, ports: HashMap<String, u16>) -> Self {
+        self.socks_ports.extend(ports);
+        self
+    }
+
+    /// The SocksPort a VM's traffic goes to: its own if it has one,
+    /// otherwise the shared one
+    pub fn socks_port(&self, vm_name: &str) -> u16 {
+        self.socks_ports.get(vm_name).copied().unwrap_or(self.tor_config.socks_port)
+    }
+
+    /// The first port above the shared SocksPort that no VM and none of
+    /// Tor's other listeners already use
+    pub fn allocate_socks_port(&self) -> Result<u16, String> {
+        let config = &self.tor_config;
+        let taken: HashSet<u16> = self
+            .socks_ports
+            .values()
+            .copied()
+            .chain([config.socks_port, config.control_port, config.dns_port, config.trans_port])
+            .collect();
+
+        (config.socks_port.saturating_add(1)..=u16::MAX)
+            .find(|port| !taken.contains(port))
+            .ok_or_else(|| "No free port left for a Tor SocksPort".to_string())
+    }
+
+    /// Forward VMs' SSH from their own host ports (see `allocate_ssh_port`)
+    pub fn with_ssh_ports(mut self, ports: HashMap<String, u16>) -> Self {
+        self.ssh_ports.extend(ports);
+        self
+    }
+
+    pub fn ssh_port(&self, vm_name: &str) -> u16 {
+        self.ssh_ports.get(vm_name).copied().unwrap_or(DEFAULT_SSH_PORT)
+    }
+
+    /// A host SSH port for `vm_name`: where its name hashes to in
+    /// `SSH_PORT_RANGE`, or the next one (wrapping around) that no other VM
+    /// has and nothing is listening on. The same name always starts from the
+    /// same port
+    pub fn allocate_ssh_port(&self, vm_name: &str) -> Result<u16, String> {
+        let taken: HashSet<u16> = self
+            .ssh_ports
+            .iter()
+            .filter(|(vm, _)| *vm != vm_name)
+            .map(|(_, port)| *port)
+            .collect();
+        ports::pick_ssh_port(vm_name, &taken, ports::loopback_port_free)
+            .ok_or_else(|| format!("No free port left for SSH in {}-{}", SSH_PORT_RANGE.start(), SSH_PORT_RANGE.end()))
+    }
+
+    pub fn tor_config(&self) -> &TorConfig {
+        &self.tor_config
+    }
+
     pub fn save_profiles(&self) -> Result<(), String> {
         let path = self.config_dir.join("security_profiles.json");
         let content = serde_json::to_string_pretty(&self.profiles)
@@ -218,13 +632,15 @@ impl SecurityManager {
     pub fn create_profile(&mut self, name: String) -> SecurityProfile {
         let profile = SecurityProfile {
             name: name.clone(),
-            sandbox_enabled: true,
+            sandbox_enabled: Some(true),
             network_isolation: NetworkIsolation::default(),
-            tor_enabled: false,
+            tor_enabled: Some(false),
             vpn_config: None,
             proxy_config: None,
             firewall_rules: Self::default_firewall_rules(),
             virtual_devices: Self::default_virtual_devices(),
+            extends: None,
+            host_confinement: Some(false),
         };
         self.profiles.insert(name, profile.clone());
         let _ = self.save_profiles();
@@ -239,15 +655,15 @@ impl SecurityManager {
                 "Maximum security - Full isolation, Tor routing, no host access",
                 SecurityProfile {
                     name: "paranoid".to_string(),
-                    sandbox_enabled: true,
+                    sandbox_enabled: Some(true),
                     network_isolation: NetworkIsolation {
-                        mode: IsolationMode::TorOnly,
-                        allow_host_access: false,
-                        allow_internet: true,
+                        mode: Some(IsolationMode::TorOnly),
+                        allow_host_access: Some(false),
+                        allow_internet: Some(true),
                         isolated_network_id: None,
                         mac_address: Some("52:54:00:00:00:01".to_string()),
                     },
-                    tor_enabled: true,
+                    tor_enabled: Some(true),
                     vpn_config: None,
                     proxy_config: None,
                     firewall_rules: vec![
@@ -279,8 +695,11 @@ impl SecurityManager {
                             enabled: true,
                             passthrough: false,
                             isolated: true,
+                            target: None,
                         },
                     ],
+                    extends: None,
+                    host_confinement: Some(true),
                 },
             ),
             (
@@ -288,15 +707,15 @@ impl SecurityManager {
                 "VPN + Tor chain for maximum anonymity",
                 SecurityProfile {
                     name: "stealth".to_string(),
-                    sandbox_enabled: true,
+                    sandbox_enabled: Some(true),
                     network_isolation: NetworkIsolation {
-                        mode: IsolationMode::VpnOnly,
-                        allow_host_access: false,
-                        allow_internet: true,
+                        mode: Some(IsolationMode::VpnOnly),
+                        allow_host_access: Some(false),
+                        allow_internet: Some(true),
                         isolated_network_id: None,
                         mac_address: Some("52:54:00:00:00:02".to_string()),
                     },
-                    tor_enabled: true,
+                    tor_enabled: Some(true),
                     vpn_config: Some(VpnConfig {
                         provider: VpnProvider::WireGuard,
                         config_file: None,
@@ -306,10 +725,13 @@ impl SecurityManager {
                         username: None,
                         kill_switch: true,
                         dns_leak_protection: true,
+                        split_tunnel_routes: Vec::new(),
                     }),
                     proxy_config: None,
                     firewall_rules: Self::default_firewall_rules(),
                     virtual_devices: Self::default_virtual_devices(),
+                    extends: None,
+                    host_confinement: Some(false),
                 },
             ),
             (
@@ -317,15 +739,15 @@ impl SecurityManager {
                 "Complete network isolation - no internet access",
                 SecurityProfile {
                     name: "isolated".to_string(),
-                    sandbox_enabled: true,
+                    sandbox_enabled: Some(true),
                     network_isolation: NetworkIsolation {
-                        mode: IsolationMode::Full,
-                        allow_host_access: false,
-                        allow_internet: false,
+                        mode: Some(IsolationMode::Full),
+                        allow_host_access: Some(false),
+                        allow_internet: Some(false),
                         isolated_network_id: Some("isolated-net-1".to_string()),
                         mac_address: None,
                     },
-                    tor_enabled: false,
+                    tor_enabled: Some(false),
                     vpn_config: None,
                     proxy_config: None,
                     firewall_rules: vec![
@@ -341,6 +763,8 @@ impl SecurityManager {
                         },
                     ],
                     virtual_devices: Vec::new(),
+                    extends: None,
+                    host_confinement: Some(false),
                 },
             ),
             (
@@ -348,15 +772,15 @@ impl SecurityManager {
                 "Isolated network with tools access",
                 SecurityProfile {
                     name: "pentesting".to_string(),
-                    sandbox_enabled: true,
+                    sandbox_enabled: Some(true),
                     network_isolation: NetworkIsolation {
-                        mode: IsolationMode::Internal,
-                        allow_host_access: true,
-                        allow_internet: true,
+                        mode: Some(IsolationMode::Internal),
+                        allow_host_access: Some(true),
+                        allow_internet: Some(true),
                         isolated_network_id: Some("pentest-net".to_string()),
                         mac_address: None,
                     },
-                    tor_enabled: false,
+                    tor_enabled: Some(false),
                     vpn_config: None,
                     proxy_config: Some(ProxyConfig {
                         proxy_type: ProxyType::Socks5,
@@ -365,14 +789,79 @@ impl SecurityManager {
                         username: None,
                         password: None,
                         chain: Vec::new(),
+                        cipher: None,
+                        local_port: None,
                     }),
                     firewall_rules: Self::default_firewall_rules(),
                     virtual_devices: Self::default_virtual_devices(),
+                    extends: None,
+                    host_confinement: Some(false),
                 },
             ),
         ]
     }
 
+    /// Look up a custom or preset profile without resolving inheritance
+    fn find_profile(&self, name: &str) -> Option<SecurityProfile> {
+        self.profiles.get(name).cloned().or_else(|| {
+            Self::get_preset_profiles()
+                .into_iter()
+                .find(|(n, _, _)| *n == name)
+                .map(|(_, _, p)| p)
+        })
+    }
+
+    /// Resolve a profile's `extends` chain into a single flattened profile
+    pub fn resolve_profile(&self, name: &str) -> Result<SecurityProfile, String> {
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut next = Some(name.to_string());
+
+        while let Some(current) = next {
+            if !visited.insert(current.clone()) {
+                return Err(format!("Security profile inheritance cycle at '{}'", current));
+            }
+            let profile = self
+                .find_profile(&current)
+                .ok_or(format!("Security profile '{}' not found", current))?;
+            next = profile.extends.clone();
+            chain.push(profile);
+        }
+
+        // Apply from the root ancestor down to the requested profile
+        let mut resolved = chain.pop().expect("chain holds at least the requested profile");
+        while let Some(child) = chain.pop() {
+            resolved = Self::merge_profiles(resolved, child);
+        }
+        resolved.extends = None;
+
+        Ok(resolved)
+    }
+
+    /// Overlay a child profile on its parent: settings the child sets win,
+    /// firewall rules are concatenated (parent first)
+    fn merge_profiles(parent: SecurityProfile, child: SecurityProfile) -> SecurityProfile {
+        let mut firewall_rules = parent.firewall_rules;
+        firewall_rules.extend(child.firewall_rules);
+
+        SecurityProfile {
+            name: child.name,
+            sandbox_enabled: child.sandbox_enabled.or(parent.sandbox_enabled),
+            network_isolation: NetworkIsolation::merge(parent.network_isolation, child.network_isolation),
+            tor_enabled: child.tor_enabled.or(parent.tor_enabled),
+            vpn_config: child.vpn_config.or(parent.vpn_config),
+            proxy_config: child.proxy_config.or(parent.proxy_config),
+            firewall_rules,
+            virtual_devices: if child.virtual_devices.is_empty() {
+                parent.virtual_devices
+            } else {
+                child.virtual_devices
+            },
+            extends: None,
+            host_confinement: child.host_confinement.or(parent.host_confinement),
+        }
+    }
+
     fn default_firewall_rules() -> Vec<FirewallRule> {
         vec![
             FirewallRule {
@@ -416,6 +905,7 @@ impl SecurityManager {
                 enabled: true,
                 passthrough: false,
                 isolated: false,
+                target: None,
             },
             VirtualDevice {
                 device_type: VirtualDeviceType::UsbController,
@@ -423,21 +913,36 @@ impl SecurityManager {
                 enabled: true,
                 passthrough: false,
                 isolated: true,
+                target: None,
             },
         ]
     }
 
+    /// Directory holding a VM's disk and per-VM state
+    fn vm_dir(&self, vm_name: &str) -> PathBuf {
+        self.config_dir.join("vms").join(vm_name)
+    }
+
     /// Generate QEMU arguments for security profile
-    pub fn generate_qemu_security_args(&self, profile: &SecurityProfile) -> Vec<String> {
+    pub fn generate_qemu_security_args(&self, profile: &SecurityProfile, vm_name: &str) -> Result<Vec<String>, String> {
         let mut args = Vec::new();
 
         // Sandbox mode
-        if profile.sandbox_enabled {
+        if profile.sandboxed() {
             args.extend(["-sandbox".to_string(), "on".to_string()]);
         }
 
+        // A disabled network adapter overrides the isolation mode's NIC
+        let nic_disabled = profile
+            .virtual_devices
+            .iter()
+            .any(|d| !d.enabled && d.device_type == VirtualDeviceType::NetworkAdapter);
+
         // Network isolation
-        match &profile.network_isolation.mode {
+        match profile.network_isolation.mode() {
+            _ if nic_disabled => {
+                args.extend(["-nic".to_string(), "none".to_string()]);
+            }
             IsolationMode::Full => {
                 args.extend(["-nic".to_string(), "none".to_string()]);
             }
@@ -451,21 +956,21 @@ impl SecurityManager {
             }
             IsolationMode::Internal => {
                 let net_id = profile.network_isolation.isolated_network_id.as_deref().unwrap_or("internal");
+                let (group, port) = internal_mcast_endpoint(net_id);
+                // Sent on loopback only, or the guests' frames would reach
+                // anyone on the LAN who joins the group
                 args.extend([
                     "-netdev".to_string(),
-                    format!("socket,id={},mcast=230.0.0.1:1234", net_id),
+                    format!("socket,id={},mcast={}:{},localaddr=127.0.0.1", net_id, group, port),
                     "-device".to_string(),
                     format!("virtio-net-pci,netdev={}", net_id),
                 ]);
             }
             IsolationMode::TorOnly => {
-                // Route through Tor SOCKS proxy
+                // The VM's own tap, which setup_tor_network routes through Tor
                 args.extend([
                     "-netdev".to_string(),
-                    format!(
-                        "user,id=tornet,hostfwd=tcp::2222-:22,guestfwd=tcp:10.0.2.100:9050-cmd:nc 127.0.0.1 {}",
-                        self.tor_config.socks_port
-                    ),
+                    format!("tap,id=tornet,ifname={},script=no,downscript=no", TorTap::for_vm(vm_name).interface),
                     "-device".to_string(),
                     "virtio-net-pci,netdev=tornet".to_string(),
                 ]);
@@ -493,41 +998,151 @@ impl SecurityManager {
             }
         }
 
-        args
+        // Virtual devices
+        args.extend(Self::virtual_device_args(&profile.virtual_devices)?);
+
+        // TPM emulation through the swtpm the caller starts in `tpm_dir`
+        if profile.wants_tpm() {
+            args.extend(tpm_qemu_args(&swtpm_socket(&self.tpm_dir(vm_name))));
+        }
+
+        Ok(args)
+    }
+
+    /// Map the profile's virtual devices to QEMU arguments.
+    ///
+    /// Network adapters and TPMs are handled separately by the caller.
+    fn virtual_device_args(devices: &[VirtualDevice]) -> Result<Vec<String>, String> {
+        let mut args = Vec::new();
+        let mut usb_controller = false;
+
+        for device in devices {
+            match device.device_type {
+                VirtualDeviceType::AudioDevice => {
+                    if device.enabled {
+                        args.extend([
+                            "-audiodev".to_string(),
+                            format!("pa,id={}", device.name),
+                            "-device".to_string(),
+                            "intel-hda".to_string(),
+                            "-device".to_string(),
+                            format!("hda-duplex,audiodev={}", device.name),
+                        ]);
+                    }
+                }
+                VirtualDeviceType::SerialPort => {
+                    let serial = match (&device.target, device.enabled) {
+                        (_, false) => vec!["-serial".to_string(), "none".to_string()],
+                        (Some(target), true) => SerialTarget::parse(target)?.qemu_args(&device.name),
+                        (None, true) => SerialTarget::Pty.qemu_args(&device.name),
+                    };
+                    args.extend(serial);
+                }
+                VirtualDeviceType::UsbController => {
+                    if !device.enabled {
+                        continue;
+                    }
+                    if !usb_controller {
+                        args.extend(["-device".to_string(), "qemu-xhci,id=xhci".to_string()]);
+                        usb_controller = true;
+                    }
+                    // Isolated controllers only get emulated input, never host devices
+                    if device.isolated || !device.passthrough {
+                        args.extend(["-device".to_string(), "usb-tablet,bus=xhci.0".to_string()]);
+                    }
+                }
+                VirtualDeviceType::StorageController => {
+                    if device.enabled {
+                        args.extend([
+                            "-device".to_string(),
+                            format!("virtio-scsi-pci,id={}", device.name),
+                        ]);
+                    }
+                }
+                VirtualDeviceType::NetworkAdapter | VirtualDeviceType::Tpm => {}
+            }
+        }
+
+        Ok(args)
+    }
+
+    /// Where a VM's own Tor keeps its torrc, state and control files
+    pub fn tor_dir(&self, vm_name: &str) -> PathBuf {
+        self.vm_dir(vm_name).join("tor")
+    }
+
+    /// Where a VM's swtpm keeps its TPM state, pidfile and socket (see
+    /// `start_swtpm`)
+    pub fn tpm_dir(&self, vm_name: &str) -> PathBuf {
+        self.vm_dir(vm_name).join("tpm")
     }
 
-    /// Generate torrc configuration
-    pub fn generate_torrc(&self, vm_name: &str) -> String {
+    /// The torrc for a VM's own Tor instance (see `start_vm_tor`). Guests
+    /// on NAT reach its SocksPort, if the VM has one, at 10.0.2.2; Tor-only
+    /// guests have none and go through the TransPort and DNSPort on their
+    /// tap instead. The control port is picked by Tor and needs the cookie
+    pub fn generate_torrc(&self, profile: &SecurityProfile, vm_name: &str) -> Result<String, String> {
         let config = &self.tor_config;
+        let mut problems = Vec::new();
+        let mut node_list = |option: &str, entries: &Option<Vec<String>>| {
+            let entries = entries.as_deref()?;
+            match normalize_tor_nodes(entries) {
+                Ok(nodes) => Some(nodes.join(",")),
+                Err(bad) => {
+                    problems.push(format!("{}: {}", option, bad.join(", ")));
+                    None
+                }
+            }
+        };
+        let exit_nodes = node_list("ExitNodes", &config.exit_nodes);
+        let exclude_exit_nodes = node_list("ExcludeExitNodes", &config.exclude_exit_nodes);
+        if !problems.is_empty() {
+            return Err(format!("Invalid Tor node entries ({})", problems.join("; ")));
+        }
+
+        let tor_dir = self.tor_dir(vm_name);
         let mut torrc = format!(
             r#"# n01d Machine Tor Configuration for {}
-SocksPort {}
-ControlPort {}
-DNSPort {}
+DataDirectory {}
+ControlPort auto
+ControlPortWriteToFile {}
+CookieAuthentication 1
+CookieAuthFile {}
+Log notice file {}
 AutomapHostsOnResolve 1
 AutomapHostsSuffixes .onion,.exit
 VirtualAddrNetworkIPv4 10.192.0.0/10
 "#,
-            vm_name, config.socks_port, config.control_port, config.dns_port
+            vm_name,
+            tor_dir.join("data").display(),
+            tor_dir.join(TOR_CONTROL_PORT_FILE).display(),
+            tor_dir.join(TOR_COOKIE_FILE).display(),
+            tor_dir.join("tor.log").display()
         );
 
-        if config.transparent_proxy {
-            torrc.push_str("TransPort 9040\n");
+        match self.socks_ports.get(vm_name) {
+            Some(port) => torrc.push_str(&format!("SocksPort 127.0.0.1:{} IsolateClientAddr IsolateDestAddr\n", port)),
+            None => torrc.push_str("SocksPort 0\n"),
+        }
+
+        // Listening on the VM's tap address is what the transparent proxy
+        // rules redirect its traffic to
+        if config.transparent_proxy && profile.network_isolation.mode() == IsolationMode::TorOnly {
+            let host = TorTap::for_vm(vm_name).host;
+            torrc.push_str(&format!("TransPort {}:{}\n", host, config.trans_port));
+            torrc.push_str(&format!("DNSPort {}:{}\n", host, config.dns_port));
         }
 
         if config.bridge_enabled && !config.bridges.is_empty() {
-            torrc.push_str("UseBridges 1\n");
-            for bridge in &config.bridges {
-                torrc.push_str(&format!("Bridge {}\n", bridge));
-            }
+            torrc.push_str(&bridge_config(&config.bridges, find_obfs4_transport().as_deref())?);
         }
 
-        if let Some(exit_nodes) = &config.exit_nodes {
-            torrc.push_str(&format!("ExitNodes {}\n", exit_nodes.join(",")));
+        if let Some(exit_nodes) = exit_nodes {
+            torrc.push_str(&format!("ExitNodes {}\n", exit_nodes));
         }
 
-        if let Some(exclude) = &config.exclude_exit_nodes {
-            torrc.push_str(&format!("ExcludeExitNodes {}\n", exclude.join(",")));
+        if let Some(exclude) = exclude_exit_nodes {
+            torrc.push_str(&format!("ExcludeExitNodes {}\n", exclude));
         }
 
         if config.strict_nodes {
@@ -536,7 +1151,7 @@ VirtualAddrNetworkIPv4 10.192.0.0/10
 
         torrc.push_str(&format!("NewCircuitPeriod {}\n", config.new_circuit_period));
 
-        torrc
+        Ok(torrc)
     }
 
     /// Generate WireGuard configuration
@@ -550,21 +1165,48 @@ DNS = 1.1.1.1
 [Peer]
 PublicKey = <SERVER_PUBLIC_KEY>
 Endpoint = {}:{}
-AllowedIPs = 0.0.0.0/0
+AllowedIPs = {}
 PersistentKeepalive = 25
 "#,
             vpn.server.as_deref().unwrap_or("vpn.example.com"),
-            vpn.port
+            vpn.port,
+            if vpn.split_tunnel_routes.is_empty() {
+                "0.0.0.0/0".to_string()
+            } else {
+                vpn.split_tunnel_routes.join(", ")
+            }
         )
     }
 
+    /// OpenVPN config fragment for split tunneling: ignore the server's
+    /// pushed routes and route only the given CIDRs. Empty for a full tunnel
+    pub fn generate_openvpn_routes(vpn: &VpnConfig) -> Result<String, String> {
+        if vpn.split_tunnel_routes.is_empty() {
+            return Ok(String::new());
+        }
+
+        let mut fragment = String::from("route-nopull\n");
+        for route in &vpn.split_tunnel_routes {
+            match parse_cidr(route) {
+                Some((std::net::IpAddr::V4(net), prefix)) => {
+                    let mask = std::net::Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0));
+                    fragment.push_str(&format!("route {} {}\n", net, mask));
+                }
+                Some((std::net::IpAddr::V6(_), _)) => fragment.push_str(&format!("route-ipv6 {}\n", route)),
+                None => return Err(format!("Invalid split-tunnel route '{}'", route)),
+            }
+        }
+        Ok(fragment)
+    }
+
     /// Generate iptables rules for VM network isolation
     pub fn generate_iptables_rules(&self, profile: &SecurityProfile, vm_interface: &str) -> Vec<String> {
-        let mut rules = Vec::new();
-        
-        // Flush existing rules for this VM
-        rules.push(format!("iptables -F n01d-{}", profile.name));
-        rules.push(format!("iptables -N n01d-{} 2>/dev/null || true", profile.name));
+        let chain = firewall_chain(profile);
+        let mut rules = vec![
+            // Flush existing rules for this VM
+            format!("iptables -F {}", chain),
+            format!("iptables -N {} 2>/dev/null || true", chain),
+        ];
 
         for rule in &profile.firewall_rules {
             let action = match rule.action {
@@ -580,107 +1222,2086 @@ PersistentKeepalive = 25
                 TrafficDirection::Both => "-i", // Will add both
             };
 
-            let mut cmd = format!("iptables -A n01d-{} {} {}", profile.name, direction, vm_interface);
+            let mut spec: Vec<String> = vec!["-A".into(), chain.clone(), direction.into(), vm_interface.into()];
 
             if let Some(proto) = &rule.protocol {
-                cmd.push_str(&format!(" -p {}", proto));
+                spec.extend(["-p".into(), proto.clone()]);
             }
 
             if let Some(src) = &rule.source {
-                cmd.push_str(&format!(" -s {}", src));
+                spec.extend(["-s".into(), src.clone()]);
             }
 
             if let Some(dst) = &rule.destination {
-                cmd.push_str(&format!(" -d {}", dst));
+                spec.extend(["-d".into(), dst.clone()]);
             }
 
             if let Some(port) = rule.port {
-                cmd.push_str(&format!(" --dport {}", port));
+                spec.extend(["--dport".into(), port.to_string()]);
             }
 
             if let Some((start, end)) = rule.port_range {
-                cmd.push_str(&format!(" --dport {}:{}", start, end));
+                spec.extend(["--dport".into(), format!("{}:{}", start, end)]);
             }
 
-            cmd.push_str(&format!(" -j {} -m comment --comment \"{}\"", action, rule.description));
-            rules.push(cmd);
+            spec.extend(["-j".into(), action.into(), "-m".into(), "comment".into(), "--comment".into(), rule.description.clone()]);
+            rules.push(format!("iptables {}", shell_join(&spec)));
 
             // Add outbound rule too if direction is Both
             if matches!(rule.direction, TrafficDirection::Both) {
-                let mut cmd_out = format!("iptables -A n01d-{} -o {}", profile.name, vm_interface);
+                let mut spec_out: Vec<String> = vec!["-A".into(), chain.clone(), "-o".into(), vm_interface.into()];
                 if let Some(proto) = &rule.protocol {
-                    cmd_out.push_str(&format!(" -p {}", proto));
+                    spec_out.extend(["-p".into(), proto.clone()]);
                 }
-                cmd_out.push_str(&format!(" -j {}", action));
-                rules.push(cmd_out);
+                spec_out.extend(["-j".into(), action.into()]);
+                rules.push(format!("iptables {}", shell_join(&spec_out)));
             }
         }
 
         rules
     }
+
+    /// Generate the rules that force everything from a Tor-only VM's tap
+    /// through Tor: new TCP connections and DNS are DNATed to the TransPort
+    /// and DNSPort on the tap's host address, DHCP and replies to the host
+    /// are let in, and anything else is dropped. IPv6 is dropped outright,
+    /// since Tor only takes the IPv4 traffic redirected to it
+    pub fn generate_transparent_proxy_rules(&self, tap: &TorTap) -> Result<Vec<String>, String> {
+        validate_interface_name(&tap.interface)?;
+        Ok(self
+            .transparent_proxy_specs(tap)
+            .iter()
+            .map(|(program, table, chain, spec)| format!("{} -t {} -A {} {}", program, table, chain, spec))
+            .collect())
+    }
+
+    /// Undo generate_transparent_proxy_rules, deleting the same rules in reverse
+    pub fn generate_transparent_proxy_teardown(&self, tap: &TorTap) -> Result<Vec<String>, String> {
+        validate_interface_name(&tap.interface)?;
+        Ok(self
+            .transparent_proxy_specs(tap)
+            .iter()
+            .rev()
+            .map(|(program, table, chain, spec)| format!("{} -t {} -D {} {}", program, table, chain, spec))
+            .collect())
+    }
+
+    fn transparent_proxy_specs(&self, tap: &TorTap) -> Vec<(&'static str, &'static str, &'static str, String)> {
+        let (iface, host) = (&tap.interface, tap.host);
+        let dns = self.tor_config.dns_port;
+        let trans = self.tor_config.trans_port;
+        vec![
+            ("iptables", "nat", "PREROUTING", format!("-i {} -p udp --dport 53 -j DNAT --to-destination {}:{}", iface, host, dns)),
+            ("iptables", "nat", "PREROUTING", format!("-i {} -p tcp --syn -j DNAT --to-destination {}:{}", iface, host, trans)),
+            ("iptables", "filter", "INPUT", format!("-i {} -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT", iface)),
+            ("iptables", "filter", "INPUT", format!("-i {} -p udp --dport 67 -j ACCEPT", iface)),
+            ("iptables", "filter", "INPUT", format!("-i {} -p udp -d {} --dport {} -j ACCEPT", iface, host, dns)),
+            ("iptables", "filter", "INPUT", format!("-i {} -p tcp -d {} --dport {} -j ACCEPT", iface, host, trans)),
+            ("iptables", "filter", "INPUT", format!("-i {} -j DROP", iface)),
+            ("iptables", "filter", "FORWARD", format!("-i {} -j DROP", iface)),
+            ("ip6tables", "filter", "INPUT", format!("-i {} -j DROP", iface)),
+            ("ip6tables", "filter", "FORWARD", format!("-i {} -j DROP", iface)),
+        ]
+    }
+
+    /// Root shell script creating a Tor-only VM's tap, owned by `uid` so
+    /// QEMU can open it, and routing it through Tor. With dnsmasq installed
+    /// the guest gets its address over DHCP; otherwise it has to be set
+    /// statically. Stops at the first failure
+    pub fn tor_network_up_script(&self, tap: &TorTap, uid: u32) -> Result<String, String> {
+        let iface = &tap.interface;
+        let mut script = vec![
+            "set -e".to_string(),
+            format!("ip tuntap add dev {} mode tap user {}", iface, uid),
+            format!("sysctl -q -w net.ipv6.conf.{}.disable_ipv6=1", iface),
+            format!("ip addr add {}/30 dev {}", tap.host, iface),
+        ];
+        script.extend(self.generate_transparent_proxy_rules(tap)?);
+        script.push(format!("ip link set {} up", iface));
+        script.push(format!(
+            "if command -v dnsmasq >/dev/null; then dnsmasq --conf-file=/dev/null --port=0 --bind-interfaces \
+             --interface={iface} --except-interface=lo --dhcp-range={guest},{guest},255.255.255.252,12h \
+             --dhcp-option=option:router,{host} --dhcp-option=option:dns-server,{host} \
+             --pid-file={pid} --dhcp-leasefile={leases}; fi",
+            iface = iface,
+            guest = tap.guest,
+            host = tap.host,
+            pid = tap.dnsmasq_pid_file(),
+            leases = tap.dnsmasq_lease_file(),
+        ));
+        Ok(script.join("\n"))
+    }
+
+    /// Root shell script undoing `tor_network_up_script`. Every step runs
+    /// even if an earlier one fails, so a half set up network is cleared too
+    pub fn tor_network_down_script(&self, tap: &TorTap) -> Result<String, String> {
+        let pid = tap.dnsmasq_pid_file();
+        let mut script = vec![format!("[ -f {pid} ] && kill \"$(cat {pid})\"", pid = pid)];
+        script.push(format!("rm -f {} {}", pid, tap.dnsmasq_lease_file()));
+        script.extend(
+            self.generate_transparent_proxy_teardown(tap)?
+                .into_iter()
+                .map(|rule| format!("{} 2>/dev/null", rule)),
+        );
+        script.push(format!("ip link del {} 2>/dev/null", tap.interface));
+        script.push("true".to_string());
+        Ok(script.join("\n"))
+    }
+
+    /// Create a Tor-only VM's tap and route it through Tor, as root (through
+    /// pkexec unless already root). The returned `TorNetwork` undoes it all
+    /// when dropped
+    pub fn setup_tor_network(&self, vm_name: &str) -> Result<TorNetwork, String> {
+        if !cfg!(target_os = "linux") {
+            return Err("Tor-only networking needs Linux (tap devices and iptables)".to_string());
+        }
+        let tap = TorTap::for_vm(vm_name);
+        let uid = current_uid().ok_or("Can't tell which user QEMU runs as")?;
+        let teardown = self.tor_network_down_script(&tap)?;
+
+        // Left over from a run that never got to clean up
+        if Path::new("/sys/class/net").join(&tap.interface).exists() {
+            run_as_root(&teardown)?;
+        }
+        if let Some(other) = interface_with_address(tap.host) {
+            return Err(format!(
+                "{} is already in use on {}; rename the VM to give it a different network",
+                tap.host, other
+            ));
+        }
+
+        run_as_root(&self.tor_network_up_script(&tap, uid)?)?;
+        Ok(TorNetwork { tap, teardown })
+    }
 }
 
-/// Helper to check if Tor is running
-pub fn check_tor_status() -> bool {
-    Command::new("pgrep")
-        .arg("tor")
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+/// Host side of a Tor-only VM's network: a tap of its own, and a /30 out of
+/// 10.152.0.0/16 for the host and guest ends. Both come from hashing the VM
+/// name, so they're the same every run
+#[derive(Debug, Clone, PartialEq)]
+pub struct TorTap {
+    pub interface: String,
+    pub host: std::net::Ipv4Addr,
+    pub guest: std::net::Ipv4Addr,
 }
 
-/// Start Tor with custom config
-pub fn start_tor(config_path: &str) -> Result<(), String> {
-    Command::new("tor")
-        .args(["-f", config_path])
-        .spawn()
-        .map_err(|e| format!("Failed to start Tor: {}", e))?;
-    Ok(())
+impl TorTap {
+    pub fn for_vm(vm_name: &str) -> Self {
+        let hash = fnv1a(vm_name);
+        let base = u32::from(std::net::Ipv4Addr::new(10, 152, 0, 0)) + (hash % 16384) as u32 * 4;
+        TorTap {
+            interface: format!("n01dt{:08x}", hash >> 32),
+            host: (base + 1).into(),
+            guest: (base + 2).into(),
+        }
+    }
+
+    fn dnsmasq_pid_file(&self) -> String {
+        format!("/run/n01d-{}.pid", self.interface)
+    }
+
+    fn dnsmasq_lease_file(&self) -> String {
+        format!("/run/n01d-{}.leases", self.interface)
+    }
 }
 
-/// Get new Tor circuit
-pub fn new_tor_circuit(control_port: u16) -> Result<(), String> {
-    use std::io::Write;
-    use std::net::TcpStream;
+/// A Tor-only VM's tap and transparent proxy rules, removed when dropped
+pub struct TorNetwork {
+    pub tap: TorTap,
+    teardown: String,
+}
+
+impl Drop for TorNetwork {
+    fn drop(&mut self) {
+        if let Err(e) = run_as_root(&self.teardown) {
+            eprintln!("[n01d] Failed to remove {}: {}", self.tap.interface, e);
+        }
+    }
+}
 
-    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", control_port))
-        .map_err(|e| format!("Failed to connect to Tor control: {}", e))?;
+/// Check an interface name before it goes into iptables arguments or a
+/// sysctl key: what the kernel allows (at most 15 bytes), less the dots,
+/// slashes and whitespace that sysctl or a shell would read as syntax
+pub fn validate_interface_name(name: &str) -> Result<(), String> {
+    let valid = (1..=15).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid interface name '{}'", name))
+    }
+}
+
+/// The interface already holding `addr`, if any
+fn interface_with_address(addr: std::net::Ipv4Addr) -> Option<String> {
+    let output = Command::new("ip").args(["-o", "-4", "addr", "show"]).output().ok()?;
+    let needle = format!(" {}/", addr);
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.contains(&needle))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .map(str::to_string)
+}
 
-    stream
-        .write_all(b"AUTHENTICATE\r\nSIGNAL NEWNYM\r\nQUIT\r\n")
-        .map_err(|e| format!("Failed to send signal: {}", e))?;
+#[cfg(target_os = "linux")]
+fn current_uid() -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata("/proc/self").ok().map(|meta| meta.uid())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_uid() -> Option<u32> {
+    None
+}
 
+/// Run a shell script as root: directly if we already are, otherwise
+/// through pkexec so the desktop session can ask for the password
+fn run_as_root(script: &str) -> Result<(), String> {
+    let mut cmd = if current_uid() == Some(0) {
+        Command::new("sh")
+    } else {
+        let mut cmd = Command::new("pkexec");
+        cmd.arg("sh");
+        cmd
+    };
+    let output = cmd
+        .args(["-c", script])
+        .output()
+        .map_err(|e| format!("Failed to run pkexec: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("Command run as root failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Files Tor writes into a VM's tor dir: its control port's address and
+/// the cookie that authenticates to it
+const TOR_CONTROL_PORT_FILE: &str = "control_port";
+const TOR_COOKIE_FILE: &str = "control_auth_cookie";
 
-    #[test]
-    fn test_preset_profiles() {
-        let presets = SecurityManager::get_preset_profiles();
-        assert!(!presets.is_empty());
-        assert!(presets.iter().any(|(name, _, _)| *name == "paranoid"));
+/// How long Tor gets to open its control port after starting
+const TOR_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// A VM's own Tor process, stopped when dropped
+pub struct VmTor {
+    child: std::process::Child,
+    pub control: TorControlAddr,
+}
+
+impl Drop for VmTor {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
     }
+}
 
-    #[test]
-    fn test_qemu_args_generation() {
-        let manager = SecurityManager::new(PathBuf::from("/tmp"));
-        let profile = SecurityProfile {
-            name: "test".to_string(),
-            sandbox_enabled: true,
-            network_isolation: NetworkIsolation {
-                mode: IsolationMode::Full,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
+/// Start a VM's own Tor from `torrc` (see `generate_torrc`) and wait for
+/// its control port to come up, so a failure to bind its ports shows up
+/// here rather than as a VM without a network
+pub fn start_vm_tor(tor_dir: &Path, torrc: &str) -> Result<VmTor, String> {
+    let tor = which::which("tor").map_err(|_| "Tor is not installed".to_string())?;
 
-        let args = manager.generate_qemu_security_args(&profile);
-        assert!(args.contains(&"-sandbox".to_string()));
+    // Tor refuses a DataDirectory other users can read
+    let data_dir = tor_dir.join("data");
+    fs::create_dir_all(&data_dir).map_err(|e| format!("Failed to create {}: {}", data_dir.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&data_dir, fs::Permissions::from_mode(0o700))
+            .map_err(|e| format!("Failed to restrict {}: {}", data_dir.display(), e))?;
+    }
+
+    let torrc_path = tor_dir.join("torrc");
+    fs::write(&torrc_path, torrc).map_err(|e| format!("Failed to write {}: {}", torrc_path.display(), e))?;
+    let port_file = tor_dir.join(TOR_CONTROL_PORT_FILE);
+    let _ = fs::remove_file(&port_file);
+
+    let child = Command::new(tor)
+        .arg("-f")
+        .arg(&torrc_path)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Failed to start Tor: {}", e))?;
+    // Dropping it from here on kills the process again
+    let mut tor = VmTor { child, control: TorControlAddr { port: 0, cookie: Some(tor_dir.join(TOR_COOKIE_FILE)) } };
+
+    let deadline = std::time::Instant::now() + TOR_START_TIMEOUT;
+    loop {
+        if let Some(port) = fs::read_to_string(&port_file).ok().as_deref().and_then(parse_control_port_file) {
+            tor.control.port = port;
+            return Ok(tor);
+        }
+        if let Ok(Some(status)) = tor.child.try_wait() {
+            return Err(format!("Tor exited ({}), see {}", status, tor_dir.join("tor.log").display()));
+        }
+        if std::time::Instant::now() > deadline {
+            return Err(format!("Tor didn't open its control port, see {}", tor_dir.join("tor.log").display()));
+        }
+        std::thread::sleep(CONTROL_RETRY_DELAY);
+    }
+}
+
+/// The port from a ControlPortWriteToFile file ("PORT=127.0.0.1:9151")
+fn parse_control_port_file(content: &str) -> Option<u16> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("PORT="))
+        .and_then(|addr| addr.trim().rsplit_once(':'))
+        .and_then(|(_, port)| port.parse().ok())
+}
+
+/// A local proxy endpoint started for a VM, stopped when dropped
+pub struct LocalProxy {
+    child: std::process::Child,
+    pub port: u16,
+    config_path: PathBuf,
+}
+
+impl Drop for LocalProxy {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_file(&self.config_path);
+    }
+}
+
+/// Start whatever local endpoint the proxy needs: for Shadowsocks, an
+/// `ss-local` SOCKS5 listener on 127.0.0.1. Other proxy types are used
+/// directly, so there's nothing to start. The password goes to ss-local in
+/// a config file only the user can read, in `dir`, rather than on its
+/// command line where any local user could see it
+pub fn start_local_proxy(config: &ProxyConfig, dir: &Path) -> Result<Option<LocalProxy>, String> {
+    if !matches!(config.proxy_type, ProxyType::Shadowsocks) {
+        return Ok(None);
+    }
+
+    let ss_local = which::which("ss-local").map_err(|_| "ss-local (shadowsocks-libev) is not installed".to_string())?;
+    let port = match config.local_port {
+        Some(port) => port,
+        None => std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|listener| listener.local_addr())
+            .map(|addr| addr.port())
+            .map_err(|e| format!("No free local port for ss-local: {}", e))?,
+    };
+
+    let settings = serde_json::json!({
+        "server": config.host,
+        "server_port": config.port,
+        "local_address": "127.0.0.1",
+        "local_port": port,
+        "password": config.password.as_deref().unwrap_or_default(),
+        "method": config.cipher.as_deref().unwrap_or_default(),
+    });
+    let config_path = dir.join("ss-local.json");
+    write_private(&config_path, settings.to_string().as_bytes())?;
+
+    let child = Command::new(ss_local)
+        .arg("-c")
+        .arg(&config_path)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            let _ = fs::remove_file(&config_path);
+            return Err(format!("Failed to start ss-local: {}", e));
+        }
+    };
+
+    Ok(Some(LocalProxy { child, port, config_path }))
+}
+
+/// Write a file only the current user can read, replacing any old one
+fn write_private(path: &Path, content: &[u8]) -> Result<(), String> {
+    use std::io::Write;
+
+    let _ = fs::remove_file(path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// proxychains config for the profile's proxy. A Shadowsocks proxy appears
+/// as `local_port`, where its `LocalProxy` listens, and comes first since
+/// ss-local itself makes the hop to the server; the chain entries follow,
+/// reached through it. Otherwise the chain entries come first, then the
+/// main proxy
+pub fn generate_proxychains_conf(config: &ProxyConfig, local_port: Option<u16>) -> Result<String, String> {
+    let shadowsocks = matches!(config.proxy_type, ProxyType::Shadowsocks);
+    let proxy_line = |proxy_type: &ProxyType, host: &str, port: u16| match proxy_type {
+        ProxyType::Socks5 => Ok(format!("socks5 {} {}", host, port)),
+        ProxyType::Socks4 => Ok(format!("socks4 {} {}", host, port)),
+        ProxyType::Http | ProxyType::Https => Ok(format!("http {} {}", host, port)),
+        ProxyType::Shadowsocks => local_port
+            .map(|port| format!("socks5 127.0.0.1 {}", port))
+            .ok_or_else(|| "Shadowsocks needs ss-local running".to_string()),
+    };
+
+    let mut main = None;
+    if !config.host.trim().is_empty() {
+        let mut line = proxy_line(&config.proxy_type, &config.host, config.port)?;
+        // ss-local handles the credentials itself
+        if let (false, Some(user), Some(pass)) = (shadowsocks, &config.username, &config.password) {
+            line.push_str(&format!(" {} {}", user, pass));
+        }
+        main = Some(line);
+    }
+    let chain = config
+        .chain
+        .iter()
+        .map(|entry| proxy_line(&entry.proxy_type, &entry.host, entry.port))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let lines: Vec<String> = if shadowsocks {
+        main.into_iter().chain(chain).collect()
+    } else {
+        chain.into_iter().chain(main).collect()
+    };
+    let mut conf = String::from("# n01d Machine proxychains configuration\nstrict_chain\nproxy_dns\n\n[ProxyList]\n");
+    for line in lines {
+        conf.push_str(&line);
+        conf.push('\n');
+    }
+    Ok(conf)
+}
+
+/// A WireGuard config as a PNG QR code in a data URL, for mobile clients
+/// to scan off the screen
+pub fn wireguard_config_qr(config: &str) -> Result<String, String> {
+    use base64::Engine;
+
+    let code = qrcode::QrCode::new(config.trim_end().as_bytes())
+        .map_err(|e| format!("Config can't be encoded as a QR code: {}", e))?;
+    let image = code.render::<image::Luma<u8>>().min_dimensions(320, 320).build();
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode QR code: {}", e))?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png.into_inner())
+    ))
+}
+
+/// Split `addr/prefix` into its parts, checking the prefix fits the address
+fn parse_cidr(cidr: &str) -> Option<(std::net::IpAddr, u8)> {
+    let (addr, prefix) = cidr.trim().split_once('/')?;
+    let addr: std::net::IpAddr = addr.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    (prefix <= max).then_some((addr, prefix))
+}
+
+/// Binaries that speak the obfs4 pluggable transport; lyrebird is the
+/// current Tor Project name for obfs4proxy
+const OBFS4_TRANSPORTS: &[&str] = &["lyrebird", "obfs4proxy"];
+
+pub fn find_obfs4_transport() -> Option<PathBuf> {
+    OBFS4_TRANSPORTS.iter().find_map(|name| which::which(name).ok())
+}
+
+/// `UseBridges`/`Bridge` lines, plus the obfs4 transport plugin when any
+/// bridge needs it
+fn bridge_config(bridges: &[String], obfs4_transport: Option<&Path>) -> Result<String, String> {
+    let mut config = String::from("UseBridges 1\n");
+    let obfs4: Vec<&String> = bridges.iter().filter(|b| b.trim_start().starts_with("obfs4 ")).collect();
+
+    if !obfs4.is_empty() {
+        let bad: Vec<String> = obfs4
+            .iter()
+            .filter_map(|bridge| validate_obfs4_bridge(bridge).err())
+            .collect();
+        if !bad.is_empty() {
+            return Err(bad.join("; "));
+        }
+
+        let transport = obfs4_transport.ok_or_else(|| {
+            format!("obfs4 bridges need an obfs4 transport; install one of: {}", OBFS4_TRANSPORTS.join(", "))
+        })?;
+        config.push_str(&format!("ClientTransportPlugin obfs4 exec {}\n", transport.display()));
+    }
+
+    for bridge in bridges {
+        config.push_str(&format!("Bridge {}\n", bridge.trim()));
+    }
+    Ok(config)
+}
+
+/// Check a bridge line of the form
+/// `obfs4 IP:PORT FINGERPRINT cert=... iat-mode=0|1|2`
+pub fn validate_obfs4_bridge(line: &str) -> Result<(), String> {
+    let invalid = |reason: &str| Err(format!("Invalid obfs4 bridge '{}': {}", line.trim(), reason));
+    let parts: Vec<&str> = line.split_whitespace().collect();
+
+    if parts.first() != Some(&"obfs4") {
+        return invalid("must start with 'obfs4'");
+    }
+    match parts.get(1).map(|addr| addr.parse::<std::net::SocketAddr>()) {
+        Some(Ok(_)) => {}
+        _ => return invalid("expected IP:port after 'obfs4'"),
+    }
+    match parts.get(2) {
+        Some(fp) if fp.len() == 40 && fp.chars().all(|c| c.is_ascii_hexdigit()) => {}
+        _ => return invalid("expected a 40-character hex fingerprint"),
+    }
+
+    let option = |key: &str| parts[3..].iter().find_map(|part| part.strip_prefix(key));
+    match option("cert=") {
+        Some(cert) if !cert.is_empty() => {}
+        _ => return invalid("missing cert="),
+    }
+    match option("iat-mode=") {
+        Some("0" | "1" | "2") => {}
+        Some(_) => return invalid("iat-mode must be 0, 1 or 2"),
+        None => return invalid("missing iat-mode="),
+    }
+    Ok(())
+}
+
+/// ISO 3166-1 alpha-2 country codes, as Tor's GeoIP database uses them
+const COUNTRY_CODES: &[&str] = &[
+    "ad", "ae", "af", "ag", "ai", "al", "am", "ao", "aq", "ar", "as", "at", "au", "aw", "ax", "az",
+    "ba", "bb", "bd", "be", "bf", "bg", "bh", "bi", "bj", "bl", "bm", "bn", "bo", "bq", "br", "bs",
+    "bt", "bv", "bw", "by", "bz", "ca", "cc", "cd", "cf", "cg", "ch", "ci", "ck", "cl", "cm", "cn",
+    "co", "cr", "cu", "cv", "cw", "cx", "cy", "cz", "de", "dj", "dk", "dm", "do", "dz", "ec", "ee",
+    "eg", "eh", "er", "es", "et", "fi", "fj", "fk", "fm", "fo", "fr", "ga", "gb", "gd", "ge", "gf",
+    "gg", "gh", "gi", "gl", "gm", "gn", "gp", "gq", "gr", "gs", "gt", "gu", "gw", "gy", "hk", "hm",
+    "hn", "hr", "ht", "hu", "id", "ie", "il", "im", "in", "io", "iq", "ir", "is", "it", "je", "jm",
+    "jo", "jp", "ke", "kg", "kh", "ki", "km", "kn", "kp", "kr", "kw", "ky", "kz", "la", "lb", "lc",
+    "li", "lk", "lr", "ls", "lt", "lu", "lv", "ly", "ma", "mc", "md", "me", "mf", "mg", "mh", "mk",
+    "ml", "mm", "mn", "mo", "mp", "mq", "mr", "ms", "mt", "mu", "mv", "mw", "mx", "my", "mz", "na",
+    "nc", "ne", "nf", "ng", "ni", "nl", "no", "np", "nr", "nu", "nz", "om", "pa", "pe", "pf", "pg",
+    "ph", "pk", "pl", "pm", "pn", "pr", "ps", "pt", "pw", "py", "qa", "re", "ro", "rs", "ru", "rw",
+    "sa", "sb", "sc", "sd", "se", "sg", "sh", "si", "sj", "sk", "sl", "sm", "sn", "so", "sr", "ss",
+    "st", "sv", "sx", "sy", "sz", "tc", "td", "tf", "tg", "th", "tj", "tk", "tl", "tm", "tn", "to",
+    "tr", "tt", "tv", "tw", "tz", "ua", "ug", "um", "us", "uy", "uz", "va", "vc", "ve", "vg", "vi",
+    "vn", "vu", "wf", "ws", "ye", "yt", "za", "zm", "zw",
+];
+
+/// Check ExitNodes-style entries: `{cc}` country codes, `$fingerprint`s, IP
+/// addresses/ranges and relay nicknames. Bare country codes are put into
+/// braces; on failure the offending entries are returned
+pub fn normalize_tor_nodes(entries: &[String]) -> Result<Vec<String>, Vec<String>> {
+    let mut nodes = Vec::new();
+    let mut bad = Vec::new();
+
+    for entry in entries {
+        match normalize_tor_node(entry.trim()) {
+            Some(node) => nodes.push(node),
+            None => bad.push(entry.clone()),
+        }
+    }
+
+    if bad.is_empty() { Ok(nodes) } else { Err(bad) }
+}
+
+fn normalize_tor_node(entry: &str) -> Option<String> {
+    let is_country = |code: &str| code == "??" || COUNTRY_CODES.contains(&code.to_ascii_lowercase().as_str());
+
+    if let Some(code) = entry.strip_prefix('{').and_then(|e| e.strip_suffix('}')) {
+        return is_country(code).then(|| format!("{{{}}}", code.to_ascii_lowercase()));
+    }
+    if entry.len() == 2 && is_country(entry) {
+        return Some(format!("{{{}}}", entry.to_ascii_lowercase()));
+    }
+
+    // $fingerprint, optionally followed by ~nickname or =nickname
+    if let Some(rest) = entry.strip_prefix('$') {
+        let (fingerprint, nickname) = match rest.find(['~', '=']) {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        let valid = fingerprint.len() == 40
+            && fingerprint.chars().all(|c| c.is_ascii_hexdigit())
+            && nickname.is_none_or(is_nickname);
+        return valid.then(|| entry.to_string());
+    }
+
+    let (addr, prefix) = match entry.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry, None),
+    };
+    if let Ok(ip) = addr.parse::<std::net::IpAddr>() {
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let valid = prefix.is_none_or(|p| p.parse::<u8>().is_ok_and(|p| p <= max));
+        return valid.then(|| entry.to_string());
+    }
+
+    is_nickname(entry).then(|| entry.to_string())
+}
+
+fn is_nickname(name: &str) -> bool {
+    (1..=19).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Helper to check if Tor is running
+pub fn check_tor_status() -> bool {
+    Command::new("pgrep")
+        .arg("tor")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// Start Tor with custom config
+pub fn start_tor(config_path: &str) -> Result<(), String> {
+    Command::new("tor")
+        .args(["-f", config_path])
+        .spawn()
+        .map_err(|e| format!("Failed to start Tor: {}", e))?;
+    Ok(())
+}
+
+/// Multicast group and port carrying an internal network's traffic.
+///
+/// The id is hashed with `fnv1a`: the low 24 bits pick the group in
+/// 230.0.0.0/8 and the next 16 a port from 1024 up, so VMs on the same id
+/// always meet and VMs on different ids land on different segments.
+pub fn internal_mcast_endpoint(network_id: &str) -> (std::net::Ipv4Addr, u16) {
+    let hash = fnv1a(network_id);
+    let [_, _, _, _, _, a, b, c] = hash.to_be_bytes();
+    let port = 1024 + ((hash >> 24) & 0xffff) as u16 % (u16::MAX - 1023);
+    (std::net::Ipv4Addr::new(230, a, b, c), port)
+}
+
+/// QEMU arguments attaching an emulated TPM 2.0 through a swtpm socket
+pub fn tpm_qemu_args(socket: &Path) -> Vec<String> {
+    vec![
+        "-chardev".to_string(),
+        format!("socket,id=chrtpm,path={}", socket.display()),
+        "-tpmdev".to_string(),
+        "emulator,id=tpm0,chardev=chrtpm".to_string(),
+        "-device".to_string(),
+        "tpm-tis,tpmdev=tpm0".to_string(),
+    ]
+}
+
+/// QEMU arguments passing host USB devices (`vendorid:productid`) through
+/// to the guest.
+///
+/// A profile that declares USB controllers only allows this through one that
+/// is enabled, not isolated and marked `passthrough`; a profile without any
+/// gets a controller of its own for the devices.
+pub fn usb_passthrough_args(profile: &SecurityProfile, ids: &[String]) -> Result<Vec<String>, String> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut args = Vec::new();
+    if profile.has_device(VirtualDeviceType::UsbController) {
+        let allowed = profile.virtual_devices.iter().any(|d| {
+            d.device_type == VirtualDeviceType::UsbController && d.enabled && d.passthrough && !d.isolated
+        });
+        if !allowed {
+            return Err(format!("Security profile '{}' doesn't allow USB passthrough", profile.name));
+        }
+    } else {
+        args.extend(["-device".to_string(), "qemu-xhci,id=xhci".to_string()]);
+    }
+
+    for id in ids {
+        let id: n01d_common::usb::UsbId = id.parse()?;
+        args.extend([
+            "-device".to_string(),
+            format!("usb-host,bus=xhci.0,vendorid=0x{:04x},productid=0x{:04x}", id.vendor, id.product),
+        ]);
+    }
+
+    Ok(args)
+}
+
+/// The control socket of the swtpm for a TPM state dir
+pub fn swtpm_socket(state_dir: &Path) -> PathBuf {
+    state_dir.join("swtpm-sock")
+}
+
+/// A VM's swtpm, stopped when dropped. swtpm daemonizes, so it's tracked
+/// through its pidfile rather than as a child
+pub struct Swtpm {
+    state_dir: PathBuf,
+}
+
+impl Drop for Swtpm {
+    fn drop(&mut self) {
+        let _ = stop_swtpm(&self.state_dir);
+    }
+}
+
+/// Start a swtpm instance for a VM, listening on `swtpm_socket(state_dir)`.
+/// It exits on its own once QEMU drops the connection; until QEMU has
+/// connected, only dropping the returned guard stops it
+pub fn start_swtpm(state_dir: &Path) -> Result<Swtpm, String> {
+    if which::which("swtpm").is_err() {
+        return Err(
+            "swtpm not found - install it to use TPM devices \
+             (apt install swtpm / dnf install swtpm / brew install swtpm)"
+                .to_string(),
+        );
+    }
+
+    fs::create_dir_all(state_dir).map_err(|e| format!("Failed to create TPM state dir: {}", e))?;
+
+    // A previous instance may still hold the socket
+    stop_swtpm(state_dir)?;
+
+    let output = Command::new("swtpm")
+        .arg("socket")
+        .arg("--tpm2")
+        .arg("--tpmstate")
+        .arg(format!("dir={}", state_dir.display()))
+        .arg("--ctrl")
+        .arg(format!("type=unixio,path={}", swtpm_socket(state_dir).display()))
+        .arg("--pid")
+        .arg(format!("file={}", state_dir.join("swtpm.pid").display()))
+        .arg("--daemon")
+        // Exit together with QEMU once it drops the control connection
+        .arg("--terminate")
+        .output()
+        .map_err(|e| format!("Failed to start swtpm: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("swtpm failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+
+    Ok(Swtpm { state_dir: state_dir.to_path_buf() })
+}
+
+/// Stop the swtpm instance running for a TPM state dir, if any
+pub fn stop_swtpm(state_dir: &Path) -> Result<(), String> {
+    let pid_path = state_dir.join("swtpm.pid");
+    if let Some(pid) = n01d_common::process::live_pid(&pid_path) {
+        n01d_common::process::terminate(pid);
+    }
+    if pid_path.exists() {
+        fs::remove_file(&pid_path).map_err(|e| e.to_string())?;
+    }
+
+    let socket = swtpm_socket(state_dir);
+    if socket.exists() {
+        fs::remove_file(&socket).map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Stop swtpm and remove its persisted TPM state
+pub fn clean_swtpm_state(state_dir: &Path) -> Result<(), String> {
+    stop_swtpm(state_dir)?;
+    if state_dir.exists() {
+        fs::remove_dir_all(state_dir).map_err(|e| format!("Failed to remove TPM state: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Name of the AppArmor profile confining a VM's QEMU process
+pub fn apparmor_profile_name(vm_dir: &Path) -> String {
+    let vm_name = vm_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("n01d-qemu-{}", vm_name)
+}
+
+/// AppArmor policy for a VM's QEMU process: its own VM directory (disk,
+/// swtpm socket, logs), `/dev/kvm`, firmware and the display sockets.
+/// Anything not listed is denied by AppArmor's default-deny, so the disk and
+/// ISO outside the VM directory have to be added with `apparmor_allow`
+pub fn generate_apparmor_profile(profile: &SecurityProfile, vm_dir: &Path) -> String {
+    let mut rules = vec![
+        "#include <abstractions/base>".to_string(),
+        "#include <abstractions/fonts>".to_string(),
+        "#include <abstractions/wayland>".to_string(),
+        "#include <abstractions/X>".to_string(),
+        String::new(),
+        "/usr/{,local/}bin/qemu-system-* mr,".to_string(),
+        "/usr/{,local/}share/{qemu,seabios,ipxe,OVMF,AAVMF,edk2}/** r,".to_string(),
+        "/usr/lib{,64}/qemu/** mr,".to_string(),
+        "/usr/lib/@{multiarch}/qemu/** mr,".to_string(),
+        "/dev/kvm rw,".to_string(),
+        "/dev/ptmx rw,".to_string(),
+        "@{PROC}/@{pid}/** r,".to_string(),
+        "/sys/devices/system/cpu/** r,".to_string(),
+        "/sys/devices/system/node/** r,".to_string(),
+        String::new(),
+        format!("{} r,", apparmor_path(vm_dir)),
+        format!("{} rwk,", apparmor_path(&vm_dir.join("**"))),
+    ];
+
+    let audio = profile
+        .virtual_devices
+        .iter()
+        .any(|d| d.device_type == VirtualDeviceType::AudioDevice && d.enabled);
+    if audio {
+        rules.push("#include <abstractions/audio>".to_string());
+    }
+
+    let offline = profile.network_isolation.mode() == IsolationMode::Full
+        || profile
+            .virtual_devices
+            .iter()
+            .any(|d| d.device_type == VirtualDeviceType::NetworkAdapter && !d.enabled);
+    if offline {
+        rules.push("deny network inet,".to_string());
+        rules.push("deny network inet6,".to_string());
+    } else {
+        rules.push("network inet stream,".to_string());
+        rules.push("network inet dgram,".to_string());
+        rules.push("network inet6 stream,".to_string());
+        rules.push("network inet6 dgram,".to_string());
+    }
+
+    let body: Vec<String> = rules
+        .into_iter()
+        .map(|rule| if rule.is_empty() { rule } else { format!("  {}", rule) })
+        .collect();
+
+    format!(
+        "#include <tunables/global>\n\nprofile {} flags=(attach_disconnected) {{\n{}\n}}\n",
+        apparmor_profile_name(vm_dir),
+        body.join("\n")
+    )
+}
+
+/// Add rules for files outside the VM directory, e.g. an imported disk or an ISO
+pub fn apparmor_allow(policy: &str, paths: &[(&Path, &str)]) -> String {
+    let extra: String = paths
+        .iter()
+        .map(|(path, perms)| format!("  {} {},\n", apparmor_path(path), perms))
+        .collect();
+
+    match policy.rfind('}') {
+        Some(end) => format!("{}{}{}", &policy[..end], extra, &policy[end..]),
+        None => policy.to_string(),
+    }
+}
+
+// Paths are quoted so spaces survive; quotes inside would end the string
+fn apparmor_path(path: &Path) -> String {
+    format!("\"{}\"", path.display().to_string().replace('"', "?"))
+}
+
+/// Whether the kernel has AppArmor enabled and its userspace tools are installed
+pub fn apparmor_available() -> bool {
+    Path::new("/sys/kernel/security/apparmor").exists()
+        && which::which("apparmor_parser").is_ok()
+        && which::which("aa-exec").is_ok()
+}
+
+/// Write the policy next to the VM and (re)load it with `apparmor_parser -r`
+pub fn load_apparmor_profile(vm_dir: &Path, policy: &str) -> Result<(), String> {
+    let policy_path = vm_dir.join("apparmor.profile");
+    fs::write(&policy_path, policy).map_err(|e| format!("Failed to write AppArmor profile: {}", e))?;
+
+    let output = Command::new("apparmor_parser")
+        .arg("-r")
+        .arg(&policy_path)
+        .output()
+        .map_err(|e| format!("Failed to run apparmor_parser: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "apparmor_parser failed (loading profiles usually needs root): {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Name of a profile's iptables chain
+fn firewall_chain(profile: &SecurityProfile) -> String {
+    format!("{}{}", firewall::FIREWALL_PREFIX, profile.name)
+}
+
+/// Name of the systemd unit that reloads a saved ruleset at boot
+pub const FIREWALL_RESTORE_UNIT: &str = "n01d-firewall.service";
+
+/// Root-owned copy of the saved ruleset the boot unit loads, so nothing the
+/// user can write decides what runs as root at boot
+const FIREWALL_BOOT_DIR: &str = "/etc/n01d";
+
+fn command_output(program: &str, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The `n01d-*` iptables chains and nftables tables currently loaded, like
+/// `iptables filter n01d-lab` or `nft inet n01d-stealth`
+pub fn firewall_status() -> Vec<String> {
+    let mut active = Vec::new();
+    if let Ok(save) = command_output("iptables-save", &[]) {
+        active.extend(firewall::iptables_chains(&save));
+    }
+    if let Ok(list) = command_output("nft", &["list", "tables"]) {
+        active.extend(firewall::nft_tables(&list));
+    }
+    active.iter().map(ToString::to_string).collect()
+}
+
+/// The nftables half of a ruleset saved at `path`
+fn nft_ruleset_path(path: &Path) -> PathBuf {
+    path.with_extension("nft")
+}
+
+/// Save the currently loaded `n01d-*` firewall rules: iptables chains to
+/// `path` in iptables-restore format and nftables tables next to it with an
+/// `.nft` extension. Returns how many chains and tables were saved
+pub fn export_ruleset(path: &Path) -> Result<usize, String> {
+    let mut saved = 0;
+    let iptables = match which::which("iptables-save") {
+        Ok(_) => firewall::n01d_iptables_section(&command_output("iptables-save", &[])?),
+        Err(_) => String::new(),
+    };
+    saved += iptables.lines().filter(|line| line.starts_with(':')).count();
+
+    // Each table is deleted and recreated on import, as when it's applied
+    let mut nft = String::new();
+    if which::which("nft").is_ok() {
+        for table in firewall::nft_tables(&command_output("nft", &["list", "tables"])?) {
+            let (family, name) = (&table.table, &table.name);
+            nft.push_str(&format!("table {family} {name}\ndelete table {family} {name}\n", family = family, name = name));
+            nft.push_str(&command_output("nft", &["list", "table", family, name])?);
+            saved += 1;
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    fs::write(path, iptables).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    let nft_path = nft_ruleset_path(path);
+    if nft.is_empty() {
+        let _ = fs::remove_file(&nft_path);
+    } else {
+        fs::write(&nft_path, nft).map_err(|e| format!("Failed to write {}: {}", nft_path.display(), e))?;
+    }
+    Ok(saved)
+}
+
+/// Load a ruleset saved by `export_ruleset`, replacing the `n01d-*` chains
+/// and tables it contains and leaving everything else alone. Jumps into the
+/// chains that are already there aren't added a second time
+pub fn import_ruleset(path: &Path) -> Result<(), String> {
+    let iptables = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if !iptables.trim().is_empty() {
+        let current = command_output("iptables-save", &[])?;
+        pipe_to("iptables-restore", &["--noflush"], &firewall::without_loaded_jumps(&iptables, &current))?;
+    }
+    let nft_path = nft_ruleset_path(path);
+    if nft_path.exists() {
+        let nft = fs::read_to_string(&nft_path).map_err(|e| format!("Failed to read {}: {}", nft_path.display(), e))?;
+        pipe_to("nft", &["-f", "-"], &nft)?;
+    }
+    Ok(())
+}
+
+/// A oneshot systemd unit reloading the ruleset in `FIREWALL_BOOT_DIR`
+/// before the network comes up, so isolation holds from boot rather than
+/// from the next VM start. Nothing is loaded yet at that point, so the
+/// jumps restored with `--noflush` can't end up doubled
+pub fn firewall_restore_unit() -> String {
+    let program = |name: &str| {
+        which::which(name)
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|_| format!("/usr/sbin/{}", name))
+    };
+    // A leading '-' lets the unit succeed when that half of the ruleset is absent
+    format!(
+        "[Unit]\n\
+         Description=Restore n01d Machine firewall rules\n\
+         Before=network-pre.target\n\
+         Wants=network-pre.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         RemainAfterExit=yes\n\
+         ExecStart={} --noflush {dir}/firewall.rules\n\
+         ExecStart=-{} -f {dir}/firewall.nft\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        program("iptables-restore"),
+        program("nft"),
+        dir = FIREWALL_BOOT_DIR,
+    )
+}
+
+/// Root shell script installing the ruleset saved at `path` into
+/// `FIREWALL_BOOT_DIR` and enabling `FIREWALL_RESTORE_UNIT`, or disabling
+/// the unit and removing both
+fn firewall_restore_script(path: &Path, enable: bool) -> String {
+    let unit_path = format!("/etc/systemd/system/{}", FIREWALL_RESTORE_UNIT);
+    let quoted = |path: &Path| shell_join(&[path.display().to_string()]);
+    if !enable {
+        return [
+            format!("systemctl disable {} 2>/dev/null", FIREWALL_RESTORE_UNIT),
+            format!("rm -f {} {dir}/firewall.rules {dir}/firewall.nft", unit_path, dir = FIREWALL_BOOT_DIR),
+            "systemctl daemon-reload".to_string(),
+        ]
+        .join("\n");
+    }
+
+    let nft_path = nft_ruleset_path(path);
+    [
+        "set -e".to_string(),
+        format!("install -d -o root -g root -m 755 {}", FIREWALL_BOOT_DIR),
+        format!("install -o root -g root -m 600 {} {}/firewall.rules", quoted(path), FIREWALL_BOOT_DIR),
+        format!(
+            "if [ -f {nft} ]; then install -o root -g root -m 600 {nft} {dir}/firewall.nft; else rm -f {dir}/firewall.nft; fi",
+            nft = quoted(&nft_path),
+            dir = FIREWALL_BOOT_DIR
+        ),
+        format!("cat > {} <<'N01D_UNIT'\n{}N01D_UNIT", unit_path, firewall_restore_unit()),
+        format!("chmod 644 {}", unit_path),
+        "systemctl daemon-reload".to_string(),
+        format!("systemctl enable {}", FIREWALL_RESTORE_UNIT),
+    ]
+    .join("\n")
+}
+
+/// Whether `FIREWALL_RESTORE_UNIT` is installed
+pub fn firewall_restore_enabled() -> bool {
+    Path::new("/etc/systemd/system").join(FIREWALL_RESTORE_UNIT).exists()
+}
+
+/// Install and enable `FIREWALL_RESTORE_UNIT` with a root-owned copy of the
+/// ruleset at `path`, or disable and remove it. Asks for root with pkexec
+pub fn set_firewall_restore(path: &Path, enable: bool) -> Result<(), String> {
+    run_as_root(&firewall_restore_script(path, enable))
+}
+
+/// Join arguments into a shell command line, double-quoting anything with
+/// spaces or shell metacharacters
+fn shell_join(args: &[String]) -> String {
+    const ESCAPED: [char; 4] = ['"', '\\', '$', '`'];
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && !arg.contains(|c: char| c.is_whitespace() || c == '\'' || ESCAPED.contains(&c));
+            if plain {
+                return arg.clone();
+            }
+            let mut quoted = String::from("\"");
+            for c in arg.chars() {
+                if ESCAPED.contains(&c) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Run `program args` with `input` on stdin, failing with its stderr
+fn pipe_to(program: &str, args: &[&str], input: &str) -> Result<(), String> {
+    use std::io::Write;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input.as_bytes())
+            .map_err(|e| format!("Failed to write to {}: {}", program, e))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(())
+}
+
+/// Where a Tor's control port listens on localhost, and the cookie file
+/// to authenticate with if it uses cookie authentication
+#[derive(Debug, Clone, PartialEq)]
+pub struct TorControlAddr {
+    pub port: u16,
+    pub cookie: Option<PathBuf>,
+}
+
+/// Get new Tor circuit
+pub fn new_tor_circuit(control: &TorControlAddr) -> Result<(), String> {
+    signal_newnym(control, false)
+}
+
+/// Connection attempts before the control port is reported unreachable.
+/// Tor takes a moment to open it after starting, so refused connections
+/// are retried, waiting CONTROL_RETRY_DELAY and doubling it each time
+const CONTROL_CONNECT_ATTEMPTS: u32 = 5;
+const CONTROL_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// How long to wait for Tor to answer a command
+const CONTROL_REPLY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The connection identity rotation keeps open, shared so a "new identity"
+/// request made meanwhile reuses it instead of connecting again
+static SHARED_CONTROL: std::sync::Mutex<Option<(u16, TorControl)>> = std::sync::Mutex::new(None);
+
+/// Send NEWNYM over the shared connection if it's to this port, otherwise
+/// over a new one, which replaces the shared one when `keep` is set
+fn signal_newnym(addr: &TorControlAddr, keep: bool) -> Result<(), String> {
+    let mut shared = SHARED_CONTROL.lock().unwrap();
+    if let Some((_, control)) = shared.as_mut().filter(|(port, _)| *port == addr.port) {
+        if control.command("SIGNAL NEWNYM").is_ok() {
+            return Ok(());
+        }
+        // The connection was lost; start over on a new one
+        *shared = None;
+    }
+
+    let mut control = TorControl::connect(addr)?;
+    control.command("SIGNAL NEWNYM")?;
+    if keep {
+        *shared = Some((addr.port, control));
+    }
+    Ok(())
+}
+
+/// An authenticated connection to Tor's control port
+struct TorControl {
+    stream: std::io::BufReader<std::net::TcpStream>,
+}
+
+impl TorControl {
+    fn connect(addr: &TorControlAddr) -> Result<Self, String> {
+        let control_port = addr.port;
+        let mut delay = CONTROL_RETRY_DELAY;
+        let mut attempt = 1;
+        let stream = loop {
+            match std::net::TcpStream::connect(("127.0.0.1", control_port)) {
+                Ok(stream) => break stream,
+                Err(_) if attempt < CONTROL_CONNECT_ATTEMPTS => {
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "Tor control port {} not reachable; is ControlPort set in torrc? ({})",
+                        control_port, e
+                    ))
+                }
+            }
+        };
+        stream
+            .set_read_timeout(Some(CONTROL_REPLY_TIMEOUT))
+            .map_err(|e| format!("Failed to set up the Tor control connection: {}", e))?;
+
+        let authenticate = match &addr.cookie {
+            Some(path) => {
+                let cookie = fs::read(path).map_err(|e| format!("Failed to read Tor's auth cookie: {}", e))?;
+                let hex: String = cookie.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("AUTHENTICATE {}", hex)
+            }
+            None => "AUTHENTICATE".to_string(),
+        };
+        let mut control = TorControl { stream: std::io::BufReader::new(stream) };
+        control
+            .command(&authenticate)
+            .map_err(|e| format!("Tor control authentication failed: {}", e))?;
+        Ok(control)
+    }
+
+    /// Send one command and wait for its reply, failing on anything but 250
+    fn command(&mut self, command: &str) -> Result<(), String> {
+        use std::io::{BufRead, Write};
+
+        self.stream
+            .get_mut()
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .map_err(|e| format!("Failed to send '{}': {}", command, e))?;
+
+        // Replies end with a "NNN " line; "NNN-" and "NNN+" lines continue it
+        loop {
+            let mut line = String::new();
+            let n = self.stream
+                .read_line(&mut line)
+                .map_err(|e| format!("Failed to read Tor reply: {}", e))?;
+            if n == 0 {
+                return Err("Tor closed the control connection".to_string());
+            }
+            if line.len() >= 4 && line.as_bytes()[3] == b' ' {
+                return if line.starts_with("250") {
+                    Ok(())
+                } else {
+                    Err(format!("Tor rejected '{}': {}", command, line.trim_end()))
+                };
+            }
+        }
+    }
+}
+
+/// Background NEWNYM signalling started by `start_identity_rotation`.
+/// Stopping (or dropping) it ends the thread and waits for it to exit
+pub struct IdentityRotator {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl IdentityRotator {
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        // Dropping the sender wakes the thread out of its wait
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for IdentityRotator {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Ask Tor for a new identity every `interval` on one authenticated control
+/// connection, reconnecting only if that connection is lost. The connection
+/// is shared with `new_tor_circuit` while rotation runs
+pub fn start_identity_rotation(control: TorControlAddr, interval: std::time::Duration) -> IdentityRotator {
+    use std::sync::mpsc::{self, RecvTimeoutError};
+
+    let (stop, stopped) = mpsc::channel::<()>();
+    let thread = std::thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            if let Err(e) = signal_newnym(&control, true) {
+                eprintln!("[n01d] Tor identity rotation: {}", e);
+            }
+        }
+
+        let shared = SHARED_CONTROL.lock().unwrap().take_if(|(port, _)| *port == control.port);
+        if let Some((_, mut control)) = shared {
+            let _ = control.command("QUIT");
+        }
+    });
+
+    IdentityRotator { stop: Some(stop), thread: Some(thread) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_profiles() {
+        let presets = SecurityManager::get_preset_profiles();
+        assert!(!presets.is_empty());
+        assert!(presets.iter().any(|(name, _, _)| *name == "paranoid"));
+    }
+
+    #[test]
+    fn test_qemu_args_generation() {
+        let manager = SecurityManager::new(PathBuf::from("/tmp"));
+        let profile = SecurityProfile {
+            name: "test".to_string(),
+            sandbox_enabled: Some(true),
+            network_isolation: NetworkIsolation {
+                mode: Some(IsolationMode::Full),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let args = manager.generate_qemu_security_args(&profile, "test").unwrap();
+        assert!(args.contains(&"-sandbox".to_string()));
         assert!(args.contains(&"on".to_string()));
         assert!(args.contains(&"-nic".to_string()));
         assert!(args.contains(&"none".to_string()));
     }
+
+    #[test]
+    fn test_internal_networks_are_separate() {
+        let pentest = internal_mcast_endpoint("pentest-net");
+        assert_eq!(pentest, internal_mcast_endpoint("pentest-net"));
+        assert_ne!(pentest.0, internal_mcast_endpoint("lab-net").0);
+        assert_eq!(pentest.0.octets()[0], 230);
+        assert!(pentest.1 >= 1024);
+
+        let manager = SecurityManager::new(PathBuf::from("/tmp"));
+        let mut profile = profile_with_mode(IsolationMode::Internal);
+        profile.network_isolation.isolated_network_id = Some("pentest-net".to_string());
+        let args = manager.generate_qemu_security_args(&profile, "test").unwrap();
+        let netdev = format!("socket,id=pentest-net,mcast={}:{},localaddr=127.0.0.1", pentest.0, pentest.1);
+        assert!(args.contains(&netdev));
+    }
+
+    #[test]
+    fn test_transparent_proxy_rules() {
+        let mut manager = SecurityManager::new(PathBuf::from("/tmp"));
+        manager.tor_config.trans_port = 9140;
+        manager.tor_config.dns_port = 5400;
+
+        let tap = TorTap::for_vm("test");
+        assert_eq!(tap, TorTap::for_vm("test"));
+        assert!(tap.interface.len() <= 15);
+        assert_eq!(u32::from(tap.guest), u32::from(tap.host) + 1);
+        assert_eq!(tap.host.octets()[..2], [10, 152]);
+
+        let torrc = manager.generate_torrc(&profile_with_mode(IsolationMode::TorOnly), "test").unwrap();
+        assert!(torrc.contains(&format!("TransPort {}:9140\n", tap.host)));
+        assert!(torrc.contains(&format!("DNSPort {}:5400\n", tap.host)));
+        assert!(torrc.contains("SocksPort 0\n"));
+        assert!(torrc.contains("DataDirectory /tmp/vms/test/tor/data\n"));
+
+        // Without a tap there's no address to listen on
+        let torrc = manager.generate_torrc(&profile_with_mode(IsolationMode::VpnOnly), "test").unwrap();
+        assert!(!torrc.contains("TransPort"));
+
+        let setup = manager.generate_transparent_proxy_rules(&tap).unwrap();
+        let iface = &tap.interface;
+        assert!(setup.contains(&format!("iptables -t nat -A PREROUTING -i {} -p tcp --syn -j DNAT --to-destination {}:9140", iface, tap.host)));
+        assert!(setup.contains(&format!("iptables -t nat -A PREROUTING -i {} -p udp --dport 53 -j DNAT --to-destination {}:5400", iface, tap.host)));
+        assert!(setup.contains(&format!("ip6tables -t filter -A FORWARD -i {} -j DROP", iface)));
+        assert!(setup.iter().all(|rule| rule.contains(iface.as_str())));
+
+        // Teardown deletes exactly what setup added, in reverse
+        let mut teardown = manager.generate_transparent_proxy_teardown(&tap).unwrap();
+        teardown.reverse();
+        let undone: Vec<String> = teardown.iter().map(|rule| rule.replace(" -D ", " -A ")).collect();
+        assert_eq!(undone, setup);
+
+        let bad = TorTap { interface: "../../x".to_string(), ..tap.clone() };
+        assert!(manager.generate_transparent_proxy_rules(&bad).is_err());
+        assert!(manager.tor_network_up_script(&bad, 1000).is_err());
+        let script = manager.tor_network_up_script(&tap, 1000).unwrap();
+        assert!(script.contains(&format!("ip tuntap add dev {} mode tap user 1000", iface)));
+    }
+
+    #[test]
+    fn test_identity_rotation_reuses_connection() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::time::Duration;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // A fake control port: one connection, answering every command
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut commands = Vec::new();
+            for line in BufReader::new(stream).lines() {
+                let line = line.unwrap();
+                writer.write_all(b"250 OK\r\n").unwrap();
+                let quit = line == "QUIT";
+                commands.push(line);
+                if quit {
+                    break;
+                }
+            }
+            commands
+        });
+
+        let rotator = start_identity_rotation(TorControlAddr { port, cookie: None }, Duration::from_millis(20));
+        std::thread::sleep(Duration::from_millis(150));
+        rotator.stop();
+
+        let commands = server.join().unwrap();
+        assert_eq!(commands.first().map(String::as_str), Some("AUTHENTICATE"));
+        assert_eq!(commands.last().map(String::as_str), Some("QUIT"));
+        let signals = commands.iter().filter(|c| *c == "SIGNAL NEWNYM").count();
+        assert!(signals >= 2, "{:?}", commands);
+        assert_eq!(signals + 2, commands.len());
+    }
+
+    #[test]
+    fn test_control_connect_waits_for_tor() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+        use std::time::Duration;
+
+        // Nothing listens on the port until "Tor" comes up a little later
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            let auth = lines.next().unwrap().unwrap();
+            writer.write_all(b"250 OK\r\n").unwrap();
+            let signal = lines.next().unwrap().unwrap();
+            writer.write_all(b"250 OK\r\n").unwrap();
+            (auth, signal)
+        });
+
+        let cookie = std::env::temp_dir().join(format!("n01d-cookie-{}", port));
+        fs::write(&cookie, [0x01, 0xab]).unwrap();
+        new_tor_circuit(&TorControlAddr { port, cookie: Some(cookie.clone()) }).unwrap();
+        let _ = fs::remove_file(&cookie);
+        assert_eq!(server.join().unwrap(), ("AUTHENTICATE 01ab".to_string(), "SIGNAL NEWNYM".to_string()));
+        assert_eq!(parse_control_port_file("PORT=127.0.0.1:9151\n"), Some(9151));
+    }
+
+    #[test]
+    fn test_ssh_ports_are_stable_per_vm() {
+        let manager = SecurityManager::new(PathBuf::from("/tmp"));
+        let alpha = manager.allocate_ssh_port("alpha").unwrap();
+        assert!(SSH_PORT_RANGE.contains(&alpha));
+        assert_eq!(manager.allocate_ssh_port("alpha").unwrap(), alpha);
+
+        // Another VM already forwarding from that port pushes alpha along
+        let manager = manager.with_ssh_ports(HashMap::from([("beta".to_string(), alpha)]));
+        assert_ne!(manager.allocate_ssh_port("alpha").unwrap(), alpha);
+        assert_eq!(manager.ssh_port("beta"), alpha);
+        assert_eq!(manager.ssh_port("gamma"), DEFAULT_SSH_PORT);
+    }
+
+    #[test]
+    fn test_per_vm_socks_ports() {
+        let mut manager = SecurityManager::new(PathBuf::from("/tmp"));
+        let first = manager.allocate_socks_port().unwrap();
+        manager = manager.with_socks_ports(HashMap::from([("alpha".to_string(), first)]));
+        let second = manager.allocate_socks_port().unwrap();
+        manager = manager.with_socks_ports(HashMap::from([("beta".to_string(), second)]));
+
+        // 9051 is the control port
+        assert_eq!((first, second), (9052, 9053));
+
+        let socks_line = |vm: &str| {
+            manager
+                .generate_torrc(&profile_with_mode(IsolationMode::VpnOnly), vm)
+                .unwrap()
+                .lines()
+                .find(|line| line.contains("IsolateClientAddr"))
+                .map(str::to_string)
+        };
+        assert_eq!(socks_line("alpha").as_deref(), Some("SocksPort 127.0.0.1:9052 IsolateClientAddr IsolateDestAddr"));
+        assert_eq!(socks_line("beta").as_deref(), Some("SocksPort 127.0.0.1:9053 IsolateClientAddr IsolateDestAddr"));
+
+        let args = manager.generate_qemu_security_args(&profile_with_mode(IsolationMode::TorOnly), "beta").unwrap();
+        let netdev = format!("tap,id=tornet,ifname={},script=no,downscript=no", TorTap::for_vm("beta").interface);
+        assert!(args.contains(&netdev));
+    }
+
+    #[test]
+    fn test_tor_node_validation() {
+        let entries = |list: &[&str]| list.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            normalize_tor_nodes(&entries(&["{US}", "de", " {ch} ", "$0123456789ABCDEF0123456789abcdef01234567~relay", "10.0.0.0/8", "myRelay01"])).unwrap(),
+            entries(&["{us}", "{de}", "{ch}", "$0123456789ABCDEF0123456789abcdef01234567~relay", "10.0.0.0/8", "myRelay01"])
+        );
+        assert_eq!(
+            normalize_tor_nodes(&entries(&["{USA}", "{xx}", "{us}", "$abc", "10.0.0.0/33", "bad-name"])).unwrap_err(),
+            entries(&["{USA}", "{xx}", "$abc", "10.0.0.0/33", "bad-name"])
+        );
+
+        let mut manager = SecurityManager::new(PathBuf::from("/tmp"));
+        manager.tor_config.exit_nodes = Some(entries(&["us", "{de}"]));
+        assert!(manager.generate_torrc(&profile_with_mode(IsolationMode::TorOnly), "test").unwrap().contains("ExitNodes {us},{de}\n"));
+
+        manager.tor_config.exclude_exit_nodes = Some(entries(&["{USA}"]));
+        let err = manager.generate_torrc(&profile_with_mode(IsolationMode::TorOnly), "test").unwrap_err();
+        assert!(err.contains("ExcludeExitNodes: {USA}"), "{}", err);
+    }
+
+    #[test]
+    fn test_obfs4_bridges() {
+        let bridge = "obfs4 192.0.2.10:443 0123456789ABCDEF0123456789ABCDEF01234567 cert=ssH+9rP8dG2NLDN2XuFw63hIO/9MNNinLmxQDpVa+7kTOa9/m+tGWT1SmSYpQ9uTBGa6Hw iat-mode=0";
+        assert!(validate_obfs4_bridge(bridge).is_ok());
+        assert!(validate_obfs4_bridge(&bridge.replace(" iat-mode=0", "")).is_err());
+        assert!(validate_obfs4_bridge(&bridge.replace("iat-mode=0", "iat-mode=3")).is_err());
+        assert!(validate_obfs4_bridge(&bridge.replace("cert=", "crt=")).is_err());
+        assert!(validate_obfs4_bridge(&bridge.replace("192.0.2.10:443", "192.0.2.10")).is_err());
+        assert!(validate_obfs4_bridge(&bridge.replace("0123456789ABCDEF", "XYZ")).is_err());
+
+        let bridges = vec![bridge.to_string()];
+        let config = bridge_config(&bridges, Some(Path::new("/usr/bin/lyrebird"))).unwrap();
+        assert!(config.contains("ClientTransportPlugin obfs4 exec /usr/bin/lyrebird\n"));
+        assert!(config.contains(&format!("Bridge {}\n", bridge)));
+
+        let err = bridge_config(&bridges, None).unwrap_err();
+        assert!(err.contains("lyrebird"), "{}", err);
+
+        // Plain bridges don't need a transport
+        let plain = vec!["192.0.2.20:9001 0123456789ABCDEF0123456789ABCDEF01234567".to_string()];
+        assert!(!bridge_config(&plain, None).unwrap().contains("ClientTransportPlugin"));
+    }
+
+    #[test]
+    fn test_shadowsocks_proxy() {
+        let mut profile = profile_with_mode(IsolationMode::None);
+        let mut proxy = ProxyConfig {
+            proxy_type: ProxyType::Shadowsocks,
+            host: "203.0.113.5".to_string(),
+            port: 8388,
+            username: None,
+            password: Some("secret".to_string()),
+            chain: vec![ProxyChainEntry {
+                proxy_type: ProxyType::Socks5,
+                host: "198.51.100.7".to_string(),
+                port: 1080,
+            }],
+            cipher: Some("chacha20-ietf-poly1305".to_string()),
+            local_port: Some(1090),
+        };
+        profile.proxy_config = Some(proxy.clone());
+        assert!(profile.validate().is_ok());
+
+        // ss-local makes the first hop, to the Shadowsocks server
+        let conf = generate_proxychains_conf(&proxy, Some(1090)).unwrap();
+        assert!(conf.ends_with("[ProxyList]\nsocks5 127.0.0.1 1090\nsocks5 198.51.100.7 1080\n"), "{}", conf);
+        assert!(!conf.contains("secret"));
+        assert!(generate_proxychains_conf(&proxy, None).is_err());
+
+        proxy.cipher = Some("rot13".to_string());
+        profile.proxy_config = Some(proxy);
+        let problems = profile.validate().unwrap_err();
+        assert!(problems.iter().any(|p| p.contains("rot13")));
+    }
+
+    #[test]
+    fn test_split_tunnel_routes() {
+        let mut vpn = VpnConfig {
+            provider: VpnProvider::WireGuard,
+            config_file: None,
+            server: Some("vpn.corp.example".to_string()),
+            port: 51820,
+            protocol: VpnProtocol::UDP,
+            username: None,
+            kill_switch: false,
+            dns_leak_protection: true,
+            split_tunnel_routes: Vec::new(),
+        };
+        assert!(SecurityManager::generate_wireguard_config(&vpn).contains("AllowedIPs = 0.0.0.0/0\n"));
+        assert_eq!(SecurityManager::generate_openvpn_routes(&vpn).unwrap(), "");
+
+        vpn.split_tunnel_routes = vec!["10.20.0.0/16".to_string(), "192.168.5.0/24".to_string(), "fd00::/8".to_string()];
+        assert!(SecurityManager::generate_wireguard_config(&vpn)
+            .contains("AllowedIPs = 10.20.0.0/16, 192.168.5.0/24, fd00::/8\n"));
+        assert_eq!(
+            SecurityManager::generate_openvpn_routes(&vpn).unwrap(),
+            "route-nopull\nroute 10.20.0.0 255.255.0.0\nroute 192.168.5.0 255.255.255.0\nroute-ipv6 fd00::/8\n"
+        );
+
+        vpn.split_tunnel_routes.push("10.0.0.0/40".to_string());
+        assert!(SecurityManager::generate_openvpn_routes(&vpn).is_err());
+    }
+
+    #[test]
+    fn test_wireguard_config_qr_is_a_png() {
+        let config = "[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+Address = 10.0.0.2/24
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+Endpoint = vpn.corp.example:51820
+AllowedIPs = 10.20.0.0/16
+";
+        let url = wireguard_config_qr(config).unwrap();
+        // base64 of the PNG signature
+        assert!(url.starts_with("data:image/png;base64,iVBORw0KGgo"));
+        assert!(wireguard_config_qr(&"x".repeat(8000)).is_err());
+    }
+
+    #[test]
+    fn test_firewall_restore_installs_under_etc() {
+        let unit = firewall_restore_unit();
+        assert!(unit.contains(" --noflush /etc/n01d/firewall.rules\n"));
+        assert!(unit.contains(" -f /etc/n01d/firewall.nft\n"));
+        assert!(unit.contains("Before=network-pre.target\n"));
+
+        let script = firewall_restore_script(Path::new("/home/me/.config/n01d/security/firewall.rules"), true);
+        assert!(script.contains("install -o root -g root -m 600 /home/me/.config/n01d/security/firewall.rules /etc/n01d/firewall.rules\n"));
+        assert!(script.contains("if [ -f /home/me/.config/n01d/security/firewall.nft ]; then"));
+        assert!(script.ends_with("systemctl enable n01d-firewall.service"));
+
+        let script = firewall_restore_script(Path::new("/unused"), false);
+        assert!(script.contains("rm -f /etc/systemd/system/n01d-firewall.service /etc/n01d/firewall.rules /etc/n01d/firewall.nft"));
+    }
+
+    #[test]
+    fn test_tpm_qemu_args() {
+        let args = tpm_qemu_args(Path::new("/tmp/vm/tpm/swtpm-sock"));
+        assert_eq!(
+            args,
+            vec![
+                "-chardev",
+                "socket,id=chrtpm,path=/tmp/vm/tpm/swtpm-sock",
+                "-tpmdev",
+                "emulator,id=tpm0,chardev=chrtpm",
+                "-device",
+                "tpm-tis,tpmdev=tpm0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tpm_args_dont_start_swtpm() {
+        let dir = std::env::temp_dir().join(format!("n01d-tpm-test-{}", std::process::id()));
+        let manager = SecurityManager::new(dir.clone());
+        let profile = SecurityProfile {
+            virtual_devices: vec![device(VirtualDeviceType::Tpm, "tpm", true)],
+            ..Default::default()
+        };
+
+        let args = manager.generate_qemu_security_args(&profile, "lab").unwrap();
+        let chardev = format!("socket,id=chrtpm,path={}", swtpm_socket(&manager.tpm_dir("lab")).display());
+        assert!(args.contains(&chardev));
+        // Starting swtpm is left to the launch path, which holds the guard
+        assert!(!dir.exists());
+    }
+
+    fn device(device_type: VirtualDeviceType, name: &str, enabled: bool) -> VirtualDevice {
+        VirtualDevice {
+            device_type,
+            name: name.to_string(),
+            enabled,
+            passthrough: false,
+            isolated: true,
+            target: None,
+        }
+    }
+
+    #[test]
+    fn test_virtual_device_args() {
+        let manager = SecurityManager::new(PathBuf::from("/tmp"));
+        let profile = SecurityProfile {
+            name: "devices".to_string(),
+            virtual_devices: vec![
+                device(VirtualDeviceType::AudioDevice, "snd0", false),
+                device(VirtualDeviceType::SerialPort, "serial0", true),
+                device(VirtualDeviceType::UsbController, "usb", true),
+                device(VirtualDeviceType::StorageController, "scsi0", true),
+            ],
+            ..Default::default()
+        };
+
+        let args = manager.generate_qemu_security_args(&profile, "test").unwrap();
+        assert!(!args.contains(&"-audiodev".to_string()));
+        assert!(args.windows(2).any(|w| w == ["-serial", "pty"]));
+        assert!(args.contains(&"qemu-xhci,id=xhci".to_string()));
+        assert!(!args.iter().any(|a| a.starts_with("usb-host")));
+        assert!(args.contains(&"virtio-scsi-pci,id=scsi0".to_string()));
+    }
+
+    #[test]
+    fn test_usb_passthrough_args() {
+        let ids = vec!["046d:c52b".to_string()];
+        let host_device = "usb-host,bus=xhci.0,vendorid=0x046d,productid=0xc52b".to_string();
+
+        // No controller in the profile: passthrough brings its own
+        let args = usb_passthrough_args(&SecurityProfile::default(), &ids).unwrap();
+        assert_eq!(args, ["-device", "qemu-xhci,id=xhci", "-device", &host_device]);
+
+        let mut usb = device(VirtualDeviceType::UsbController, "usb", true);
+        let mut profile = SecurityProfile {
+            name: "usb".to_string(),
+            virtual_devices: vec![usb.clone()],
+            ..Default::default()
+        };
+        assert!(usb_passthrough_args(&profile, &ids).is_err());
+        assert!(usb_passthrough_args(&profile, &[]).unwrap().is_empty());
+
+        usb.passthrough = true;
+        usb.isolated = false;
+        profile.virtual_devices = vec![usb];
+        assert_eq!(usb_passthrough_args(&profile, &ids).unwrap(), ["-device", &host_device]);
+        assert!(usb_passthrough_args(&profile, &["046d".to_string()]).is_err());
+    }
+
+    fn serial_args(target: &str) -> Result<Vec<String>, String> {
+        let mut serial = device(VirtualDeviceType::SerialPort, "serial0", true);
+        serial.target = Some(target.to_string());
+        SecurityManager::virtual_device_args(&[serial])
+    }
+
+    #[test]
+    fn test_serial_target_pty() {
+        assert_eq!(serial_args("pty").unwrap(), ["-serial", "pty"]);
+        assert_eq!(SerialTarget::parse("pty").unwrap(), SerialTarget::Pty);
+    }
+
+    #[test]
+    fn test_serial_target_file() {
+        let log = std::env::temp_dir().join("guest,console.log");
+        assert_eq!(
+            serial_args(&format!("file:{}", log.display())).unwrap(),
+            [
+                "-chardev".to_string(),
+                format!("file,id=serial0,path={}", log.display().to_string().replace(',', ",,")),
+                "-serial".to_string(),
+                "chardev:serial0".to_string(),
+            ]
+        );
+        assert!(serial_args("file:relative.log").is_err());
+
+        let mut serial = device(VirtualDeviceType::SerialPort, "serial 0", true);
+        serial.target = Some(format!("file:{}", log.display()));
+        let profile = SecurityProfile { name: "serial".to_string(), virtual_devices: vec![serial], ..Default::default() };
+        assert!(profile.validate().is_err());
+        assert!(serial_args("file:/nonexistent-n01d-dir/console.log").is_err());
+    }
+
+    #[test]
+    fn test_serial_target_tcp() {
+        assert_eq!(
+            serial_args("tcp:127.0.0.1:4555").unwrap(),
+            ["-serial", "tcp:127.0.0.1:4555,server=on,wait=off"]
+        );
+        assert_eq!(
+            SerialTarget::parse("tcp:[::1]:4555").unwrap(),
+            SerialTarget::Tcp { host: "[::1]".to_string(), port: 4555 }
+        );
+        for bad in ["tcp:127.0.0.1", "tcp::4555", "tcp:host:0", "tcp:host:99999", "telnet:host:23"] {
+            assert!(serial_args(bad).is_err(), "{}", bad);
+        }
+
+        let mut serial = device(VirtualDeviceType::SerialPort, "serial0", true);
+        serial.target = Some("tcp:host".to_string());
+        let profile = SecurityProfile {
+            name: "serial".to_string(),
+            virtual_devices: vec![serial],
+            ..Default::default()
+        };
+        assert!(profile.validate().is_err());
+    }
+
+    #[test]
+    fn test_disabled_network_adapter_removes_nic() {
+        let manager = SecurityManager::new(PathBuf::from("/tmp"));
+        let profile = SecurityProfile {
+            name: "no-nic".to_string(),
+            network_isolation: NetworkIsolation {
+                mode: Some(IsolationMode::VpnOnly),
+                ..Default::default()
+            },
+            virtual_devices: vec![device(VirtualDeviceType::NetworkAdapter, "net0", false)],
+            ..Default::default()
+        };
+
+        let args = manager.generate_qemu_security_args(&profile, "test").unwrap();
+        assert!(args.windows(2).any(|w| w == ["-nic", "none"]));
+        assert!(!args.contains(&"-netdev".to_string()));
+    }
+
+    #[test]
+    fn test_profile_export_import_roundtrip() {
+        let (_, _, profile) = SecurityManager::get_preset_profiles()
+            .into_iter()
+            .find(|(name, _, _)| *name == "stealth")
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("n01d-profile-{}.json", std::process::id()));
+        profile.export_to_file(&path).unwrap();
+        let imported = SecurityProfile::import_from_file(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(
+            serde_json::to_value(&profile).unwrap(),
+            serde_json::to_value(&imported).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_profile_inheritance() {
+        let mut custom = HashMap::new();
+        custom.insert(
+            "paranoid-v2".to_string(),
+            SecurityProfile {
+                name: "paranoid-v2".to_string(),
+                firewall_rules: vec![FirewallRule {
+                    action: FirewallAction::Drop,
+                    direction: TrafficDirection::Inbound,
+                    protocol: Some("udp".to_string()),
+                    source: None,
+                    destination: None,
+                    port: None,
+                    port_range: None,
+                    description: "Drop inbound UDP".to_string(),
+                }],
+                extends: Some("paranoid".to_string()),
+                sandbox_enabled: Some(false),
+                ..Default::default()
+            },
+        );
+        let manager = SecurityManager::new(PathBuf::from("/tmp")).with_custom_profiles(custom);
+
+        let resolved = manager.resolve_profile("paranoid-v2").unwrap();
+        assert_eq!(resolved.name, "paranoid-v2");
+        assert_eq!(resolved.network_isolation.mode(), IsolationMode::TorOnly);
+        assert!(resolved.uses_tor());
+        // An explicit false in the child overrides the parent
+        assert!(!resolved.sandboxed());
+        assert_eq!(resolved.firewall_rules.len(), 3);
+        assert_eq!(resolved.firewall_rules[2].description, "Drop inbound UDP");
+        assert!(resolved.extends.is_none());
+    }
+
+    #[test]
+    fn test_child_profile_network_isolation_is_merged_per_setting() {
+        let mut custom = HashMap::new();
+        let child = |name: &str, network_isolation| SecurityProfile {
+            name: name.to_string(),
+            network_isolation,
+            extends: Some("paranoid".to_string()),
+            ..Default::default()
+        };
+        custom.insert(
+            "new-mac".to_string(),
+            child("new-mac", NetworkIsolation { mac_address: Some("52:54:00:12:34:56".to_string()), ..Default::default() }),
+        );
+        custom.insert(
+            "open".to_string(),
+            child("open", NetworkIsolation { mode: Some(IsolationMode::None), ..Default::default() }),
+        );
+        let manager = SecurityManager::new(PathBuf::from("/tmp")).with_custom_profiles(custom);
+
+        // Only the MAC changes; the rest comes from the parent
+        let new_mac = manager.resolve_profile("new-mac").unwrap().network_isolation;
+        assert_eq!(new_mac.mode(), IsolationMode::TorOnly);
+        assert!(new_mac.allows_internet());
+        assert_eq!(new_mac.mac_address.as_deref(), Some("52:54:00:12:34:56"));
+
+        // Setting the default mode explicitly still overrides the parent's
+        let open = manager.resolve_profile("open").unwrap().network_isolation;
+        assert_eq!(open.mode(), IsolationMode::None);
+        assert_eq!(open.mac_address.as_deref(), Some("52:54:00:00:00:01"));
+    }
+
+    #[test]
+    fn test_resolve_profile_cycle() {
+        let mut custom = HashMap::new();
+        for (name, parent) in [("a", "b"), ("b", "a")] {
+            custom.insert(
+                name.to_string(),
+                SecurityProfile {
+                    name: name.to_string(),
+                    extends: Some(parent.to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+        let manager = SecurityManager::new(PathBuf::from("/tmp")).with_custom_profiles(custom);
+
+        let err = manager.resolve_profile("a").unwrap_err();
+        assert!(err.contains("cycle"));
+    }
+
+    fn profile_with_mode(mode: IsolationMode) -> SecurityProfile {
+        SecurityProfile {
+            name: "check".to_string(),
+            network_isolation: NetworkIsolation {
+                mode: Some(mode),
+                allow_internet: Some(true),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn allow_rule(port: Option<u16>, port_range: Option<(u16, u16)>) -> FirewallRule {
+        FirewallRule {
+            action: FirewallAction::Allow,
+            direction: TrafficDirection::Outbound,
+            protocol: Some("tcp".to_string()),
+            source: None,
+            destination: None,
+            port,
+            port_range,
+            description: "rule".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate_presets() {
+        for (name, _, profile) in SecurityManager::get_preset_profiles() {
+            assert!(profile.validate().is_ok(), "preset '{}' should be valid", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_tor_with_full_isolation() {
+        let mut profile = profile_with_mode(IsolationMode::Full);
+        profile.tor_enabled = Some(true);
+        let problems = profile.validate().unwrap_err();
+        assert!(problems.iter().any(|p| p.contains("Tor")));
+    }
+
+    #[test]
+    fn test_validate_vpn_with_full_isolation() {
+        let mut profile = profile_with_mode(IsolationMode::Full);
+        profile.vpn_config = Some(VpnConfig {
+            provider: VpnProvider::WireGuard,
+            config_file: None,
+            server: None,
+            port: 51820,
+            protocol: VpnProtocol::UDP,
+            username: None,
+            kill_switch: true,
+            dns_leak_protection: true,
+            split_tunnel_routes: Vec::new(),
+        });
+        let problems = profile.validate().unwrap_err();
+        assert!(problems.iter().any(|p| p.contains("VPN")));
+    }
+
+    #[test]
+    fn test_validate_mode_without_backend() {
+        assert!(profile_with_mode(IsolationMode::TorOnly).validate().is_err());
+        assert!(profile_with_mode(IsolationMode::VpnOnly).validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_empty_proxy() {
+        let mut profile = profile_with_mode(IsolationMode::None);
+        profile.proxy_config = Some(ProxyConfig {
+            proxy_type: ProxyType::Socks5,
+            host: String::new(),
+            port: 1080,
+            username: None,
+            password: None,
+            chain: Vec::new(),
+            cipher: None,
+            local_port: None,
+        });
+        let problems = profile.validate().unwrap_err();
+        assert!(problems.iter().any(|p| p.contains("Proxy")));
+    }
+
+    #[test]
+    fn test_validate_port_range() {
+        let mut profile = profile_with_mode(IsolationMode::None);
+        profile.firewall_rules = vec![allow_rule(None, Some((9000, 8000)))];
+        assert!(profile.validate().is_err());
+
+        profile.firewall_rules = vec![allow_rule(Some(80), Some((8000, 9000)))];
+        assert!(profile.validate().is_err());
+
+        profile.firewall_rules = vec![allow_rule(None, Some((8000, 9000)))];
+        assert!(profile.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_profile_name() {
+        let mut profile = profile_with_mode(IsolationMode::None);
+        for name in ["lab_2", "paranoid-v2", &"a".repeat(MAX_PROFILE_NAME_LEN)] {
+            profile.name = name.to_string();
+            assert!(profile.validate().is_ok(), "'{}' should be valid", name);
+        }
+        for name in ["", "my profile", "x\n-A INPUT -j ACCEPT", "../lab", &"a".repeat(MAX_PROFILE_NAME_LEN + 1)] {
+            profile.name = name.to_string();
+            let problems = profile.validate().unwrap_err();
+            assert!(problems.iter().any(|p| p.contains("Profile name")), "'{}' should be invalid", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_firewall_protocol() {
+        let mut profile = profile_with_mode(IsolationMode::None);
+        let mut rule = allow_rule(Some(53), None);
+        for protocol in FIREWALL_PROTOCOLS {
+            rule.protocol = Some(protocol.to_string());
+            profile.firewall_rules = vec![rule.clone()];
+            assert!(profile.validate().is_ok(), "'{}' should be valid", protocol);
+        }
+        rule.protocol = Some("tcp -j ACCEPT".to_string());
+        profile.firewall_rules = vec![rule];
+        let problems = profile.validate().unwrap_err();
+        assert!(problems.iter().any(|p| p.contains("unknown protocol")));
+    }
+
+    #[test]
+    fn test_validate_firewall_addresses() {
+        let mut profile = profile_with_mode(IsolationMode::None);
+        let mut rule = allow_rule(Some(443), None);
+        rule.source = Some("10.0.0.0/8".to_string());
+        rule.destination = Some("2001:db8::1".to_string());
+        profile.firewall_rules = vec![rule.clone()];
+        assert!(profile.validate().is_ok());
+
+        for address in ["example.com", "10.0.0.0/33", "10.0.0.0/8\n-A INPUT -j ACCEPT", "10.0.0.1 "] {
+            rule.destination = Some(address.to_string());
+            profile.firewall_rules = vec![rule.clone()];
+            let problems = profile.validate().unwrap_err();
+            assert!(problems.iter().any(|p| p.contains("not an IP address")), "'{}' should be invalid", address);
+        }
+    }
+
+    #[test]
+    fn test_validate_firewall_description() {
+        let mut profile = profile_with_mode(IsolationMode::None);
+        let mut rule = allow_rule(Some(443), None);
+        rule.description = "web\nCOMMIT\n*nat".to_string();
+        profile.firewall_rules = vec![rule];
+        let problems = profile.validate().unwrap_err();
+        assert!(problems.iter().any(|p| p.contains("control characters")));
+    }
+
+    #[test]
+    fn test_validate_outbound_allow_without_internet() {
+        let mut profile = profile_with_mode(IsolationMode::HostOnly);
+        profile.network_isolation.allow_internet = Some(false);
+        profile.firewall_rules = vec![allow_rule(Some(443), None)];
+        let problems = profile.validate().unwrap_err();
+        assert!(problems.iter().any(|p| p.contains("outbound")));
+    }
+
+    #[test]
+    fn test_firewall_rules_are_shell_quoted() {
+        let manager = SecurityManager::new(PathBuf::from("/tmp"));
+        let mut profile = profile_with_mode(IsolationMode::None);
+        let mut rule = allow_rule(None, Some((8000, 9000)));
+        rule.description = "dev \"servers\" $HOME".to_string();
+        profile.firewall_rules = vec![allow_rule(Some(443), None), rule];
+
+        let per_rule = manager.generate_iptables_rules(&profile, "tap0");
+        assert_eq!(per_rule[2], "iptables -A n01d-check -o tap0 -p tcp --dport 443 -j ACCEPT -m comment --comment rule");
+        assert!(per_rule[3].ends_with(r#"--comment "dev \"servers\" \$HOME""#));
+    }
+
+    #[test]
+    fn test_apparmor_profile() {
+        let vm_dir = Path::new("/home/user/My VMs/lab");
+        let mut profile = SecurityManager::get_preset_profiles()
+            .into_iter()
+            .find(|(name, _, _)| *name == "isolated")
+            .unwrap()
+            .2;
+        profile.network_isolation.mode = Some(IsolationMode::Full);
+
+        let policy = generate_apparmor_profile(&profile, vm_dir);
+        assert!(policy.contains("profile n01d-qemu-lab flags=(attach_disconnected) {"));
+        assert!(policy.contains("  \"/home/user/My VMs/lab/**\" rwk,"));
+        assert!(policy.contains("  /dev/kvm rw,"));
+        assert!(policy.contains("  deny network inet,"));
+        assert!(!policy.contains("/tmp/"));
+        assert!(!policy.contains("abstractions/gnome"));
+
+        let policy = apparmor_allow(&policy, &[(Path::new("/isos/nullsec.iso"), "r")]);
+        assert!(policy.trim_end().ends_with("  \"/isos/nullsec.iso\" r,\n}"));
+    }
 }
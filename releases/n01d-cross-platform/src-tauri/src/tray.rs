@@ -0,0 +1,95 @@
+// System tray menu for starting VMs without opening the main window
+// Item ids are `start:<vm>` per VM plus the fixed ones below; the menu is
+// rebuilt whenever a VM is created, imported or deleted
+
+use crate::{get_running_vms, load_config, run_vm, stop_vm, AppConfig, RunningVms, Vault};
+use tauri::api::notification::Notification;
+use tauri::{AppHandle, CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem};
+
+const START_PREFIX: &str = "start:";
+const STOP_ALL: &str = "stop-all";
+const SHOW: &str = "show";
+const QUIT: &str = "quit";
+
+pub fn tray(config: &AppConfig) -> SystemTray {
+    SystemTray::new().with_menu(menu(config))
+}
+
+fn menu(config: &AppConfig) -> SystemTrayMenu {
+    let mut names: Vec<&String> = config.vms.keys().collect();
+    names.sort();
+
+    let mut menu = SystemTrayMenu::new();
+    for name in &names {
+        menu = menu.add_item(CustomMenuItem::new(format!("{}{}", START_PREFIX, name), format!("Start {}", name)));
+    }
+    if names.is_empty() {
+        menu = menu.add_item(CustomMenuItem::new("no-vms", "No VMs yet").disabled());
+    }
+
+    menu.add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new(STOP_ALL, "Stop all"))
+        .add_item(CustomMenuItem::new(SHOW, "Show n01d Machine"))
+        .add_item(CustomMenuItem::new(QUIT, "Quit"))
+}
+
+/// Bring the menu back in line with the configured VMs
+pub fn refresh(app: &AppHandle) {
+    let _ = app.tray_handle().set_menu(menu(&load_config()));
+}
+
+pub fn handle_event(app: &AppHandle, event: SystemTrayEvent) {
+    let SystemTrayEvent::MenuItemClick { id, .. } = event else {
+        return;
+    };
+
+    match id.as_str() {
+        SHOW => {
+            if let Some(window) = app.get_window("main") {
+                let _ = window.unminimize();
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+        }
+        QUIT => app.exit(0),
+        // Starting and stopping wait on QEMU, which mustn't block the event loop
+        STOP_ALL => {
+            let app = app.clone();
+            std::thread::spawn(move || stop_all(&app));
+        }
+        id => {
+            if let Some(name) = id.strip_prefix(START_PREFIX) {
+                let (app, name) = (app.clone(), name.to_string());
+                std::thread::spawn(move || match run_vm(app.state::<RunningVms>(), app.state::<Vault>(), name.clone(), false, false, None) {
+                    Ok(message) => notify(&app, "VM started", &message),
+                    Err(e) => notify(&app, &format!("Failed to start '{}'", name), &e.to_string()),
+                });
+            }
+        }
+    }
+}
+
+fn stop_all(app: &AppHandle) {
+    let names = get_running_vms(app.state::<RunningVms>());
+    if names.is_empty() {
+        notify(app, "Stop all", "No VMs are running");
+        return;
+    }
+
+    let failed: Vec<String> = names
+        .iter()
+        .filter_map(|name| stop_vm(app.state::<RunningVms>(), name.clone(), false).err().map(|e| format!("{}: {}", name, e)))
+        .collect();
+    if failed.is_empty() {
+        notify(app, "Stop all", &format!("Stopped {} VM(s)", names.len()));
+    } else {
+        notify(app, "Some VMs didn't stop", &failed.join("\n"));
+    }
+}
+
+fn notify(app: &AppHandle, title: &str, body: &str) {
+    let _ = Notification::new(&app.config().tauri.bundle.identifier)
+        .title(title)
+        .body(body)
+        .show();
+}
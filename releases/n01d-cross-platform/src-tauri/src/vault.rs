@@ -0,0 +1,121 @@
+// Encryption at rest for custom security profiles
+// The key is derived from a passphrase with Argon2id and only ever kept in
+// memory; values are sealed with ChaCha20-Poly1305 under a fresh nonce each
+// time they're saved
+
+use crate::error::AppError;
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+const SALT_LEN: usize = 16;
+
+/// An encrypted value as stored in the config, hex-encoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sealed {
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// The derived key and the salt it came from, so values sealed later can be
+/// opened with the same passphrase. Deliberately not Debug
+struct Unlocked {
+    salt: Vec<u8>,
+    key: Key,
+}
+
+/// Managed state holding the key once the user has entered the passphrase
+#[derive(Default)]
+pub struct Vault(Mutex<Option<Unlocked>>);
+
+impl Vault {
+    pub fn is_unlocked(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    /// Start sealing under a new passphrase with a fresh salt
+    pub fn set_passphrase(&self, passphrase: &str) -> Result<(), AppError> {
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt)?;
+        *self.0.lock().unwrap() = Some(Unlocked { salt, key });
+        Ok(())
+    }
+
+    /// Check the passphrase against a sealed value and keep its key if it
+    /// opens it
+    pub fn unlock(&self, passphrase: &str, sealed: &Sealed) -> Result<(), AppError> {
+        let salt = from_hex(&sealed.salt)?;
+        let key = derive_key(passphrase, &salt)?;
+        decrypt(&key, sealed).map_err(|_| AppError::Invalid("Wrong passphrase".to_string()))?;
+        *self.0.lock().unwrap() = Some(Unlocked { salt, key });
+        Ok(())
+    }
+
+    pub fn lock(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+
+    pub fn seal<T: Serialize>(&self, value: &T) -> Result<Sealed, AppError> {
+        let guard = self.0.lock().unwrap();
+        let unlocked = guard.as_ref().ok_or(AppError::Locked)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&unlocked.key)
+            .encrypt(&nonce, serde_json::to_vec(value)?.as_slice())
+            .map_err(|_| AppError::Other("Encryption failed".to_string()))?;
+
+        Ok(Sealed {
+            salt: to_hex(&unlocked.salt),
+            nonce: to_hex(&nonce),
+            ciphertext: to_hex(&ciphertext),
+        })
+    }
+
+    pub fn open<T: DeserializeOwned>(&self, sealed: &Sealed) -> Result<T, AppError> {
+        let guard = self.0.lock().unwrap();
+        let unlocked = guard.as_ref().ok_or(AppError::Locked)?;
+        let plaintext = decrypt(&unlocked.key, sealed)?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, AppError> {
+    if passphrase.is_empty() {
+        return Err(AppError::Invalid("The passphrase can't be empty".to_string()));
+    }
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| AppError::Other(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+fn decrypt(key: &Key, sealed: &Sealed) -> Result<Vec<u8>, AppError> {
+    let nonce = from_hex(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(AppError::Invalid("Encrypted profiles have a malformed nonce".to_string()));
+    }
+    ChaCha20Poly1305::new(key)
+        .decrypt(Nonce::from_slice(&nonce), from_hex(&sealed.ciphertext)?.as_slice())
+        .map_err(|_| AppError::Invalid("Encrypted profiles can't be decrypted with this passphrase".to_string()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, AppError> {
+    let malformed = || AppError::Invalid("Encrypted profiles are malformed".to_string());
+    if !hex.len().is_multiple_of(2) {
+        return Err(malformed());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()).ok_or_else(malformed))
+        .collect()
+}
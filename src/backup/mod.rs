@@ -0,0 +1,249 @@
+//! Backup Module - Archive every VM at once
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::vm::{self, VmInfo, VmStatus};
+
+const MANIFEST: &str = "manifest.json";
+
+/// What `backup_all` does with VMs that are running
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum BackupRunning {
+    /// Leave them out of the archive
+    Skip,
+    /// Archive a point-in-time copy of their disks, taken with a QMP
+    /// backup job while they keep running
+    Snapshot,
+    /// Refuse to back up while any VM is running
+    Error,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    created: String,
+    vms: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    name: String,
+    /// Disk file name inside the VM's archived directory
+    disk: String,
+    /// Where the disk lived when it was backed up
+    original_disk: PathBuf,
+    size: u64,
+}
+
+fn mb(bytes: u64) -> u64 {
+    bytes / (1024 * 1024)
+}
+
+/// Write every VM directory into a timestamped `.tar.zst` in `out`, with a
+/// manifest used by `restore_all`. Disks living outside their VM directory
+/// are pulled into the archive too
+pub fn backup_all(out: &Path, running: BackupRunning) -> Result<()> {
+    use colored::*;
+    
+    let vms = vm::scan_vms()?;
+    if vms.is_empty() {
+        bail!("No VMs to back up in {}", vm::get_vm_dir().display());
+    }
+    
+    let busy: Vec<&str> = vms.iter()
+        .filter(|info| info.status == VmStatus::Running)
+        .map(|info| info.name.as_str())
+        .collect();
+    if running == BackupRunning::Error && !busy.is_empty() {
+        bail!("VMs still running: {} (stop them or use --running skip/snapshot)", busy.join(", "));
+    }
+    
+    fs::create_dir_all(out)?;
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let archive_path = out.join(format!("n01d-backup-{}.tar.zst", stamp));
+    let part = out.join(format!("n01d-backup-{}.tar.zst.part", stamp));
+    
+    let manifest = match write_archive(&part, &vms, running) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let _ = fs::remove_file(&part);
+            return Err(e);
+        }
+    };
+    fs::rename(&part, &archive_path)?;
+    
+    let total: u64 = manifest.vms.iter().map(|entry| entry.size).sum();
+    let compressed = fs::metadata(&archive_path)?.len();
+    println!(
+        "{} Backed up {} VM(s), {} MB ({} MB compressed) to {}",
+        "[+]".green(), manifest.vms.len(), mb(total), mb(compressed), archive_path.display()
+    );
+    
+    Ok(())
+}
+
+fn write_archive(path: &Path, vms: &[VmInfo], running: BackupRunning) -> Result<Manifest> {
+    use colored::*;
+    
+    let file = fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut builder = tar::Builder::new(zstd::Encoder::new(file, 3)?);
+    
+    let mut manifest = Manifest {
+        created: chrono::Utc::now().to_rfc3339(),
+        vms: Vec::new(),
+    };
+    
+    for info in vms {
+        let is_running = info.status == VmStatus::Running;
+        if is_running && running == BackupRunning::Skip {
+            println!("{} {}: skipped (running)", "[*]".blue(), info.name);
+            continue;
+        }
+        
+        // The copy sits next to the disk; ".tmp-" keeps append_vm from
+        // archiving it twice
+        let copy = info.disk_path.with_file_name(format!(
+            ".{}.tmp-backup",
+            info.disk_path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        if is_running {
+            if let Err(e) = vm::backup_running_disk(info, &copy) {
+                let _ = fs::remove_file(&copy);
+                println!("{} {}: skipped, couldn't copy its disk: {}", "[!]".yellow(), info.name, e);
+                continue;
+            }
+        }
+        
+        let result = append_vm(&mut builder, info, if is_running { &copy } else { &info.disk_path });
+        if is_running {
+            let _ = fs::remove_file(&copy);
+        }
+        
+        // A failed write leaves the archive unusable, so don't carry on
+        let entry = result.with_context(|| format!("Failed to archive '{}'", info.name))?;
+        println!("{} {}: {} MB", "[+]".green(), info.name, mb(entry.size));
+        manifest.vms.push(entry);
+    }
+    
+    if manifest.vms.is_empty() {
+        bail!("Nothing was backed up");
+    }
+    
+    // Written last, once we know which VMs made it in
+    let json = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST, json.as_slice())?;
+    
+    let mut file = builder.into_inner()?.finish()?;
+    file.flush()?;
+    file.sync_all()?;
+    
+    Ok(manifest)
+}
+
+/// Archive a VM's directory, taking its disk's contents from `disk_source`
+fn append_vm<W: Write>(builder: &mut tar::Builder<W>, info: &VmInfo, disk_source: &Path) -> Result<ManifestEntry> {
+    let vm_dir = vm::get_vm_dir().join(&info.name);
+    let disk = info.disk_path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .with_context(|| format!("Bad disk path {}", info.disk_path.display()))?;
+    let mut size = 0;
+    
+    for entry in fs::read_dir(&vm_dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        
+        // Runtime state that means nothing after a restore
+        if name == "vm.pid" || name == "qmp.sock" || name.contains(".tmp-") {
+            continue;
+        }
+        // Added below, from wherever the disk's contents come from
+        if path == info.disk_path {
+            continue;
+        }
+        
+        // metadata() follows symlinks, so linked disks are archived by content
+        match fs::metadata(&path) {
+            Ok(meta) if meta.is_file() => size += meta.len(),
+            _ => continue,
+        }
+        builder.append_path_with_name(&path, format!("{}/{}", info.name, name))?;
+    }
+    
+    // Disks registered in place live outside the VM directory
+    if info.disk_path.parent() != Some(vm_dir.as_path()) && vm_dir.join(&disk).exists() {
+        bail!("{} clashes with a file of the same name in {}", info.disk_path.display(), vm_dir.display());
+    }
+    size += fs::metadata(disk_source)?.len();
+    builder.append_path_with_name(disk_source, format!("{}/{}", info.name, disk))?;
+    
+    Ok(ManifestEntry {
+        name: info.name.clone(),
+        disk,
+        original_disk: info.disk_path.clone(),
+        size,
+    })
+}
+
+/// Restore every VM from a `backup_all` archive. Nothing is moved into place
+/// if any of the VMs already exists
+pub fn restore_all(archive: &Path) -> Result<()> {
+    let vm_root = vm::get_vm_dir();
+    fs::create_dir_all(&vm_root)?;
+    
+    // Unpack next to the VMs so moving each one into place is a rename
+    let staging = vm_root.join(format!(".restore-{}", std::process::id()));
+    let result = restore_from(archive, &vm_root, &staging);
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn restore_from(archive: &Path, vm_root: &Path, staging: &Path) -> Result<()> {
+    use colored::*;
+    
+    let file = fs::File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+    // unpack() refuses entries that would land outside the destination
+    tar.unpack(staging).context("Failed to unpack backup")?;
+    
+    let manifest = fs::read(staging.join(MANIFEST)).context("Backup has no manifest")?;
+    let manifest: Manifest = serde_json::from_slice(&manifest).context("Invalid backup manifest")?;
+    
+    for entry in &manifest.vms {
+        vm::validate_vm_name(&entry.name)?;
+        if Path::new(&entry.disk).file_name() != Some(entry.disk.as_ref()) {
+            bail!("Invalid disk name '{}' for '{}' in manifest", entry.disk, entry.name);
+        }
+        if vm_root.join(&entry.name).exists() {
+            bail!("VM '{}' already exists; delete or rename it before restoring", entry.name);
+        }
+    }
+    
+    for entry in &manifest.vms {
+        let dest = vm_root.join(&entry.name);
+        fs::rename(staging.join(&entry.name), &dest)?;
+        
+        let disk = dest.join(&entry.disk);
+        vm::relocate_disk(&entry.name, &disk)?;
+        
+        if disk != entry.original_disk {
+            println!("{} {}: restored, disk moved from {}", "[+]".green(), entry.name, entry.original_disk.display());
+        } else {
+            println!("{} {}: restored", "[+]".green(), entry.name);
+        }
+    }
+    
+    let total: u64 = manifest.vms.iter().map(|entry| entry.size).sum();
+    println!(
+        "{} Restored {} VM(s), {} MB, backed up {}",
+        "[+]".green(), manifest.vms.len(), mb(total), manifest.created
+    );
+    
+    Ok(())
+}
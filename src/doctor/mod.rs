@@ -0,0 +1,321 @@
+//! Doctor Module - Find leftovers from crashed or interrupted operations
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use crate::{network, vm};
+
+/// How long a directory without a vm.toml is left alone, since a create,
+/// import or restore still running looks just like an interrupted one
+const NO_CONFIG_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// Something out of place in the VM directory or the host's interfaces
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// A directory under the VM root without a vm.toml that hasn't changed
+    /// for `NO_CONFIG_GRACE`, e.g. from an interrupted create or restore
+    NoConfig(PathBuf),
+    /// A vm.toml that doesn't parse
+    BadConfig { dir: PathBuf, error: String },
+    /// A VM whose disk image is gone
+    MissingDisk { vm: String, disk: PathBuf },
+    /// A vm.pid naming a process that no longer exists
+    StalePidfile { vm: String, pid: String },
+    /// A `tap-<vm>` device named after one of n01d's VMs that isn't
+    /// running. Taps that don't match a VM belong to something else
+    OrphanedTap(String),
+    /// A `nullsec-*` bridge with nothing attached. Networks aren't recorded
+    /// anywhere, so it may just be waiting for a VM
+    UnusedBridge(String),
+}
+
+impl Issue {
+    /// Whether `fix` can clean this up. Broken configs and missing disks
+    /// need a human, and an unused bridge may still be wanted
+    pub fn fixable(&self) -> bool {
+        matches!(self, Issue::NoConfig(_) | Issue::StalePidfile { .. } | Issue::OrphanedTap(_))
+    }
+    
+    /// What `fix` would do, phrased for a confirmation prompt
+    pub fn fix_description(&self) -> String {
+        match self {
+            Issue::NoConfig(dir) => format!("Delete {} and everything in it", dir.display()),
+            Issue::StalePidfile { vm, .. } => format!("Remove the pidfile of '{}'", vm),
+            Issue::OrphanedTap(tap) => format!("Delete TAP device {}", tap),
+            _ => String::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::NoConfig(dir) => write!(f, "{} has no vm.toml", dir.display()),
+            Issue::BadConfig { dir, error } => write!(f, "{}/vm.toml is invalid: {}", dir.display(), error.trim()),
+            Issue::MissingDisk { vm, disk } => write!(f, "VM '{}' uses missing disk {}", vm, disk.display()),
+            Issue::StalePidfile { vm, pid } => write!(f, "VM '{}' has a pidfile for dead process {}", vm, pid),
+            Issue::OrphanedTap(tap) => write!(f, "TAP device {} has no running VM", tap),
+            Issue::UnusedBridge(bridge) => write!(f, "Bridge {} has nothing attached (delete it with `network delete` if unneeded)", bridge),
+        }
+    }
+}
+
+/// An external program n01d runs
+#[derive(Debug, Clone, Serialize)]
+pub struct Dependency {
+    pub name: &'static str,
+    /// What it's needed for, shown when it's missing
+    pub purpose: &'static str,
+    pub installed: bool,
+    pub version: Option<String>,
+}
+
+/// Programs to look for: alternatives that do the same job, and what for
+const DEPENDENCIES: &[(&[&str], &str)] = &[
+    (&["qemu-system-x86_64"], "running VMs"),
+    (&["qemu-img"], "disks and snapshots"),
+    (&["tor"], "Tor routing and onion services"),
+    (&["openvpn"], "OpenVPN connections"),
+    (&["wg", "wg-quick"], "WireGuard connections"),
+    (&["swtpm"], "TPM emulation"),
+    (&["iptables"], "NAT and firewall rules"),
+    (&["nft"], "nftables firewall rules"),
+];
+
+/// One `qemu-system-<arch>` emulator and whether its guests would run
+/// accelerated on this host
+#[derive(Debug, Clone, Serialize)]
+pub struct QemuBinary {
+    pub arch: &'static str,
+    pub installed: bool,
+    /// Only guests of the host's own arch get KVM/HVF/WHPX; the rest are
+    /// emulated with TCG
+    pub accelerated: bool,
+}
+
+/// Every supported guest arch's emulator, installed or not
+pub fn qemu_binaries() -> Vec<QemuBinary> {
+    let accelerator = accelerator().is_some();
+    vm::SUPPORTED_ARCHES
+        .iter()
+        .map(|arch| {
+            let installed = vm::qemu_binary(arch).is_ok_and(|binary| which::which(binary).is_ok());
+            QemuBinary { arch, installed, accelerated: installed && accelerator && *arch == std::env::consts::ARCH }
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+pub struct DoctorReport {
+    pub dependencies: Vec<Dependency>,
+    pub qemu: Vec<QemuBinary>,
+    /// Whether /dev/kvm can be opened for hardware acceleration
+    pub kvm: bool,
+    pub issues: Vec<Issue>,
+}
+
+/// Scan the VM directory and host interfaces for inconsistencies
+pub fn doctor() -> Result<DoctorReport> {
+    let mut report = DoctorReport {
+        dependencies: DEPENDENCIES.iter().map(|(names, purpose)| find_dependency(names, purpose)).collect(),
+        qemu: qemu_binaries(),
+        kvm: vm::kvm_available(),
+        ..Default::default()
+    };
+    let root = vm::get_vm_dir();
+    let mut known = Vec::new();
+    let mut running = Vec::new();
+    
+    if root.exists() {
+        let mut dirs: Vec<PathBuf> = fs::read_dir(&root)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_dir())
+            .collect();
+        dirs.sort();
+        
+        for dir in dirs {
+            if let Some((issues, is_running)) = check_vm_dir(&dir)? {
+                report.issues.extend(issues);
+                let name = dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+                if is_running {
+                    running.push(name.clone());
+                }
+                known.push(name);
+            } else if !recently_modified(&dir) {
+                report.issues.push(Issue::NoConfig(dir));
+            }
+        }
+    }
+    
+    for tap in link_names("tun") {
+        let vm = tap.strip_prefix("tap-");
+        if vm.is_some_and(|vm| known.iter().any(|name| name == vm) && !running.iter().any(|name| name == vm)) {
+            report.issues.push(Issue::OrphanedTap(tap));
+        }
+    }
+    
+    for bridge in link_names("bridge") {
+        if bridge.starts_with("nullsec-") && bridge_is_empty(&bridge) {
+            report.issues.push(Issue::UnusedBridge(bridge));
+        }
+    }
+    
+    Ok(report)
+}
+
+/// Look for the first of `names` on PATH and ask it for its version
+fn find_dependency(names: &[&'static str], purpose: &'static str) -> Dependency {
+    match n01d_common::deps::find_program(names) {
+        Some((name, version)) => Dependency { name, purpose, installed: true, version },
+        None => Dependency { name: names[0], purpose, installed: false, version: None },
+    }
+}
+
+/// The host's hardware accelerator QEMU can use, if any
+pub fn accelerator() -> Option<&'static str> {
+    if cfg!(target_os = "linux") {
+        vm::kvm_available().then_some("kvm")
+    } else if cfg!(target_os = "macos") {
+        let output = Command::new("sysctl").args(["-n", "kern.hv_support"]).output().ok()?;
+        (String::from_utf8_lossy(&output.stdout).trim() == "1").then_some("hvf")
+    } else if cfg!(target_os = "windows") {
+        // Only tells that QEMU was built with WHPX; whether the Hypervisor
+        // Platform feature is on shows up at launch
+        let output = Command::new("qemu-system-x86_64").args(["-accel", "help"]).output().ok()?;
+        String::from_utf8_lossy(&output.stdout).lines().any(|line| line.trim() == "whpx").then_some("whpx")
+    } else {
+        None
+    }
+}
+
+/// Everything about the host worth pasting into a bug report
+#[derive(Debug, Serialize)]
+pub struct EnvInfo {
+    pub version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// Kernel release, from `uname -r`
+    pub kernel: Option<String>,
+    /// kvm, hvf or whpx
+    pub accelerator: Option<&'static str>,
+    pub dependencies: Vec<Dependency>,
+    pub config_file: PathBuf,
+    pub vm_dir: PathBuf,
+    pub vms: usize,
+    pub sandboxes: usize,
+}
+
+/// Versions, host details and what's installed, for `info`
+pub fn info() -> EnvInfo {
+    let kernel = Command::new("uname")
+        .arg("-r")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+    let vm_dir = vm::get_vm_dir();
+    let sandbox_dir = dirs::home_dir().unwrap_or_else(|| PathBuf::from(".")).join("NullSec-Sandboxes");
+    
+    EnvInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        kernel,
+        accelerator: accelerator(),
+        dependencies: DEPENDENCIES.iter().map(|(names, purpose)| find_dependency(names, purpose)).collect(),
+        config_file: vm::config_path(),
+        vms: count_dirs(&vm_dir, |dir| dir.join("vm.toml").is_file()),
+        sandboxes: count_dirs(&sandbox_dir, |_| true),
+        vm_dir,
+    }
+}
+
+/// Subdirectories of `root` that pass `keep`, or 0 if it doesn't exist
+fn count_dirs(root: &Path, keep: impl Fn(&Path) -> bool) -> usize {
+    fs::read_dir(root)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir() && keep(path)).count())
+        .unwrap_or(0)
+}
+
+/// Issues in one VM directory and whether the VM is running, or `None` if
+/// the directory has no vm.toml at all
+fn check_vm_dir(dir: &Path) -> Result<Option<(Vec<Issue>, bool)>> {
+    let config_path = dir.join("vm.toml");
+    if !config_path.is_file() {
+        return Ok(None);
+    }
+    
+    let mut issues = Vec::new();
+    let name = dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+    
+    match toml::from_str::<vm::VmInfo>(&fs::read_to_string(&config_path)?) {
+        Ok(info) if !info.disk_path.exists() => {
+            issues.push(Issue::MissingDisk { vm: name.clone(), disk: info.disk_path });
+        }
+        Ok(_) => {}
+        Err(e) => issues.push(Issue::BadConfig { dir: dir.to_path_buf(), error: e.to_string() }),
+    }
+    
+    let pid_path = dir.join("vm.pid");
+    let is_running = vm::running_pid(&name).is_some();
+    if pid_path.exists() && !is_running {
+        let pid = fs::read_to_string(&pid_path).unwrap_or_default().trim().to_string();
+        issues.push(Issue::StalePidfile { vm: name, pid });
+    }
+    
+    Ok(Some((issues, is_running)))
+}
+
+/// Whether `dir` or anything directly in it changed within `NO_CONFIG_GRACE`
+fn recently_modified(dir: &Path) -> bool {
+    let entries = fs::read_dir(dir).into_iter().flatten().flatten().map(|entry| entry.path());
+    std::iter::once(dir.to_path_buf())
+        .chain(entries)
+        .filter_map(|path| fs::symlink_metadata(path).and_then(|meta| meta.modified()).ok())
+        .any(|modified| SystemTime::now().duration_since(modified).map_or(true, |age| age < NO_CONFIG_GRACE))
+}
+
+/// Names of the host's links of one `ip link` type
+fn link_names(kind: &str) -> Vec<String> {
+    let Ok(output) = Command::new("ip").args(["-o", "link", "show", "type", kind]).output() else {
+        return Vec::new();
+    };
+    
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split(':').nth(1))
+        .map(|name| name.trim().split('@').next().unwrap_or("").to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn bridge_is_empty(bridge: &str) -> bool {
+    Command::new("ip")
+        .args(["-o", "link", "show", "master", bridge])
+        .output()
+        .map(|output| output.status.success() && output.stdout.trim_ascii().is_empty())
+        .unwrap_or(false)
+}
+
+/// Clean up one fixable issue
+pub fn fix(issue: &Issue) -> Result<()> {
+    match issue {
+        Issue::NoConfig(dir) => {
+            // Checked again in case a create started since the scan
+            if dir.join("vm.toml").exists() || recently_modified(dir) {
+                anyhow::bail!("{} is in use again, leaving it", dir.display());
+            }
+            fs::remove_dir_all(dir).with_context(|| format!("Failed to delete {}", dir.display()))?;
+        }
+        Issue::StalePidfile { vm, .. } => {
+            let pid_path = vm::get_vm_dir().join(vm).join("vm.pid");
+            fs::remove_file(&pid_path).with_context(|| format!("Failed to remove {}", pid_path.display()))?;
+        }
+        Issue::OrphanedTap(tap) => network::delete_tap_device(tap)?,
+        _ => anyhow::bail!("Can't fix automatically: {}", issue),
+    }
+    Ok(())
+}
@@ -1,10 +1,12 @@
 //! GUI Module - GTK4/Libadwaita interface for NullSec VM Console
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
-/// GUI configuration
-#[derive(Debug, Clone)]
+/// GUI configuration, kept in gui.toml next to the CLI's config.toml
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GuiConfig {
     pub theme: Theme,
     pub window_width: i32,
@@ -25,13 +27,51 @@ impl Default for GuiConfig {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl GuiConfig {
+    pub fn path() -> PathBuf {
+        crate::vm::config_path().with_file_name("gui.toml")
+    }
+    
+    /// The saved settings, or the defaults if nothing has been saved yet
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(&path)?;
+        toml::from_str(&text).with_context(|| format!("Invalid GUI settings in {}", path.display()))
+    }
+    
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        crate::vm::write_atomic(&path, toml::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
 pub enum Theme {
     Dark,
     Light,
     System,
 }
 
+impl Theme {
+    /// The `adw::ColorScheme` the GTK4 interface will set the style manager
+    /// to; `System` follows the desktop's preference
+    pub fn color_scheme(self) -> &'static str {
+        match self {
+            Theme::Dark => "ForceDark",
+            Theme::Light => "ForceLight",
+            Theme::System => "Default",
+        }
+    }
+}
+
 /// Launch the GUI application
 /// 
 /// This launches a GTK4-based GUI for managing VMs visually.
@@ -60,6 +100,16 @@ pub fn launch_gui() -> Result<()> {
     }
     
     println!("{} Display server detected", "[+]".green());
+    
+    // A broken gui.toml shouldn't keep the GUI from starting
+    let config = GuiConfig::load().unwrap_or_else(|e| {
+        println!("{} {:#}, using the defaults", "[!]".yellow(), e);
+        GuiConfig::default()
+    });
+    println!(
+        "{} Saved settings: {:?} theme ({}), {}x{} window - not applied until the GTK4 interface lands",
+        "[*]".blue(), config.theme, config.theme.color_scheme(), config.window_width, config.window_height
+    );
     println!();
     
     // Print ASCII art banner
@@ -141,7 +191,7 @@ pub enum VmGuiStatus {
 impl NullSecVmApp {
     pub fn new() -> Self {
         Self {
-            config: GuiConfig::default(),
+            config: GuiConfig::load().unwrap_or_default(),
             vm_list: Vec::new(),
             selected_vm: None,
         }
@@ -164,75 +214,703 @@ impl NullSecVmApp {
 /// TUI (Terminal UI) alternative using crossterm/ratatui
 pub mod tui {
     use super::*;
+    use crate::vm::{self, VmInfo, VmStatus};
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+    use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+    use crossterm::execute;
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::layout::{Constraint, Direction, Layout};
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::text::Line;
+    use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+    use ratatui::{Frame, Terminal};
+    use nix::unistd;
+    use std::io::{BufRead, BufReader};
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    
+    const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+    const LOG_LINES: usize = 20;
+    
+    struct TuiState {
+        vms: Vec<VmInfo>,
+        list: ListState,
+        status: String,
+        /// Last line the vm module printed
+        output: String,
+    }
+    
+    impl TuiState {
+        fn refresh(&mut self) {
+            match vm::scan_vms() {
+                Ok(vms) => self.vms = vms,
+                Err(e) => self.status = format!("Failed to scan VMs: {}", e),
+            }
+            
+            let selected = match self.list.selected() {
+                _ if self.vms.is_empty() => None,
+                Some(i) => Some(i.min(self.vms.len() - 1)),
+                None => Some(0),
+            };
+            self.list.select(selected);
+        }
+        
+        fn selected(&self) -> Option<&VmInfo> {
+            self.list.selected().and_then(|i| self.vms.get(i))
+        }
+        
+        fn move_selection(&mut self, delta: isize) {
+            if self.vms.is_empty() {
+                return;
+            }
+            let len = self.vms.len() as isize;
+            let current = self.list.selected().unwrap_or(0) as isize;
+            self.list.select(Some((current + delta).rem_euclid(len) as usize));
+        }
+        
+        fn run_action(&mut self, key: KeyCode) {
+            let Some(info) = self.selected().cloned() else {
+                return;
+            };
+            
+            let result = match key {
+                KeyCode::Char('s') => vm::start_vm(&info.name, info.isolated, None, false, None, vm::StartFlags::default())
+                    .map(|_| format!("Started '{}'", info.name)),
+                KeyCode::Char('x') => vm::stop_vm(&info.name, false)
+                    .map(|_| format!("Stopped '{}'", info.name)),
+                KeyCode::Char('n') => {
+                    let snapshot = format!("snap-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S"));
+                    vm::create_snapshot(&info.name, &snapshot)
+                        .map(|_| format!("Snapshot '{}' created for '{}'", snapshot, info.name))
+                }
+                _ => return,
+            };
+            
+            self.status = result.unwrap_or_else(|e| format!("Error: {}", e));
+            self.refresh();
+        }
+    }
+    
+    /// The vm module reports warnings and hook output with `println!`, which
+    /// would scribble over the raw-mode screen. While the TUI is up stdout
+    /// and stderr go to a pipe instead, whose lines are shown in the status
+    /// bar, and the UI draws on /dev/tty
+    struct Capture {
+        saved: Vec<(RawFd, RawFd)>,
+        lines: Arc<Mutex<Vec<String>>>,
+    }
+    
+    impl Capture {
+        fn start() -> Result<Self> {
+            let (reader, writer) = std::io::pipe()?;
+            let lines = Arc::new(Mutex::new(Vec::new()));
+            let sink = lines.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(reader).lines().map_while(|line| line.ok()) {
+                    sink.lock().unwrap().push(line);
+                }
+            });
+            
+            colored::control::set_override(false);
+            // Dropping a half-set-up capture puts back what was redirected
+            let mut capture = Capture { saved: Vec::new(), lines };
+            for fd in [1, 2] {
+                capture.saved.push((unistd::dup(fd)?, fd));
+                unistd::dup2(writer.as_raw_fd(), fd)?;
+            }
+            Ok(capture)
+        }
+        
+        /// Lines printed since the last call
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut *self.lines.lock().unwrap())
+        }
+    }
+    
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            // Closing the last write end lets the reader thread finish
+            for (copy, fd) in self.saved.drain(..) {
+                let _ = unistd::dup2(copy, fd);
+                let _ = unistd::close(copy);
+            }
+            colored::control::unset_override();
+        }
+    }
     
     /// Launch terminal UI mode
     pub fn launch_tui() -> Result<()> {
-        use colored::*;
+        let mut tty = std::fs::OpenOptions::new().write(true).open("/dev/tty")?;
+        terminal::enable_raw_mode()?;
+        execute!(tty, EnterAlternateScreen)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(tty))?;
         
-        println!("{} TUI mode - Work in progress", "[*]".blue());
-        println!();
-        println!("{}", "The TUI will provide:".bold());
-        println!("  • Full terminal-based VM management");
-        println!("  • Mouse support");
-        println!("  • Keyboard navigation");
-        println!("  • Split pane layouts");
-        println!("  • Real-time updates");
-        println!();
-        println!("{} Use CLI commands for now:", "[*]".blue());
-        println!("  nullsec-vm list        - List all VMs");
-        println!("  nullsec-vm create      - Create new VM");
-        println!("  nullsec-vm start <vm>  - Start a VM");
-        println!("  nullsec-vm sandbox     - Run in sandbox");
+        let result = Capture::start().and_then(|capture| run(&mut terminal, &capture));
         
-        Ok(())
+        // Always hand the terminal back, even if the loop failed
+        terminal::disable_raw_mode()?;
+        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+        terminal.show_cursor()?;
+        
+        result
+    }
+    
+    fn run(terminal: &mut Terminal<CrosstermBackend<std::fs::File>>, capture: &Capture) -> Result<()> {
+        let mut state = TuiState {
+            vms: Vec::new(),
+            list: ListState::default(),
+            status: "s: start  x: stop  n: snapshot  q: quit".to_string(),
+            output: String::new(),
+        };
+        state.refresh();
+        let mut last_refresh = Instant::now();
+        
+        loop {
+            if let Some(line) = capture.take().into_iter().rev().find(|line| !line.trim().is_empty()) {
+                state.output = line;
+            }
+            terminal.draw(|f| draw(f, &mut state))?;
+            
+            let timeout = REFRESH_INTERVAL.saturating_sub(last_refresh.elapsed());
+            if event::poll(timeout)? {
+                match event::read()? {
+                    Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Up | KeyCode::Char('k') => state.move_selection(-1),
+                        KeyCode::Down | KeyCode::Char('j') => state.move_selection(1),
+                        code => state.run_action(code),
+                    },
+                    Event::Resize(_, _) => terminal.autoresize()?,
+                    _ => {}
+                }
+            }
+            
+            if last_refresh.elapsed() >= REFRESH_INTERVAL {
+                state.refresh();
+                last_refresh = Instant::now();
+            }
+        }
+    }
+    
+    fn draw(f: &mut Frame, state: &mut TuiState) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(4)])
+            .split(f.size());
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+            .split(rows[0]);
+        
+        let items: Vec<ListItem> = state
+            .vms
+            .iter()
+            .map(|info| {
+                let (marker, color) = match info.status {
+                    VmStatus::Running => ("●", Color::Green),
+                    VmStatus::Paused => ("◐", Color::Yellow),
+                    _ => ("○", Color::Red),
+                };
+                ListItem::new(format!("{} {}", marker, info.name)).style(Style::default().fg(color))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(" VMs "))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_stateful_widget(list, panes[0], &mut state.list);
+        
+        let details = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(9), Constraint::Min(3)])
+            .split(panes[1]);
+        
+        let (info_lines, log_lines) = match state.selected() {
+            Some(info) => (detail_lines(info), recent_log(&info.name)),
+            None => (vec![Line::from("No VMs found")], Vec::new()),
+        };
+        f.render_widget(
+            Paragraph::new(info_lines).block(Block::default().borders(Borders::ALL).title(" Details ")),
+            details[0],
+        );
+        f.render_widget(
+            Paragraph::new(log_lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::default().borders(Borders::ALL).title(" Log ")),
+            details[1],
+        );
+        
+        f.render_widget(
+            Paragraph::new(vec![
+                Line::from(state.status.as_str()),
+                Line::styled(state.output.as_str(), Style::default().fg(Color::DarkGray)),
+            ])
+            .block(Block::default().borders(Borders::ALL)),
+            rows[1],
+        );
+    }
+    
+    fn detail_lines(info: &VmInfo) -> Vec<Line<'static>> {
+        let mut lines = vec![
+            Line::from(format!("Name:     {}", info.name)),
+            Line::from(format!("Status:   {}", info.status)),
+            Line::from(format!("RAM:      {} | CPUs: {}", info.ram, info.cpus)),
+            Line::from(format!("Disk:     {}", info.disk_path.display())),
+            Line::from(format!("Network:  {} | Isolated: {}", info.network, info.isolated)),
+        ];
+        if let Some(pid) = vm::running_pid(&info.name) {
+            lines.push(Line::from(format!("PID:      {}", pid)));
+        }
+        if !info.snapshots.is_empty() {
+            lines.push(Line::from(format!("Snapshots: {}", info.snapshots.join(", "))));
+        }
+        lines
+    }
+    
+    /// Last lines of the VM's QEMU log, if one has been written
+    fn recent_log(name: &str) -> Vec<Line<'static>> {
+        let log_path = vm::qemu_log_path(name);
+        match std::fs::read_to_string(log_path) {
+            Ok(content) => {
+                let lines: Vec<&str> = content.lines().collect();
+                let start = lines.len().saturating_sub(LOG_LINES);
+                lines[start..].iter().map(|l| Line::from(l.to_string())).collect()
+            }
+            Err(_) => vec![Line::from("No log output yet")],
+        }
     }
 }
 
 /// Dashboard module for system overview
 pub mod dashboard {
     use super::*;
+    use serde::Serialize;
     
-    /// Print system dashboard to terminal
-    pub fn print_dashboard() -> Result<()> {
-        use colored::*;
+    /// Machine-readable snapshot of everything the dashboard shows
+    #[derive(Debug, Clone, Serialize)]
+    pub struct DashboardReport {
+        pub load_average: Option<[f64; 3]>,
+        pub memory: Option<MemoryStats>,
+        pub disks: Vec<DiskRow>,
+        pub active_vms: Vec<ActiveVm>,
+        pub bridges: Vec<String>,
+    }
+    
+    #[derive(Debug, Clone, Serialize)]
+    pub struct MemoryStats {
+        pub total_mb: u64,
+        pub used_mb: u64,
+    }
+    
+    impl MemoryStats {
+        pub fn percent_used(&self) -> u64 {
+            (self.used_mb * 100).checked_div(self.total_mb).unwrap_or(0)
+        }
+    }
+    
+    /// One row of `df -h` output
+    #[derive(Debug, Clone, Serialize)]
+    pub struct DiskRow {
+        pub filesystem: String,
+        pub size: String,
+        pub used: String,
+        pub available: String,
+        pub use_percent: String,
+        pub mounted_on: String,
+    }
+    
+    #[derive(Debug, Clone, Serialize)]
+    pub struct ActiveVm {
+        pub pid: u32,
+        /// Value of QEMU's `-name` argument, if it was given
+        pub name: Option<String>,
+        /// CPU usage over the sampling interval (100% = one full core)
+        pub cpu_percent: Option<f64>,
+        pub rss_mb: Option<u64>,
+    }
+    
+    /// Source of host statistics, implemented per operating system
+    pub trait SystemStats {
+        fn load_average(&self) -> Option<[f64; 3]>;
+        fn memory(&self) -> Option<MemoryStats>;
+        fn disks(&self) -> Vec<DiskRow>;
+        fn qemu_processes(&self) -> Vec<ActiveVm>;
         
-        println!("{}", "═".repeat(60).blue());
-        println!("{:^60}", "NullSec System Dashboard".bold());
-        println!("{}", "═".repeat(60).blue());
+        fn bridges(&self) -> Vec<String> {
+            Vec::new()
+        }
         
-        // System info
-        println!("\n{}", "System Resources:".green().bold());
+        /// Fill in per-process CPU and memory usage where the OS allows it
+        fn sample_usage(&self, _vms: &mut [ActiveVm]) {}
+    }
+    
+    #[cfg(target_os = "macos")]
+    use MacStats as HostStats;
+    #[cfg(target_os = "windows")]
+    use WindowsStats as HostStats;
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    use LinuxStats as HostStats;
+    
+    /// Stats implementation for the current host OS
+    pub fn system_stats() -> Box<dyn SystemStats> {
+        Box::new(HostStats)
+    }
+    
+    /// Collect the dashboard data without printing it
+    pub fn dashboard_json() -> Result<DashboardReport> {
+        let stats = system_stats();
+        let mut active_vms = stats.qemu_processes();
+        stats.sample_usage(&mut active_vms);
         
-        // Get CPU info
-        if let Ok(content) = std::fs::read_to_string("/proc/loadavg") {
-            let parts: Vec<&str> = content.split_whitespace().collect();
-            if parts.len() >= 3 {
-                println!("  Load Average: {} {} {}", parts[0], parts[1], parts[2]);
+        Ok(DashboardReport {
+            load_average: stats.load_average(),
+            memory: stats.memory(),
+            disks: stats.disks(),
+            active_vms,
+            bridges: stats.bridges(),
+        })
+    }
+    
+    fn command_stdout(program: &str, args: &[&str]) -> Option<String> {
+        let output = std::process::Command::new(program).args(args).output().ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+    
+    /// Parse `<pid> <command line>` rows, keeping only QEMU processes
+    fn parse_qemu_processes(output: &str) -> Vec<ActiveVm> {
+        output
+            .lines()
+            .filter(|line| line.contains("qemu"))
+            .filter_map(|line| {
+                let line = line.trim();
+                let pid = line.split_whitespace().next()?.parse().ok()?;
+                // Extract just the VM name if possible
+                let name = line.find("-name").and_then(|start| {
+                    line[start + 5..].split_whitespace().next().map(String::from)
+                });
+                Some(ActiveVm { pid, name, cpu_percent: None, rss_mb: None })
+            })
+            .collect()
+    }
+    
+    /// The root filesystem and, when it's a separate mount, the one holding
+    /// the VMs. `-P` keeps macOS to the same six columns as GNU df and stops
+    /// long device names wrapping onto a second line, so everything from the
+    /// sixth column on is the mount point, spaces and all
+    #[cfg(unix)]
+    fn df_disks() -> Vec<DiskRow> {
+        let vm_dir = crate::vm::get_vm_dir();
+        let vm_dir_arg = vm_dir.to_string_lossy();
+        let mut args = vec!["-P", "-h", "/"];
+        if vm_dir.exists() {
+            args.push(&vm_dir_arg);
+        }
+        let Some(output) = command_stdout("df", &args) else {
+            return Vec::new();
+        };
+        
+        let mut disks: Vec<DiskRow> = Vec::new();
+        for line in output.lines().skip(1) {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 6 {
+                continue;
+            }
+            let mounted_on = cols[5..].join(" ");
+            if disks.iter().any(|disk| disk.mounted_on == mounted_on) {
+                continue;
             }
+            disks.push(DiskRow {
+                filesystem: cols[0].to_string(),
+                size: cols[1].to_string(),
+                used: cols[2].to_string(),
+                available: cols[3].to_string(),
+                use_percent: cols[4].to_string(),
+                mounted_on,
+            });
+        }
+        disks
+    }
+    
+    /// Linux: `/proc`, `df`, `pgrep` and `ip`
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    pub struct LinuxStats;
+    
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    impl SystemStats for LinuxStats {
+        fn load_average(&self) -> Option<[f64; 3]> {
+            let content = std::fs::read_to_string("/proc/loadavg").ok()?;
+            let mut parts = content.split_whitespace().map(|p| p.parse::<f64>().ok());
+            Some([parts.next()??, parts.next()??, parts.next()??])
         }
         
-        // Get memory info
-        if let Ok(content) = std::fs::read_to_string("/proc/meminfo") {
-            let mut total = 0u64;
-            let mut available = 0u64;
+        fn memory(&self) -> Option<MemoryStats> {
+            let content = std::fs::read_to_string("/proc/meminfo").ok()?;
+            let mut total = None;
+            let mut available = None;
             
             for line in content.lines() {
+                let value = || line.split_whitespace().nth(1).and_then(|s| s.parse::<u64>().ok());
                 if line.starts_with("MemTotal:") {
-                    total = line.split_whitespace().nth(1)
-                        .and_then(|s| s.parse().ok())
-                        .unwrap_or(0);
+                    total = value();
                 } else if line.starts_with("MemAvailable:") {
-                    available = line.split_whitespace().nth(1)
-                        .and_then(|s| s.parse().ok())
-                        .unwrap_or(0);
+                    available = value();
+                }
+            }
+            
+            let total = total?;
+            Some(MemoryStats {
+                total_mb: total / 1024,
+                used_mb: total.saturating_sub(available.unwrap_or(0)) / 1024,
+            })
+        }
+        
+        fn disks(&self) -> Vec<DiskRow> {
+            df_disks()
+        }
+        
+        fn qemu_processes(&self) -> Vec<ActiveVm> {
+            command_stdout("pgrep", &["-a", "qemu"])
+                .map(|out| parse_qemu_processes(&out))
+                .unwrap_or_default()
+        }
+        
+        fn sample_usage(&self, vms: &mut [ActiveVm]) {
+            use std::time::Instant;
+            
+            let ticks_per_sec = crate::vm::clock_ticks_per_sec() as f64;
+            let mut last = LAST_CPU_SAMPLE.lock().unwrap();
+            
+            // Usage is measured since the previous sample. VMs without one
+            // (every VM on a one-shot run) get a reading now and are
+            // measured over CPU_SAMPLE_INTERVAL, all with the same sleep
+            let first_seen: Vec<(u32, u64, Instant)> = vms
+                .iter()
+                .filter(|vm| !last.iter().any(|(pid, _, _)| *pid == vm.pid))
+                .filter_map(|vm| Some((vm.pid, process_cpu_ticks(vm.pid)?, Instant::now())))
+                .collect();
+            if !first_seen.is_empty() {
+                std::thread::sleep(CPU_SAMPLE_INTERVAL);
+                last.extend(first_seen);
+            }
+            
+            let now = Instant::now();
+            let mut samples = Vec::new();
+            for vm in vms.iter_mut() {
+                vm.rss_mb = process_rss_mb(vm.pid);
+                // A VM that exits mid-sample simply has no usage figures
+                let Some(ticks) = process_cpu_ticks(vm.pid) else {
+                    continue;
+                };
+                samples.push((vm.pid, ticks, now));
+                let Some(&(_, before, at)) = last.iter().find(|(pid, _, _)| *pid == vm.pid) else {
+                    continue;
+                };
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    vm.cpu_percent = Some(ticks.saturating_sub(before) as f64 / ticks_per_sec / elapsed * 100.0);
                 }
             }
+            *last = samples;
+        }
+        
+        fn bridges(&self) -> Vec<String> {
+            let Some(output) = command_stdout("ip", &["link", "show", "type", "bridge"]) else {
+                return Vec::new();
+            };
+            
+            output
+                .lines()
+                .filter(|line| line.contains("nullsec"))
+                .filter_map(|line| line.split(':').nth(1))
+                .map(|name| name.trim().split('@').next().unwrap_or("").to_string())
+                .collect()
+        }
+    }
+    
+    /// CPU ticks of each QEMU process at the last `sample_usage`
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    static LAST_CPU_SAMPLE: std::sync::Mutex<Vec<(u32, u64, std::time::Instant)>> = std::sync::Mutex::new(Vec::new());
+    
+    /// How long a VM's CPU usage is measured over when there's no earlier
+    /// sample to compare with
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    const CPU_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+    
+    /// utime + stime of a process in clock ticks, from `/proc/<pid>/stat`
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    fn process_cpu_ticks(pid: u32) -> Option<u64> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name can contain spaces, so split after its closing paren
+        let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        let utime: u64 = fields.get(11)?.parse().ok()?;
+        let stime: u64 = fields.get(12)?.parse().ok()?;
+        Some(utime + stime)
+    }
+    
+    /// Resident set size of a process, from `/proc/<pid>/status`
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    fn process_rss_mb(pid: u32) -> Option<u64> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let kb: u64 = status
+            .lines()
+            .find(|line| line.starts_with("VmRSS:"))?
+            .split_whitespace()
+            .nth(1)?
+            .parse()
+            .ok()?;
+        Some(kb / 1024)
+    }
+    
+    /// macOS: `sysctl`, `vm_stat`, `df` and `ps`
+    #[cfg(target_os = "macos")]
+    pub struct MacStats;
+    
+    #[cfg(target_os = "macos")]
+    impl SystemStats for MacStats {
+        fn load_average(&self) -> Option<[f64; 3]> {
+            // Format: "{ 1.52 1.61 1.73 }"
+            let output = command_stdout("sysctl", &["-n", "vm.loadavg"])?;
+            let mut parts = output
+                .split_whitespace()
+                .filter(|p| *p != "{" && *p != "}")
+                .map(|p| p.parse::<f64>().ok());
+            Some([parts.next()??, parts.next()??, parts.next()??])
+        }
+        
+        fn memory(&self) -> Option<MemoryStats> {
+            let total: u64 = command_stdout("sysctl", &["-n", "hw.memsize"])?.trim().parse().ok()?;
+            let vm_stat = command_stdout("vm_stat", &[])?;
             
-            let used = total.saturating_sub(available);
-            let percent = if total > 0 { (used * 100) / total } else { 0 };
+            // Header: "Mach Virtual Memory Statistics: (page size of 16384 bytes)"
+            let page_size: u64 = vm_stat
+                .split("page size of")
+                .nth(1)
+                .and_then(|rest| rest.split_whitespace().next())
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(4096);
             
-            println!("  Memory: {} / {} MB ({}% used)", 
-                used / 1024, total / 1024, percent);
+            let pages = |key: &str| -> u64 {
+                vm_stat
+                    .lines()
+                    .find(|line| line.starts_with(key))
+                    .and_then(|line| line.split(':').nth(1))
+                    .and_then(|v| v.trim().trim_end_matches('.').parse().ok())
+                    .unwrap_or(0)
+            };
+            let available = (pages("Pages free") + pages("Pages inactive") + pages("Pages speculative")) * page_size;
+            
+            Some(MemoryStats {
+                total_mb: total / 1024 / 1024,
+                used_mb: total.saturating_sub(available) / 1024 / 1024,
+            })
+        }
+        
+        fn disks(&self) -> Vec<DiskRow> {
+            df_disks()
+        }
+        
+        fn qemu_processes(&self) -> Vec<ActiveVm> {
+            command_stdout("ps", &["-A", "-o", "pid=,command="])
+                .map(|out| parse_qemu_processes(&out))
+                .unwrap_or_default()
+        }
+    }
+    
+    /// Windows: CIM queries through PowerShell
+    #[cfg(target_os = "windows")]
+    pub struct WindowsStats;
+    
+    #[cfg(target_os = "windows")]
+    fn powershell(script: &str) -> Option<String> {
+        command_stdout("powershell", &["-NoProfile", "-NonInteractive", "-Command", script])
+    }
+    
+    #[cfg(target_os = "windows")]
+    impl SystemStats for WindowsStats {
+        fn load_average(&self) -> Option<[f64; 3]> {
+            // Windows has no load average
+            None
+        }
+        
+        fn memory(&self) -> Option<MemoryStats> {
+            // Both values are reported in KB
+            let output = powershell(
+                "$os = Get-CimInstance Win32_OperatingSystem; \
+                 \"$($os.TotalVisibleMemorySize) $($os.FreePhysicalMemory)\"",
+            )?;
+            let mut parts = output.split_whitespace().map(|p| p.parse::<u64>().ok());
+            let total = parts.next()??;
+            let free = parts.next()??;
+            
+            Some(MemoryStats {
+                total_mb: total / 1024,
+                used_mb: total.saturating_sub(free) / 1024,
+            })
+        }
+        
+        fn disks(&self) -> Vec<DiskRow> {
+            let Some(output) = powershell(
+                "Get-CimInstance Win32_LogicalDisk -Filter 'DriveType=3' | \
+                 ForEach-Object { \"$($_.DeviceID) $($_.Size) $($_.FreeSpace)\" }",
+            ) else {
+                return Vec::new();
+            };
+            
+            let gib = |bytes: u64| format!("{}G", bytes / 1024 / 1024 / 1024);
+            output
+                .lines()
+                .filter_map(|line| {
+                    let cols: Vec<&str> = line.split_whitespace().collect();
+                    let drive = cols.first()?.to_string();
+                    let size: u64 = cols.get(1)?.parse().ok()?;
+                    let free: u64 = cols.get(2)?.parse().ok()?;
+                    let used = size.saturating_sub(free);
+                    let percent = if size > 0 { used * 100 / size } else { 0 };
+                    
+                    Some(DiskRow {
+                        filesystem: drive.clone(),
+                        size: gib(size),
+                        used: gib(used),
+                        available: gib(free),
+                        use_percent: format!("{}%", percent),
+                        mounted_on: drive,
+                    })
+                })
+                .collect()
+        }
+        
+        fn qemu_processes(&self) -> Vec<ActiveVm> {
+            powershell(
+                "Get-CimInstance Win32_Process -Filter \"Name like 'qemu%'\" | \
+                 ForEach-Object { \"$($_.ProcessId) $($_.CommandLine)\" }",
+            )
+            .map(|out| parse_qemu_processes(&out))
+            .unwrap_or_default()
+        }
+    }
+    
+    /// Render the dashboard as the text shown to humans
+    fn render_dashboard(report: &DashboardReport) -> String {
+        use colored::*;
+        
+        let mut lines = Vec::new();
+        
+        lines.push(format!("{}", "═".repeat(60).blue()));
+        lines.push(format!("{:^60}", "NullSec System Dashboard".bold()));
+        lines.push(format!("{}", "═".repeat(60).blue()));
+        
+        // System info
+        lines.push(format!("\n{}", "System Resources:".green().bold()));
+        
+        if let Some([one, five, fifteen]) = report.load_average {
+            lines.push(format!("  Load Average: {:.2} {:.2} {:.2}", one, five, fifteen));
+        }
+        
+        if let Some(memory) = &report.memory {
+            let percent = memory.percent_used();
+            
+            lines.push(format!("  Memory: {} / {} MB ({}% used)", 
+                memory.used_mb, memory.total_mb, percent));
             
             // Memory bar
             let bar_width = 40;
@@ -242,71 +920,239 @@ pub mod dashboard {
                 "█".repeat(filled),
                 "░".repeat(empty)
             );
-            println!("  {}", if percent > 80 { bar.red() } else if percent > 60 { bar.yellow() } else { bar.green() });
+            lines.push(format!("  {}", if percent > 80 { bar.red() } else if percent > 60 { bar.yellow() } else { bar.green() }));
         }
         
         // Disk info
-        println!("\n{}", "Disk Space:".green().bold());
-        if let Ok(output) = std::process::Command::new("df")
-            .args(["-h", "/"])
-            .output()
-        {
-            let out = String::from_utf8_lossy(&output.stdout);
-            for line in out.lines().skip(1) {
-                println!("  {}", line);
-            }
+        lines.push(format!("\n{}", "Disk Space:".green().bold()));
+        for disk in &report.disks {
+            lines.push(format!("  {}  {}  {}  {}  {}  {}",
+                disk.filesystem, disk.size, disk.used, disk.available, disk.use_percent, disk.mounted_on));
         }
         
         // Active VMs
-        println!("\n{}", "Active VMs:".green().bold());
-        if let Ok(output) = std::process::Command::new("pgrep")
-            .args(["-a", "qemu"])
-            .output()
-        {
-            let out = String::from_utf8_lossy(&output.stdout);
-            if out.trim().is_empty() {
-                println!("  No QEMU processes running");
-            } else {
-                for line in out.lines().take(5) {
-                    // Extract just the VM name if possible
-                    if let Some(name_start) = line.find("-name") {
-                        let rest = &line[name_start + 6..];
-                        let name = rest.split_whitespace().next().unwrap_or("unknown");
-                        println!("  • {}", name.green());
-                    } else {
-                        println!("  • QEMU instance");
-                    }
+        lines.push(format!("\n{}", "Active VMs:".green().bold()));
+        if report.active_vms.is_empty() {
+            lines.push("  No QEMU processes running".to_string());
+        } else {
+            for vm in report.active_vms.iter().take(5) {
+                let mut line = match &vm.name {
+                    Some(name) => format!("  • {}", name.green()),
+                    None => "  • QEMU instance".to_string(),
+                };
+                if let Some(cpu) = vm.cpu_percent {
+                    line.push_str(&format!(" — CPU {:.0}%", cpu));
                 }
+                if let Some(rss) = vm.rss_mb {
+                    line.push_str(&format!(" — RSS {}M", rss));
+                }
+                lines.push(line);
             }
         }
         
         // Network status
-        println!("\n{}", "Network Bridges:".green().bold());
-        if let Ok(output) = std::process::Command::new("ip")
-            .args(["link", "show", "type", "bridge"])
-            .output()
-        {
-            let out = String::from_utf8_lossy(&output.stdout);
-            if out.trim().is_empty() {
-                println!("  No bridges configured");
-            } else {
-                let mut count = 0;
-                for line in out.lines() {
-                    if line.contains("nullsec") {
-                        if let Some(name) = line.split(':').nth(1) {
-                            println!("  • {}", name.trim().split('@').next().unwrap_or(""));
-                            count += 1;
-                        }
-                    }
+        lines.push(format!("\n{}", "Network Bridges:".green().bold()));
+        if report.bridges.is_empty() {
+            lines.push("  No NullSec bridges found".to_string());
+        } else {
+            for bridge in &report.bridges {
+                lines.push(format!("  • {}", bridge));
+            }
+        }
+        
+        lines.push(format!("{}", "═".repeat(60).blue()));
+        
+        lines.join("\n") + "\n"
+    }
+    
+    /// Print system dashboard to terminal
+    pub fn print_dashboard() -> Result<()> {
+        print!("{}", render_dashboard(&dashboard_json()?));
+        Ok(())
+    }
+    
+    /// Redraw the dashboard every `interval` until Ctrl-C or `q`
+    pub fn watch_dashboard(interval: std::time::Duration) -> Result<()> {
+        use crossterm::{cursor, execute, terminal};
+        
+        let mut stdout = std::io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        
+        let result = watch_loop(&mut stdout, interval);
+        
+        // Always hand the terminal back, even if a redraw failed
+        execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        
+        result
+    }
+    
+    fn watch_loop(stdout: &mut std::io::Stdout, interval: std::time::Duration) -> Result<()> {
+        use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+        use crossterm::{cursor, queue, terminal};
+        use std::io::Write;
+        
+        loop {
+            let report = dashboard_json()?;
+            
+            queue!(stdout, cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::All))?;
+            // Raw mode doesn't translate newlines into carriage returns
+            write!(stdout, "{}", render_dashboard(&report).replace('\n', "\r\n"))?;
+            write!(stdout, "\r\nRefreshing every {}s — press q or Ctrl-C to exit", interval.as_secs())?;
+            stdout.flush()?;
+            
+            let deadline = std::time::Instant::now() + interval;
+            while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+                if !event::poll(remaining)? {
+                    break;
                 }
-                if count == 0 {
-                    println!("  No NullSec bridges found");
+                if let Event::Key(key) = event::read()? {
+                    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if key.kind == KeyEventKind::Press && (ctrl_c || key.code == KeyCode::Char('q')) {
+                        return Ok(());
+                    }
                 }
             }
         }
+    }
+    
+    /// Set from the SIGINT/SIGTERM handler to stop `serve_metrics`
+    static METRICS_STOP: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    
+    extern "C" fn stop_metrics(_: nix::libc::c_int) {
+        METRICS_STOP.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+    
+    /// Quote a Prometheus label value
+    fn metric_label(value: &str) -> String {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        format!("\"{}\"", escaped)
+    }
+    
+    /// Render a dashboard snapshot and the VM list in the Prometheus text format
+    pub fn render_metrics(report: &DashboardReport, vms: &[crate::vm::VmInfo]) -> String {
+        use std::fmt::Write;
+        
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        
+        let running = vms.iter().filter(|info| info.status == crate::vm::VmStatus::Running).count();
+        metric("n01d_vms_total", "gauge", "Number of VMs defined", vec![(String::new(), vms.len().to_string())]);
+        metric("n01d_vms_running", "gauge", "Number of VMs running", vec![(String::new(), running.to_string())]);
+        
+        // QEMU processes started without -name are told apart by pid alone
+        let vm_labels = |vm: &ActiveVm| {
+            let name = vm.name.as_deref().unwrap_or("");
+            format!("{{name={},pid=\"{}\"}}", metric_label(name), vm.pid)
+        };
+        metric(
+            "n01d_vm_cpu_percent", "gauge", "CPU usage of a QEMU process (100 = one full core)",
+            report.active_vms.iter()
+                .filter_map(|vm| vm.cpu_percent.map(|cpu| (vm_labels(vm), format!("{:.2}", cpu))))
+                .collect(),
+        );
+        metric(
+            "n01d_vm_rss_bytes", "gauge", "Resident memory of a QEMU process",
+            report.active_vms.iter()
+                .filter_map(|vm| vm.rss_mb.map(|rss| (vm_labels(vm), (rss * 1024 * 1024).to_string())))
+                .collect(),
+        );
+        
+        if let Some(load) = report.load_average {
+            metric(
+                "n01d_host_load_average", "gauge", "Host load average",
+                ["1m", "5m", "15m"].iter().zip(load)
+                    .map(|(period, value)| (format!("{{period=\"{}\"}}", period), value.to_string()))
+                    .collect(),
+            );
+        }
+        if let Some(memory) = &report.memory {
+            metric(
+                "n01d_host_memory_total_bytes", "gauge", "Host memory",
+                vec![(String::new(), (memory.total_mb * 1024 * 1024).to_string())],
+            );
+            metric(
+                "n01d_host_memory_used_bytes", "gauge", "Host memory in use",
+                vec![(String::new(), (memory.used_mb * 1024 * 1024).to_string())],
+            );
+        }
         
-        println!("{}", "═".repeat(60).blue());
+        out
+    }
+    
+    /// Serve `render_metrics` on `listen` at `/metrics` until Ctrl-C
+    pub fn serve_metrics(listen: &str) -> Result<()> {
+        use anyhow::Context;
+        use colored::*;
+        use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+        use std::sync::atomic::Ordering;
+        
+        let server = tiny_http::Server::http(listen)
+            .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", listen, e))?;
+        
+        let action = SigAction::new(SigHandler::Handler(stop_metrics), SaFlags::empty(), SigSet::empty());
+        for sig in [Signal::SIGINT, Signal::SIGTERM] {
+            // SAFETY: the handler only stores to an atomic
+            unsafe { signal::sigaction(sig, &action) }.context("Failed to install signal handler")?;
+        }
         
+        println!("{} Serving metrics on http://{}/metrics (Ctrl-C to stop)", "[*]".blue(), listen);
+        
+        // Polled so the stop flag is noticed between scrapes
+        while !METRICS_STOP.load(Ordering::SeqCst) {
+            let request = match server.recv_timeout(std::time::Duration::from_millis(500)) {
+                Ok(Some(request)) => request,
+                Ok(None) => continue,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            
+            let response = if request.url().split('?').next() != Some("/metrics") {
+                tiny_http::Response::from_string("Not found\n").with_status_code(404)
+            } else {
+                match dashboard_json().and_then(|report| Ok(render_metrics(&report, &crate::vm::scan_vms()?))) {
+                    Ok(body) => tiny_http::Response::from_string(body),
+                    Err(e) => tiny_http::Response::from_string(format!("{}\n", e)).with_status_code(500),
+                }
+            };
+            let response = response.with_header(
+                "Content-Type: text/plain; version=0.0.4; charset=utf-8".parse::<tiny_http::Header>().unwrap(),
+            );
+            if let Err(e) = request.respond(response) {
+                println!("{} Failed to answer scrape: {}", "[!]".yellow(), e);
+            }
+        }
+        
+        println!("{} Metrics server stopped", "[+]".green());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_gui_config_round_trip() {
+        let config = GuiConfig {
+            theme: Theme::Light,
+            window_width: 1600,
+            show_statusbar: false,
+            ..GuiConfig::default()
+        };
+        let text = toml::to_string(&config).unwrap();
+        assert!(text.contains("theme = \"light\""));
+        assert_eq!(toml::from_str::<GuiConfig>(&text).unwrap(), config);
+        
+        // Settings missing from an older file keep their defaults
+        let partial: GuiConfig = toml::from_str("theme = \"system\"").unwrap();
+        assert_eq!(partial, GuiConfig { theme: Theme::System, ..GuiConfig::default() });
+    }
+}
@@ -0,0 +1,116 @@
+//! ISO Module - Fetch installation media
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Where downloaded ISOs are kept, shared with the desktop app
+pub fn get_iso_dir() -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("n01d-machine")
+        .join("isos")
+}
+
+/// Pick a local file name from the last path segment of the URL
+fn file_name_from_url(url: &str) -> Result<String> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    
+    if name.is_empty() || name.starts_with('.') || name.contains('\\') {
+        bail!("Can't derive a file name from '{}'", url);
+    }
+    
+    Ok(name.to_string())
+}
+
+/// Download `url` into the ISO directory, resuming a `.part` file left by an
+/// earlier attempt. `progress` gets (downloaded, total) bytes as chunks arrive.
+/// With `sha256`, the file is only kept if the checksum matches
+pub fn download_iso<F>(url: &str, sha256: Option<&str>, mut progress: F) -> Result<PathBuf>
+where
+    F: FnMut(u64, Option<u64>),
+{
+    let iso_dir = get_iso_dir();
+    let name = file_name_from_url(url)?;
+    let dest = iso_dir.join(&name);
+    let part = iso_dir.join(format!("{}.part", name));
+    
+    if dest.exists() {
+        bail!("{} already exists", dest.display());
+    }
+    fs::create_dir_all(&iso_dir)?;
+    
+    let resume_from = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    
+    let client = reqwest::blocking::Client::builder()
+        .timeout(None)
+        .build()?;
+    let mut request = client.get(url);
+    if resume_from > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", resume_from));
+    }
+    
+    let mut response = request.send().context("Download failed")?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("Download failed: HTTP {}", status);
+    }
+    
+    // A server that ignores Range sends the whole file again
+    let resumed = status == reqwest::StatusCode::PARTIAL_CONTENT;
+    let mut downloaded = if resumed { resume_from } else { 0 };
+    let total = response.content_length().map(|len| len + downloaded);
+    
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(&part)
+        .with_context(|| format!("Failed to open {}", part.display()))?;
+    
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = response.read(&mut buf).context("Download interrupted")?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])?;
+        downloaded += n as u64;
+        progress(downloaded, total);
+    }
+    file.sync_all()?;
+    drop(file);
+    
+    if let Some(expected) = sha256 {
+        let actual = sha256_file(&part)?;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            let _ = fs::remove_file(&part);
+            bail!("Checksum mismatch for {}: expected {}, got {}", name, expected, actual);
+        }
+    }
+    
+    fs::rename(&part, &dest)?;
+    Ok(dest)
+}
+
+/// Hex-encoded SHA-256 of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    use ring::digest::{Context as Digest, SHA256};
+    
+    let mut file = fs::File::open(path)?;
+    let mut digest = Digest::new(&SHA256);
+    let mut buf = vec![0u8; 1 << 20];
+    
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        digest.update(&buf[..n]);
+    }
+    
+    Ok(digest.finish().as_ref().iter().map(|b| format!("{:02x}", b)).collect())
+}
@@ -4,10 +4,15 @@ mod vm;
 mod sandbox;
 mod network;
 mod gui;
+mod iso;
+mod shell;
+mod backup;
+mod doctor;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use colored::*;
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 
 const BANNER: &str = r#"
                 ___  __    __                      __    _          
@@ -23,12 +28,16 @@ const BANNER: &str = r#"
 #[derive(Parser)]
 #[command(name = "n01d")]
 #[command(about = "n01d Machine - Secure virtualization with sandboxing")]
-#[command(version = "1.0.0")]
+#[command(version)]
 struct Cli {
     /// Launch GUI mode
     #[arg(long)]
     gui: bool,
     
+    /// Launch terminal UI mode
+    #[arg(long)]
+    tui: bool,
+    
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -40,13 +49,33 @@ enum Commands {
         /// Show detailed info
         #[arg(short, long)]
         verbose: bool,
+        
+        /// Only VMs with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        
+        /// Only VMs whose name or description contains this text
+        #[arg(long)]
+        search: Option<String>,
+        
+        /// Print the VMs as a JSON array instead
+        #[arg(long, conflicts_with = "verbose")]
+        json: bool,
     },
     
     /// Create a new VM
     Create {
-        /// VM name
+        /// VM name; without one, the VMs in ./.n01d.toml are created
         #[arg(short, long)]
-        name: String,
+        name: Option<String>,
+        
+        /// Create every VM in a TOML manifest instead
+        #[arg(long, value_name = "MANIFEST", conflicts_with = "name")]
+        from: Option<PathBuf>,
+        
+        /// With a manifest, skip VMs that already exist instead of failing
+        #[arg(long, conflicts_with = "name")]
+        skip_existing: bool,
         
         /// RAM allocation (e.g., 2G)
         #[arg(long, default_value = "2G")]
@@ -67,21 +96,225 @@ enum Commands {
         /// VM template to use
         #[arg(long)]
         template: Option<String>,
+        
+        /// Guest architecture (x86_64, aarch64, riscv64)
+        #[arg(long, default_value = "x86_64")]
+        arch: String,
+        
+        /// Group tag, for starting related VMs together
+        #[arg(long)]
+        group: Option<String>,
+        
+        /// Free-form description
+        #[arg(long = "desc")]
+        description: Option<String>,
+        
+        /// Tag for filtering `list` (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        
+        /// Don't give the guest a virtio-rng entropy device
+        #[arg(long)]
+        no_rng: bool,
+        
+        /// Add a memory balloon device, for `balloon` to resize guest RAM
+        #[arg(long)]
+        balloon: bool,
+        
+        /// Don't pass guest TRIM through to the disk image (with it on, the
+        /// guest still needs `fstrim` or the `discard` mount option)
+        #[arg(long)]
+        no_discard: bool,
+        
+        /// Script to run before each start; the VM isn't started if it fails
+        #[arg(long, value_name = "SCRIPT")]
+        pre_start: Option<PathBuf>,
+        
+        /// Script to run after each stop
+        #[arg(long, value_name = "SCRIPT")]
+        post_stop: Option<PathBuf>,
+        
+        /// Network mode (nat, isolated, none, bridge); defaults to the
+        /// config's default_network
+        #[arg(long)]
+        network: Option<String>,
     },
     
-    /// Start a VM
-    Start {
+    /// Change a VM's description, tags and hooks
+    Edit {
+        /// VM name
+        vm: String,
+        
+        /// Add a tag (repeatable)
+        #[arg(long)]
+        add_tag: Vec<String>,
+        
+        /// Remove a tag (repeatable)
+        #[arg(long)]
+        remove_tag: Vec<String>,
+        
+        /// New description; an empty string clears it
+        #[arg(long = "desc")]
+        description: Option<String>,
+        
+        /// New pre-start hook script; an empty string clears it
+        #[arg(long, value_name = "SCRIPT")]
+        pre_start: Option<String>,
+        
+        /// New post-stop hook script; an empty string clears it
+        #[arg(long, value_name = "SCRIPT")]
+        post_stop: Option<String>,
+    },
+    
+    /// Register an existing disk image as a VM
+    Register {
+        /// VM name
+        #[arg(short, long)]
+        name: String,
+        
+        /// Disk image (qcow2, raw, vmdk, ...)
+        disk: PathBuf,
+        
+        /// RAM allocation (e.g., 2G)
+        #[arg(long, default_value = "2G")]
+        ram: String,
+        
+        /// Number of CPUs
+        #[arg(long, default_value = "2")]
+        cpus: u32,
+        
+        /// Copy the image into the VM directory
+        #[arg(long, conflicts_with = "symlink")]
+        copy: bool,
+        
+        /// Symlink the image into the VM directory
+        #[arg(long)]
+        symlink: bool,
+    },
+    
+    /// Convert a VirtualBox, VMware or Hyper-V disk (vdi, vmdk, vhdx) into a new VM
+    ImportDisk {
+        /// Disk image to convert
+        src: PathBuf,
+        
         /// VM name
+        #[arg(short, long)]
         name: String,
         
+        /// Delete the source image (and a split VMDK's extents) after conversion
+        #[arg(long = "move")]
+        move_source: bool,
+    },
+    
+    /// Start a VM
+    Start {
+        /// VM name; without one (or --group), the VMs in ./.n01d.toml are started
+        name: Option<String>,
+        
+        /// Start every VM in this group instead
+        #[arg(long, conflicts_with = "name")]
+        group: Option<String>,
+        
         /// Run in isolated mode
         #[arg(long)]
         isolated: bool,
         
-        /// Network mode (nat, isolated, none, bridge)
-        #[arg(long, default_value = "nat")]
-        network: String,
+        /// Network mode (nat, isolated, none, bridge); defaults to the VM's
+        /// current one (saved for later starts)
+        #[arg(long)]
+        network: Option<String>,
+        
+        /// Headless mode
+        #[arg(long)]
+        headless: bool,
+        
+        /// Print the QEMU command instead of running it
+        #[arg(long, requires = "name")]
+        dry_run: bool,
+        
+        /// CPU topology as sockets,cores,threads; sets the CPU count to their product
+        #[arg(long, value_name = "S,C,T", conflicts_with = "group")]
+        topology: Option<vm::Topology>,
+        
+        /// Wait until the guest's SSH port answers (default timeout 120s)
+        #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "120", conflicts_with = "group")]
+        wait_ssh: Option<u64>,
+        
+        /// Expose VT-x/AMD-V so the guest can run its own KVM guests (saved for later starts)
+        #[arg(long, conflicts_with = "group")]
+        nested: bool,
+        
+        /// Back guest RAM with hugepages (saved for later starts)
+        #[arg(long, conflicts_with = "group")]
+        hugepages: bool,
         
+        /// Bind guest RAM and vCPUs to a host NUMA node (saved for later starts, Linux only)
+        #[arg(long, value_name = "NODE", conflicts_with = "group")]
+        numa: Option<u32>,
+        
+        /// Snapshot the disk before every boot (saved for later starts)
+        #[arg(long, conflicts_with = "group")]
+        snapshot_on_start: bool,
+        
+        /// Roll back to the newest autostart snapshot on every stop (saved for later starts); a guest that shuts itself down is rolled back on its next start
+        #[arg(long, conflicts_with = "group")]
+        revert_on_stop: bool,
+        
+        /// Autostart snapshots to keep (saved, default 3)
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "group")]
+        keep_snapshots: Option<u32>,
+        
+        /// Pass a host USB device through, by vendorid:productid (repeatable, saved for later starts)
+        #[arg(long, value_name = "VID:PID", conflicts_with = "group")]
+        usb: Vec<vm::UsbId>,
+        
+        /// Add a watchdog and what to do when it fires (saved for later starts); the guest has to run a watchdog daemon
+        #[arg(long, value_name = "ACTION", value_parser = clap::builder::PossibleValuesParser::new(vm::WATCHDOG_ACTIONS), conflicts_with = "group")]
+        watchdog: Option<String>,
+        
+        /// Boot firmware (saved for later starts); secure-boot needs OVMF with Secure Boot support
+        #[arg(long, value_enum, conflicts_with = "group")]
+        firmware: Option<vm::Firmware>,
+        
+        /// Limit disk I/O operations per second (saved for later starts)
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "group")]
+        iops: Option<u64>,
+        
+        /// Limit disk throughput in bytes per second (saved for later starts)
+        #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "group")]
+        bps: Option<u64>,
+        
+        /// How to show the guest's screen (saved for later starts); spice opens remote-viewer
+        #[arg(long, value_enum, conflicts_with = "group")]
+        display: Option<vm::DisplayMode>,
+        
+        /// Share the clipboard over SPICE (saved for later starts); the guest needs spice-vdagent
+        #[arg(long, conflicts_with = "group")]
+        clipboard: bool,
+        
+        /// Offer a host folder to the guest over SPICE (saved for later starts); the guest needs spice-webdavd
+        #[arg(long, value_name = "DIR", conflicts_with = "group")]
+        share_folder: Option<PathBuf>,
+        
+        /// vCPUs `cpu add` can grow the VM to (saved for later starts)
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "group")]
+        maxcpus: Option<u32>,
+        
+        /// Memory `mem add` can grow the VM to, e.g. 8G (saved for later starts)
+        #[arg(long, value_name = "SIZE", conflicts_with = "group")]
+        maxmem: Option<String>,
+        
+        /// Raw argument passed to QEMU after n01d's own, repeatable (saved for later starts, replacing any stored ones); not validated
+        #[arg(long = "qemu-arg", value_name = "ARG", allow_hyphen_values = true, conflicts_with = "group")]
+        qemu_arg: Vec<String>,
+        
+        /// Reset a saved setting to its default before applying the flags above, repeatable
+        #[arg(long, value_enum, value_name = "SETTING", conflicts_with = "group")]
+        clear: Vec<vm::StartSetting>,
+    },
+    
+    /// Create the VMs in ./.n01d.toml that don't exist yet and start them
+    Up {
         /// Headless mode
         #[arg(long)]
         headless: bool,
@@ -90,30 +323,146 @@ enum Commands {
     /// Stop a VM
     Stop {
         /// VM name
-        name: String,
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+        
+        /// Stop every running VM
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
         
         /// Force stop
         #[arg(short, long)]
         force: bool,
     },
     
-    /// Create a sandbox environment
+    /// Create a sandbox environment, or enter an existing one
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Sandbox {
+        #[command(subcommand)]
+        command: Option<SandboxCommands>,
+        
         /// Sandbox name
-        #[arg(short, long)]
-        name: String,
+        #[arg(short, long, required = true)]
+        name: Option<String>,
         
         /// Isolation level (minimal, low, medium, high, max)
         #[arg(long, default_value = "medium")]
         isolation: String,
         
-        /// Base image
+        /// Root filesystem for the sandbox: a rootfs directory or a .tar / .tar.zst
         #[arg(long)]
         image: Option<String>,
         
         /// Command to run
         #[arg(long)]
         cmd: Option<String>,
+        
+        /// Allow read-only access to a path (Landlock; repeatable)
+        #[arg(long = "allow-read", value_name = "PATH")]
+        allow_read: Vec<String>,
+        
+        /// Allow read-write access to a path (Landlock; repeatable)
+        #[arg(long = "allow-write", value_name = "PATH")]
+        allow_write: Vec<String>,
+        
+        /// Let GUI apps reach the host's X11/Wayland display (with an
+        /// untrusted X11 cookie); ignored on headless hosts
+        #[arg(long)]
+        gui: bool,
+        
+        /// Isolation backend (unshare, firejail)
+        #[arg(long, default_value = "unshare")]
+        backend: String,
+        
+        /// Enter the sandbox right away instead of asking
+        #[arg(short, long)]
+        yes: bool,
+    },
+    
+    /// Enter a sandbox's rootfs, apply its Landlock policy and exec a command (used by enter.sh)
+    #[command(hide = true)]
+    SandboxExec {
+        /// Directory to pivot into as the new root
+        #[arg(long)]
+        root: Option<PathBuf>,
+        
+        /// Bring up loopback in a fresh network namespace
+        #[arg(long)]
+        loopback: bool,
+        
+        /// Read-only bind of a host path into the root (HOST:INSIDE)
+        #[arg(long, requires = "root", value_parser = sandbox::parse_bind)]
+        bind: Vec<(PathBuf, PathBuf)>,
+        
+        /// Path to allow read-only access to
+        #[arg(long = "ro")]
+        read_only: Vec<PathBuf>,
+        
+        /// Path to allow read-write access to
+        #[arg(long = "rw")]
+        writable: Vec<PathBuf>,
+        
+        /// Command to exec
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    
+    /// Set up or tear down a NAT sandbox's network namespace (used by enter.sh)
+    #[command(hide = true)]
+    SandboxNet {
+        /// up or down
+        #[arg(value_parser = ["up", "down"])]
+        action: String,
+        
+        /// Sandbox name
+        name: String,
+    },
+    
+    /// SSH into a running VM through its forwarded port
+    Ssh {
+        /// VM name
+        vm: String,
+        
+        /// Guest user to log in as
+        #[arg(short, long, default_value = "root")]
+        user: String,
+    },
+    
+    /// Set a running VM's memory through its balloon device
+    Balloon {
+        /// VM name
+        vm: String,
+        
+        /// Target guest memory in MB
+        target_mb: u64,
+    },
+    
+    /// Show a running VM's current ballooned memory
+    BalloonInfo {
+        /// VM name
+        vm: String,
+    },
+    
+    /// Add vCPUs to a running VM
+    Cpu {
+        #[command(subcommand)]
+        command: CpuCommands,
+    },
+    
+    /// Add memory to a running VM
+    Mem {
+        #[command(subcommand)]
+        command: MemCommands,
+    },
+    
+    /// Show a VM's QEMU log
+    Logs {
+        /// VM name
+        vm: String,
+        
+        /// Keep printing new output as it arrives
+        #[arg(short, long)]
+        follow: bool,
     },
     
     /// Take a snapshot
@@ -136,6 +485,24 @@ enum Commands {
         snapshot: String,
     },
     
+    /// Disk maintenance
+    Disk {
+        #[command(subcommand)]
+        command: DiskCommands,
+    },
+    
+    /// Back up or restore every VM at once
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommands,
+    },
+    
+    /// Installation media
+    Iso {
+        #[command(subcommand)]
+        command: IsoCommands,
+    },
+    
     /// Network management
     Network {
         #[command(subcommand)]
@@ -153,14 +520,184 @@ enum Commands {
         set: Option<String>,
     },
     
+    /// Show or change the GUI's theme, window size and bars
+    GuiConfig {
+        /// Color scheme
+        #[arg(long, value_enum)]
+        theme: Option<gui::Theme>,
+        
+        /// Initial window width in pixels
+        #[arg(long, value_name = "PX", value_parser = clap::value_parser!(i32).range(1..))]
+        width: Option<i32>,
+        
+        /// Initial window height in pixels
+        #[arg(long, value_name = "PX", value_parser = clap::value_parser!(i32).range(1..))]
+        height: Option<i32>,
+        
+        /// Show the toolbar
+        #[arg(long, value_name = "BOOL")]
+        toolbar: Option<bool>,
+        
+        /// Show the status bar
+        #[arg(long, value_name = "BOOL")]
+        statusbar: Option<bool>,
+    },
+    
+    /// Check installed dependencies and find leftovers from crashed
+    /// operations: VM dirs without config, missing disks, stale pidfiles,
+    /// orphaned TAP devices
+    Doctor {
+        /// Offer to remove each stale artifact found
+        #[arg(long)]
+        fix: bool,
+    },
+    
+    /// Report versions, host details and installed tools for bug reports
+    Info {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    
     /// Show system dashboard
-    Dashboard,
+    Dashboard {
+        /// Print the dashboard as JSON
+        #[arg(long)]
+        json: bool,
+        
+        /// Redraw every N seconds until Ctrl-C
+        #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "2")]
+        watch: Option<u64>,
+    },
+    
+    /// Serve VM and host metrics for Prometheus
+    Metrics {
+        /// Address to serve /metrics on
+        #[arg(long, default_value = "127.0.0.1:9101")]
+        listen: String,
+    },
     
     /// VPN management
     Vpn {
         #[command(subcommand)]
         command: VpnCommands,
     },
+    
+    /// Tor onion services for VMs
+    Tor {
+        #[command(subcommand)]
+        command: TorCommands,
+    },
+    
+    /// Firewall rules loaded by security profiles
+    Firewall {
+        #[command(subcommand)]
+        command: FirewallCommands,
+    },
+    
+    /// Print a shell completion script (e.g. `n01d completions zsh > _n01d`)
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Subcommand)]
+enum SandboxCommands {
+    /// Enter a sandbox, rebuilding its isolation from sandbox.toml
+    Enter {
+        /// Sandbox name
+        name: String,
+        
+        /// Command to run instead of the stored one
+        #[arg(long)]
+        cmd: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CpuCommands {
+    /// Hotplug vCPUs, up to the VM's --maxcpus
+    Add {
+        /// VM name
+        vm: String,
+        
+        /// vCPUs to add
+        #[arg(default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemCommands {
+    /// Hotplug a memory DIMM, up to the VM's --maxmem
+    Add {
+        /// VM name
+        vm: String,
+        
+        /// Memory to add (e.g., 1G)
+        size: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DiskCommands {
+    /// Check a VM's disk for leaks and corruption
+    Check {
+        /// VM name
+        vm: String,
+        
+        /// Repair leaks and corruptions that are found
+        #[arg(long)]
+        repair: bool,
+    },
+    
+    /// Show how much host disk each VM is using
+    Usage,
+    
+    /// Rewrite a VM's disk to reclaim space freed in the guest
+    Compact {
+        /// VM name
+        vm: String,
+        
+        /// Also compress the image (smaller, slower to read)
+        #[arg(long)]
+        compress: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupCommands {
+    /// Archive all VMs into a timestamped .tar.zst
+    Create {
+        /// Directory to write the archive to
+        #[arg(short, long, default_value = ".")]
+        out: PathBuf,
+        
+        /// What to do with running VMs
+        #[arg(long, value_enum, default_value = "skip")]
+        running: backup::BackupRunning,
+    },
+    
+    /// Restore all VMs from a backup archive
+    Restore {
+        /// Archive written by `backup create`
+        archive: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum IsoCommands {
+    /// Download an ISO into ~/n01d-machine/isos
+    Download {
+        /// ISO URL
+        url: String,
+        
+        /// Expected SHA-256 of the ISO
+        #[arg(long)]
+        sha256: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -215,6 +752,36 @@ enum VpnCommands {
         /// Interface name (for WireGuard)
         #[arg(long)]
         interface: Option<String>,
+        
+        /// Only send this CIDR through the VPN (repeatable); default is everything
+        #[arg(long = "route", value_name = "CIDR")]
+        routes: Vec<String>,
+        
+        /// Stay in the foreground and reconnect whenever the VPN drops,
+        /// checking every N seconds, until `vpn disconnect`
+        #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "30")]
+        watchdog: Option<u64>,
+    },
+    
+    /// Print a WireGuard config as a QR code for mobile clients
+    Qr {
+        /// WireGuard config file
+        config: PathBuf,
+        
+        /// Swap dark and light, if the code shows up as a negative
+        #[arg(long)]
+        invert: bool,
+    },
+    
+    /// Show whether the VPN is up
+    Status {
+        /// VPN type
+        #[arg(long, default_value = "openvpn")]
+        vpn_type: String,
+        
+        /// Interface name (wg0 for WireGuard, first tun device for OpenVPN)
+        #[arg(long)]
+        interface: Option<String>,
     },
     
     /// Disconnect VPN
@@ -229,7 +796,42 @@ enum VpnCommands {
     },
     
     /// Start Tor proxy
-    Tor,
+    Tor {
+        /// Also route everything from this VM tap/bridge interface through Tor
+        #[arg(long, value_name = "IFACE")]
+        transparent: Option<String>,
+        
+        /// Remove the transparent proxy rules for --transparent's interface
+        #[arg(long, requires = "transparent")]
+        stop: bool,
+        
+        /// Tor TransPort for transparent proxying
+        #[arg(long, default_value = "9040")]
+        trans_port: u16,
+        
+        /// Tor DNSPort for transparent proxying
+        #[arg(long, default_value = "5353")]
+        dns_port: u16,
+    },
+}
+
+#[derive(Subcommand)]
+enum TorCommands {
+    /// Publish a VM port as an onion service and print its address
+    HiddenService {
+        /// VM name
+        vm: String,
+        
+        /// Onion port and guest port, e.g. 80:8080
+        #[arg(value_name = "VPORT:GPORT")]
+        ports: network::proxy::ServicePorts,
+    },
+}
+
+#[derive(Subcommand)]
+enum FirewallCommands {
+    /// List the n01d-* iptables chains and nftables tables currently loaded
+    Status,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -245,52 +847,263 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
     
+    if cli.tui {
+        gui::tui::launch_tui()?;
+        return Ok(());
+    }
+    
     match cli.command {
-        Some(Commands::List { verbose }) => {
+        Some(Commands::List { json: true, tag, search, .. }) => {
+            let vms: Vec<vm::VmInfo> = vm::list_vms_json()?
+                .into_iter()
+                .filter(|info| vm::matches_filter(info, tag.as_deref(), search.as_deref()))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&vms)?);
+        }
+        
+        Some(Commands::List { verbose, tag, search, .. }) => {
             println!("{}", BANNER.cyan());
-            vm::list_vms(verbose)?;
+            vm::list_vms(verbose, tag.as_deref(), search.as_deref())?;
         }
         
-        Some(Commands::Create { name, ram, disk, cpus, iso, template }) => {
+        Some(Commands::Create { name, from, skip_existing, ram, disk, cpus, iso, template, arch, group, description, tags, no_rng, balloon, no_discard, pre_start, post_stop, network }) => {
             println!("{}", BANNER.cyan());
-            println!("{} Creating VM '{}'...", "[n01d]".blue(), name);
             
-            let config = vm::VmConfig {
-                name: name.clone(),
-                ram,
-                disk,
-                cpus,
-                iso,
-                template,
+            if let Some(manifest) = from {
+                println!("{} Creating VMs from {}...", "[n01d]".blue(), manifest.display());
+                let created = vm::create_from_manifest(&manifest, skip_existing)?;
+                for name in &created {
+                    println!("{} VM '{}' created", "[+]".green(), name);
+                }
+                println!("{} {} VM(s) created", "[+]".green(), created.len());
+            } else if let Some(name) = name {
+                println!("{} Creating VM '{}'...", "[n01d]".blue(), name);
+                
+                let config = vm::VmConfig {
+                    name: name.clone(),
+                    ram,
+                    disk,
+                    cpus,
+                    iso,
+                    template,
+                    arch,
+                    group,
+                    description,
+                    tags,
+                    rng: !no_rng,
+                    balloon,
+                    discard: !no_discard,
+                    pre_start,
+                    post_stop,
+                    network,
+                };
+                
+                vm::create_vm(config)?;
+                println!("{} VM '{}' created successfully!", "[+]".green(), name);
+            } else {
+                let project = vm::project_file().ok_or_else(|| {
+                    anyhow::anyhow!("No VM name given and no {} in this directory", vm::PROJECT_FILE)
+                })?;
+                println!("{} Creating VMs from {}...", "[n01d]".blue(), project.display());
+                let created = vm::create_project(&project, skip_existing)?;
+                for name in &created {
+                    println!("{} VM '{}' created", "[+]".green(), name);
+                }
+                println!("{} {} VM(s) created", "[+]".green(), created.len());
+            }
+        }
+        
+        Some(Commands::Edit { vm, add_tag, remove_tag, description, pre_start, post_stop }) => {
+            vm::edit_vm(&vm, &add_tag, &remove_tag, description.as_deref(), pre_start.as_deref().map(Path::new), post_stop.as_deref().map(Path::new))?;
+            println!("{} VM '{}' updated", "[+]".green(), vm);
+        }
+        
+        Some(Commands::Register { name, disk, ram, cpus, copy, symlink }) => {
+            println!("{} Registering '{}' as VM '{}'...", "[n01d]".blue(), disk.display(), name);
+            
+            let import = if copy {
+                vm::DiskImport::Copy
+            } else if symlink {
+                vm::DiskImport::Symlink
+            } else {
+                vm::DiskImport::InPlace
             };
             
-            vm::create_vm(config)?;
-            println!("{} VM '{}' created successfully!", "[+]".green(), name);
+            vm::register_existing_disk(&name, &disk, &ram, cpus, import)?;
+            println!("{} VM '{}' registered", "[+]".green(), name);
+        }
+        
+        Some(Commands::ImportDisk { src, name, move_source }) => {
+            println!("{} Converting '{}' into VM '{}'...", "[n01d]".blue(), src.display(), name);
+            let (format, size) = vm::import_foreign_disk(&src, &name, move_source)?;
+            
+            println!("{} VM '{}' imported from {}, {} MB as qcow2", "[+]".green(), name, format, size / (1024 * 1024));
+            if move_source {
+                println!("{} Removed the original {}", "[*]".blue(), src.display());
+            }
+            println!(
+                "{} The guest was installed for other virtual hardware; it may need virtio drivers (virtio-win on Windows) to see every device",
+                "[!]".yellow()
+            );
         }
         
-        Some(Commands::Start { name, isolated, network, headless }) => {
+        Some(Commands::Start { name: Some(name), dry_run: true, isolated, network, headless, topology, nested, hugepages, numa, usb, watchdog, firmware, iops, bps, display, clipboard, share_folder, maxcpus, maxmem, qemu_arg, clear, .. }) => {
+            let flags = vm::StartFlags {
+                clear, nested, hugepages, numa_node: numa, usb, watchdog, firmware, iops_limit: iops, bps_limit: bps,
+                display, clipboard, share_folder, maxcpus, maxmem, qemu_args: qemu_arg,
+                ..Default::default()
+            };
+            println!("{}", vm::print_launch_command(&name, isolated, network.as_deref(), headless, topology, flags)?);
+        }
+        
+        Some(Commands::Start { name, group, isolated, network, headless, topology, wait_ssh, nested, hugepages, numa, snapshot_on_start, revert_on_stop, keep_snapshots, usb, watchdog, firmware, iops, bps, display, clipboard, share_folder, maxcpus, maxmem, qemu_arg, clear, .. }) => {
             println!("{}", BANNER.cyan());
-            println!("{} Starting VM '{}'...", "[n01d]".blue(), name);
             
             if isolated {
                 println!("{} Running in isolated mode", "[!]".yellow());
             }
             
-            vm::start_vm(&name, isolated, &network, headless)?;
+            if let Some(group) = group {
+                println!("{} Starting VMs in group '{}'...", "[n01d]".blue(), group);
+                for name in vm::start_group(&group, isolated, network.as_deref(), headless)? {
+                    println!("{} VM '{}' started", "[+]".green(), name);
+                }
+            } else if let Some(name) = name {
+                println!("{} Starting VM '{}'...", "[n01d]".blue(), name);
+                let flags = vm::StartFlags { clear, nested, hugepages, numa_node: numa, snapshot_on_start, revert_on_stop, keep_snapshots, usb, watchdog, firmware,
+                    iops_limit: iops, bps_limit: bps, display, clipboard, share_folder, maxcpus, maxmem, qemu_args: qemu_arg,
+                };
+                vm::start_vm(&name, isolated, network.as_deref(), headless, topology, flags)?;
+                let info = vm::load_vm_info(&name)?;
+                match vm::forwarded_ssh_port(&info) {
+                    Some(port) => println!("{} VM '{}' started, SSH on 127.0.0.1:{}", "[+]".green(), name, port),
+                    None => println!("{} VM '{}' started", "[+]".green(), name),
+                }
+                
+                if let Some(secs) = wait_ssh {
+                    let port = vm::ssh_port(&info);
+                    println!("{} Waiting for SSH on port {}...", "[*]".blue(), port);
+                    vm::wait_for_ssh(port, std::time::Duration::from_secs(secs))?;
+                    println!("{} SSH is reachable", "[+]".green());
+                }
+            } else {
+                let project = vm::project_file().ok_or_else(|| {
+                    anyhow::anyhow!("No VM name or --group given and no {} in this directory", vm::PROJECT_FILE)
+                })?;
+                println!("{} Starting VMs from {}...", "[n01d]".blue(), project.display());
+                for name in vm::start_project(&project, isolated, network.as_deref(), headless)? {
+                    println!("{} VM '{}' started", "[+]".green(), name);
+                }
+            }
         }
         
-        Some(Commands::Stop { name, force }) => {
-            println!("{} Stopping VM '{}'...", "[n01d]".blue(), name);
-            vm::stop_vm(&name, force)?;
-            println!("{} VM '{}' stopped", "[+]".green(), name);
+        Some(Commands::Up { headless }) => {
+            println!("{}", BANNER.cyan());
+            let project = vm::project_file().ok_or_else(|| {
+                anyhow::anyhow!("No {} in this directory", vm::PROJECT_FILE)
+            })?;
+            println!("{} Bringing up {}...", "[n01d]".blue(), project.display());
+            
+            let (created, started) = vm::project_up(&project, headless)?;
+            for name in &created {
+                println!("{} VM '{}' created", "[+]".green(), name);
+            }
+            for name in &started {
+                println!("{} VM '{}' started", "[+]".green(), name);
+            }
+        }
+        
+        Some(Commands::Stop { name, all, force }) => {
+            if all {
+                println!("{} Stopping all running VMs...", "[n01d]".blue());
+                let stopped = vm::stop_all_vms(force)?;
+                if stopped.is_empty() {
+                    println!("{} No running VMs", "[*]".blue());
+                }
+                for name in stopped {
+                    println!("{} VM '{}' stopped", "[+]".green(), name);
+                }
+            } else if let Some(name) = name {
+                println!("{} Stopping VM '{}'...", "[n01d]".blue(), name);
+                vm::stop_vm(&name, force)?;
+                println!("{} VM '{}' stopped", "[+]".green(), name);
+            }
+        }
+        
+        Some(Commands::Sandbox { command: Some(SandboxCommands::Enter { name, cmd }), .. }) => {
+            sandbox::enter_sandbox(&name, cmd.as_deref())?;
         }
         
-        Some(Commands::Sandbox { name, isolation, image, cmd }) => {
+        Some(Commands::Sandbox { name, isolation, image, cmd, allow_read, allow_write, gui, backend, yes, .. }) => {
+            // clap requires --name whenever there's no subcommand
+            let name = name.unwrap_or_default();
             println!("{}", BANNER.cyan());
             println!("{} Creating sandbox '{}'...", "[n01d]".blue(), name);
             println!("{} Isolation level: {}", "[*]".blue(), isolation.yellow());
             
-            sandbox::create_sandbox(&name, &isolation, image.as_deref(), cmd.as_deref())?;
+            let mounts = allow_read.into_iter().map(|p| (p, "ro".to_string()))
+                .chain(allow_write.into_iter().map(|p| (p, "rw".to_string())))
+                .collect();
+            let dir = sandbox::create_sandbox(&name, &isolation, image.as_deref(), cmd.as_deref(), mounts, gui, &backend)?;
+            
+            // Only ask when someone can answer; piped or scripted runs
+            // don't enter unless told to
+            let enter = yes || (std::io::stdin().is_terminal() && {
+                print!("\n{} Enter sandbox now? [y/N] ", "[?]".cyan());
+                std::io::Write::flush(&mut std::io::stdout())?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+            });
+            
+            if enter {
+                sandbox::enter_sandbox(&name, None)?;
+            } else {
+                println!("{} To enter sandbox: {} (or n01d sandbox enter {})", "[*]".blue(),
+                    dir.join("enter.sh").display(), name);
+            }
+        }
+        
+        Some(Commands::SandboxExec { root, loopback, bind, read_only, writable, command }) => {
+            let policy = (!read_only.is_empty() || !writable.is_empty())
+                .then_some(sandbox::LandlockPolicy { read_only, writable });
+            sandbox::exec_sandboxed(root.as_deref(), &bind, policy.as_ref(), loopback, &command)?;
+        }
+        
+        Some(Commands::SandboxNet { action, name }) => {
+            match action.as_str() {
+                "up" => { sandbox::setup_sandbox_net(&name)?; }
+                _ => sandbox::teardown_sandbox_net(&name)?,
+            }
+        }
+        
+        Some(Commands::Ssh { vm, user }) => {
+            vm::ssh_vm(&vm, &user)?;
+        }
+        
+        Some(Commands::Balloon { vm, target_mb }) => {
+            vm::set_balloon(&vm, target_mb)?;
+            println!("{} Balloon target for '{}' set to {} MB", "[+]".green(), vm, target_mb);
+        }
+        
+        Some(Commands::BalloonInfo { vm }) => {
+            let actual = vm::balloon_actual_mb(&vm)?;
+            println!("{} '{}' currently has {} MB", "[*]".blue(), vm, actual);
+        }
+        
+        Some(Commands::Cpu { command: CpuCommands::Add { vm, count } }) => {
+            let total = vm::hotplug_cpu(&vm, count)?;
+            println!("{} '{}' now has {} vCPUs", "[+]".green(), vm, total);
+        }
+        
+        Some(Commands::Mem { command: MemCommands::Add { vm, size } }) => {
+            let size_mb = vm::ram_mb(&size).ok_or_else(|| anyhow::anyhow!("Invalid memory size '{}'", size))?;
+            let total = vm::hotplug_memory(&vm, size_mb)?;
+            println!("{} '{}' now has {} MB", "[+]".green(), vm, total);
+        }
+        
+        Some(Commands::Logs { vm, follow }) => {
+            vm::show_logs(&vm, follow)?;
         }
         
         Some(Commands::Snapshot { vm, name }) => {
@@ -305,6 +1118,101 @@ fn main() -> anyhow::Result<()> {
             println!("{} VM restored successfully!", "[+]".green());
         }
         
+        Some(Commands::Disk { command }) => {
+            match command {
+                DiskCommands::Check { vm, repair } => {
+                    println!("{} Checking disk of VM '{}'...", "[n01d]".blue(), vm);
+                    let check = if repair { vm::repair_disk(&vm)? } else { vm::check_disk(&vm)? };
+                    
+                    println!("  Errors:      {}", check.check_errors);
+                    println!("  Corruptions: {}", check.corruptions);
+                    println!("  Leaks:       {}", check.leaks);
+                    if let Some(offset) = check.image_end_offset {
+                        println!("  Image end:   {} bytes", offset);
+                    }
+                    if repair {
+                        println!("  Repaired:    {} corruptions, {} leaks", check.corruptions_fixed, check.leaks_fixed);
+                    }
+                    
+                    if check.is_clean {
+                        println!("{} No errors found", "[+]".green());
+                    } else if repair {
+                        println!("{} Some problems could not be repaired", "[!]".yellow());
+                    } else {
+                        println!("{} Problems found, run with --repair to fix them", "[!]".yellow());
+                    }
+                }
+                DiskCommands::Usage => {
+                    let report = vm::disk_report()?;
+                    let gb = |bytes: u64| format!("{:.1}G", bytes as f64 / (1u64 << 30) as f64);
+                    
+                    println!("{:<24} {:>10} {:>10} {:>6}", "VM".bold(), "Allocated".bold(), "Virtual".bold(), "Used".bold());
+                    for (name, actual, virt) in &report {
+                        let used = if *virt > 0 { actual * 100 / virt } else { 0 };
+                        let line = format!("{:<24} {:>10} {:>10} {:>5}%", name, gb(*actual), gb(*virt), used);
+                        // Allocation close to the virtual size leaves little room to grow
+                        if used >= 90 {
+                            println!("{}", line.yellow());
+                        } else {
+                            println!("{}", line);
+                        }
+                    }
+                    
+                    let allocated: u64 = report.iter().map(|(_, actual, _)| actual).sum();
+                    let virtual_total: u64 = report.iter().map(|(_, _, virt)| virt).sum();
+                    println!("{:<24} {:>10} {:>10}", "Total".bold(), gb(allocated), gb(virtual_total));
+                }
+                DiskCommands::Compact { vm, compress } => {
+                    println!("{} Compacting disk of VM '{}'...", "[n01d]".blue(), vm);
+                    let (before, after) = vm::compact_disk(&vm, compress)?;
+                    
+                    let mb = |bytes: u64| bytes / (1024 * 1024);
+                    println!(
+                        "{} {} MB -> {} MB ({} MB reclaimed)",
+                        "[+]".green(), mb(before), mb(after), mb(before.saturating_sub(after))
+                    );
+                }
+            }
+        }
+        
+        Some(Commands::Backup { command }) => {
+            match command {
+                BackupCommands::Create { out, running } => {
+                    println!("{} Backing up all VMs...", "[n01d]".blue());
+                    backup::backup_all(&out, running)?;
+                }
+                BackupCommands::Restore { archive } => {
+                    println!("{} Restoring VMs from {}...", "[n01d]".blue(), archive.display());
+                    backup::restore_all(&archive)?;
+                }
+            }
+        }
+        
+        Some(Commands::Iso { command }) => {
+            match command {
+                IsoCommands::Download { url, sha256 } => {
+                    println!("{} Downloading {}...", "[n01d]".blue(), url);
+                    
+                    let path = iso::download_iso(&url, sha256.as_deref(), |done, total| {
+                        let mb = done / (1024 * 1024);
+                        match total {
+                            Some(total) if total > 0 => {
+                                print!("\r{} {} / {} MB ({}%)", "[*]".blue(), mb, total / (1024 * 1024), done * 100 / total)
+                            }
+                            _ => print!("\r{} {} MB", "[*]".blue(), mb),
+                        }
+                        let _ = std::io::Write::flush(&mut std::io::stdout());
+                    })?;
+                    
+                    println!();
+                    if sha256.is_some() {
+                        println!("{} Checksum verified", "[+]".green());
+                    }
+                    println!("{} Saved to {}", "[+]".green(), path.display());
+                }
+            }
+        }
+        
         Some(Commands::Network { command }) => {
             match command {
                 NetworkCommands::List => {
@@ -330,25 +1238,186 @@ fn main() -> anyhow::Result<()> {
                 vm::show_config()?;
             } else if let Some(value) = set {
                 vm::set_config(&value)?;
+                println!("{} Set {}", "[+]".green(), value.trim());
             }
         }
         
-        Some(Commands::Dashboard) => {
-            println!("{}", BANNER.cyan());
-            gui::dashboard::print_dashboard()?;
+        Some(Commands::GuiConfig { theme, width, height, toolbar, statusbar }) => {
+            let mut config = gui::GuiConfig::load()?;
+            let changed = theme.is_some() || width.is_some() || height.is_some() || toolbar.is_some() || statusbar.is_some();
+            config.theme = theme.unwrap_or(config.theme);
+            config.window_width = width.unwrap_or(config.window_width);
+            config.window_height = height.unwrap_or(config.window_height);
+            config.show_toolbar = toolbar.unwrap_or(config.show_toolbar);
+            config.show_statusbar = statusbar.unwrap_or(config.show_statusbar);
+            if changed {
+                config.save()?;
+                println!("{} Saved GUI settings to {}", "[+]".green(), gui::GuiConfig::path().display());
+            }
+            
+            println!("  Theme:      {:?}", config.theme);
+            println!("  Window:     {}x{}", config.window_width, config.window_height);
+            println!("  Toolbar:    {}", config.show_toolbar);
+            println!("  Status bar: {}", config.show_statusbar);
+        }
+        
+        Some(Commands::Info { json }) => {
+            let info = doctor::info();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+            } else {
+                let kernel = info.kernel.as_deref().map(|kernel| format!(", kernel {}", kernel)).unwrap_or_default();
+                println!("n01d {}", info.version);
+                println!("  OS:           {} ({}){}", info.os, info.arch, kernel);
+                println!("  Accelerator:  {}", info.accelerator.unwrap_or("none (TCG emulation)"));
+                println!("  Config:       {}", info.config_file.display());
+                println!("  VMs:          {} in {}", info.vms, info.vm_dir.display());
+                println!("  Sandboxes:    {}", info.sandboxes);
+                println!("  Tools:");
+                for dep in &info.dependencies {
+                    let version = match (dep.installed, &dep.version) {
+                        (true, Some(version)) => version.as_str(),
+                        (true, None) => "installed",
+                        (false, _) => "not found",
+                    };
+                    println!("    {:<20} {}", dep.name, version);
+                }
+            }
+        }
+        
+        Some(Commands::Dashboard { json, watch }) => {
+            if let Some(secs) = watch {
+                gui::dashboard::watch_dashboard(std::time::Duration::from_secs(secs.max(1)))?;
+            } else if json {
+                let report = gui::dashboard::dashboard_json()?;
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{}", BANNER.cyan());
+                gui::dashboard::print_dashboard()?;
+            }
+        }
+        
+        Some(Commands::Metrics { listen }) => {
+            gui::dashboard::serve_metrics(&listen)?;
+        }
+        
+        Some(Commands::Doctor { fix }) => {
+            let report = doctor::doctor()?;
+            
+            println!("{}", "Dependencies:".green().bold());
+            for dep in &report.dependencies {
+                match (dep.installed, &dep.version) {
+                    (true, Some(version)) => println!("  {} {} {}", "[+]".green(), dep.name, version),
+                    (true, None) => println!("  {} {}", "[+]".green(), dep.name),
+                    (false, _) => println!("  {} {} not found (needed for {})", "[-]".red(), dep.name, dep.purpose),
+                }
+            }
+            if report.kvm {
+                println!("  {} /dev/kvm is accessible", "[+]".green());
+            } else {
+                println!("  {} /dev/kvm is not accessible, VMs fall back to slow TCG emulation", "[!]".yellow());
+            }
+            println!();
+            
+            println!("{}", "Emulators:".green().bold());
+            for qemu in report.qemu.iter().filter(|qemu| qemu.installed) {
+                let speed = if qemu.accelerated { "hardware accelerated".green() } else { "emulated (TCG)".yellow() };
+                println!("  {} qemu-system-{} {}", "[+]".green(), qemu.arch, speed);
+            }
+            // Usually an ARM host with only the x86_64 emulator installed
+            if let Some(native) = report.qemu.iter().find(|qemu| qemu.arch == std::env::consts::ARCH && !qemu.installed) {
+                println!(
+                    "  {} Install qemu-system-{} and create VMs with --arch {} to run them at native speed",
+                    "[*]".blue(), native.arch, native.arch
+                );
+            }
+            println!();
+            
+            if report.issues.is_empty() {
+                println!("{} No problems found", "[+]".green());
+            }
+            
+            for issue in &report.issues {
+                println!("{} {}", "[!]".yellow(), issue);
+                if !fix || !issue.fixable() {
+                    continue;
+                }
+                
+                print!("    {}? [y/N] ", issue.fix_description());
+                std::io::Write::flush(&mut std::io::stdout())?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !answer.trim().eq_ignore_ascii_case("y") {
+                    continue;
+                }
+                
+                match doctor::fix(issue) {
+                    Ok(()) => println!("{} Fixed", "[+]".green()),
+                    Err(e) => println!("{} {:#}", "[-]".red(), e),
+                }
+            }
+            
+            let fixable = report.issues.iter().filter(|issue| issue.fixable()).count();
+            if !fix && fixable > 0 {
+                println!("{} Run with --fix to clean up {} of these", "[*]".blue(), fixable);
+            }
         }
         
         Some(Commands::Vpn { command }) => {
             match command {
-                VpnCommands::Connect { config, vpn_type, interface } => {
-                    match vpn_type.to_lowercase().as_str() {
-                        "wireguard" | "wg" => {
-                            let iface = interface.unwrap_or_else(|| "wg0".to_string());
-                            network::vpn::connect_wireguard(&iface, &config.to_string_lossy())?;
+                VpnCommands::Connect { config, vpn_type, interface, routes, watchdog } => {
+                    let (provider, name) = match vpn_type.to_lowercase().as_str() {
+                        "wireguard" | "wg" => (network::vpn::VpnProvider::WireGuard, interface.unwrap_or_else(|| "wg0".to_string())),
+                        _ => (network::vpn::VpnProvider::OpenVPN, "openvpn".to_string()),
+                    };
+                    let vpn = network::vpn::VpnConfig {
+                        name,
+                        provider,
+                        config_file: Some(config.to_string_lossy().to_string()),
+                        credentials: None,
+                        routes,
+                    };
+                    network::vpn::connect(&vpn)?;
+                    
+                    if let Some(secs) = watchdog {
+                        println!("{} Watchdog checking every {}s; `n01d vpn disconnect` stops it", "[*]".blue(), secs);
+                        network::vpn::start_vpn_watchdog(vpn, std::time::Duration::from_secs(secs.max(1)))?.wait();
+                    }
+                }
+                VpnCommands::Qr { config, invert } => {
+                    use anyhow::Context;
+                    
+                    let text = std::fs::read_to_string(&config)
+                        .with_context(|| format!("Failed to read {}", config.display()))?;
+                    print!("{}", network::vpn::wireguard_config_qr(&text, invert)?);
+                    println!();
+                    println!("{} The config holds the private key; don't scan it where others can see", "[!]".yellow());
+                }
+                VpnCommands::Status { vpn_type, interface } => {
+                    use network::vpn::VpnState;
+                    
+                    let provider = match vpn_type.to_lowercase().as_str() {
+                        "wireguard" | "wg" => network::vpn::VpnProvider::WireGuard,
+                        _ => network::vpn::VpnProvider::OpenVPN,
+                    };
+                    let status = network::vpn::vpn_status(provider.clone(), interface.as_deref())?;
+                    let iface = status.interface.as_deref().unwrap_or("-");
+                    
+                    match status.state {
+                        VpnState::Connected => println!("{} {:?} {}: connected", "[+]".green(), provider, iface),
+                        VpnState::Stale => println!("{} {:?} {}: stale (no recent handshake)", "[!]".yellow(), provider, iface),
+                        VpnState::Down => println!("{} {:?} {}: down", "[!]".yellow(), provider, iface),
+                    }
+                    if status.state != VpnState::Down {
+                        if let Some(endpoint) = &status.endpoint {
+                            println!("    Endpoint:       {}", endpoint);
                         }
-                        _ => {
-                            network::vpn::connect_openvpn(&config.to_string_lossy())?;
+                        match status.last_handshake {
+                            Some(age) => println!("    Last handshake: {} ago", vm::format_duration(age)),
+                            None if matches!(provider, network::vpn::VpnProvider::WireGuard) => println!("    Last handshake: never"),
+                            None => {}
                         }
+                        println!("    Transfer:       {} MB received, {} MB sent", status.rx_bytes / (1024 * 1024), status.tx_bytes / (1024 * 1024));
                     }
                 }
                 VpnCommands::Disconnect { vpn_type, interface } => {
@@ -358,16 +1427,53 @@ fn main() -> anyhow::Result<()> {
                     };
                     network::vpn::disconnect_vpn(provider, interface.as_deref())?;
                 }
-                VpnCommands::Tor => {
-                    network::proxy::start_tor_proxy()?;
+                VpnCommands::Tor { transparent, stop, trans_port, dns_port } => {
+                    let ports = network::proxy::TorPorts { trans_port, dns_port };
+                    match transparent {
+                        Some(iface) if stop => network::proxy::teardown_transparent_proxy(&iface, ports)?,
+                        Some(iface) => {
+                            network::proxy::start_tor_proxy(Some(ports))?;
+                            network::proxy::setup_transparent_proxy(&iface, ports)?;
+                        }
+                        None => network::proxy::start_tor_proxy(None)?,
+                    }
+                }
+            }
+        }
+        
+        Some(Commands::Tor { command }) => {
+            match command {
+                TorCommands::HiddenService { vm, ports } => {
+                    network::proxy::create_hidden_service(&vm, ports.guest_port, ports.virtual_port)?;
+                }
+            }
+        }
+        
+        Some(Commands::Firewall { command }) => {
+            match command {
+                FirewallCommands::Status => {
+                    let chains = network::firewall_chains()?;
+                    if chains.is_empty() {
+                        println!("{} No n01d firewall chains are loaded", "[*]".blue());
+                    }
+                    for chain in &chains {
+                        println!("  {} {:<8} {:<8} {}", "[+]".green(), chain.backend, chain.table, chain.name);
+                    }
                 }
             }
         }
         
+        Some(Commands::Completions { shell }) => {
+            let mut cmd = Cli::command();
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+        }
+        
         None => {
             println!("{}", BANNER.cyan());
             println!("Use --help for usage information");
             println!("Use --gui to launch the graphical interface");
+            println!("Use --tui to launch the terminal interface");
         }
     }
     
@@ -171,13 +171,14 @@ pub fn create_network(name: &str, mode: &str, subnet: Option<&str>) -> Result<()
                 .args(["sysctl", "-w", "net.ipv4.ip_forward=1"])
                 .status()?;
             
-            // Add NAT rule
-            let _ = Command::new("sudo")
-                .args([
-                    "iptables", "-t", "nat", "-A", "POSTROUTING",
-                    "-s", subnet, "-j", "MASQUERADE"
-                ])
-                .status();
+            // Add NAT rule, in the network's own chain so it can be listed,
+            // saved and removed with it
+            let chain = network_chain(name);
+            apply_firewall_rules(name, &[
+                IptablesRule::chain("nat", &chain),
+                IptablesRule::new("nat", "-A", &chain, &["-s", subnet, "-j", "MASQUERADE"]),
+                IptablesRule::new("nat", "-A", "POSTROUTING", &["-j", &chain]),
+            ])?;
             
             println!("{} NAT network '{}' created with gateway {}", "[+]".green(), name, gateway);
         }
@@ -195,11 +196,12 @@ pub fn create_network(name: &str, mode: &str, subnet: Option<&str>) -> Result<()
                 .status()?;
             
             // Block all forwarding for this bridge
-            let _ = Command::new("sudo")
-                .args([
-                    "iptables", "-I", "FORWARD", "-i", &bridge_name, "-j", "DROP"
-                ])
-                .status();
+            let chain = network_chain(name);
+            apply_firewall_rules(name, &[
+                IptablesRule::chain("filter", &chain),
+                IptablesRule::new("filter", "-A", &chain, &["-j", "DROP"]),
+                IptablesRule::new("filter", "-I", "FORWARD", &["-i", &bridge_name, "-j", &chain]),
+            ])?;
             
             println!("{} Isolated network '{}' created (no external access)", "[+]".green(), name);
         }
@@ -228,6 +230,23 @@ pub fn delete_network(name: &str) -> Result<()> {
         .status()
         .context("Failed to delete bridge")?;
     
+    // Whichever of the NAT or isolation chains the network had
+    if firewall_backend() == FirewallBackend::Nft {
+        let _ = delete_nft_table(name);
+    } else {
+        let chain = network_chain(name);
+        let jumps = [
+            ("nat", "POSTROUTING", vec!["-j".to_string(), chain.clone()]),
+            ("filter", "FORWARD", vec!["-i".to_string(), bridge_name.clone(), "-j".to_string(), chain.clone()]),
+        ];
+        for (table, builtin, jump) in jumps {
+            if iptables(table, "-D", builtin, &jump).is_ok() {
+                let _ = iptables(table, "-F", &chain, &[]);
+                let _ = iptables(table, "-X", &chain, &[]);
+            }
+        }
+    }
+    
     if status.success() {
         println!("{} Network '{}' deleted", "[+]".green(), name);
     } else {
@@ -272,6 +291,127 @@ pub fn inspect_traffic(target: &str, output: Option<&Path>) -> Result<()> {
     Ok(())
 }
 
+pub use n01d_common::firewall::IptablesRule;
+
+/// The `n01d-*` chain holding a network's NAT or isolation rule
+fn network_chain(name: &str) -> String {
+    format!("{}{}", n01d_common::firewall::FIREWALL_PREFIX, name)
+}
+
+/// How n01d's firewall rules get applied on this host. Picked the same
+/// way when adding and removing rules, so teardown undoes what setup did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FirewallBackend {
+    /// One `iptables-restore --noflush` transaction
+    IptablesRestore,
+    /// One `nft -f` transaction building an `n01d-*` table
+    Nft,
+    /// One `iptables` call per rule
+    Iptables,
+}
+
+pub fn firewall_backend() -> FirewallBackend {
+    if which::which("iptables-restore").is_ok() {
+        FirewallBackend::IptablesRestore
+    } else if which::which("nft").is_ok() {
+        FirewallBackend::Nft
+    } else {
+        FirewallBackend::Iptables
+    }
+}
+
+/// Add `rules` so they all land or none do: in one `iptables-restore` or
+/// `nft -f` transaction, or failing both one `iptables` call per rule,
+/// deleting the ones already added if a later one fails. `name` names the
+/// nft table the rules go in
+pub fn apply_firewall_rules(name: &str, rules: &[IptablesRule]) -> Result<()> {
+    match firewall_backend() {
+        FirewallBackend::IptablesRestore => {
+            let input = n01d_common::firewall::iptables_restore_input(rules);
+            if !pipe_to_sudo(&["iptables-restore", "--noflush"], &input)? {
+                anyhow::bail!("iptables-restore rejected the ruleset; no rules were applied");
+            }
+        }
+        FirewallBackend::Nft => {
+            let ruleset = n01d_common::firewall::nft_ruleset(name, rules).map_err(anyhow::Error::msg)?;
+            if !pipe_to_sudo(&["nft", "-f", "-"], &ruleset)? {
+                anyhow::bail!("nft rejected the ruleset; no rules were applied");
+            }
+        }
+        FirewallBackend::Iptables => {
+            for (i, rule) in rules.iter().enumerate() {
+                // An existing chain is emptied, as iptables-restore would
+                let added = if rule.action == "-N" {
+                    iptables(rule.table, "-N", &rule.chain, &[]).or_else(|_| iptables(rule.table, "-F", &rule.chain, &[]))
+                } else {
+                    iptables(rule.table, rule.action, &rule.chain, &rule.spec)
+                };
+                if let Err(e) = added {
+                    for rule in rules[..i].iter().rev() {
+                        let undo = if rule.action == "-N" { "-X" } else { "-D" };
+                        let _ = iptables(rule.table, undo, &rule.chain, &rule.spec);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drop the nft table `apply_firewall_rules` built for `name`
+fn delete_nft_table(name: &str) -> Result<()> {
+    let table = format!("{}{}", n01d_common::firewall::FIREWALL_PREFIX, name);
+    let status = Command::new("sudo")
+        .args(["nft", "delete", "table", "inet", &table])
+        .status()
+        .context("Failed to run nft")?;
+    
+    if !status.success() {
+        anyhow::bail!("nft delete table inet {} failed", table);
+    }
+    Ok(())
+}
+
+/// Run `args` under sudo with `input` on stdin
+fn pipe_to_sudo(args: &[&str], input: &str) -> Result<bool> {
+    use std::io::Write;
+    
+    let mut child = Command::new("sudo")
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", args[0]))?;
+    child.stdin.take().with_context(|| format!("{} has no stdin", args[0]))?
+        .write_all(input.as_bytes())?;
+    Ok(child.wait()?.success())
+}
+
+/// Check an interface name before it goes into iptables arguments or a
+/// sysctl key: what the kernel allows (at most 15 bytes), less the dots,
+/// slashes and whitespace that sysctl or a shell would read as syntax
+pub fn validate_interface_name(name: &str) -> Result<()> {
+    let valid = (1..=15).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        anyhow::bail!("Invalid interface name '{}'", name);
+    }
+    Ok(())
+}
+
+fn iptables(table: &str, action: &str, chain: &str, spec: &[String]) -> Result<()> {
+    let status = Command::new("sudo")
+        .args(["iptables", "-t", table, action, chain])
+        .args(spec)
+        .status()
+        .context("Failed to run iptables")?;
+    
+    if !status.success() {
+        anyhow::bail!("iptables {} {} {} failed", action, chain, spec.join(" "));
+    }
+    Ok(())
+}
+
 /// Create a TAP device for a VM
 pub fn create_tap_device(name: &str, bridge: &str) -> Result<String> {
     let tap_name = format!("tap-{}", name);
@@ -305,16 +445,43 @@ pub fn delete_tap_device(name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Every `n01d-*` chain and table currently loaded. A backend that isn't
+/// installed simply contributes nothing
+pub fn firewall_chains() -> Result<Vec<n01d_common::firewall::FirewallChain>> {
+    use n01d_common::firewall;
+    
+    let run = |args: &[&str]| -> Result<String> {
+        let output = Command::new("sudo").args(args).output().with_context(|| format!("Failed to run {}", args[0]))?;
+        if !output.status.success() {
+            anyhow::bail!("{} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    };
+    let mut chains = Vec::new();
+    
+    if which::which("iptables-save").is_ok() {
+        chains.extend(firewall::iptables_chains(&run(&["iptables-save"])?));
+    }
+    if which::which("nft").is_ok() {
+        chains.extend(firewall::nft_tables(&run(&["nft", "list", "tables"])?));
+    }
+    
+    Ok(chains)
+}
+
 /// VPN Integration
 pub mod vpn {
     use super::*;
     
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub struct VpnConfig {
+        /// Interface name for WireGuard
         pub name: String,
         pub provider: VpnProvider,
         pub config_file: Option<String>,
         pub credentials: Option<(String, String)>,
+        /// Split-tunnel CIDRs; empty for a full tunnel
+        pub routes: Vec<String>,
     }
     
     #[derive(Debug, Clone)]
@@ -324,30 +491,146 @@ pub mod vpn {
         Custom,
     }
     
-    pub fn connect_openvpn(config_file: &str) -> Result<()> {
+    /// Split `addr/prefix` into its parts, checking the prefix fits the address
+    pub fn parse_cidr(cidr: &str) -> Result<(std::net::IpAddr, u8)> {
+        let parsed = cidr.trim().split_once('/').and_then(|(addr, prefix)| {
+            let addr: std::net::IpAddr = addr.parse().ok()?;
+            let prefix: u8 = prefix.parse().ok()?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            (prefix <= max).then_some((addr, prefix))
+        });
+        parsed.with_context(|| format!("'{}' is not a valid CIDR", cidr))
+    }
+    
+    /// Where rewritten VPN configs are staged: `n01d` under $XDG_RUNTIME_DIR,
+    /// or a per-user dir in the temp dir. It's created 0700, and refused if
+    /// another user owns it or can get into it
+    fn private_runtime_dir() -> Result<std::path::PathBuf> {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+        
+        let uid = nix::unistd::getuid().as_raw();
+        let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(runtime) if !runtime.is_empty() => std::path::PathBuf::from(runtime).join("n01d"),
+            _ => std::env::temp_dir().join(format!("n01d-{}", uid)),
+        };
+        match fs::DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+        }
+        
+        let meta = fs::symlink_metadata(&dir)?;
+        if !meta.is_dir() || meta.uid() != uid || meta.permissions().mode() & 0o077 != 0 {
+            anyhow::bail!("{} isn't a private directory of this user", dir.display());
+        }
+        Ok(dir)
+    }
+    
+    /// Write `content` to `name` in `private_runtime_dir`, readable only by
+    /// this user
+    fn write_private(name: &str, content: &str) -> Result<std::path::PathBuf> {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        
+        let path = private_runtime_dir()?.join(name);
+        let _ = fs::remove_file(&path);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+    
+    /// OpenVPN directives that ignore the server's pushed routes and send
+    /// only `routes` through the tunnel
+    fn openvpn_route_fragment(routes: &[String]) -> Result<String> {
+        let mut fragment = String::from("route-nopull\n");
+        for route in routes {
+            match parse_cidr(route)? {
+                (std::net::IpAddr::V4(net), prefix) => {
+                    let mask = std::net::Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0));
+                    fragment.push_str(&format!("route {} {}\n", net, mask));
+                }
+                (std::net::IpAddr::V6(_), _) => fragment.push_str(&format!("route-ipv6 {}\n", route.trim())),
+            }
+        }
+        Ok(fragment)
+    }
+    
+    /// Connect with OpenVPN; a non-empty `routes` makes it a split tunnel
+    pub fn connect_openvpn(config_file: &str, routes: &[String]) -> Result<()> {
         use colored::*;
         
+        for warning in n01d_common::openvpn::validate_openvpn_config(Path::new(config_file))? {
+            println!("{} {}", "[!]".yellow(), warning);
+        }
+        
         println!("{} Connecting via OpenVPN...", "[*]".blue());
         
-        Command::new("sudo")
-            .args(["openvpn", "--config", config_file, "--daemon"])
-            .status()
-            .context("Failed to start OpenVPN")?;
+        let mut cmd = Command::new("sudo");
+        cmd.args(["openvpn", "--config", config_file]);
+        
+        // A second --config is read after the first, so its routes win
+        let fragment = if routes.is_empty() {
+            None
+        } else {
+            let path = write_private(&format!("openvpn-routes-{}.conf", std::process::id()), &openvpn_route_fragment(routes)?)?;
+            cmd.arg("--config").arg(&path);
+            println!("{} Split tunnel: {}", "[*]".blue(), routes.join(", "));
+            Some(path)
+        };
+        
+        // --daemon only forks once the config files have been read
+        let status = cmd.arg("--daemon").status();
+        if let Some(path) = fragment {
+            let _ = fs::remove_file(path);
+        }
+        status.context("Failed to start OpenVPN")?;
         
         println!("{} OpenVPN connection started", "[+]".green());
         Ok(())
     }
     
-    pub fn connect_wireguard(interface: &str, config_file: &str) -> Result<()> {
+    /// Bring up WireGuard; a non-empty `routes` replaces every peer's
+    /// AllowedIPs, making it a split tunnel
+    pub fn connect_wireguard(interface: &str, config_file: &str, routes: &[String]) -> Result<()> {
         use colored::*;
         
         println!("{} Bringing up WireGuard interface '{}'...", "[*]".blue(), interface);
         
-        // Copy config
+        let mut source = std::path::PathBuf::from(config_file);
+        let mut rewritten = None;
+        if !routes.is_empty() {
+            for route in routes {
+                parse_cidr(route)?;
+            }
+            let config = fs::read_to_string(config_file)
+                .with_context(|| format!("Failed to read {}", config_file))?;
+            let allowed = format!("AllowedIPs = {}", routes.join(", "));
+            let config: Vec<String> = config.lines()
+                .map(|line| if line.trim_start().starts_with("AllowedIPs") { allowed.clone() } else { line.to_string() })
+                .collect();
+            
+            let path = write_private(&format!("{}-{}.conf", interface, std::process::id()), &(config.join("\n") + "\n"))?;
+            println!("{} Split tunnel: {}", "[*]".blue(), routes.join(", "));
+            source = path.clone();
+            rewritten = Some(path);
+        }
+        
+        // Install the config, private to root whatever the source's mode
         let target_path = format!("/etc/wireguard/{}.conf", interface);
-        Command::new("sudo")
-            .args(["cp", config_file, &target_path])
-            .status()?;
+        let copied = Command::new("sudo")
+            .args(["install", "-m", "600"])
+            .arg(&source)
+            .arg(&target_path)
+            .status();
+        if let Some(path) = rewritten {
+            let _ = fs::remove_file(path);
+        }
+        copied?;
         
         // Bring up interface
         Command::new("sudo")
@@ -359,9 +642,310 @@ pub mod vpn {
         Ok(())
     }
     
-    pub fn disconnect_vpn(provider: VpnProvider, interface: Option<&str>) -> Result<()> {
+    /// Render a WireGuard config as a QR code of Unicode half blocks for
+    /// mobile clients to scan off the terminal.
+    ///
+    /// Blocks are drawn in the terminal's text color, so the light modules
+    /// and quiet zone are blocks and the dark modules are left blank: dark
+    /// on light on the usual light-on-dark terminal. `invert` draws the
+    /// dark modules instead, for terminals with a light background
+    pub fn wireguard_config_qr(config: &str, invert: bool) -> Result<String> {
+        use qrcode::render::unicode::Dense1x2;
+        
+        let code = qrcode::QrCode::new(config.trim_end().as_bytes())
+            .context("Config is too large for a QR code")?;
+        let (dark, light) = if invert {
+            (Dense1x2::Dark, Dense1x2::Light)
+        } else {
+            (Dense1x2::Light, Dense1x2::Dark)
+        };
+        Ok(code.render::<Dense1x2>()
+            .dark_color(dark)
+            .light_color(light)
+            .build())
+    }
+    
+    /// A WireGuard handshake older than this means the tunnel has gone quiet;
+    /// peers re-handshake every 2 minutes while traffic flows
+    pub const STALE_HANDSHAKE_SECS: u64 = 180;
+    
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum VpnState {
+        Connected,
+        /// Up, but no recent handshake
+        Stale,
+        Down,
+    }
+    
+    #[derive(Debug, Clone)]
+    pub struct VpnStatus {
+        pub state: VpnState,
+        pub interface: Option<String>,
+        pub endpoint: Option<String>,
+        /// Seconds since the last WireGuard handshake
+        pub last_handshake: Option<u64>,
+        pub rx_bytes: u64,
+        pub tx_bytes: u64,
+    }
+    
+    impl VpnStatus {
+        fn down(interface: Option<String>) -> Self {
+            VpnStatus { state: VpnState::Down, interface, endpoint: None, last_handshake: None, rx_bytes: 0, tx_bytes: 0 }
+        }
+    }
+    
+    /// Whether the VPN is actually up: WireGuard from `wg show <iface> dump`,
+    /// OpenVPN from its process and tun device
+    pub fn vpn_status(provider: VpnProvider, interface: Option<&str>) -> Result<VpnStatus> {
+        match provider {
+            VpnProvider::WireGuard => wireguard_status(interface.unwrap_or("wg0")),
+            VpnProvider::OpenVPN => openvpn_status(interface),
+            VpnProvider::Custom => anyhow::bail!("Status isn't available for custom VPNs"),
+        }
+    }
+    
+    fn wireguard_status(interface: &str) -> Result<VpnStatus> {
+        let output = Command::new("sudo")
+            .args(["wg", "show", interface, "dump"])
+            .output()
+            .context("Failed to run wg")?;
+        if !output.status.success() {
+            return Ok(VpnStatus::down(Some(interface.to_string())));
+        }
+        
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(parse_wg_dump(interface, &String::from_utf8_lossy(&output.stdout), now))
+    }
+    
+    /// Parse `wg show <iface> dump`: an interface line, then one line per peer
+    /// with public key, preshared key, endpoint, allowed IPs, latest
+    /// handshake (unix time, 0 for never), rx, tx and keepalive
+    fn parse_wg_dump(interface: &str, dump: &str, now: u64) -> VpnStatus {
+        let mut status = VpnStatus::down(Some(interface.to_string()));
+        let mut latest = 0;
+        
+        for line in dump.lines().skip(1) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 7 {
+                continue;
+            }
+            let handshake: u64 = fields[4].parse().unwrap_or(0);
+            status.rx_bytes += fields[5].parse::<u64>().unwrap_or(0);
+            status.tx_bytes += fields[6].parse::<u64>().unwrap_or(0);
+            
+            // Report the peer we heard from most recently
+            if status.endpoint.is_none() || handshake > latest {
+                latest = handshake;
+                status.endpoint = Some(fields[2].to_string()).filter(|e| e != "(none)");
+            }
+        }
+        
+        status.last_handshake = (latest > 0).then(|| now.saturating_sub(latest));
+        status.state = match status.last_handshake {
+            Some(age) if age <= STALE_HANDSHAKE_SECS => VpnState::Connected,
+            _ => VpnState::Stale,
+        };
+        status
+    }
+    
+    /// Keepalive interval given to WireGuard peers the watchdog looks after
+    const WATCHDOG_KEEPALIVE_SECS: u32 = 25;
+    
+    /// Turn on PersistentKeepalive for every peer of `interface` that has
+    /// none. An idle tunnel otherwise stops handshaking and would look stale
+    /// to the watchdog; keepalives make it rekey every 2 minutes regardless
+    fn ensure_keepalive(interface: &str) -> Result<()> {
+        let output = Command::new("sudo")
+            .args(["wg", "show", interface, "dump"])
+            .output()
+            .context("Failed to run wg")?;
+        if !output.status.success() {
+            anyhow::bail!("WireGuard interface '{}' is not up", interface);
+        }
+        
+        // Peer lines end with the keepalive interval, "off" when unset
+        for line in String::from_utf8_lossy(&output.stdout).lines().skip(1) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 8 || fields[7] != "off" {
+                continue;
+            }
+            let status = Command::new("sudo")
+                .args(["wg", "set", interface, "peer", fields[0], "persistent-keepalive"])
+                .arg(WATCHDOG_KEEPALIVE_SECS.to_string())
+                .status()
+                .context("Failed to run wg")?;
+            if !status.success() {
+                anyhow::bail!("Failed to set a keepalive on {}", interface);
+            }
+        }
+        Ok(())
+    }
+    
+    fn openvpn_status(interface: Option<&str>) -> Result<VpnStatus> {
+        let running = Command::new("pgrep")
+            .args(["-x", "openvpn"])
+            .output()
+            .context("Failed to run pgrep")?
+            .status
+            .success();
+        
+        // Without a name, take the first tun device
+        let device = match interface {
+            Some(name) => Some(name.to_string()).filter(|name| Path::new("/sys/class/net").join(name).exists()),
+            None => fs::read_dir("/sys/class/net").ok().and_then(|entries| {
+                let mut tuns: Vec<String> = entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().to_string())
+                    .filter(|name| name.starts_with("tun"))
+                    .collect();
+                tuns.sort();
+                tuns.into_iter().next()
+            }),
+        };
+        
+        let Some(device) = device.filter(|_| running) else {
+            return Ok(VpnStatus::down(interface.map(str::to_string)));
+        };
+        
+        let stat = |name: &str| {
+            fs::read_to_string(Path::new("/sys/class/net").join(&device).join("statistics").join(name))
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Ok(VpnStatus {
+            state: VpnState::Connected,
+            rx_bytes: stat("rx_bytes"),
+            tx_bytes: stat("tx_bytes"),
+            interface: Some(device),
+            endpoint: None,
+            last_handshake: None,
+        })
+    }
+    
+    /// Bring the VPN described by `config` up
+    pub fn connect(config: &VpnConfig) -> Result<()> {
+        let config_file = config.config_file.as_deref().context("VPN has no config file")?;
+        match config.provider {
+            VpnProvider::WireGuard => connect_wireguard(&config.name, config_file, &config.routes),
+            VpnProvider::OpenVPN => connect_openvpn(config_file, &config.routes),
+            VpnProvider::Custom => anyhow::bail!("Custom VPNs can't be connected automatically"),
+        }
+    }
+    
+    /// Name of a VPN's watchdog marker in `private_runtime_dir`. It holds the
+    /// watchdog's pid, and a manual disconnect removes it so the watchdog
+    /// stands down instead of reconnecting
+    fn watchdog_marker(provider: &VpnProvider, interface: Option<&str>) -> String {
+        let key = match provider {
+            VpnProvider::WireGuard => interface.unwrap_or("wg0"),
+            VpnProvider::OpenVPN => "openvpn",
+            VpnProvider::Custom => "custom",
+        };
+        format!("vpn-watchdog-{}", key)
+    }
+    
+    /// Background thread started by `start_vpn_watchdog`. Dropping the
+    /// handle stops the watchdog and waits for it to exit
+    pub struct WatchdogHandle {
+        stop: Option<std::sync::mpsc::Sender<()>>,
+        thread: Option<std::thread::JoinHandle<()>>,
+    }
+    
+    impl WatchdogHandle {
+        /// Block until the watchdog exits on its own (after a manual disconnect)
+        pub fn wait(mut self) {
+            self.wait_inner();
+        }
+        
+        fn wait_inner(&mut self) {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+    
+    impl Drop for WatchdogHandle {
+        fn drop(&mut self) {
+            self.stop.take();
+            self.wait_inner();
+        }
+    }
+    
+    /// Check the VPN every `interval` and reconnect it when it drops (down, or
+    /// a stale WireGuard handshake). WireGuard peers get a keepalive so an
+    /// idle tunnel isn't taken for a dead one. Exits when stopped or when
+    /// `disconnect_vpn` is run for the same VPN
+    pub fn start_vpn_watchdog(config: VpnConfig, interval: std::time::Duration) -> Result<WatchdogHandle> {
         use colored::*;
+        use std::sync::mpsc::{self, RecvTimeoutError};
+        
+        let interface = matches!(config.provider, VpnProvider::WireGuard).then_some(config.name.as_str());
+        if let Some(iface) = interface {
+            if let Err(e) = ensure_keepalive(iface) {
+                println!("{} {:#}", "[!]".yellow(), e);
+            }
+        }
+        let pid = std::process::id().to_string();
+        let marker = write_private(&watchdog_marker(&config.provider, interface), &pid)?;
+        
+        let log = |message: String| println!("{} {} {}", "[watchdog]".blue(), chrono::Local::now().format("%H:%M:%S"), message);
         
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            let interface = matches!(config.provider, VpnProvider::WireGuard).then_some(config.name.as_str());
+            // Another watchdog may have taken over the marker since
+            let disconnected = || fs::read_to_string(&marker).map(|owner| owner != pid).unwrap_or(true);
+            
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if disconnected() {
+                    log("VPN was disconnected manually, stopping".to_string());
+                    return;
+                }
+                
+                let status = match vpn_status(config.provider.clone(), interface) {
+                    Ok(status) => status,
+                    Err(e) => {
+                        log(format!("status check failed: {}", e));
+                        continue;
+                    }
+                };
+                if status.state == VpnState::Connected {
+                    continue;
+                }
+                
+                log(format!("VPN is {}, reconnecting", format!("{:?}", status.state).to_lowercase()));
+                if let Err(e) = teardown(&config.provider, interface) {
+                    log(format!("teardown failed: {}", e));
+                }
+                // The disconnect may have raced with our teardown
+                if disconnected() {
+                    log("VPN was disconnected manually, stopping".to_string());
+                    return;
+                }
+                match connect(&config) {
+                    Ok(()) => {
+                        log("reconnected".to_string());
+                        if let Some(Err(e)) = interface.map(ensure_keepalive) {
+                            log(format!("keepalive not set: {}", e));
+                        }
+                    }
+                    Err(e) => log(format!("reconnect failed: {}", e)),
+                }
+            }
+            
+            if !disconnected() {
+                let _ = fs::remove_file(&marker);
+            }
+        });
+        
+        Ok(WatchdogHandle { stop: Some(stop), thread: Some(thread) })
+    }
+    
+    fn teardown(provider: &VpnProvider, interface: Option<&str>) -> Result<()> {
         match provider {
             VpnProvider::OpenVPN => {
                 Command::new("sudo")
@@ -377,6 +961,17 @@ pub mod vpn {
             }
             VpnProvider::Custom => {}
         }
+        Ok(())
+    }
+    
+    pub fn disconnect_vpn(provider: VpnProvider, interface: Option<&str>) -> Result<()> {
+        use colored::*;
+        
+        // Tell any watchdog this is deliberate before the tunnel goes away
+        if let Ok(dir) = private_runtime_dir() {
+            let _ = fs::remove_file(dir.join(watchdog_marker(&provider, interface)));
+        }
+        teardown(&provider, interface)?;
         
         println!("{} VPN disconnected", "[+]".green());
         Ok(())
@@ -403,43 +998,352 @@ pub mod proxy {
         Socks5,
     }
     
-    pub fn start_tor_proxy() -> Result<()> {
+    /// Tor's local ports for transparent proxying (`TransPort`/`DNSPort`),
+    /// matching the desktop app's `TorConfig` defaults
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct TorPorts {
+        pub trans_port: u16,
+        pub dns_port: u16,
+    }
+    
+    impl Default for TorPorts {
+        fn default() -> Self {
+            TorPorts { trans_port: 9040, dns_port: 5353 }
+        }
+    }
+    
+    /// Start Tor; with `transparent`, also open its TransPort and DNSPort
+    pub fn start_tor_proxy(transparent: Option<TorPorts>) -> Result<()> {
         use colored::*;
         
         println!("{} Starting Tor proxy...", "[*]".blue());
         
-        Command::new("tor")
-            .args(["--runasdaemon", "1"])
-            .status()
-            .context("Failed to start Tor")?;
+        let mut cmd = Command::new("tor");
+        cmd.args(["--runasdaemon", "1"]);
+        if let Some(ports) = transparent {
+            cmd.args(["--TransPort", &format!("127.0.0.1:{}", ports.trans_port)]);
+            cmd.args(["--DNSPort", &format!("127.0.0.1:{}", ports.dns_port)]);
+        }
+        cmd.status().context("Failed to start Tor")?;
         
         println!("{} Tor proxy running on 127.0.0.1:9050", "[+]".green());
         Ok(())
     }
     
-    pub fn setup_transparent_proxy(port: u16) -> Result<()> {
+    /// iptables rules, as (table, chain, match/target), that push everything
+    /// a VM sends on `iface` through Tor. New TCP connections and DNS are
+    /// DNATed to Tor on localhost; anything else is dropped so it can't leak.
+    /// IPv6 is handled separately, see `IPV6_DROP_CHAINS`
+    pub fn transparent_proxy_rules(iface: &str, ports: TorPorts) -> Vec<(&'static str, &'static str, Vec<String>)> {
+        let rule = |parts: &[&str]| parts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let dns = format!("127.0.0.1:{}", ports.dns_port);
+        let trans = format!("127.0.0.1:{}", ports.trans_port);
+        let dns_port = ports.dns_port.to_string();
+        let trans_port = ports.trans_port.to_string();
+        
+        vec![
+            ("nat", "PREROUTING", rule(&["-i", iface, "-p", "udp", "--dport", "53", "-j", "DNAT", "--to-destination", &dns])),
+            ("nat", "PREROUTING", rule(&["-i", iface, "-p", "tcp", "--syn", "-j", "DNAT", "--to-destination", &trans])),
+            ("filter", "INPUT", rule(&["-i", iface, "-p", "udp", "-d", "127.0.0.1", "--dport", &dns_port, "-j", "ACCEPT"])),
+            ("filter", "INPUT", rule(&["-i", iface, "-p", "tcp", "-d", "127.0.0.1", "--dport", &trans_port, "-j", "ACCEPT"])),
+            ("filter", "INPUT", rule(&["-i", iface, "-j", "DROP"])),
+            ("filter", "FORWARD", rule(&["-i", iface, "-j", "DROP"])),
+        ]
+    }
+    
+    /// Chains where ip6tables drops everything from the interface. Tor only
+    /// takes the IPv4 traffic redirected to it, so IPv6 would otherwise go
+    /// out directly. Not needed with nft, whose inet table sees both
+    const IPV6_DROP_CHAINS: [&str; 2] = ["INPUT", "FORWARD"];
+    
+    /// The nft table holding the transparent proxy rules for `iface`
+    fn proxy_ruleset_name(iface: &str) -> String {
+        format!("tor-{}", iface)
+    }
+    
+    /// Route a VM's tap/bridge interface through Tor. The rules are added
+    /// together, so a failure leaves none of them behind
+    pub fn setup_transparent_proxy(iface: &str, ports: TorPorts) -> Result<()> {
         use colored::*;
         
-        println!("{} Setting up transparent proxy on port {}", "[*]".blue(), port);
+        validate_interface_name(iface)?;
+        println!("{} Routing {} through Tor (TransPort {}, DNSPort {})", "[*]".blue(), iface, ports.trans_port, ports.dns_port);
         
-        // Add iptables rules for transparent proxying
-        Command::new("sudo")
-            .args([
-                "iptables", "-t", "nat", "-A", "OUTPUT",
-                "-p", "tcp", "--dport", "80",
-                "-j", "REDIRECT", "--to-port", &port.to_string()
-            ])
-            .status()?;
+        // DNAT to 127.0.0.1 is dropped as martian unless the interface allows it
+        set_route_localnet(iface, true)?;
         
-        Command::new("sudo")
-            .args([
-                "iptables", "-t", "nat", "-A", "OUTPUT",
-                "-p", "tcp", "--dport", "443",
-                "-j", "REDIRECT", "--to-port", &port.to_string()
-            ])
-            .status()?;
+        let rules: Vec<IptablesRule> = transparent_proxy_rules(iface, ports)
+            .into_iter()
+            .map(|(table, chain, spec)| IptablesRule { table, action: "-A", chain: chain.to_string(), spec })
+            .collect();
+        if let Err(e) = apply_firewall_rules(&proxy_ruleset_name(iface), &rules) {
+            let _ = set_route_localnet(iface, false);
+            return Err(e);
+        }
+        if firewall_backend() == FirewallBackend::Nft {
+            println!("{} Transparent proxy configured", "[+]".green());
+            return Ok(());
+        }
+        
+        for (i, chain) in IPV6_DROP_CHAINS.iter().enumerate() {
+            if let Err(e) = ip6tables_drop("-A", chain, iface) {
+                for chain in IPV6_DROP_CHAINS[..i].iter().rev() {
+                    let _ = ip6tables_drop("-D", chain, iface);
+                }
+                for rule in rules.iter().rev() {
+                    let _ = iptables(rule.table, "-D", &rule.chain, &rule.spec);
+                }
+                let _ = set_route_localnet(iface, false);
+                return Err(e);
+            }
+        }
         
         println!("{} Transparent proxy configured", "[+]".green());
         Ok(())
     }
+    
+    /// Remove exactly the rules `setup_transparent_proxy` added for `iface`
+    pub fn teardown_transparent_proxy(iface: &str, ports: TorPorts) -> Result<()> {
+        use colored::*;
+        
+        validate_interface_name(iface)?;
+        let mut failed = 0;
+        if firewall_backend() == FirewallBackend::Nft {
+            if delete_nft_table(&proxy_ruleset_name(iface)).is_err() {
+                failed += 1;
+            }
+        } else {
+            for chain in IPV6_DROP_CHAINS.iter().rev() {
+                if ip6tables_drop("-D", chain, iface).is_err() {
+                    failed += 1;
+                }
+            }
+            for (table, chain, spec) in transparent_proxy_rules(iface, ports).iter().rev() {
+                if iptables(table, "-D", chain, spec).is_err() {
+                    failed += 1;
+                }
+            }
+        }
+        set_route_localnet(iface, false)?;
+        
+        if failed > 0 {
+            println!("{} {} rule(s) for {} were already gone", "[!]".yellow(), failed, iface);
+        }
+        println!("{} Transparent proxy removed from {}", "[+]".green(), iface);
+        Ok(())
+    }
+    
+    /// `<virtual port>:<guest port>` as given to `tor hidden-service`
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct ServicePorts {
+        pub virtual_port: u16,
+        pub guest_port: u16,
+    }
+    
+    impl std::str::FromStr for ServicePorts {
+        type Err = String;
+        
+        fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+            let (virtual_port, guest_port) = s.split_once(':')
+                .ok_or_else(|| format!("expected VPORT:GPORT, got '{}'", s))?;
+            let port = |p: &str| match p.trim().parse::<u16>() {
+                Ok(port) if port > 0 => Ok(port),
+                _ => Err(format!("invalid port '{}'", p)),
+            };
+            Ok(ServicePorts { virtual_port: port(virtual_port)?, guest_port: port(guest_port)? })
+        }
+    }
+    
+    /// Expose a VM's `guest_port` as `virtual_port` on an onion address and
+    /// return the address. Each VM gets its own Tor instance, configured by
+    /// `torrc` in the VM directory, with the service keys kept alongside it
+    pub fn create_hidden_service(vm: &str, guest_port: u16, virtual_port: u16) -> Result<String> {
+        use colored::*;
+        
+        crate::vm::add_hidden_service(vm, virtual_port, guest_port)?;
+        let info = crate::vm::load_vm_info(vm)?;
+        let vm_dir = crate::vm::get_vm_dir().join(vm);
+        start_hidden_services(&info)?;
+        
+        println!("{} Waiting for Tor to publish the service...", "[*]".blue());
+        let hostname = vm_dir.join(format!("hidden_service_{}", virtual_port)).join("hostname");
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+        loop {
+            if let Ok(address) = fs::read_to_string(&hostname) {
+                if !address.trim().is_empty() {
+                    let address = address.trim().to_string();
+                    println!("{} {}:{} -> {} port {}", "[+]".green(), address, virtual_port, vm, guest_port);
+                    return Ok(address);
+                }
+            }
+            if std::time::Instant::now() > deadline {
+                anyhow::bail!("Tor didn't create {} within 60s; check {}", hostname.display(), vm_dir.join("tor.log").display());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(500));
+        }
+    }
+    
+    /// Bring up (or reload) the Tor publishing a VM's onion services
+    pub fn start_hidden_services(info: &crate::vm::VmInfo) -> Result<()> {
+        let vm_dir = crate::vm::get_vm_dir().join(&info.name);
+        let torrc = vm_dir.join("torrc");
+        fs::write(&torrc, hidden_service_torrc(&info.name, &vm_dir, &info.hidden_services))
+            .context("Failed to write torrc")?;
+        reload_tor(&vm_dir, &torrc)
+    }
+    
+    /// Stop the Tor publishing a VM's onion services, if it's running. The
+    /// keys stay, so the addresses are the same when it starts again
+    pub fn stop_hidden_services(vm_dir: &Path) -> Result<()> {
+        let pid_path = vm_dir.join("tor.pid");
+        if let Some(pid) = tor_pid(vm_dir) {
+            match nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGTERM) {
+                Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+                Err(e) => return Err(e).context("Failed to stop tor"),
+            }
+        }
+        if pid_path.exists() {
+            fs::remove_file(&pid_path)?;
+        }
+        Ok(())
+    }
+    
+    /// The pid of the VM's onion service Tor, if it's alive
+    fn tor_pid(vm_dir: &Path) -> Option<nix::unistd::Pid> {
+        fs::read_to_string(vm_dir.join("tor.pid")).ok()
+            .and_then(|pid| pid.trim().parse::<i32>().ok())
+            .map(nix::unistd::Pid::from_raw)
+            .filter(|pid| nix::sys::signal::kill(*pid, None).is_ok())
+    }
+    
+    fn hidden_service_torrc(vm: &str, vm_dir: &Path, services: &[crate::vm::HiddenService]) -> String {
+        let quote = |path: &Path| format!("\"{}\"", path.display().to_string().replace('\\', "\\\\").replace('"', "\\\""));
+        
+        let mut torrc = format!("# n01d onion services for {}\n", vm);
+        torrc.push_str(&format!("DataDirectory {}\n", quote(&vm_dir.join("tor-data"))));
+        torrc.push_str(&format!("PidFile {}\n", quote(&vm_dir.join("tor.pid"))));
+        torrc.push_str(&format!("Log notice file {}\n", quote(&vm_dir.join("tor.log"))));
+        torrc.push_str("RunAsDaemon 1\nSocksPort 0\n");
+        
+        for service in services {
+            let dir = vm_dir.join(format!("hidden_service_{}", service.virtual_port));
+            torrc.push_str(&format!("HiddenServiceDir {}\n", quote(&dir)));
+            torrc.push_str(&format!("HiddenServicePort {} 127.0.0.1:{}\n", service.virtual_port, service.host_port));
+        }
+        torrc
+    }
+    
+    /// Make the VM's Tor re-read its torrc: over its ControlPort if the torrc
+    /// has one, otherwise with SIGHUP. Starts Tor if it isn't running
+    fn reload_tor(vm_dir: &Path, torrc: &Path) -> Result<()> {
+        let config = fs::read_to_string(torrc)?;
+        let control_port = config.lines()
+            .filter_map(|line| line.trim().strip_prefix("ControlPort "))
+            .find_map(|port| port.trim().parse::<u16>().ok())
+            .filter(|port| *port > 0);
+        
+        let Some(pid) = tor_pid(vm_dir) else {
+            let status = Command::new("tor")
+                .arg("-f")
+                .arg(torrc)
+                .status()
+                .context("Failed to start tor (is it installed?)")?;
+            if !status.success() {
+                anyhow::bail!("tor failed to start; see {}", vm_dir.join("tor.log").display());
+            }
+            return Ok(());
+        };
+        
+        if let Some(port) = control_port {
+            if control_reload(port).is_ok() {
+                return Ok(());
+            }
+        }
+        nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGHUP).context("Failed to signal tor")?;
+        Ok(())
+    }
+    
+    fn control_reload(port: u16) -> Result<()> {
+        use std::io::{BufRead, BufReader, Write};
+        
+        let mut stream = std::net::TcpStream::connect(("127.0.0.1", port))?;
+        stream.write_all(b"AUTHENTICATE\r\nSIGNAL RELOAD\r\nQUIT\r\n")?;
+        for line in BufReader::new(stream).lines().take(2) {
+            let line = line?;
+            if !line.starts_with("250") {
+                anyhow::bail!("Tor refused: {}", line);
+            }
+        }
+        Ok(())
+    }
+    
+    fn ip6tables_drop(action: &str, chain: &str, iface: &str) -> Result<()> {
+        let status = Command::new("sudo")
+            .args(["ip6tables", action, chain, "-i", iface, "-j", "DROP"])
+            .status()
+            .context("Failed to run ip6tables")?;
+        
+        if !status.success() {
+            anyhow::bail!("ip6tables {} {} -i {} -j DROP failed", action, chain, iface);
+        }
+        Ok(())
+    }
+    
+    fn set_route_localnet(iface: &str, enabled: bool) -> Result<()> {
+        let status = Command::new("sudo")
+            .args(["sysctl", "-q", "-w", &format!("net.ipv4.conf.{}.route_localnet={}", iface, enabled as u8)])
+            .status()
+            .context("Failed to run sysctl")?;
+        
+        if !status.success() {
+            anyhow::bail!("Failed to set route_localnet on {}", iface);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const WG_CONFIG: &str = "[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+Address = 10.0.0.2/24
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+Endpoint = vpn.example.com:51820
+AllowedIPs = 0.0.0.0/0
+";
+    
+    #[test]
+    fn test_wireguard_config_qr() {
+        let qr = vpn::wireguard_config_qr(WG_CONFIG, false).unwrap();
+        let rows: Vec<&str> = qr.lines().collect();
+        assert!(rows.len() > 10);
+        assert!(rows.iter().all(|row| row.chars().count() == rows[0].chars().count()));
+        // The quiet zone is light, so blocks by default and blank inverted
+        assert!(rows[0].chars().all(|c| c == '█'));
+        
+        // The last row only has a top half when the code's height is odd,
+        // so it doesn't invert cell for cell
+        let inverted = vpn::wireguard_config_qr(WG_CONFIG, true).unwrap();
+        let inverted: Vec<&str> = inverted.lines().collect();
+        for (row, inverted_row) in rows.iter().zip(&inverted).take(rows.len() - 1) {
+            let swapped: String = row.chars().map(|c| match c {
+                '█' => ' ',
+                ' ' => '█',
+                '▀' => '▄',
+                '▄' => '▀',
+                c => c,
+            }).collect();
+            assert_eq!(*inverted_row, swapped);
+        }
+    }
+    
+    #[test]
+    fn test_oversized_wireguard_config_is_refused() {
+        let config = format!("{}# {}\n", WG_CONFIG, "x".repeat(4000));
+        assert!(vpn::wireguard_config_qr(&config, false).is_err());
+    }
 }
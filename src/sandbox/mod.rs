@@ -2,8 +2,15 @@
 
 use anyhow::{Result, Context};
 use std::process::Command;
-use std::path::PathBuf;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
 use std::fs;
+use serde::{Deserialize, Serialize};
+use landlock::{
+    path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr,
+    RulesetStatus, ABI,
+};
+use crate::shell::{sh_comment, sh_join, sh_quote};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum IsolationLevel {
@@ -29,6 +36,35 @@ impl std::str::FromStr for IsolationLevel {
     }
 }
 
+/// What enforces the isolation: our own unshare-based launcher, or
+/// firejail driven by a generated profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SandboxBackend {
+    Unshare,
+    Firejail,
+}
+
+impl std::str::FromStr for SandboxBackend {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "unshare" => Ok(SandboxBackend::Unshare),
+            "firejail" => Ok(SandboxBackend::Firejail),
+            _ => anyhow::bail!("Unknown sandbox backend: {}", s),
+        }
+    }
+}
+
+impl std::fmt::Display for SandboxBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SandboxBackend::Unshare => "unshare",
+            SandboxBackend::Firejail => "firejail",
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct SandboxConfig {
     pub name: String,
@@ -41,27 +77,29 @@ pub struct SandboxConfig {
     pub capabilities: CapConfig,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub enabled: bool,
     pub mode: String, // none, isolated, nat
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FilesystemConfig {
     pub mode: String,    // full, readonly, tmpfs, memory
-    pub mounts: Vec<(String, String)>,
+    #[serde(default)]
+    pub mounts: Vec<(String, String)>, // (path, "ro" | "rw")
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeccompConfig {
     pub enabled: bool,
     pub profile: String, // permissive, standard, strict
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CapConfig {
     pub drop_all: bool,
+    #[serde(default)]
     pub allowed: Vec<String>,
 }
 
@@ -122,142 +160,1043 @@ impl SandboxConfig {
     }
 }
 
+/// Where a sandbox's files live
+fn sandbox_dir(name: &str) -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("NullSec-Sandboxes")
+        .join(name)
+}
+
+/// sandbox.toml: everything needed to rebuild a sandbox's isolation on
+/// later entries
+#[derive(Debug, Serialize, Deserialize)]
+struct SandboxFile {
+    sandbox: SandboxSection,
+    network: NetworkConfig,
+    filesystem: FilesystemSection,
+    seccomp: SeccompConfig,
+    capabilities: CapConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SandboxSection {
+    name: String,
+    isolation: String,
+    backend: String,
+    created: String,
+    // Older sandboxes were saved without these
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(default)]
+    gui: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FilesystemSection {
+    #[serde(flatten)]
+    config: FilesystemConfig,
+    /// Whether enter.sh applies a Landlock policy; informational
+    #[serde(default)]
+    landlock: bool,
+}
+
+impl SandboxFile {
+    fn load(sandbox_dir: &Path) -> Result<Self> {
+        let path = sandbox_dir.join("sandbox.toml");
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+    
+    fn config(&self) -> Result<SandboxConfig> {
+        Ok(SandboxConfig {
+            name: self.sandbox.name.clone(),
+            level: self.sandbox.isolation.parse()?,
+            image: self.sandbox.image.clone(),
+            command: self.sandbox.command.clone(),
+            network: self.network.clone(),
+            filesystem: self.filesystem.config.clone(),
+            seccomp: self.seccomp.clone(),
+            capabilities: self.capabilities.clone(),
+        })
+    }
+}
+
+/// Create a sandbox and write its sandbox.toml and enter.sh. Returns the
+/// sandbox's directory
 pub fn create_sandbox(
     name: &str,
     isolation: &str,
     image: Option<&str>,
     cmd: Option<&str>,
-) -> Result<()> {
+    mounts: Vec<(String, String)>,
+    gui: bool,
+    backend: &str,
+) -> Result<PathBuf> {
     use colored::*;
     
     let level: IsolationLevel = isolation.parse()?;
+    let backend: SandboxBackend = backend.parse()?;
     let mut config = SandboxConfig::from_level(name, level);
     config.image = image.map(String::from);
     config.command = cmd.map(String::from);
+    config.filesystem.mounts = mounts;
     
     println!("{} Creating sandbox with {} isolation", "[*]".blue(), isolation.yellow());
     
     // Create sandbox directory
-    let sandbox_dir = dirs::home_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("NullSec-Sandboxes")
-        .join(name);
+    let sandbox_dir = sandbox_dir(name);
     
     fs::create_dir_all(&sandbox_dir)?;
     
-    // Generate unshare command based on isolation level
-    let mut unshare_cmd = build_unshare_command(&config);
-    
-    // If we have an image, use it
-    if let Some(img) = &config.image {
-        println!("{} Using base image: {}", "[*]".blue(), img);
-    }
+    // An image becomes the sandbox's root; tarballs are unpacked into the
+    // sandbox dir so the original stays untouched
+    let rootfs = match &config.image {
+        Some(_) if backend == SandboxBackend::Firejail => {
+            anyhow::bail!("--image is not supported with the firejail backend");
+        }
+        Some(img) => {
+            println!("{} Using base image: {}", "[*]".blue(), img);
+            Some(prepare_rootfs(Path::new(img), &sandbox_dir.join("rootfs"))?)
+        }
+        None => None,
+    };
     
-    // If we have a command, add it
-    if let Some(c) = &config.command {
-        unshare_cmd.push_str(&format!(" -- {}", c));
-    } else {
-        unshare_cmd.push_str(" -- /bin/bash");
-    }
+    let gui_access = gui_access(gui, &sandbox_dir, rootfs.is_some());
+    let (launch_setup, launch, landlock) = launch_command(&config, backend, &sandbox_dir, rootfs.as_deref(), &gui_access)?;
     
     // Save sandbox config
     let config_path = sandbox_dir.join("sandbox.toml");
-    let config_toml = format!(r#"
-[sandbox]
-name = "{}"
-isolation = "{}"
-created = "{}"
+    let record = SandboxFile {
+        sandbox: SandboxSection {
+            name: config.name.clone(),
+            isolation: isolation.to_string(),
+            backend: backend.to_string(),
+            created: chrono::Utc::now().to_rfc3339(),
+            image: config.image.clone(),
+            command: config.command.clone(),
+            gui,
+        },
+        network: config.network,
+        filesystem: FilesystemSection { config: config.filesystem, landlock },
+        seccomp: config.seccomp,
+        capabilities: config.capabilities,
+    };
+    fs::write(&config_path, toml::to_string(&record)?)?;
+    
+    // Create launcher script
+    let launcher_path = sandbox_dir.join("enter.sh");
+    fs::write(&launcher_path, launcher_script(name, isolation, &gui_access, &launch_setup, &launch))?;
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(&launcher_path)?.permissions();
+        perms.set_mode(0o755);
+        fs::set_permissions(&launcher_path, perms)?;
+    }
+    
+    println!("{} Sandbox '{}' created at: {}", "[+]".green(), name, sandbox_dir.display());
+    
+    Ok(sandbox_dir)
+}
 
-[network]
-enabled = {}
-mode = "{}"
+/// Enter an existing sandbox. The isolation is rebuilt from sandbox.toml
+/// each time rather than taken from enter.sh, and `cmd` replaces the
+/// stored command for this entry only.
+pub fn enter_sandbox(name: &str, cmd: Option<&str>) -> Result<()> {
+    let sandbox_dir = sandbox_dir(name);
+    if !sandbox_dir.join("sandbox.toml").exists() {
+        anyhow::bail!("Sandbox '{}' not found", name);
+    }
+    
+    let record = SandboxFile::load(&sandbox_dir)?;
+    let backend: SandboxBackend = record.sandbox.backend.parse()?;
+    let mut config = record.config()?;
+    if let Some(cmd) = cmd {
+        config.command = Some(cmd.to_string());
+    }
+    
+    // A tarball was unpacked into rootfs/ on creation; keep using it so
+    // changes made inside survive between entries
+    let rootfs = match &config.image {
+        Some(img) => {
+            let dest = sandbox_dir.join("rootfs");
+            Some(if dest.is_dir() { dest.canonicalize()? } else { prepare_rootfs(Path::new(img), &dest)? })
+        }
+        None => None,
+    };
+    
+    let gui = gui_access(record.sandbox.gui, &sandbox_dir, rootfs.is_some());
+    let (launch_setup, launch, _) = launch_command(&config, backend, &sandbox_dir, rootfs.as_deref(), &gui)?;
+    let script = launcher_script(name, &record.sandbox.isolation, &gui, &launch_setup, &launch);
+    
+    let status = Command::new("/bin/bash")
+        .arg("-c")
+        .arg(script)
+        .status()
+        .context("Failed to run bash")?;
+    if !status.success() {
+        anyhow::bail!("Sandbox '{}' exited with {}", name, status);
+    }
+    Ok(())
+}
 
-[filesystem]
-mode = "{}"
+/// Host display access for a sandbox started with `--gui`
+fn gui_access(gui: bool, sandbox_dir: &Path, in_image: bool) -> GuiForwarding {
+    use colored::*;
+    
+    if !gui {
+        return GuiForwarding::default();
+    }
+    let forwarding = gui_forwarding(sandbox_dir, in_image);
+    if forwarding.is_none() {
+        println!("{} No local X11 or Wayland display found (headless host?); starting without GUI access",
+            "[!]".yellow());
+    }
+    forwarding.unwrap_or_default()
+}
 
-[seccomp]
-enabled = {}
-profile = "{}"
+/// Shell that starts the sandbox: setup lines to run first, the launch
+/// command line itself, and whether a Landlock policy is applied
+fn launch_command(
+    config: &SandboxConfig,
+    backend: SandboxBackend,
+    sandbox_dir: &Path,
+    rootfs: Option<&Path>,
+    gui: &GuiForwarding,
+) -> Result<(String, String, bool)> {
+    use colored::*;
+    
+    if backend == SandboxBackend::Firejail {
+        return firejail_launch(config, sandbox_dir);
+    }
+    
+    let name = &config.name;
+    
+    // Generate unshare command based on isolation level
+    let mut unshare_cmd = build_unshare_command(config);
+    
+    // The rootfs switch and Landlock are applied by `n01d sandbox-exec`
+    // inside the namespaces, right before it execs the shell. Inside an
+    // image the scratch dir is the image's own /tmp.
+    let scratch = match rootfs {
+        Some(_) => PathBuf::from("/tmp"),
+        None => sandbox_dir.join("scratch"),
+    };
+    let mut policy = LandlockPolicy::for_config(config, &scratch);
+    if let Some(policy) = &mut policy {
+        policy.read_only.extend(gui.read.iter().cloned());
+    }
+    
+    // An isolated sandbox's fresh network namespace only needs loopback up;
+    // a NAT one is entered through a prepared namespace instead (see below)
+    let loopback = config.network.mode == "isolated";
+    let exe = std::env::current_exe().context("Failed to locate the n01d binary")?;
+    let exe = exe.to_string_lossy().into_owned();
+    unshare_cmd.push("--".to_string());
+    if rootfs.is_some() || policy.is_some() || loopback {
+        if rootfs.is_none() && policy.is_some() {
+            fs::create_dir_all(&scratch)?;
+        }
+        unshare_cmd.push(exe.clone());
+        unshare_cmd.extend(sandbox_exec_args(rootfs, &gui.binds, policy.as_ref(), loopback));
+    }
+    
+    // NAT mode: enter.sh brings up a namespace with a veth on the sandbox
+    // bridge, runs unshare inside it as the calling user and tears the
+    // namespace down again on exit. sandbox_netns() limits the name to
+    // characters that need no quoting.
+    let (net_setup, net_prefix) = if uses_nat(config) {
+        let netns = sandbox_netns(name)?;
+        println!("{} Network: NAT via {} ({})", "[*]".blue(), SANDBOX_BRIDGE, SANDBOX_SUBNET);
+        (
+            format!(
+                "N01D={exe}\n\"$N01D\" sandbox-net up {name} || exit 1\ntrap '\"$N01D\" sandbox-net down {name}' EXIT\n",
+                exe = sh_quote(&exe),
+            ),
+            format!(
+                "sudo -E ip netns exec {} setpriv --reuid=\"$(id -u)\" --regid=\"$(id -g)\" --init-groups -- ",
+                sh_quote(&netns),
+            ),
+        )
+    } else {
+        (String::new(), String::new())
+    };
+    if let Some(policy) = &policy {
+        println!("{} Landlock: read-only {}, writable {}", "[*]".blue(),
+            policy.read_only.len(), policy.writable.len());
+    }
+    
+    // If we have a command, add it; it's a command line, so the shell
+    // parses it. Images aren't guaranteed to ship bash, only /bin/sh.
+    let shell = match rootfs {
+        Some(root) if resolve_in_root(root, "bin/bash").is_some() => "/bin/bash",
+        Some(_) => "/bin/sh",
+        None => "/bin/bash",
+    };
+    unshare_cmd.push(shell.to_string());
+    if let Some(c) = &config.command {
+        unshare_cmd.push("-c".to_string());
+        unshare_cmd.push(c.clone());
+    }
+    
+    Ok((net_setup, format!("{}{}", net_prefix, sh_join(&unshare_cmd)), policy.is_some()))
+}
 
-[capabilities]
-drop_all = {}
-"#, 
-        config.name,
-        isolation,
-        chrono::Utc::now().to_rfc3339(),
-        config.network.enabled,
-        config.network.mode,
-        config.filesystem.mode,
-        config.seccomp.enabled,
-        config.seccomp.profile,
-        config.capabilities.drop_all,
-    );
-    
-    fs::write(&config_path, config_toml)?;
+/// Launch line for the firejail backend, with the profile it reads
+/// written next to enter.sh
+fn firejail_launch(config: &SandboxConfig, sandbox_dir: &Path) -> Result<(String, String, bool)> {
+    use colored::*;
     
-    // Create launcher script
-    let launcher_path = sandbox_dir.join("enter.sh");
-    let launcher_script = format!(r#"#!/bin/bash
+    let profile_path = sandbox_dir.join(format!("{}.profile", config.name));
+    fs::write(&profile_path, generate_firejail_profile(config))?;
+    println!("{} Firejail profile written to {}", "[*]".blue(), profile_path.display());
+    
+    let mut firejail_cmd = vec![
+        "firejail".to_string(),
+        format!("--profile={}", profile_path.display()),
+        "--".to_string(),
+        "/bin/bash".to_string(),
+    ];
+    if let Some(c) = &config.command {
+        firejail_cmd.push("-c".to_string());
+        firejail_cmd.push(c.clone());
+    }
+    Ok((String::new(), sh_join(&firejail_cmd), false))
+}
+
+/// enter.sh, which `enter_sandbox` also runs directly
+fn launcher_script(name: &str, isolation: &str, gui: &GuiForwarding, launch_setup: &str, launch: &str) -> String {
+    format!(r#"#!/bin/bash
 # NullSec Sandbox Launcher - {}
 # Isolation Level: {}
 
-echo "[*] Entering sandbox '{}' with {} isolation"
+echo {}
 echo "[!] Press Ctrl+D or type 'exit' to leave sandbox"
 echo ""
-
 {}
-"#, name, isolation, name, isolation, unshare_cmd);
+{}{}
+"#,
+        sh_comment(name),
+        sh_comment(isolation),
+        sh_quote(&format!("[*] Entering sandbox '{}' with {} isolation", name, isolation)),
+        gui.script(),
+        launch_setup,
+        launch,
+    )
+}
+
+/// Our settings with no firejail counterpart; listed at the top of every
+/// generated profile
+const FIREJAIL_UNSUPPORTED: &[&str] = &[
+    "cgroup namespace: firejail has no option to unshare it",
+    "strict seccomp allowlist: approximated by firejail's default blocklist plus seccomp.block-secondary",
+    "writable Landlock scratch dir: strict levels get a private /tmp instead",
+    "NAT networking needs the nullsec-sandbox bridge to exist (n01d network create --name sandbox --subnet 10.200.0.0/24)",
+    "--image rootfs: firejail only chroots into root-owned trees from the command line",
+];
+
+/// Express a sandbox config as a firejail profile
+pub fn generate_firejail_profile(config: &SandboxConfig) -> String {
+    let mut profile = format!("# n01d Machine firejail profile - {}\n", sh_comment(&config.name));
+    profile.push_str(&format!("# Isolation level: {:?}\n#\n# Not expressible in firejail:\n", config.level));
+    for note in FIREJAIL_UNSUPPORTED {
+        profile.push_str(&format!("#   - {}\n", note));
+    }
+    profile.push('\n');
     
-    fs::write(&launcher_path, launcher_script)?;
+    let mut lines: Vec<String> = Vec::new();
     
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&launcher_path)?.permissions();
-        perms.set_mode(0o755);
-        fs::set_permissions(&launcher_path, perms)?;
+    // Network: isolated still gets loopback, which is what `net none` gives
+    if !config.network.enabled || config.network.mode == "none" || config.network.mode == "isolated" {
+        lines.push("net none".into());
+    } else if uses_nat(config) {
+        lines.push(format!("net {}", SANDBOX_BRIDGE));
     }
     
-    println!("{} Sandbox '{}' created at: {}", "[+]".green(), name, sandbox_dir.display());
-    println!("{} To enter sandbox: {}/enter.sh", "[*]".blue(), sandbox_dir.display());
+    // Filesystem
+    let strict = config.seccomp.profile == "strict";
+    match config.filesystem.mode.as_str() {
+        "readonly" => lines.push("read-only /".into()),
+        "tmpfs" => lines.extend(["read-only /".into(), "private-tmp".into()]),
+        "memory" => lines.extend(["read-only /".into(), "private".into(), "private-tmp".into(), "private-dev".into()]),
+        _ => {}
+    }
+    if strict && !lines.iter().any(|l| l == "private-tmp") {
+        lines.push("private-tmp".into());
+    }
+    for (path, access) in &config.filesystem.mounts {
+        match access.as_str() {
+            "rw" => lines.push(format!("read-write {}", path)),
+            _ => lines.push(format!("read-only {}", path)),
+        }
+    }
     
-    // Optionally start the sandbox immediately
-    println!("\n{} Enter sandbox now? [y/N]", "[?]".cyan());
+    // Syscalls
+    if config.seccomp.enabled && config.seccomp.profile != "permissive" {
+        lines.push("seccomp".into());
+        if strict {
+            lines.push("seccomp.block-secondary".into());
+        }
+    }
     
-    Ok(())
+    // Capabilities and privileges
+    if config.capabilities.drop_all {
+        if config.capabilities.allowed.is_empty() {
+            lines.push("caps.drop all".into());
+        } else {
+            let keep: Vec<String> = config.capabilities.allowed.iter()
+                .map(|cap| cap.trim_start_matches("CAP_").to_lowercase())
+                .collect();
+            lines.push(format!("caps.keep {}", keep.join(",")));
+        }
+        lines.push("noroot".into());
+    }
+    if config.level != IsolationLevel::Minimal {
+        lines.push("nonewprivs".into());
+    }
+    
+    // Same namespaces unshare creates
+    lines.push("ipc-namespace".into());
+    lines.push(format!("hostname {}", config.name));
+    
+    for line in lines {
+        profile.push_str(&line);
+        profile.push('\n');
+    }
+    profile
 }
 
-fn build_unshare_command(config: &SandboxConfig) -> String {
-    let mut cmd = String::from("unshare");
+fn build_unshare_command(config: &SandboxConfig) -> Vec<String> {
+    let mut cmd = vec!["unshare"];
     
     // Always create new PID namespace
-    cmd.push_str(" --pid --fork");
+    cmd.extend(["--pid", "--fork"]);
     
-    // Mount namespace for filesystem isolation
-    if config.filesystem.mode != "full" {
-        cmd.push_str(" --mount");
+    // Mount namespace for filesystem isolation; an image needs one to
+    // pivot into, and a user namespace to be allowed to mount at all
+    if config.filesystem.mode != "full" || config.image.is_some() {
+        cmd.push("--mount");
     }
     
     // User namespace for capability dropping
-    if config.capabilities.drop_all {
-        cmd.push_str(" --user --map-root-user");
+    if config.capabilities.drop_all || config.image.is_some() {
+        cmd.extend(["--user", "--map-root-user"]);
     }
     
-    // Network namespace
-    if !config.network.enabled || config.network.mode == "none" {
-        cmd.push_str(" --net");
+    // Network namespace; NAT sandboxes get theirs from enter.sh, already
+    // wired up, so unshare must not replace it
+    if !uses_nat(config) {
+        cmd.push("--net");
     }
     
     // UTS namespace (hostname)
-    cmd.push_str(" --uts");
+    cmd.push("--uts");
     
     // IPC namespace
-    cmd.push_str(" --ipc");
+    cmd.push("--ipc");
     
     // Cgroup namespace
-    cmd.push_str(" --cgroup");
+    cmd.push("--cgroup");
+    
+    cmd.into_iter().map(String::from).collect()
+}
+
+/// Landlock ABI the rulesets are written against (5.19+). Older kernels
+/// enforce what they support and the rest is reported as partial.
+const LANDLOCK_ABI: ABI = ABI::V2;
+
+/// Directories a non-strict sandbox needs to read to run a shell at all
+const SYSTEM_READ_PATHS: &[&str] = &["/bin", "/sbin", "/usr", "/lib", "/lib64", "/etc"];
+
+/// Device nodes a shell writes to even when everything else is read-only
+const DEVICE_WRITE_PATHS: &[&str] = &["/dev/null", "/dev/zero", "/dev/full", "/dev/tty", "/dev/pts"];
+
+/// Paths a sandbox may read or write once Landlock is applied; anything
+/// not listed (or beneath a listed directory) is denied
+#[derive(Debug, Clone, PartialEq)]
+pub struct LandlockPolicy {
+    pub read_only: Vec<PathBuf>,
+    pub writable: Vec<PathBuf>,
+}
+
+impl LandlockPolicy {
+    /// Policy for a sandbox config. Strict levels (high, maximum) are
+    /// read-only everywhere except the scratch dir and declared `rw`
+    /// mounts; other levels are only restricted when mounts are declared.
+    pub fn for_config(config: &SandboxConfig, scratch: &Path) -> Option<Self> {
+        let strict = config.seccomp.profile == "strict";
+        if !strict && config.filesystem.mounts.is_empty() {
+            return None;
+        }
+        
+        let mut policy = Self { read_only: vec![], writable: vec![] };
+        if strict {
+            policy.read_only.push(PathBuf::from("/"));
+            policy.writable.push(scratch.to_path_buf());
+        } else {
+            policy.read_only.extend(SYSTEM_READ_PATHS.iter().map(PathBuf::from));
+        }
+        policy.writable.extend(DEVICE_WRITE_PATHS.iter().map(PathBuf::from));
+        
+        for (path, access) in &config.filesystem.mounts {
+            match access.as_str() {
+                "rw" => policy.writable.push(PathBuf::from(path)),
+                _ => policy.read_only.push(PathBuf::from(path)),
+            }
+        }
+        Some(policy)
+    }
+    
+    /// Restrict the calling thread (and everything it execs) to this policy.
+    /// Paths that don't exist are skipped, since a rule needs an open fd.
+    pub fn apply(&self) -> Result<RulesetStatus> {
+        let existing = |paths: &[PathBuf]| -> Vec<PathBuf> {
+            paths.iter().filter(|p| p.exists()).cloned().collect()
+        };
+        
+        let status = Ruleset::default()
+            .handle_access(AccessFs::from_all(LANDLOCK_ABI))?
+            .create()?
+            .add_rules(path_beneath_rules(existing(&self.read_only), AccessFs::from_read(LANDLOCK_ABI)))?
+            .add_rules(path_beneath_rules(existing(&self.writable), AccessFs::from_all(LANDLOCK_ABI)))?
+            .restrict_self()
+            .context("Failed to apply Landlock ruleset")?;
+        Ok(status.ruleset)
+    }
+}
+
+/// Arguments for the hidden `sandbox-exec` subcommand, up to the `--`
+/// that precedes the sandboxed command
+fn sandbox_exec_args(
+    root: Option<&Path>,
+    binds: &[(PathBuf, PathBuf)],
+    policy: Option<&LandlockPolicy>,
+    loopback: bool,
+) -> Vec<String> {
+    let mut args = vec!["sandbox-exec".to_string()];
+    if loopback {
+        args.push("--loopback".into());
+    }
+    if let Some(root) = root {
+        args.push("--root".into());
+        args.push(root.to_string_lossy().into_owned());
+        for (host, inside) in binds {
+            args.push("--bind".into());
+            args.push(format!("{}:{}", host.display(), inside.display()));
+        }
+    }
+    if let Some(policy) = policy {
+        for path in &policy.read_only {
+            args.push("--ro".into());
+            args.push(path.to_string_lossy().into_owned());
+        }
+        for path in &policy.writable {
+            args.push("--rw".into());
+            args.push(path.to_string_lossy().into_owned());
+        }
+    }
+    args.push("--".into());
+    args
+}
+
+/// Bridge NAT-mode sandboxes hang off, mirroring `create_network`'s NAT mode
+pub const SANDBOX_BRIDGE: &str = "nullsec-sandbox";
+pub const SANDBOX_SUBNET: &str = "10.200.0.0/24";
+const SANDBOX_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 200, 0, 1);
+
+/// Prefix of the host ends of sandbox veth pairs; the suffix is the last
+/// octet of the sandbox's address, so free addresses can be found from
+/// /sys/class/net alone
+const SANDBOX_VETH_PREFIX: &str = "n01d-v";
+
+/// Whether the sandbox is networked through a NAT'd veth pair rather than
+/// an empty network namespace
+fn uses_nat(config: &SandboxConfig) -> bool {
+    config.network.enabled && config.network.mode == "nat"
+}
+
+/// Named network namespace (under /run/netns) for a NAT sandbox
+pub fn sandbox_netns(name: &str) -> Result<String> {
+    let valid = !name.is_empty()
+        && name.len() <= 48
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !name.starts_with('.');
+    if !valid {
+        anyhow::bail!("Sandbox name '{}' can't be used for a network namespace \
+            (use letters, digits, '-', '_' and '.')", name);
+    }
+    Ok(format!("n01d-sb-{}", name))
+}
+
+fn sudo(args: &[&str]) -> Result<()> {
+    let status = Command::new("sudo")
+        .args(args)
+        .status()
+        .with_context(|| format!("Failed to run {}", args[0]))?;
+    
+    if !status.success() {
+        anyhow::bail!("{} failed", args.join(" "));
+    }
+    Ok(())
+}
+
+/// First address in the sandbox subnet without a veth on the host
+fn next_sandbox_address() -> Result<Ipv4Addr> {
+    let used: Vec<u8> = fs::read_dir("/sys/class/net")
+        .map(|entries| {
+            entries.flatten()
+                .filter_map(|e| e.file_name().to_str()?.strip_prefix(SANDBOX_VETH_PREFIX)?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    
+    (2..=254u8)
+        .find(|octet| !used.contains(octet))
+        .map(|octet| Ipv4Addr::new(10, 200, 0, octet))
+        .context("No free addresses left in the sandbox subnet")
+}
+
+/// The sandbox's eth0 address, read from inside its namespace
+fn sandbox_address(netns: &str) -> Option<Ipv4Addr> {
+    let output = Command::new("sudo")
+        .args(["ip", "-n", netns, "-4", "-o", "addr", "show", "dev", "eth0"])
+        .output()
+        .ok()?;
+    
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .skip_while(|field| *field != "inet")
+        .nth(1)?
+        .split('/')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Nameservers for the sandbox: the host's, unless they're loopback
+/// resolvers (systemd-resolved, dnsmasq) the namespace can't reach
+fn sandbox_resolv_conf() -> String {
+    let host = fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    let servers: Vec<&str> = host.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .map(str::trim)
+        .filter(|ip| ip.parse::<std::net::IpAddr>().is_ok_and(|ip| !ip.is_loopback()))
+        .collect();
+    
+    let servers = if servers.is_empty() { vec!["1.1.1.1"] } else { servers };
+    servers.iter().map(|ip| format!("nameserver {}\n", ip)).collect()
+}
+
+/// Bring up a NAT sandbox's network namespace: a veth pair with one end
+/// on the sandbox bridge and the other inside as eth0 with the next free
+/// address, a default route via the bridge and a MASQUERADE rule for that
+/// one address. Anything left half-done is torn down again on failure.
+pub fn setup_sandbox_net(name: &str) -> Result<Ipv4Addr> {
+    use colored::*;
+    
+    let netns = sandbox_netns(name)?;
+    // A namespace left behind by a killed session still holds an address
+    teardown_sandbox_net(name)?;
+    
+    // The bridge is shared by every sandbox and created on first use
+    if !Path::new("/sys/class/net").join(SANDBOX_BRIDGE).exists() {
+        sudo(&["ip", "link", "add", SANDBOX_BRIDGE, "type", "bridge"])?;
+        sudo(&["ip", "addr", "add", &format!("{}/24", SANDBOX_GATEWAY), "dev", SANDBOX_BRIDGE])?;
+    }
+    sudo(&["ip", "link", "set", SANDBOX_BRIDGE, "up"])?;
+    sudo(&["sysctl", "-q", "-w", "net.ipv4.ip_forward=1"])?;
+    
+    let address = next_sandbox_address()?;
+    let host_veth = format!("{}{}", SANDBOX_VETH_PREFIX, address.octets()[3]);
+    let peer = format!("{}p", host_veth);
+    
+    sudo(&["ip", "netns", "add", &netns])?;
+    let wire = || -> Result<()> {
+        sudo(&["ip", "link", "add", &host_veth, "type", "veth", "peer", "name", &peer])?;
+        sudo(&["ip", "link", "set", &peer, "netns", &netns])?;
+        sudo(&["ip", "link", "set", &host_veth, "master", SANDBOX_BRIDGE, "up"])?;
+        sudo(&["ip", "-n", &netns, "link", "set", &peer, "name", "eth0"])?;
+        sudo(&["ip", "-n", &netns, "addr", "add", &format!("{}/24", address), "dev", "eth0"])?;
+        sudo(&["ip", "-n", &netns, "link", "set", "eth0", "up"])?;
+        sudo(&["ip", "-n", &netns, "link", "set", "lo", "up"])?;
+        sudo(&["ip", "-n", &netns, "route", "add", "default", "via", &SANDBOX_GATEWAY.to_string()])?;
+        sudo(&["iptables", "-t", "nat", "-A", "POSTROUTING", "-s", &format!("{}/32", address), "-j", "MASQUERADE"])?;
+        
+        // `ip netns exec` bind-mounts this over /etc/resolv.conf
+        let etc = format!("/etc/netns/{}", netns);
+        sudo(&["mkdir", "-p", &etc])?;
+        let mut tee = Command::new("sudo")
+            .args(["tee", &format!("{}/resolv.conf", etc)])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .context("Failed to run tee")?;
+        use std::io::Write;
+        tee.stdin.take().context("tee has no stdin")?.write_all(sandbox_resolv_conf().as_bytes())?;
+        if !tee.wait()?.success() {
+            anyhow::bail!("Failed to write {}/resolv.conf", etc);
+        }
+        Ok(())
+    };
+    
+    if let Err(e) = wire() {
+        let _ = teardown_sandbox_net(name);
+        return Err(e);
+    }
+    
+    println!("{} Sandbox network up: {} via {}", "[+]".green(), address, SANDBOX_GATEWAY);
+    Ok(address)
+}
+
+/// Remove a NAT sandbox's MASQUERADE rule, veth pair and namespace
+pub fn teardown_sandbox_net(name: &str) -> Result<()> {
+    let netns = sandbox_netns(name)?;
+    if !Path::new("/run/netns").join(&netns).exists() {
+        return Ok(());
+    }
+    
+    if let Some(address) = sandbox_address(&netns) {
+        let _ = sudo(&["iptables", "-t", "nat", "-D", "POSTROUTING", "-s", &format!("{}/32", address), "-j", "MASQUERADE"]);
+        // The namespace's own cleanup is asynchronous; drop the pair now
+        // so the address is free for the next sandbox straight away
+        let host_veth = format!("{}{}", SANDBOX_VETH_PREFIX, address.octets()[3]);
+        let _ = sudo(&["ip", "link", "delete", &host_veth]);
+    }
+    sudo(&["ip", "netns", "delete", &netns])?;
+    let _ = sudo(&["rm", "-rf", &format!("/etc/netns/{}", netns)]);
+    Ok(())
+}
+
+/// Parse a `HOST:INSIDE` bind for `sandbox-exec --bind`
+pub fn parse_bind(s: &str) -> Result<(PathBuf, PathBuf)> {
+    let (host, inside) = s.split_once(':').context("Expected HOST:INSIDE")?;
+    if !inside.starts_with('/') {
+        anyhow::bail!("Bind target '{}' must be an absolute path", inside);
+    }
+    Ok((PathBuf::from(host), PathBuf::from(inside)))
+}
+
+/// How a `--gui` sandbox reaches the host display
+#[derive(Debug, Default)]
+struct GuiForwarding {
+    /// Variables exported to the sandbox
+    env: Vec<(&'static str, String)>,
+    /// Host sockets and files, and where they appear inside an image
+    binds: Vec<(PathBuf, PathBuf)>,
+    /// Files Landlock has to leave readable (the X11 cookie)
+    read: Vec<PathBuf>,
+    /// Commands enter.sh runs on the host before entering
+    setup: Vec<String>,
+}
+
+impl GuiForwarding {
+    /// enter.sh lines that mint the cookie and export the display variables
+    fn script(&self) -> String {
+        let mut lines = self.setup.clone();
+        for (key, value) in &self.env {
+            lines.push(format!("export {}={}", key, sh_quote(value)));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Work out the host display sockets to hand to a sandbox. Only local
+/// displays can be forwarded: the abstract X11 socket and TCP displays
+/// aren't reachable from a separate network namespace. X11 clients get a
+/// per-sandbox untrusted cookie, so they can't read other clients' input
+/// or windows. Returns None on a headless host.
+fn gui_forwarding(sandbox_dir: &Path, in_image: bool) -> Option<GuiForwarding> {
+    use colored::*;
+    
+    let mut gui = GuiForwarding::default();
+    
+    if let Ok(display) = std::env::var("DISPLAY") {
+        let socket = display.strip_prefix(':')
+            .and_then(|d| d.split('.').next())
+            .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+            .map(|n| PathBuf::from(format!("/tmp/.X11-unix/X{}", n)))
+            .filter(|socket| socket.exists());
+        
+        if socket.is_some() {
+            if in_image {
+                gui.binds.push(("/tmp/.X11-unix".into(), "/tmp/.X11-unix".into()));
+            }
+            
+            if which::which("xauth").is_ok() {
+                let cookie = sandbox_dir.join("Xauthority");
+                let inside = if in_image { PathBuf::from("/tmp/.Xauthority") } else { cookie.clone() };
+                let cookie_arg = sh_quote(&cookie.to_string_lossy());
+                gui.setup.push(format!(": > {}", cookie_arg));
+                gui.setup.push(format!(
+                    "xauth -q -f {} generate {} . untrusted timeout 0 >/dev/null 2>&1 || echo {}",
+                    cookie_arg,
+                    sh_quote(&display),
+                    sh_quote("[!] xauth could not create an X11 cookie; the display may refuse sandboxed apps"),
+                ));
+                if in_image {
+                    gui.binds.push((cookie, inside.clone()));
+                }
+                gui.env.push(("XAUTHORITY", inside.to_string_lossy().into_owned()));
+                gui.read.push(inside);
+            } else {
+                println!("{} xauth not found; X11 apps in the sandbox won't get a restricted cookie", "[!]".yellow());
+            }
+            gui.env.push(("DISPLAY", display));
+        } else {
+            println!("{} DISPLAY={} has no local X11 socket to forward", "[!]".yellow(), display);
+        }
+    }
+    
+    if let Ok(wayland) = std::env::var("WAYLAND_DISPLAY") {
+        let socket = if wayland.starts_with('/') {
+            Some(PathBuf::from(&wayland))
+        } else {
+            std::env::var("XDG_RUNTIME_DIR").ok().map(|dir| Path::new(&dir).join(&wayland))
+        };
+        
+        if let Some(socket) = socket.filter(|socket| socket.exists()) {
+            // An absolute WAYLAND_DISPLAY works without XDG_RUNTIME_DIR
+            let inside = match (in_image, socket.file_name()) {
+                (true, Some(file)) => Path::new("/tmp").join(file),
+                _ => socket.clone(),
+            };
+            if in_image {
+                gui.binds.push((socket, inside.clone()));
+            }
+            gui.env.push(("WAYLAND_DISPLAY", inside.to_string_lossy().into_owned()));
+        }
+    }
+    
+    if gui.env.iter().any(|(key, _)| *key == "DISPLAY" || *key == "WAYLAND_DISPLAY") {
+        println!("{} GUI: forwarding {}", "[*]".blue(),
+            gui.env.iter().map(|(key, value)| format!("{}={}", key, value)).collect::<Vec<_>>().join(" "));
+        Some(gui)
+    } else {
+        None
+    }
+}
+
+/// Switch into `root` (if any), bring up loopback (if asked), apply a Landlock policy (if any) and exec
+/// `command`. Runs inside the sandbox namespaces as `n01d sandbox-exec`.
+pub fn exec_sandboxed(
+    root: Option<&Path>,
+    binds: &[(PathBuf, PathBuf)],
+    policy: Option<&LandlockPolicy>,
+    loopback: bool,
+    command: &[String],
+) -> Result<()> {
+    use colored::*;
+    use std::os::unix::process::CommandExt;
+    
+    let (program, args) = command.split_first().context("No command to run")?;
     
-    cmd
+    // Before the pivot: the image may not ship `ip`
+    if loopback {
+        let up = Command::new("ip").args(["link", "set", "lo", "up"]).status();
+        if !up.is_ok_and(|status| status.success()) {
+            println!("{} Could not bring up loopback in the sandbox", "[!]".yellow());
+        }
+    }
+    
+    if let Some(root) = root {
+        enter_rootfs(root, binds)?;
+    }
+    
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    
+    if let Some(policy) = policy {
+        match policy.apply()? {
+            RulesetStatus::FullyEnforced => {}
+            RulesetStatus::PartiallyEnforced => println!(
+                "{} Landlock is only partially supported by this kernel; some file-access rules are not enforced",
+                "[!]".yellow()
+            ),
+            RulesetStatus::NotEnforced => println!(
+                "{} Landlock is not available (needs Linux 5.13+ with it enabled); file access is not restricted",
+                "[!]".yellow()
+            ),
+        }
+        if let Some(scratch) = policy.writable.first().filter(|p| p.is_dir()) {
+            cmd.env("TMPDIR", scratch);
+        }
+    }
+    
+    let err = cmd.exec();
+    Err(err).with_context(|| format!("Failed to exec {}", program))
+}
+
+/// Turn an image (a rootfs directory or a .tar / .tar.zst) into a
+/// directory usable as the sandbox root. Tarballs are unpacked into `dest`.
+fn prepare_rootfs(image: &Path, dest: &Path) -> Result<PathBuf> {
+    let root = if image.is_dir() {
+        image.canonicalize()?
+    } else if image.is_file() {
+        if dest.exists() {
+            fs::remove_dir_all(dest)
+                .with_context(|| format!("Failed to clear old rootfs at {}", dest.display()))?;
+        }
+        fs::create_dir_all(dest)?;
+        
+        let file = fs::File::open(image).with_context(|| format!("Failed to open {}", image.display()))?;
+        let name = image.to_string_lossy();
+        let result = if name.ends_with(".zst") || name.ends_with(".tzst") {
+            tar::Archive::new(zstd::Decoder::new(file)?).unpack(dest)
+        } else {
+            tar::Archive::new(file).unpack(dest)
+        };
+        result.with_context(|| format!("Failed to unpack {}", image.display()))?;
+        dest.canonicalize()?
+    } else {
+        anyhow::bail!("Image not found: {}", image.display());
+    };
+    
+    if resolve_in_root(&root, "bin/sh").is_none() {
+        anyhow::bail!("Image {} has no /bin/sh", image.display());
+    }
+    fs::create_dir_all(root.join("tmp"))
+        .with_context(|| format!("Failed to create /tmp in {}", root.display()))?;
+    Ok(root)
+}
+
+/// Resolve `path` as if `root` were `/`, following symlinks (absolute ones
+/// relative to `root`). Returns None if it doesn't exist in the image.
+fn resolve_in_root(root: &Path, path: &str) -> Option<PathBuf> {
+    let mut pending: Vec<String> = path.split('/').rev().map(String::from).collect();
+    let mut current = root.to_path_buf();
+    let mut hops = 0;
+    
+    while let Some(part) = pending.pop() {
+        match part.as_str() {
+            "" | "." => continue,
+            ".." => {
+                if current != root {
+                    current.pop();
+                }
+                continue;
+            }
+            _ => {}
+        }
+        let next = current.join(&part);
+        let meta = fs::symlink_metadata(&next).ok()?;
+        if !meta.file_type().is_symlink() {
+            current = next;
+            continue;
+        }
+        
+        hops += 1;
+        if hops > 40 {
+            return None;
+        }
+        let target = fs::read_link(&next).ok()?;
+        let target = target.to_string_lossy();
+        if target.starts_with('/') {
+            current = root.to_path_buf();
+        }
+        pending.extend(target.split('/').rev().map(String::from));
+    }
+    Some(current)
+}
+
+/// Make `root` the new `/` of this mount namespace: bind it onto itself,
+/// give it a minimal /dev (see `populate_dev`), bring any extra `binds`
+/// along read-only, mount a fresh /proc for the PID namespace and
+/// pivot_root into it, detaching the old root.
+fn enter_rootfs(root: &Path, binds: &[(PathBuf, PathBuf)]) -> Result<()> {
+    use nix::mount::{mount, umount2, MntFlags, MsFlags};
+    
+    // Keep the mounts below from propagating back to the host
+    mount(None::<&str>, "/", None::<&str>, MsFlags::MS_REC | MsFlags::MS_PRIVATE, None::<&str>)
+        .context("Failed to make mounts private")?;
+    // pivot_root needs the new root to be a mount point
+    mount(Some(root), root, None::<&str>, MsFlags::MS_BIND | MsFlags::MS_REC, None::<&str>)
+        .with_context(|| format!("Failed to bind {}", root.display()))?;
+    
+    populate_dev(&root.join("dev"))?;
+    
+    for (host, inside) in binds {
+        let target = root.join(inside.strip_prefix("/").unwrap_or(inside));
+        if host.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::OpenOptions::new().create(true).append(true).open(&target)?;
+        }
+        mount(Some(host.as_path()), &target, None::<&str>, MsFlags::MS_BIND, None::<&str>)
+            .with_context(|| format!("Failed to bind {} into the rootfs", host.display()))?;
+        mount(None::<&str>, &target, None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY, None::<&str>)
+            .with_context(|| format!("Failed to make {} read-only", inside.display()))?;
+    }
+    
+    let proc_dir = root.join("proc");
+    fs::create_dir_all(&proc_dir)?;
+    mount(Some("proc"), &proc_dir, Some("proc"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC, None::<&str>)
+        .context("Failed to mount /proc in the rootfs")?;
+    
+    let old_root = root.join(".old_root");
+    fs::create_dir_all(&old_root)?;
+    nix::unistd::pivot_root(root, &old_root).context("pivot_root failed")?;
+    std::env::set_current_dir("/")?;
+    umount2("/.old_root", MntFlags::MNT_DETACH).context("Failed to detach the host root")?;
+    let _ = fs::remove_dir("/.old_root");
+    
+    Ok(())
+}
+
+/// Host device nodes a sandboxed rootfs gets; nothing else of the host's
+/// /dev (disks, /dev/mem, input devices, GPUs) is reachable from inside
+const ROOTFS_DEVICES: &[&str] = &["null", "zero", "full", "random", "urandom", "tty"];
+
+/// Mount a tmpfs on `dev` holding only `ROOTFS_DEVICES`, bound from the
+/// host since a user namespace can't mknod, plus a private devpts
+/// instance, a /dev/shm and the usual symlinks into /proc
+fn populate_dev(dev: &Path) -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+    use std::os::unix::fs::symlink;
+    
+    fs::create_dir_all(dev)?;
+    mount(Some("tmpfs"), dev, Some("tmpfs"), MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC, Some("mode=755,size=64k"))
+        .context("Failed to mount /dev in the rootfs")?;
+    
+    for name in ROOTFS_DEVICES {
+        let host = Path::new("/dev").join(name);
+        if !host.exists() {
+            continue;
+        }
+        let target = dev.join(name);
+        fs::File::create(&target)?;
+        mount(Some(host.as_path()), &target, None::<&str>, MsFlags::MS_BIND, None::<&str>)
+            .with_context(|| format!("Failed to bind {} into the rootfs", host.display()))?;
+    }
+    
+    // A devpts of its own, so the host's other terminals aren't visible.
+    // Without one (an old kernel) the sandbox still runs on the fds it has
+    let pts = dev.join("pts");
+    fs::create_dir_all(&pts)?;
+    if mount(Some("devpts"), &pts, Some("devpts"), MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+        Some("newinstance,ptmxmode=0666,mode=620")).is_ok()
+    {
+        symlink("pts/ptmx", dev.join("ptmx"))?;
+    }
+    
+    let shm = dev.join("shm");
+    fs::create_dir_all(&shm)?;
+    mount(Some("tmpfs"), &shm, Some("tmpfs"), MsFlags::MS_NOSUID | MsFlags::MS_NODEV, Some("mode=1777"))
+        .context("Failed to mount /dev/shm in the rootfs")?;
+    
+    for (link, target) in [("fd", "/proc/self/fd"), ("stdin", "/proc/self/fd/0"), ("stdout", "/proc/self/fd/1"), ("stderr", "/proc/self/fd/2")] {
+        symlink(target, dev.join(link))?;
+    }
+    Ok(())
 }
 
 /// Seccomp filter profiles
@@ -351,3 +1290,145 @@ pub mod seccomp_profiles {
         "preadv2", "pwritev2",
     ];
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_landlock_denies_write_outside_scratch() {
+        let base = std::env::temp_dir().join(format!("n01d-landlock-{}", std::process::id()));
+        let scratch = base.join("scratch");
+        let outside = base.join("outside");
+        fs::create_dir_all(&scratch).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        
+        let config = SandboxConfig::from_level("test", IsolationLevel::High);
+        let policy = LandlockPolicy::for_config(&config, &scratch).unwrap();
+        
+        // Landlock restricts the calling thread, so keep it off the test runner's
+        let (status, inside_ok, outside_err) = {
+            let (scratch, outside) = (scratch.clone(), outside.clone());
+            std::thread::spawn(move || {
+                let status = policy.apply().unwrap();
+                let inside = fs::write(scratch.join("ok"), b"ok");
+                let outside = fs::write(outside.join("denied"), b"no");
+                (status, inside.is_ok(), outside.err().map(|e| e.kind()))
+            }).join().unwrap()
+        };
+        fs::remove_dir_all(&base).ok();
+        
+        if status == RulesetStatus::NotEnforced {
+            eprintln!("Landlock not supported by this kernel, skipping");
+            return;
+        }
+        assert!(inside_ok);
+        assert_eq!(outside_err, Some(std::io::ErrorKind::PermissionDenied));
+    }
+    
+    #[test]
+    fn test_resolve_in_root_follows_image_symlinks() {
+        let root = std::env::temp_dir().join(format!("n01d-rootfs-{}", std::process::id()));
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/busybox"), b"").unwrap();
+        // Alpine-style: /bin -> usr/bin, /bin/sh -> /bin/busybox
+        std::os::unix::fs::symlink("usr/bin", root.join("bin")).unwrap();
+        std::os::unix::fs::symlink("/bin/busybox", root.join("usr/bin/sh")).unwrap();
+        std::os::unix::fs::symlink("/../../etc/passwd", root.join("escape")).unwrap();
+        
+        let sh = resolve_in_root(&root, "bin/sh");
+        let escape = resolve_in_root(&root, "escape");
+        let bash = resolve_in_root(&root, "bin/bash");
+        fs::remove_dir_all(&root).ok();
+        
+        assert_eq!(sh, Some(root.join("usr/bin/busybox")));
+        assert_eq!(escape, None);
+        assert_eq!(bash, None);
+    }
+    
+    #[test]
+    fn test_firejail_profile_levels() {
+        let max = generate_firejail_profile(&SandboxConfig::from_level("web", IsolationLevel::Maximum));
+        for line in ["net none", "read-only /", "private-tmp", "seccomp", "seccomp.block-secondary", "caps.drop all", "nonewprivs"] {
+            assert!(max.lines().any(|l| l == line), "maximum profile lacks '{}'", line);
+        }
+        assert!(max.contains("# Not expressible in firejail:"));
+        
+        let low = generate_firejail_profile(&SandboxConfig::from_level("web", IsolationLevel::Low));
+        assert!(low.lines().any(|l| l == format!("net {}", SANDBOX_BRIDGE)));
+        assert!(!low.lines().any(|l| l.starts_with("seccomp") || l.starts_with("caps") || l.starts_with("read-only")));
+    }
+    
+    #[test]
+    fn test_sandbox_toml_round_trip() {
+        let mut config = SandboxConfig::from_level("web", IsolationLevel::Low);
+        config.command = Some("curl \"$URL\"".into());
+        config.filesystem.mounts.push(("/srv/data".into(), "rw".into()));
+        let record = SandboxFile {
+            sandbox: SandboxSection {
+                name: "web".into(),
+                isolation: "low".into(),
+                backend: "unshare".into(),
+                created: "2026-01-01T00:00:00Z".into(),
+                image: None,
+                command: config.command.clone(),
+                gui: true,
+            },
+            network: config.network.clone(),
+            filesystem: FilesystemSection { config: config.filesystem.clone(), landlock: true },
+            seccomp: config.seccomp.clone(),
+            capabilities: config.capabilities.clone(),
+        };
+        
+        let loaded: SandboxFile = toml::from_str(&toml::to_string(&record).unwrap()).unwrap();
+        let restored = loaded.config().unwrap();
+        assert!(loaded.sandbox.gui);
+        assert_eq!(restored.level, IsolationLevel::Low);
+        assert_eq!(restored.command, config.command);
+        assert_eq!(restored.filesystem.mounts, config.filesystem.mounts);
+        assert_eq!(restored.capabilities.allowed, vec!["CAP_NET_BIND_SERVICE"]);
+        assert_eq!(build_unshare_command(&restored), build_unshare_command(&config));
+        
+        // Sandboxes created before the command and mounts were saved
+        let legacy: SandboxFile = toml::from_str(r#"
+[sandbox]
+name = "old"
+isolation = "high"
+backend = "unshare"
+created = "2025-01-01T00:00:00Z"
+
+[network]
+enabled = false
+mode = "none"
+
+[filesystem]
+mode = "tmpfs"
+landlock = true
+
+[seccomp]
+enabled = true
+profile = "strict"
+
+[capabilities]
+drop_all = true
+"#).unwrap();
+        let restored = legacy.config().unwrap();
+        assert_eq!(restored.level, IsolationLevel::High);
+        assert!(restored.command.is_none() && restored.filesystem.mounts.is_empty());
+    }
+    
+    #[test]
+    fn test_landlock_policy_defaults() {
+        let scratch = Path::new("/tmp/scratch");
+        
+        let medium = SandboxConfig::from_level("test", IsolationLevel::Medium);
+        assert!(LandlockPolicy::for_config(&medium, scratch).is_none());
+        
+        let mut max = SandboxConfig::from_level("test", IsolationLevel::Maximum);
+        max.filesystem.mounts.push(("/srv/data".into(), "rw".into()));
+        let policy = LandlockPolicy::for_config(&max, scratch).unwrap();
+        assert_eq!(policy.read_only, vec![PathBuf::from("/")]);
+        assert_eq!(policy.writable[0], scratch);
+        assert!(policy.writable.contains(&PathBuf::from("/srv/data")));
+    }
+}
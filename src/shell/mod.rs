@@ -0,0 +1,57 @@
+//! Shell Module - Helpers for generating shell scripts
+
+/// Quote a value for a POSIX shell so it is always read back as one word.
+/// Plain words are left alone to keep generated scripts readable
+pub fn sh_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | ':' | '=' | ',' | '+' | '@'));
+    
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+/// Quote every argument and join them into one command line
+pub fn sh_join<I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    args.into_iter()
+        .map(|arg| sh_quote(arg.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Make a value safe to put in a `#` comment line
+pub fn sh_comment(value: &str) -> String {
+    value.replace(['\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_plain_words_are_untouched() {
+        assert_eq!(sh_quote("/home/user/vm.qcow2"), "/home/user/vm.qcow2");
+        assert_eq!(sh_quote("-enable-kvm"), "-enable-kvm");
+    }
+    
+    #[test]
+    fn test_quotes_spaces_and_metacharacters() {
+        assert_eq!(sh_quote("/home/user/My VMs/lab.qcow2"), "'/home/user/My VMs/lab.qcow2'");
+        assert_eq!(sh_quote("$(reboot)"), "'$(reboot)'");
+        assert_eq!(sh_quote("it's"), r"'it'\''s'");
+        assert_eq!(sh_quote(""), "''");
+    }
+    
+    #[test]
+    fn test_join() {
+        assert_eq!(sh_join(["ls", "-la", "My Files"]), "ls -la 'My Files'");
+    }
+}
@@ -1,19 +1,176 @@
 //! VM Management Module
 
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 use std::fs;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use anyhow::{Result, Context};
+use thiserror::Error;
+use crate::network::NetworkMode;
+use n01d_common::snapshot::AUTOSTART_PREFIX;
+use crate::shell::{sh_comment, sh_join, sh_quote};
 
+/// Errors from VM management, so callers can react to specific failures
+#[derive(Debug, Error)]
+pub enum VmError {
+    #[error("VM '{0}' not found")]
+    NotFound(String),
+    
+    #[error("VM '{name}' is already running (pid {pid})")]
+    AlreadyRunning { name: String, pid: i32 },
+    
+    #[error("VM '{0}' already exists")]
+    AlreadyExists(String),
+    
+    #[error("Disk not found at {0}")]
+    DiskNotFound(PathBuf),
+    
+    #[error("{path} is not a usable disk image: {reason}")]
+    InvalidDisk { path: PathBuf, reason: String },
+    
+    #[error("Failed to create virtual disk: {0}")]
+    DiskCreateFailed(#[source] std::io::Error),
+    
+    #[error("qemu-img failed: {0}")]
+    QemuImgFailed(String),
+    
+    #[error("Failed to start VM: {0}")]
+    SpawnFailed(#[source] std::io::Error),
+    
+    #[error("QEMU exited during startup: {0}")]
+    LaunchFailed(String),
+    
+    #[error("VM '{0}' is not running")]
+    NotRunning(String),
+    
+    #[error("Failed to signal VM process {pid}: {source}")]
+    StopFailed { pid: i32, source: nix::Error },
+    
+    #[error("Disk {0} is in use by another QEMU process")]
+    DiskInUse(PathBuf),
+    
+    #[error("Failed to run ssh: {0}")]
+    SshFailed(#[source] std::io::Error),
+    
+    #[error("SSH on port {port} not reachable after {secs}s")]
+    SshTimeout { port: u16, secs: u64 },
+    
+    #[error("QMP error: {0}")]
+    Qmp(String),
+    
+    #[error("USB device {0} is not attached")]
+    UsbDeviceNotFound(String),
+    
+    #[error("VM '{0}' has no balloon device; enable it with `create --balloon` and restart")]
+    NoBalloon(String),
+    
+    #[error("VM '{vm}' has no room to hotplug more {resource}; restart it with a higher {flag}")]
+    NoHotplugHeadroom { vm: String, resource: &'static str, flag: &'static str },
+    
+    #[error("Failed to create snapshot: {0}")]
+    SnapshotFailed(String),
+    
+    #[error("Invalid snapshot name '{name}': {reason}")]
+    InvalidSnapshotName { name: String, reason: &'static str },
+    
+    #[error("Snapshot '{name}' already exists on VM '{vm}'")]
+    SnapshotExists { vm: String, name: String },
+    
+    #[error("Failed to restore snapshot: {0}")]
+    RestoreFailed(String),
+    
+    #[error("Disk check failed: {0}")]
+    DiskCheckFailed(String),
+    
+    #[error("Invalid pid file: {0}")]
+    InvalidPidFile(#[from] std::num::ParseIntError),
+    
+    #[error("Invalid VM config: {0}")]
+    ConfigParse(#[from] toml::de::Error),
+    
+    #[error("Failed to write VM config: {0}")]
+    ConfigWrite(#[from] toml::ser::Error),
+    
+    #[error("{0}")]
+    InvalidConfig(String),
+    
+    #[error("Refusing to run hook {path}: {reason}")]
+    UnsafeHook { path: PathBuf, reason: String },
+    
+    #[error("Hook {path} failed: {reason}")]
+    HookFailed { path: PathBuf, reason: String },
+    
+    #[error("Unsupported architecture '{0}' (expected one of: x86_64, aarch64, riscv64)")]
+    UnsupportedArch(String),
+    
+    #[error("Not enough free hugepages: {needed} needed, {free} free; reserve more with `sudo sysctl vm.nr_hugepages={suggested}`")]
+    HugepagesUnavailable { needed: u64, free: u64, suggested: u64 },
+    
+    #[error("Host has no NUMA node {node} (available: {available})")]
+    NumaNodeNotFound { node: u32, available: String },
+    
+    #[error("numactl not found; install the numactl package to pin VMs to a NUMA node")]
+    NumactlMissing,
+    
+    #[error("Bridge networking on {bridge} isn't set up: {problem}")]
+    BridgeSetup { bridge: String, problem: String },
+    
+    #[error("{binary} not found; {hint}")]
+    QemuMissing { binary: String, hint: &'static str },
+    
+    #[error("No OVMF firmware for {firmware} found; install ovmf (Debian/Ubuntu), edk2-ovmf (Fedora/Arch) or qemu (Homebrew)")]
+    FirmwareMissing { firmware: Firmware },
+    
+    #[error("Invalid VM name '{name}': {reason}")]
+    InvalidName { name: String, reason: &'static str },
+    
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, VmError>;
+
+/// Defaults match the `create` flags, so manifest entries only need a name.
+/// This covers what `create` takes; everything `start` can set (nested,
+/// firmware, display, limits and so on) is given to `start` once and kept
+/// in the VM's vm.toml from then on
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct VmConfig {
     pub name: String,
+    #[serde(default = "default_ram")]
     pub ram: String,
+    #[serde(default = "default_disk_size")]
     pub disk: String,
+    #[serde(default = "default_cpus")]
     pub cpus: u32,
     pub iso: Option<PathBuf>,
     pub template: Option<String>,
+    #[serde(default = "default_arch")]
+    pub arch: String,
+    pub group: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default = "default_true")]
+    pub rng: bool,
+    #[serde(default)]
+    pub balloon: bool,
+    #[serde(default = "default_true")]
+    pub discard: bool,
+    pub pre_start: Option<PathBuf>,
+    pub post_stop: Option<PathBuf>,
+    /// Network mode; the config's `default_network` if unset
+    pub network: Option<String>,
+}
+
+/// A lab of VMs for `create_from_manifest`, one `[[vm]]` table per machine
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VmManifest {
+    #[serde(rename = "vm")]
+    vms: Vec<VmConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,6 +183,376 @@ pub struct VmInfo {
     pub snapshots: Vec<String>,
     pub network: String,
     pub isolated: bool,
+    #[serde(default = "default_arch")]
+    pub arch: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default = "default_disk_format")]
+    pub disk_format: String,
+    /// Host port forwarded to the guest's :22; picked from `SSH_PORT_RANGE`
+    /// and saved the first time the VM starts with a forward
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_started: Option<DateTime<Utc>>,
+    /// Seconds run across all cleanly stopped sessions
+    #[serde(default)]
+    pub total_runtime_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Give the guest a virtio-rng device fed from the host
+    #[serde(default = "default_true")]
+    pub rng: bool,
+    /// Add a virtio-balloon device so the host can reclaim guest memory
+    #[serde(default)]
+    pub balloon: bool,
+    /// Pass guest TRIM through so freed blocks shrink the image. The guest
+    /// still has to issue it, via `fstrim` or the `discard` mount option
+    #[serde(default = "default_true")]
+    pub discard: bool,
+    /// CPU topology; when unset QEMU gets a flat `-smp <cpus>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sockets: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+    /// Guest ports published as Tor onion services
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hidden_services: Vec<HiddenService>,
+    /// Expose the host's virtualization extensions so the guest can run
+    /// KVM guests of its own
+    #[serde(default)]
+    pub nested: bool,
+    /// Back guest RAM with preallocated hugepages from /dev/hugepages
+    #[serde(default)]
+    pub hugepages: bool,
+    /// Host NUMA node the guest's RAM and vCPUs are bound to (Linux only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub numa_node: Option<u32>,
+    /// Script run before QEMU is launched; if it fails the VM isn't started.
+    /// Hooks run as the invoking user, so they must be owned by that user
+    /// and not world-writable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_start: Option<PathBuf>,
+    /// Script run once the VM has been stopped; failures are only reported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_stop: Option<PathBuf>,
+    /// Snapshot the disk as `autostart-<timestamp>` before every boot
+    #[serde(default)]
+    pub auto_snapshot: bool,
+    /// Roll the disk back to the newest autostart snapshot on stop
+    #[serde(default)]
+    pub revert_on_stop: bool,
+    /// Autostart snapshots kept; older ones are deleted
+    #[serde(default = "default_autostart_keep")]
+    pub autostart_keep: u32,
+    /// Host USB devices handed to the guest, as `vendorid:productid`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub usb_passthrough: Vec<String>,
+    /// Disk I/O limits for the VM's main disk, in operations and bytes per
+    /// second (reads and writes together)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iops_limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bps_limit: Option<u64>,
+    /// Boot firmware; UEFI and Secure Boot keep their variables in the VM
+    /// directory
+    #[serde(default)]
+    pub firmware: Firmware,
+    /// What QEMU does when the i6300esb watchdog expires, one of
+    /// `WATCHDOG_ACTIONS`. The timer only runs once a daemon in the guest
+    /// (`watchdog`, or systemd's `RuntimeWatchdogSec`) opens /dev/watchdog,
+    /// and it keeps the guest alive by petting it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watchdog: Option<String>,
+    /// How the guest's screen is shown when not headless
+    #[serde(default)]
+    pub display: DisplayMode,
+    /// Share the clipboard through the SPICE agent; SPICE only
+    #[serde(default)]
+    pub clipboard_sharing: bool,
+    /// Host folder offered to the guest over SPICE's WebDAV channel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder_sharing: Option<PathBuf>,
+    /// vCPUs the guest can grow to with `cpu add`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxcpus: Option<u32>,
+    /// Memory the guest can grow to with `mem add`, like `ram`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxmem: Option<String>,
+    /// Raw QEMU arguments appended after everything n01d generates, so they
+    /// can override it. Passed through unvalidated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_args: Vec<String>,
+    /// The `.n01d.toml` the VM was created from. Project commands only touch
+    /// VMs of their own file, so two projects can't both claim one name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<PathBuf>,
+}
+
+impl VmInfo {
+    /// A stopped VM with default settings for everything but its disk and
+    /// network
+    pub fn new(name: &str, disk_path: PathBuf, network: String) -> Self {
+        VmInfo {
+            name: name.to_string(),
+            status: VmStatus::Stopped,
+            ram: default_ram(),
+            cpus: default_cpus(),
+            disk_path,
+            snapshots: vec![],
+            network,
+            isolated: false,
+            arch: default_arch(),
+            group: None,
+            disk_format: default_disk_format(),
+            ssh_port: None,
+            last_started: None,
+            total_runtime_secs: 0,
+            description: None,
+            tags: vec![],
+            rng: true,
+            balloon: false,
+            discard: true,
+            sockets: None,
+            cores: None,
+            threads: None,
+            hidden_services: Vec::new(),
+            nested: false,
+            hugepages: false,
+            pre_start: None,
+            post_stop: None,
+            auto_snapshot: false,
+            revert_on_stop: false,
+            autostart_keep: default_autostart_keep(),
+            usb_passthrough: Vec::new(),
+            watchdog: None,
+            firmware: Firmware::Bios,
+            iops_limit: None,
+            bps_limit: None,
+            numa_node: None,
+            display: DisplayMode::Gtk,
+            clipboard_sharing: false,
+            folder_sharing: None,
+            maxcpus: None,
+            maxmem: None,
+            extra_args: Vec::new(),
+            project: None,
+        }
+    }
+}
+
+/// A guest port reachable as an onion service through a loopback host forward
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HiddenService {
+    /// Port on the .onion address
+    pub virtual_port: u16,
+    pub guest_port: u16,
+    /// Loopback host port forwarded to `guest_port`, which Tor connects to
+    pub host_port: u16,
+}
+
+/// `sockets,cores,threads` as given to `start --topology`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Topology {
+    pub sockets: u32,
+    pub cores: u32,
+    pub threads: u32,
+}
+
+impl std::str::FromStr for Topology {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts: Vec<u32> = s.split(',')
+            .map(|part| part.trim().parse().ok().filter(|&n| n > 0))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("'{}' is not sockets,cores,threads (all positive numbers)", s))?;
+        
+        let topology = match parts[..] {
+            [sockets, cores, threads] => Topology { sockets, cores, threads },
+            _ => return Err(format!("'{}' is not sockets,cores,threads", s)),
+        };
+        topology.cpus().ok_or_else(|| format!("'{}' is more CPUs than can be counted", s))?;
+        Ok(topology)
+    }
+}
+
+impl Topology {
+    /// Total vCPUs, `None` if the product doesn't fit a u32
+    pub fn cpus(&self) -> Option<u32> {
+        self.sockets.checked_mul(self.cores)?.checked_mul(self.threads)
+    }
+}
+
+/// A USB device as `vendorid:productid` in hex, as given to `start --usb`
+pub use n01d_common::usb::UsbId;
+
+/// Saved settings `start --clear` resets to their defaults
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum StartSetting {
+    Nested,
+    Hugepages,
+    Numa,
+    SnapshotOnStart,
+    RevertOnStop,
+    Usb,
+    Watchdog,
+    Firmware,
+    Iops,
+    Bps,
+    Clipboard,
+    ShareFolder,
+    Maxcpus,
+    Maxmem,
+    QemuArgs,
+}
+
+/// Settings `start` turns on, saved for this and later runs of a VM
+#[derive(Debug, Clone, Default)]
+pub struct StartFlags {
+    /// Settings reset before the ones below are applied
+    pub clear: Vec<StartSetting>,
+    pub nested: bool,
+    pub hugepages: bool,
+    /// Replaces the NUMA node the VM is pinned to
+    pub numa_node: Option<u32>,
+    pub snapshot_on_start: bool,
+    pub revert_on_stop: bool,
+    /// Replaces the stored number of autostart snapshots to keep
+    pub keep_snapshots: Option<u32>,
+    /// Added to the VM's passed-through USB devices
+    pub usb: Vec<UsbId>,
+    /// Replaces the watchdog action
+    pub watchdog: Option<String>,
+    pub firmware: Option<Firmware>,
+    /// Replace the stored disk I/O limits
+    pub iops_limit: Option<u64>,
+    pub bps_limit: Option<u64>,
+    pub display: Option<DisplayMode>,
+    pub clipboard: bool,
+    /// Replaces the shared folder
+    pub share_folder: Option<PathBuf>,
+    /// Replace the hotplug headroom
+    pub maxcpus: Option<u32>,
+    pub maxmem: Option<String>,
+    /// Replaces the raw QEMU arguments
+    pub qemu_args: Vec<String>,
+}
+
+impl StartFlags {
+    fn apply(&self, info: &mut VmInfo) {
+        for setting in &self.clear {
+            match setting {
+                StartSetting::Nested => info.nested = false,
+                StartSetting::Hugepages => info.hugepages = false,
+                StartSetting::Numa => info.numa_node = None,
+                StartSetting::SnapshotOnStart => info.auto_snapshot = false,
+                StartSetting::RevertOnStop => info.revert_on_stop = false,
+                StartSetting::Usb => info.usb_passthrough.clear(),
+                StartSetting::Watchdog => info.watchdog = None,
+                StartSetting::Firmware => info.firmware = Firmware::default(),
+                StartSetting::Iops => info.iops_limit = None,
+                StartSetting::Bps => info.bps_limit = None,
+                StartSetting::Clipboard => info.clipboard_sharing = false,
+                StartSetting::ShareFolder => info.folder_sharing = None,
+                StartSetting::Maxcpus => info.maxcpus = None,
+                StartSetting::Maxmem => info.maxmem = None,
+                StartSetting::QemuArgs => info.extra_args.clear(),
+            }
+        }
+        info.nested |= self.nested;
+        info.hugepages |= self.hugepages;
+        if self.numa_node.is_some() {
+            info.numa_node = self.numa_node;
+        }
+        info.auto_snapshot |= self.snapshot_on_start;
+        info.revert_on_stop |= self.revert_on_stop;
+        if let Some(keep) = self.keep_snapshots {
+            info.autostart_keep = keep;
+        }
+        for id in &self.usb {
+            let id = id.to_string();
+            if !info.usb_passthrough.contains(&id) {
+                info.usb_passthrough.push(id);
+            }
+        }
+        if let Some(action) = &self.watchdog {
+            info.watchdog = Some(action.clone());
+        }
+        if let Some(firmware) = self.firmware {
+            info.firmware = firmware;
+        }
+        if self.iops_limit.is_some() {
+            info.iops_limit = self.iops_limit;
+        }
+        if self.bps_limit.is_some() {
+            info.bps_limit = self.bps_limit;
+        }
+        if let Some(display) = self.display {
+            info.display = display;
+        }
+        info.clipboard_sharing |= self.clipboard;
+        if let Some(folder) = &self.share_folder {
+            info.folder_sharing = Some(folder.clone());
+        }
+        if self.maxcpus.is_some() {
+            info.maxcpus = self.maxcpus;
+        }
+        if let Some(maxmem) = &self.maxmem {
+            info.maxmem = Some(maxmem.clone());
+        }
+        if !self.qemu_args.is_empty() {
+            info.extra_args = self.qemu_args.clone();
+        }
+    }
+}
+
+/// Values QEMU's `-watchdog-action` accepts
+pub const WATCHDOG_ACTIONS: &[&str] = &["reset", "shutdown", "poweroff", "pause", "debug", "none", "inject-nmi"];
+
+pub use n01d_common::ports::{DEFAULT_SSH_PORT, SSH_PORT_RANGE};
+
+fn default_ram() -> String {
+    "2G".into()
+}
+
+fn default_disk_size() -> String {
+    "20G".into()
+}
+
+fn default_cpus() -> u32 {
+    2
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_arch() -> String {
+    "x86_64".into()
+}
+
+fn default_disk_format() -> String {
+    "qcow2".into()
+}
+
+fn default_autostart_keep() -> u32 {
+    3
+}
+
+/// How `register_existing_disk` brings the image into the VM directory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiskImport {
+    /// Point the VM at the image where it is
+    InPlace,
+    /// Copy the image into the VM directory, leaving the original untouched
+    Copy,
+    /// Symlink the image into the VM directory
+    Symlink,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -49,27 +576,397 @@ impl std::fmt::Display for VmStatus {
     }
 }
 
-fn get_vm_dir() -> PathBuf {
+/// Longest VM name we accept; it ends up in paths, process names and sockets
+pub const MAX_VM_NAME_LEN: usize = 64;
+
+/// Check that a VM name is safe to join onto the VM directory
+pub fn validate_vm_name(name: &str) -> Result<()> {
+    let reason = if name.is_empty() {
+        Some("name is empty")
+    } else if name.len() > MAX_VM_NAME_LEN {
+        Some("name is longer than 64 characters")
+    } else if name == "." || name == ".." {
+        Some("name cannot be '.' or '..'")
+    } else if name.starts_with('-') {
+        Some("name cannot start with '-'")
+    } else if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        Some("only letters, digits, '.', '_' and '-' are allowed")
+    } else {
+        None
+    };
+    
+    match reason {
+        Some(reason) => Err(VmError::InvalidName { name: name.to_string(), reason }),
+        None => Ok(()),
+    }
+}
+
+/// Whether `/dev/kvm` exists and we can open it for hardware acceleration
+pub fn kvm_available() -> bool {
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .is_ok()
+}
+
+/// Guest architectures we know how to boot
+pub const SUPPORTED_ARCHES: &[&str] = &["x86_64", "aarch64", "riscv64"];
+
+/// UEFI firmware locations for aarch64 guests across distros and Homebrew
+const AARCH64_FIRMWARE: &[&str] = &[
+    "/usr/share/qemu-efi-aarch64/QEMU_EFI.fd",
+    "/usr/share/edk2/aarch64/QEMU_EFI.fd",
+    "/usr/share/AAVMF/AAVMF_CODE.fd",
+    "/opt/homebrew/share/qemu/edk2-aarch64-code.fd",
+    "/usr/local/share/qemu/edk2-aarch64-code.fd",
+];
+
+/// How a VM's screen is shown
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DisplayMode {
+    /// A GTK window from QEMU itself
+    #[default]
+    Gtk,
+    /// A SPICE server on a socket in the VM directory, opened in
+    /// remote-viewer; needed for clipboard and folder sharing
+    Spice,
+}
+
+/// Boot firmware of an x86_64 guest
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Firmware {
+    /// SeaBIOS, QEMU's default
+    #[default]
+    Bios,
+    /// OVMF without Secure Boot
+    Uefi,
+    /// OVMF with Secure Boot enforced and Microsoft's keys enrolled, as
+    /// Windows 11 expects
+    SecureBoot,
+}
+
+impl std::fmt::Display for Firmware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Firmware::Bios => "BIOS",
+            Firmware::Uefi => "UEFI",
+            Firmware::SecureBoot => "Secure Boot",
+        })
+    }
+}
+
+/// OVMF code images with a matching variable store template, across
+/// distros and Homebrew. The two have to come from the same build
+const OVMF_FIRMWARE: &[(&str, &str)] = &[
+    ("/usr/share/OVMF/OVMF_CODE_4M.fd", "/usr/share/OVMF/OVMF_VARS_4M.fd"),
+    ("/usr/share/OVMF/OVMF_CODE.fd", "/usr/share/OVMF/OVMF_VARS.fd"),
+    ("/usr/share/edk2/ovmf/OVMF_CODE.fd", "/usr/share/edk2/ovmf/OVMF_VARS.fd"),
+    ("/usr/share/edk2/x64/OVMF_CODE.4m.fd", "/usr/share/edk2/x64/OVMF_VARS.4m.fd"),
+    ("/usr/share/qemu/ovmf-x86_64-code.bin", "/usr/share/qemu/ovmf-x86_64-vars.bin"),
+    ("/opt/homebrew/share/qemu/edk2-x86_64-code.fd", "/opt/homebrew/share/qemu/edk2-i386-vars.fd"),
+    ("/usr/local/share/qemu/edk2-x86_64-code.fd", "/usr/local/share/qemu/edk2-i386-vars.fd"),
+];
+
+/// Secure Boot builds of OVMF (SMM required), preferring variable stores
+/// with Microsoft's keys already enrolled
+const OVMF_SECURE_BOOT_FIRMWARE: &[(&str, &str)] = &[
+    ("/usr/share/OVMF/OVMF_CODE_4M.secboot.fd", "/usr/share/OVMF/OVMF_VARS_4M.ms.fd"),
+    ("/usr/share/OVMF/OVMF_CODE.secboot.fd", "/usr/share/OVMF/OVMF_VARS.ms.fd"),
+    ("/usr/share/edk2/ovmf/OVMF_CODE.secboot.fd", "/usr/share/edk2/ovmf/OVMF_VARS.secboot.fd"),
+    ("/usr/share/edk2/x64/OVMF_CODE.secboot.4m.fd", "/usr/share/edk2/x64/OVMF_VARS.4m.fd"),
+    ("/usr/share/qemu/ovmf-x86_64-smm-ms-code.bin", "/usr/share/qemu/ovmf-x86_64-smm-ms-vars.bin"),
+    ("/opt/homebrew/share/qemu/edk2-x86_64-secure-code.fd", "/opt/homebrew/share/qemu/edk2-i386-vars.fd"),
+    ("/usr/local/share/qemu/edk2-x86_64-secure-code.fd", "/usr/local/share/qemu/edk2-i386-vars.fd"),
+];
+
+/// The installed OVMF code image and variable store template for `firmware`
+fn find_ovmf(firmware: Firmware) -> Result<(&'static str, &'static str)> {
+    let candidates = match firmware {
+        Firmware::Bios => return Err(VmError::InvalidConfig("BIOS guests don't use OVMF".to_string())),
+        Firmware::Uefi => OVMF_FIRMWARE,
+        Firmware::SecureBoot => OVMF_SECURE_BOOT_FIRMWARE,
+    };
+    candidates.iter()
+        .find(|(code, vars)| Path::new(code).exists() && Path::new(vars).exists())
+        .copied()
+        .ok_or(VmError::FirmwareMissing { firmware })
+}
+
+/// A VM's own copy of the UEFI variable store, one per firmware since the
+/// Secure Boot template carries enrolled keys
+fn varstore_path(info: &VmInfo) -> PathBuf {
+    let file = match info.firmware {
+        Firmware::SecureBoot => "OVMF_VARS.secboot.fd",
+        _ => "OVMF_VARS.fd",
+    };
+    get_vm_dir().join(&info.name).join(file)
+}
+
+/// Copy the variable store template into the VM directory on first boot
+fn ensure_varstore(info: &VmInfo) -> Result<()> {
+    if info.firmware == Firmware::Bios {
+        return Ok(());
+    }
+    
+    let varstore = varstore_path(info);
+    if varstore.exists() {
+        return Ok(());
+    }
+    
+    let (_, template) = find_ovmf(info.firmware)?;
+    fs::copy(template, &varstore)?;
+    // Distro templates are read-only, but the guest writes its variables here
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&varstore, fs::Permissions::from_mode(0o644))?;
+    }
+    Ok(())
+}
+
+/// Machine and pflash arguments booting OVMF from `code` with `varstore`
+fn pflash_args(firmware: Firmware, code: &str, varstore: &Path) -> Vec<String> {
+    let mut args = Vec::new();
+    if firmware == Firmware::SecureBoot {
+        // Secure Boot is only tamper-proof when the varstore sits behind SMM
+        args.extend(["-machine", "q35,smm=on", "-global", "driver=cfi.pflash01,property=secure,value=on"].map(String::from));
+    }
+    let varstore = varstore.display().to_string().replace(',', ",,");
+    args.extend([
+        "-drive".to_string(),
+        format!("if=pflash,format=raw,unit=0,readonly=on,file={}", code.replace(',', ",,")),
+        "-drive".to_string(),
+        format!("if=pflash,format=raw,unit=1,file={}", varstore),
+    ]);
+    args
+}
+
+/// Firmware arguments for a VM, none for BIOS guests
+fn firmware_args(info: &VmInfo) -> Result<Vec<String>> {
+    if info.firmware == Firmware::Bios {
+        return Ok(Vec::new());
+    }
+    if info.arch != "x86_64" {
+        return Err(VmError::InvalidConfig(format!("{} firmware is only available for x86_64 guests", info.firmware)));
+    }
+    
+    let (code, _) = find_ovmf(info.firmware)?;
+    Ok(pflash_args(info.firmware, code, &varstore_path(info)))
+}
+
+/// Name of the QEMU system emulator for `arch`
+pub fn qemu_binary(arch: &str) -> Result<String> {
+    if SUPPORTED_ARCHES.contains(&arch) {
+        Ok(format!("qemu-system-{}", arch))
+    } else {
+        Err(VmError::UnsupportedArch(arch.to_string()))
+    }
+}
+
+/// Which package to install to get `qemu-system-<arch>`
+fn qemu_install_hint(arch: &str) -> &'static str {
+    match arch {
+        "aarch64" => "install qemu-system-arm (Debian/Ubuntu), qemu-system-aarch64 (Fedora) or qemu (Homebrew)",
+        "riscv64" => "install qemu-system-misc (Debian/Ubuntu), qemu-system-riscv (Fedora) or qemu (Homebrew)",
+        _ => "install qemu-system-x86 (Debian/Ubuntu/Fedora) or qemu (Homebrew)",
+    }
+}
+
+/// Resolve the emulator for `arch`, failing with an install hint if it's missing
+fn find_qemu(arch: &str) -> Result<PathBuf> {
+    let binary = qemu_binary(arch)?;
+    which::which(&binary).map_err(|_| VmError::QemuMissing { binary, hint: qemu_install_hint(arch) })
+}
+
+/// Machine type and firmware arguments for `arch`
+fn machine_args(arch: &str) -> Vec<String> {
+    match arch {
+        "aarch64" => {
+            let mut args = vec!["-M".to_string(), "virt".to_string()];
+            if let Some(firmware) = AARCH64_FIRMWARE.iter().find(|p| Path::new(p).exists()) {
+                args.push("-bios".into());
+                args.push(firmware.to_string());
+            }
+            args
+        }
+        "riscv64" => vec!["-M".into(), "virt".into(), "-bios".into(), "default".into()],
+        _ => vec![],
+    }
+}
+
+/// CPU and accelerator arguments; KVM only helps when the guest matches the
+/// host. Nested guests also get the host's vmx/svm flag
+fn accel_args(info: &VmInfo) -> Vec<String> {
+    if info.arch == std::env::consts::ARCH && kvm_available() {
+        let cpu = match nested_kvm_support().filter(|_| info.nested) {
+            Some((flag, _)) => format!("host,+{}", flag),
+            None => "host".to_string(),
+        };
+        vec!["-cpu".into(), cpu, "-enable-kvm".into()]
+    } else {
+        vec!["-cpu".into(), "max".into(), "-accel".into(), "tcg".into()]
+    }
+}
+
+/// The host's KVM CPU flag (vmx or svm, from whichever of kvm_intel and
+/// kvm_amd is loaded) and whether that module has nesting turned on
+fn nested_kvm_support() -> Option<(&'static str, bool)> {
+    [("kvm_intel", "vmx"), ("kvm_amd", "svm")].into_iter().find_map(|(module, flag)| {
+        let nested = fs::read_to_string(format!("/sys/module/{}/parameters/nested", module)).ok()?;
+        // Older kernels report 1/0 rather than Y/N
+        Some((flag, matches!(nested.trim(), "Y" | "1")))
+    })
+}
+
+/// Warn when a nested VM won't actually be able to run KVM guests
+fn check_nested(info: &VmInfo) {
+    use colored::*;
+    
+    if info.arch != std::env::consts::ARCH || !kvm_available() {
+        println!("{} Nested virtualization needs KVM with a same-arch guest; '{}' runs under TCG", "[!]".yellow(), info.name);
+        return;
+    }
+    match nested_kvm_support() {
+        None => println!("{} Neither kvm_intel nor kvm_amd is loaded; nested virtualization is unavailable", "[!]".yellow()),
+        Some((flag, false)) => {
+            let module = if flag == "vmx" { "kvm_intel" } else { "kvm_amd" };
+            println!(
+                "{} Nested KVM is disabled on this host; enable it with `options {} nested=1` in /etc/modprobe.d and reload {}",
+                "[!]".yellow(), module, module
+            );
+        }
+        Some(_) => {}
+    }
+}
+
+pub fn get_vm_dir() -> PathBuf {
     dirs::home_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join("NullSec-VMs")
 }
 
-pub fn list_vms(verbose: bool) -> Result<()> {
-    use colored::*;
+/// Write a file through a temp file and rename, so a crash mid-write never
+/// leaves it truncated. The temp name is unique per call, as two threads
+/// saving at once would otherwise write into the same temp file
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(
+        ".{}.tmp-{}-{}", file_name, std::process::id(), WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    
+    let written = fs::File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&tmp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Write a VM's `vm.toml` atomically
+fn save_vm_info(config_path: &Path, info: &VmInfo) -> Result<()> {
+    write_atomic(config_path, toml::to_string_pretty(info)?.as_bytes())?;
+    Ok(())
+}
+
+/// Read a VM's `vm.toml`
+pub fn load_vm_info(name: &str) -> Result<VmInfo> {
+    validate_vm_name(name)?;
+    
+    let config_path = get_vm_dir().join(name).join("vm.toml");
+    if !config_path.exists() {
+        return Err(VmError::NotFound(name.to_string()));
+    }
+    
+    Ok(toml::from_str(&fs::read_to_string(&config_path)?)?)
+}
+
+/// PID of a VM's QEMU process, if its pidfile points at a live process
+pub fn running_pid(name: &str) -> Option<i32> {
+    n01d_common::process::live_pid(&get_vm_dir().join(name).join("vm.pid"))
+        .and_then(|pid| i32::try_from(pid).ok())
+}
+
+/// Kernel clock ticks per second (USER_HZ), the unit /proc reports CPU and
+/// start times in. 100 on every mainstream config, but not guaranteed
+pub fn clock_ticks_per_sec() -> u64 {
+    nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK)
+        .ok()
+        .flatten()
+        .and_then(|ticks| u64::try_from(ticks).ok())
+        .filter(|&ticks| ticks > 0)
+        .unwrap_or(100)
+}
+
+/// When a process started, from `/proc/<pid>/stat` and the boot time in
+/// `/proc/stat`. `None` off Linux or if the process is gone
+fn process_start_time(pid: i32) -> Option<DateTime<Utc>> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name can contain spaces, so count fields after its closing paren
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let start_ticks: i64 = fields.get(19)?.parse().ok()?;
+    
+    let boot_time: i64 = fs::read_to_string("/proc/stat").ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    
+    DateTime::from_timestamp(boot_time + start_ticks / clock_ticks_per_sec() as i64, 0)
+}
+
+/// Start of a running VM's current session. The recorded time goes stale
+/// when a VM dies without a clean stop and is relaunched outside n01d, so a
+/// later QEMU process start wins
+fn session_start(info: &VmInfo, pid: i32) -> Option<DateTime<Utc>> {
+    match (info.last_started, process_start_time(pid)) {
+        (Some(recorded), Some(process)) => Some(recorded.max(process)),
+        (recorded, process) => recorded.or(process),
+    }
+}
+
+/// How long a running VM has been up
+pub fn uptime_secs(info: &VmInfo) -> Option<u64> {
+    let started = session_start(info, running_pid(&info.name)?)?;
+    Some((Utc::now() - started).num_seconds().max(0) as u64)
+}
+
+/// Compact duration like `2h14m`, `3d4h` or `45s`
+pub fn format_duration(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
     
+    if days > 0 {
+        format!("{}d{}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h{}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m", mins)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Scan the VM directory and return every VM with its live status
+pub fn scan_vms() -> Result<Vec<VmInfo>> {
     let vm_dir = get_vm_dir();
+    let mut vms = Vec::new();
     
     if !vm_dir.exists() {
-        println!("{} No VMs found. VM directory: {}", "[!]".yellow(), vm_dir.display());
-        return Ok(());
+        return Ok(vms);
     }
     
-    println!("{}", "═".repeat(60).blue());
-    println!("{:^60}", "NullSec VMs".bold());
-    println!("{}", "═".repeat(60).blue());
-    
-    let mut found = false;
     for entry in fs::read_dir(&vm_dir)? {
         let entry = entry?;
         let path = entry.path();
@@ -77,86 +974,428 @@ pub fn list_vms(verbose: bool) -> Result<()> {
         if path.is_dir() {
             let config_path = path.join("vm.toml");
             if config_path.exists() {
-                found = true;
                 let config_str = fs::read_to_string(&config_path)?;
-                let info: VmInfo = toml::from_str(&config_str).unwrap_or_else(|_| VmInfo {
-                    name: path.file_name().unwrap().to_string_lossy().to_string(),
-                    status: VmStatus::Stopped,
+                let mut info: VmInfo = toml::from_str(&config_str).unwrap_or_else(|_| VmInfo {
                     ram: "unknown".into(),
                     cpus: 0,
-                    disk_path: path.clone(),
-                    snapshots: vec![],
                     network: "unknown".into(),
-                    isolated: false,
+                    ..VmInfo::new(&path.file_name().unwrap().to_string_lossy(), path.clone(), String::new())
                 });
                 
-                let status_color = match info.status {
-                    VmStatus::Running => "Running".green(),
-                    VmStatus::Stopped => "Stopped".red(),
-                    VmStatus::Paused => "Paused".yellow(),
-                    _ => info.status.to_string().normal(),
-                };
-                
-                println!("\n{} {}", "▶".cyan(), info.name.bold());
-                println!("  Status: {}", status_color);
-                println!("  RAM: {} | CPUs: {}", info.ram, info.cpus);
-                
-                if verbose {
-                    println!("  Disk: {}", info.disk_path.display());
-                    println!("  Network: {} | Isolated: {}", info.network, info.isolated);
-                    if !info.snapshots.is_empty() {
-                        println!("  Snapshots: {}", info.snapshots.join(", "));
-                    }
-                }
+                refresh_status(&mut info);
+                vms.push(info);
             }
         }
     }
     
-    if !found {
-        println!("{} No VMs found", "[!]".yellow());
+    vms.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(vms)
+}
+
+/// The stored status goes stale if QEMU exits on its own, so check the pidfile
+fn refresh_status(info: &mut VmInfo) {
+    if running_pid(&info.name).is_some() {
+        info.status = VmStatus::Running;
+    } else if info.status == VmStatus::Running {
+        info.status = VmStatus::Stopped;
     }
-    
-    println!("{}", "═".repeat(60).blue());
-    Ok(())
 }
 
-pub fn create_vm(config: VmConfig) -> Result<()> {
-    let vm_dir = get_vm_dir().join(&config.name);
-    
-    // Create VM directory
-    fs::create_dir_all(&vm_dir)?;
+/// Every VM with its live status, for `list --json`. Unlike `scan_vms`,
+/// directories without a vm.toml and configs that don't parse are left out
+/// rather than shown as placeholders, with a warning on stderr so stdout
+/// stays valid JSON
+pub fn list_vms_json() -> Result<Vec<VmInfo>> {
+    use colored::*;
     
-    // Parse disk size
-    let disk_size = &config.disk;
-    let disk_path = vm_dir.join(format!("{}.qcow2", config.name));
+    let vm_dir = get_vm_dir();
+    let mut vms = Vec::new();
     
-    // Create virtual disk
-    let output = Command::new("qemu-img")
-        .args(["create", "-f", "qcow2"])
-        .arg(&disk_path)
-        .arg(disk_size)
-        .output()
-        .context("Failed to create virtual disk")?;
+    if !vm_dir.exists() {
+        return Ok(vms);
+    }
     
-    if !output.status.success() {
-        anyhow::bail!("qemu-img failed: {}", String::from_utf8_lossy(&output.stderr));
+    for entry in fs::read_dir(&vm_dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        
+        let config_path = path.join("vm.toml");
+        let parsed = fs::read_to_string(&config_path)
+            .map_err(VmError::from)
+            .and_then(|config| Ok(toml::from_str::<VmInfo>(&config)?));
+        match parsed {
+            Ok(mut info) => {
+                refresh_status(&mut info);
+                vms.push(info);
+            }
+            Err(_) if !config_path.exists() => {
+                eprintln!("{} Skipping {}: no vm.toml", "[!]".yellow(), path.display());
+            }
+            Err(e) => {
+                eprintln!("{} Skipping {}: {}", "[!]".yellow(), config_path.display(), e);
+            }
+        }
     }
     
-    // Save VM config
-    let info = VmInfo {
-        name: config.name.clone(),
-        status: VmStatus::Stopped,
-        ram: config.ram,
-        cpus: config.cpus,
-        disk_path,
-        snapshots: vec![],
-        network: "nat".into(),
-        isolated: false,
-    };
+    vms.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(vms)
+}
+
+/// Create every VM described in a TOML manifest, returning the names created.
+/// The whole manifest is checked first so a typo can't leave a lab half
+/// provisioned; VMs that already exist are skipped with `skip_existing`,
+/// otherwise they're an error
+pub fn create_from_manifest(path: &Path, skip_existing: bool) -> Result<Vec<String>> {
+    let manifest: VmManifest = toml::from_str(&fs::read_to_string(path)?)?;
+    create_all(manifest.vms, path, skip_existing, None)
+}
+
+/// Create `configs`, read from `path`. VMs made from a project file are
+/// marked with `project`, and an existing VM of the same name only counts
+/// as already created if it carries the same mark
+fn create_all(configs: Vec<VmConfig>, path: &Path, skip_existing: bool, project: Option<&Path>) -> Result<Vec<String>> {
+    use colored::*;
+    
+    // Everything create_vm could trip over is checked before the first VM
+    // is made
+    if configs.iter().any(|config| config.network.is_none()) {
+        default_network()?;
+    }
+    let mut seen = std::collections::HashSet::new();
+    let mut to_create = Vec::new();
+    for config in configs {
+        validate_vm_config(&config)?;
+        for hook in config.pre_start.iter().chain(&config.post_stop) {
+            resolve_hook(hook)?;
+        }
+        if !seen.insert(config.name.clone()) {
+            return Err(VmError::InvalidConfig(format!("VM '{}' appears twice in {}", config.name, path.display())));
+        }
+        if let Some(iso) = &config.iso {
+            if !iso.exists() {
+                return Err(VmError::InvalidConfig(format!("ISO for '{}' not found: {}", config.name, iso.display())));
+            }
+        }
+        
+        if get_vm_dir().join(&config.name).join("vm.toml").exists() {
+            if let Some(project) = project {
+                check_project_owns(project, &load_vm_info(&config.name)?)?;
+            }
+            if !skip_existing {
+                return Err(VmError::AlreadyExists(config.name));
+            }
+            println!("{} {}: already exists, skipped", "[*]".blue(), config.name);
+            continue;
+        }
+        to_create.push(config);
+    }
+    
+    let mut created = Vec::new();
+    for config in to_create {
+        let name = config.name.clone();
+        create_vm(config)?;
+        if let Some(project) = project {
+            let mut info = load_vm_info(&name)?;
+            info.project = Some(project.to_path_buf());
+            save_vm_info(&get_vm_dir().join(&name).join("vm.toml"), &info)?;
+        }
+        created.push(name);
+    }
+    
+    Ok(created)
+}
+
+/// Project-local VM definitions, in the manifest format, that `create`,
+/// `start` and `up` pick up from the current directory
+pub const PROJECT_FILE: &str = ".n01d.toml";
+
+/// The project file in the current directory, if there is one
+pub fn project_file() -> Option<PathBuf> {
+    let path = std::env::current_dir().ok()?.join(PROJECT_FILE);
+    path.is_file().then_some(path)
+}
+
+/// Read a project file. Unlike a `--from` manifest, relative ISO paths are
+/// taken relative to the file, so the project works from any checkout.
+/// Hooks aren't allowed: a project comes with whatever repo was cloned, and
+/// running `up` in it shouldn't run its scripts
+fn load_project(path: &Path) -> Result<Vec<VmConfig>> {
+    let manifest: VmManifest = toml::from_str(&fs::read_to_string(path)?)
+        .map_err(|e| VmError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+    if manifest.vms.is_empty() {
+        return Err(VmError::InvalidConfig(format!("{} defines no VMs; add a [[vm]] table", path.display())));
+    }
+    
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut vms = manifest.vms;
+    for config in &mut vms {
+        if config.pre_start.is_some() || config.post_stop.is_some() {
+            return Err(VmError::InvalidConfig(format!(
+                "{}: VM '{}' sets a hook, which a project file can't; add it with `n01d edit {} --pre-start/--post-stop`",
+                path.display(), config.name, config.name
+            )));
+        }
+        if let Some(iso) = config.iso.as_mut().filter(|iso| iso.is_relative()) {
+            *iso = base.join(&*iso);
+        }
+    }
+    Ok(vms)
+}
+
+/// Fail unless `info` was created from the project file at `project`
+fn check_project_owns(project: &Path, info: &VmInfo) -> Result<()> {
+    if info.project.as_deref() == Some(project) {
+        return Ok(());
+    }
+    Err(VmError::InvalidConfig(format!(
+        "VM '{}' already exists and wasn't created from {}; rename the VM there",
+        info.name, project.display()
+    )))
+}
+
+/// Create the VMs in a project file, returning the names created
+pub fn create_project(path: &Path, skip_existing: bool) -> Result<Vec<String>> {
+    let project = path.canonicalize()?;
+    create_all(load_project(&project)?, &project, skip_existing, Some(&project))
+}
+
+/// Start every stopped VM in a project file, returning the names that were
+/// started. Errors are handled per VM, like `start_group`
+pub fn start_project(path: &Path, isolated: bool, network: Option<&str>, headless: bool) -> Result<Vec<String>> {
+    let project = path.canonicalize()?;
+    let members = load_project(&project)?
+        .iter()
+        .map(|config| {
+            let info = load_vm_info(&config.name)?;
+            check_project_owns(&project, &info)?;
+            Ok(info)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(start_each(members, isolated, network, headless))
+}
+
+/// `up`: create whatever in the project file doesn't exist yet, then start
+/// everything that isn't running. Returns the names created and started
+pub fn project_up(path: &Path, headless: bool) -> Result<(Vec<String>, Vec<String>)> {
+    let created = create_project(path, true)?;
+    let started = start_project(path, false, None, headless)?;
+    Ok((created, started))
+}
+
+/// Tags are matched exactly by `list --tag`, so keep them to a single word
+fn validate_tag(tag: &str) -> Result<()> {
+    if tag.is_empty() || tag.chars().any(|c| c.is_whitespace() || c == ',') {
+        return Err(VmError::InvalidConfig(format!("Invalid tag '{}': tags can't be empty or contain spaces or commas", tag)));
+    }
+    Ok(())
+}
+
+/// Whether a VM passes the `list` filters: carries `tag`, and has `search`
+/// somewhere in its name or description (case-insensitive)
+pub fn matches_filter(info: &VmInfo, tag: Option<&str>, search: Option<&str>) -> bool {
+    if let Some(tag) = tag {
+        if !info.tags.iter().any(|t| t == tag) {
+            return false;
+        }
+    }
+    
+    if let Some(search) = search {
+        let search = search.to_lowercase();
+        let in_desc = info.description.as_deref().is_some_and(|d| d.to_lowercase().contains(&search));
+        if !info.name.to_lowercase().contains(&search) && !in_desc {
+            return false;
+        }
+    }
+    
+    true
+}
+
+/// Change a VM's description, tags and hooks. An empty description or hook
+/// path clears it
+pub fn edit_vm(
+    name: &str,
+    add_tags: &[String],
+    remove_tags: &[String],
+    description: Option<&str>,
+    pre_start: Option<&Path>,
+    post_stop: Option<&Path>,
+) -> Result<()> {
+    let mut info = load_vm_info(name)?;
+    
+    for tag in add_tags {
+        validate_tag(tag)?;
+        if !info.tags.contains(tag) {
+            info.tags.push(tag.clone());
+        }
+    }
+    info.tags.retain(|tag| !remove_tags.contains(tag));
+    
+    if let Some(description) = description {
+        info.description = Some(description.to_string()).filter(|d| !d.is_empty());
+    }
+    if let Some(path) = pre_start {
+        info.pre_start = edit_hook(path)?;
+    }
+    if let Some(path) = post_stop {
+        info.post_stop = edit_hook(path)?;
+    }
+    
+    save_vm_info(&get_vm_dir().join(name).join("vm.toml"), &info)
+}
+
+pub fn list_vms(verbose: bool, tag: Option<&str>, search: Option<&str>) -> Result<()> {
+    use colored::*;
+    
+    let vm_dir = get_vm_dir();
+    
+    if !vm_dir.exists() {
+        println!("{} No VMs found. VM directory: {}", "[!]".yellow(), vm_dir.display());
+        return Ok(());
+    }
+    
+    println!("{}", "═".repeat(60).blue());
+    println!("{:^60}", "NullSec VMs".bold());
+    println!("{}", "═".repeat(60).blue());
+    
+    let vms: Vec<VmInfo> = scan_vms()?
+        .into_iter()
+        .filter(|info| matches_filter(info, tag, search))
+        .collect();
+    for info in &vms {
+        let status_color = match info.status {
+            VmStatus::Running => "Running".green(),
+            VmStatus::Stopped => "Stopped".red(),
+            VmStatus::Paused => "Paused".yellow(),
+            _ => info.status.to_string().normal(),
+        };
+        
+        println!("\n{} {}", "▶".cyan(), info.name.bold());
+        println!("  Status: {}", status_color);
+        println!("  RAM: {} | CPUs: {}", info.ram, info.cpus);
+        
+        if let Some(description) = &info.description {
+            println!("  {}", description.dimmed());
+        }
+        
+        if verbose {
+            println!("  Disk: {}", info.disk_path.display());
+            if info.iops_limit.is_some() || info.bps_limit.is_some() {
+                let limit = |value: Option<u64>, unit: &str| match value {
+                    Some(value) => format!("{} {}", value, unit),
+                    None => "unlimited".to_string(),
+                };
+                println!("  Disk limits: {} | {}", limit(info.iops_limit, "IOPS"), limit(info.bps_limit, "B/s"));
+            }
+            if !info.tags.is_empty() {
+                println!("  Tags: {}", info.tags.join(", "));
+            }
+            println!("  Network: {} | Isolated: {}", info.network, info.isolated);
+            if info.status == VmStatus::Running {
+                if let Some(secs) = uptime_secs(info) {
+                    println!("  Uptime: up {}", format_duration(secs));
+                }
+                if info.balloon {
+                    if let Ok(actual) = balloon_actual_mb(&info.name) {
+                        println!("  Memory: {} MB of {} (ballooned)", actual, info.ram);
+                    }
+                }
+            } else if info.total_runtime_secs > 0 {
+                println!("  Runtime: {} total", format_duration(info.total_runtime_secs));
+            }
+            if !info.snapshots.is_empty() {
+                println!("  Snapshots: {}", info.snapshots.join(", "));
+            }
+        }
+    }
+    
+    if vms.is_empty() {
+        println!("{} No VMs found", "[!]".yellow());
+    }
+    
+    println!("{}", "═".repeat(60).blue());
+    Ok(())
+}
+
+/// Checks that don't touch the disk, shared by `create_vm` and manifests
+fn validate_vm_config(config: &VmConfig) -> Result<()> {
+    validate_vm_name(&config.name)?;
+    qemu_binary(&config.arch)?;
+    for tag in &config.tags {
+        validate_tag(tag)?;
+    }
+    if config.cpus == 0 {
+        return Err(VmError::InvalidConfig(format!("VM '{}' needs at least one CPU", config.name)));
+    }
+    if !valid_size(&config.ram) {
+        return Err(VmError::InvalidConfig(format!("VM '{}' has an invalid RAM size '{}'", config.name, config.ram)));
+    }
+    if !valid_size(&config.disk) {
+        return Err(VmError::InvalidConfig(format!("VM '{}' has an invalid disk size '{}'", config.name, config.disk)));
+    }
+    if let Some(network) = &config.network {
+        validate_network(network)?;
+    }
+    Ok(())
+}
+
+/// Whether a size looks like something QEMU takes, e.g. `2G` or `1.5T`
+fn valid_size(size: &str) -> bool {
+    let size = size.trim();
+    let (number, unit) = size.split_at(size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len()));
+    number.parse::<f64>().is_ok_and(|n| n > 0.0)
+        && matches!(unit.to_ascii_uppercase().trim_end_matches('B'), "" | "K" | "M" | "G" | "T")
+}
+
+pub fn create_vm(config: VmConfig) -> Result<()> {
+    validate_vm_config(&config)?;
+    let pre_start = config.pre_start.as_deref().map(resolve_hook).transpose()?;
+    let post_stop = config.post_stop.as_deref().map(resolve_hook).transpose()?;
+    let network = match config.network {
+        Some(network) => network,
+        None => default_network()?,
+    };
+    
+    let vm_dir = get_vm_dir().join(&config.name);
+    
+    // Create VM directory
+    fs::create_dir_all(&vm_dir)?;
+    
+    // Parse disk size
+    let disk_size = &config.disk;
+    let disk_path = vm_dir.join(format!("{}.qcow2", config.name));
+    
+    // Create virtual disk
+    let output = Command::new("qemu-img")
+        .args(["create", "-f", "qcow2"])
+        .arg(&disk_path)
+        .arg(disk_size)
+        .output()
+        .map_err(VmError::DiskCreateFailed)?;
+    
+    if !output.status.success() {
+        return Err(VmError::QemuImgFailed(String::from_utf8_lossy(&output.stderr).to_string()));
+    }
+    
+    // Save VM config
+    let info = VmInfo {
+        ram: config.ram,
+        cpus: config.cpus,
+        arch: config.arch,
+        group: config.group,
+        description: config.description,
+        tags: config.tags,
+        rng: config.rng,
+        balloon: config.balloon,
+        discard: config.discard,
+        pre_start,
+        post_stop,
+        ..VmInfo::new(&config.name, disk_path, network)
+    };
     
     let config_path = vm_dir.join("vm.toml");
-    let config_str = toml::to_string_pretty(&info)?;
-    fs::write(&config_path, config_str)?;
+    save_vm_info(&config_path, &info)?;
     
     // Create launcher script
     create_launcher_script(&vm_dir, &info, config.iso.as_ref())?;
@@ -164,29 +1403,256 @@ pub fn create_vm(config: VmConfig) -> Result<()> {
     Ok(())
 }
 
+/// Detect the format of a disk image with `qemu-img info`
+fn detect_disk_format(disk: &Path) -> Result<String> {
+    let output = Command::new("qemu-img")
+        .args(["info", "--output=json"])
+        .arg(disk)
+        .output()?;
+    
+    if !output.status.success() {
+        return Err(VmError::InvalidDisk {
+            path: disk.to_path_buf(),
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| VmError::QemuImgFailed(e.to_string()))?;
+    info["format"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| VmError::QemuImgFailed("qemu-img info reported no format".to_string()))
+}
+
+/// Catch a disk some other QEMU already has open, say a VM started outside
+/// n01d. QEMU holds its images with OFD locks that `qemu-img info` also
+/// asks for, so it fails the same way the launch would, only more readably
+fn check_disk_unlocked(disk: &Path) -> Result<()> {
+    if !disk.exists() {
+        return Ok(());
+    }
+    
+    let output = Command::new("qemu-img").arg("info").arg(disk).output()?;
+    if !output.status.success() && String::from_utf8_lossy(&output.stderr).contains("lock") {
+        return Err(VmError::DiskInUse(disk.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Register a disk image made elsewhere as a new VM
+pub fn register_existing_disk(name: &str, disk: &Path, ram: &str, cpus: u32, import: DiskImport) -> Result<()> {
+    validate_vm_name(name)?;
+    let network = default_network()?;
+    
+    let vm_dir = get_vm_dir().join(name);
+    if vm_dir.join("vm.toml").exists() {
+        return Err(VmError::AlreadyExists(name.to_string()));
+    }
+    
+    let disk = disk.canonicalize().map_err(|_| VmError::DiskNotFound(disk.to_path_buf()))?;
+    
+    // Make sure we can actually read it before handing it to QEMU
+    fs::File::open(&disk).map_err(|e| VmError::InvalidDisk { path: disk.clone(), reason: e.to_string() })?;
+    
+    let disk_format = detect_disk_format(&disk)?;
+    
+    // qemu-img calls any file "raw", so at least insist on whole sectors
+    if disk_format == "raw" && fs::metadata(&disk)?.len() % 512 != 0 {
+        return Err(VmError::InvalidDisk {
+            path: disk,
+            reason: "raw image size is not a multiple of 512 bytes".to_string(),
+        });
+    }
+    
+    fs::create_dir_all(&vm_dir)?;
+    
+    let disk_path = match import {
+        DiskImport::InPlace => disk,
+        DiskImport::Copy | DiskImport::Symlink => {
+            let file_name = disk.file_name().map(PathBuf::from).unwrap_or_else(|| PathBuf::from(name));
+            let target = vm_dir.join(file_name);
+            if target.exists() {
+                return Err(VmError::InvalidDisk { path: target, reason: "file already exists".to_string() });
+            }
+            
+            if import == DiskImport::Copy {
+                fs::copy(&disk, &target)?;
+            } else {
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(&disk, &target)?;
+                #[cfg(not(unix))]
+                fs::copy(&disk, &target)?;
+            }
+            target
+        }
+    };
+    
+    let info = VmInfo {
+        ram: ram.to_string(),
+        cpus,
+        disk_format,
+        ..VmInfo::new(name, disk_path, network)
+    };
+    
+    save_vm_info(&vm_dir.join("vm.toml"), &info)?;
+    create_launcher_script(&vm_dir, &info, None)?;
+    
+    Ok(())
+}
+
+/// Formats `import_foreign_disk` converts, as `qemu-img info` names them
+const FOREIGN_DISK_FORMATS: &[&str] = &["vdi", "vmdk", "vhdx"];
+
+/// Extent files named in a VMDK descriptor, resolved next to it. Split and
+/// flat VMDKs keep their data in these; a monolithic image, or anything that
+/// isn't a VMDK descriptor, has none
+fn vmdk_extents(src: &Path) -> Result<Vec<PathBuf>> {
+    // Descriptors are a few hundred bytes of text; anything big is a data file
+    if fs::metadata(src)?.len() > 64 * 1024 {
+        return Ok(Vec::new());
+    }
+    let text = match fs::read_to_string(src) {
+        Ok(text) => text,
+        Err(_) => return Ok(Vec::new()),
+    };
+    
+    let dir = src.parent().unwrap_or(Path::new("."));
+    let extents: Vec<PathBuf> = parse_vmdk_extents(&text).into_iter().map(|file| dir.join(file)).collect();
+    if let Some(missing) = extents.iter().find(|extent| !extent.exists()) {
+        return Err(VmError::InvalidDisk {
+            path: src.to_path_buf(),
+            reason: format!("its extent {} is missing; keep the split files next to the descriptor", missing.display()),
+        });
+    }
+    Ok(extents)
+}
+
+/// File names from the extent lines of a VMDK descriptor, such as
+/// `RW 8388608 SPARSE "disk-s001.vmdk"`
+fn parse_vmdk_extents(descriptor: &str) -> Vec<String> {
+    if !descriptor.starts_with("# Disk DescriptorFile") {
+        return Vec::new();
+    }
+    descriptor
+        .lines()
+        .filter(|line| ["RW ", "RDONLY ", "NOACCESS "].iter().any(|access| line.trim_start().starts_with(access)))
+        .filter_map(|line| line.split('"').nth(1))
+        .filter(|file| !file.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Convert a VirtualBox (VDI), VMware (VMDK) or Hyper-V (VHDX) disk into a
+/// new VM with a qcow2 disk and default settings. Returns the source format
+/// and the converted image's size. A split VMDK is imported through its
+/// descriptor. The source is left alone unless `move_source` is set, in which
+/// case it (and any VMDK extents) is deleted once the conversion succeeded
+pub fn import_foreign_disk(src: &Path, name: &str, move_source: bool) -> Result<(String, u64)> {
+    validate_vm_name(name)?;
+    let network = default_network()?;
+    
+    let vm_dir = get_vm_dir().join(name);
+    if vm_dir.join("vm.toml").exists() {
+        return Err(VmError::AlreadyExists(name.to_string()));
+    }
+    
+    let src = src.canonicalize().map_err(|_| VmError::DiskNotFound(src.to_path_buf()))?;
+    let extents = vmdk_extents(&src)?;
+    let format = detect_disk_format(&src)?;
+    if !FOREIGN_DISK_FORMATS.contains(&format.as_str()) {
+        return Err(VmError::InvalidDisk {
+            path: src,
+            reason: format!("it's a {} image; import-disk converts vdi, vmdk and vhdx (use register for others)", format),
+        });
+    }
+    
+    fs::create_dir_all(&vm_dir)?;
+    let disk_path = vm_dir.join(format!("{}.qcow2", name));
+    let tmp = vm_dir.join(format!("{}.qcow2.import-tmp", name));
+    
+    // Converted under a temporary name so a failed run leaves nothing that
+    // looks like a usable disk
+    let output = Command::new("qemu-img")
+        .args(["convert", "-f", &format, "-O", "qcow2"])
+        .arg(&src)
+        .arg(&tmp)
+        .output()?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&tmp);
+        let _ = fs::remove_dir(&vm_dir);
+        return Err(VmError::QemuImgFailed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    fs::rename(&tmp, &disk_path)?;
+    
+    let info = VmInfo::new(name, disk_path.clone(), network);
+    
+    save_vm_info(&vm_dir.join("vm.toml"), &info)?;
+    create_launcher_script(&vm_dir, &info, None)?;
+    
+    let (size, _) = disk_sizes(&disk_path)?;
+    if move_source {
+        for extent in &extents {
+            fs::remove_file(extent)?;
+        }
+        fs::remove_file(&src)?;
+    }
+    
+    Ok((format, size))
+}
+
 fn create_launcher_script(vm_dir: &PathBuf, info: &VmInfo, iso: Option<&PathBuf>) -> Result<()> {
     let script_path = vm_dir.join("start.sh");
     
-    let iso_arg = iso.map(|p| format!("-cdrom {} -boot d", p.display()))
+    let iso_arg = iso.map(|p| format!("-cdrom {} -boot d", sh_quote(&p.display().to_string())))
         .unwrap_or_default();
     
+    let qemu = qemu_binary(&info.arch)?;
+    // Nested guests need the vmx/svm flag of whichever host runs the script
+    let kvm_cpu = if info.nested {
+        "host,+$([ -d /sys/module/kvm_amd ] && echo svm || echo vmx)"
+    } else {
+        "host"
+    };
+    
+    // The script can outlive the host it was made on, so KVM is looked for
+    // each time it runs, the same way start_vm does
     let script = format!(r#"#!/bin/bash
 # NullSec VM Launcher - {}
 
-VM_DIR="$(dirname "$0")"
-DISK="$VM_DIR/{}.qcow2"
+DISK={}
 
-qemu-system-x86_64 \
+if [ "$(uname -m)" = {} ] && [ -r /dev/kvm ] && [ -w /dev/kvm ]; then
+    ACCEL=(-cpu "{}" -enable-kvm)
+else
+    [ "$(uname -m)" = {} ] && echo "/dev/kvm is not accessible, falling back to TCG emulation" >&2
+    ACCEL=(-cpu max -accel tcg)
+fi
+
+{} \
     -m {} \
     -smp {} \
-    -cpu host \
-    -enable-kvm \
-    -drive file="$DISK",format=qcow2 \
+    "${{ACCEL[@]}}"{} \
+    -drive file="${{DISK//,/,,}}",format={}{} \
     {} \
     -display gtk \
-    -name "{}" \
+    -name {} \
     "$@"
-"#, info.name, info.name, info.ram, info.cpus, iso_arg, info.name);
+"#,
+        sh_comment(&info.name),
+        sh_quote(&info.disk_path.display().to_string()),
+        sh_quote(&info.arch),
+        kvm_cpu,
+        sh_quote(&info.arch),
+        qemu,
+        sh_quote(&info.ram),
+        smp_arg(info)?,
+        machine_args(&info.arch).iter().map(|arg| format!(" {}", sh_quote(arg))).collect::<String>(),
+        sh_quote(&info.disk_format),
+        discard_options(info),
+        iso_arg,
+        sh_quote(&info.name),
+    );
     
     fs::write(&script_path, script)?;
     
@@ -202,171 +1668,1817 @@ qemu-system-x86_64 \
     Ok(())
 }
 
-pub fn start_vm(name: &str, isolated: bool, network: &str, headless: bool) -> Result<()> {
-    let vm_dir = get_vm_dir().join(name);
-    let config_path = vm_dir.join("vm.toml");
+/// How long QEMU has to survive before a launch counts as successful
+const LAUNCH_GRACE: Duration = Duration::from_millis(500);
+
+/// Last `lines` lines of a log file, for error messages
+fn log_tail(path: &Path, lines: usize) -> String {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let tail: Vec<&str> = content.lines().rev().take(lines).collect();
+    
+    if tail.is_empty() {
+        return format!("no output, see {}", path.display());
+    }
+    tail.into_iter().rev().collect::<Vec<_>>().join("\n")
+}
+
+/// Where a VM's QEMU stdout/stderr ends up
+pub fn qemu_log_path(name: &str) -> PathBuf {
+    get_vm_dir().join(name).join("qemu.log")
+}
+
+/// Start a fresh `qemu.log`, keeping the previous run's as `qemu.log.1`
+fn open_qemu_log(vm_dir: &Path) -> Result<fs::File> {
+    let log_path = vm_dir.join("qemu.log");
+    if log_path.exists() {
+        fs::rename(&log_path, vm_dir.join("qemu.log.1"))?;
+    }
+    
+    Ok(fs::OpenOptions::new().create(true).append(true).open(&log_path)?)
+}
+
+/// Print a VM's QEMU log, optionally following it like `tail -f`
+pub fn show_logs(vm: &str, follow: bool) -> Result<()> {
+    use std::io::{Read, Seek, SeekFrom, Write};
+    
+    validate_vm_name(vm)?;
+    
+    if !get_vm_dir().join(vm).join("vm.toml").exists() {
+        return Err(VmError::NotFound(vm.to_string()));
+    }
+    
+    let log_path = qemu_log_path(vm);
+    let mut pos = 0u64;
+    let mut stdout = std::io::stdout();
+    
+    loop {
+        if let Ok(mut file) = fs::File::open(&log_path) {
+            // A restart rotates the log, so start again from the top
+            let len = file.metadata()?.len();
+            if len < pos {
+                pos = 0;
+            }
+            
+            file.seek(SeekFrom::Start(pos))?;
+            let mut chunk = Vec::new();
+            file.read_to_end(&mut chunk)?;
+            pos += chunk.len() as u64;
+            
+            stdout.write_all(&chunk)?;
+            stdout.flush()?;
+        } else if !follow {
+            use colored::*;
+            println!("{} No log yet for '{}'", "[!]".yellow(), vm);
+        }
+        
+        if !follow {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+}
+
+/// Path of a VM's QMP control socket
+pub fn qmp_socket_path(name: &str) -> PathBuf {
+    get_vm_dir().join(name).join("qmp.sock")
+}
+
+/// Path of the socket a SPICE VM's display is served on
+pub fn spice_socket_path(name: &str) -> PathBuf {
+    get_vm_dir().join(name).join("spice.sock")
+}
+
+/// The shared folder has to exist; it's stored absolute since the viewer
+/// may be opened from anywhere
+fn check_shared_folder(info: &mut VmInfo) -> Result<()> {
+    if let Some(folder) = &info.folder_sharing {
+        let folder = folder.canonicalize()
+            .ok()
+            .filter(|folder| folder.is_dir())
+            .ok_or_else(|| VmError::InvalidConfig(format!("Shared folder {} is not a directory", folder.display())))?;
+        info.folder_sharing = Some(folder);
+    }
+    Ok(())
+}
+
+/// Open remote-viewer on a SPICE VM, handing it the shared folder, or say
+/// how to connect if it isn't installed
+fn open_spice_viewer(info: &VmInfo) {
+    use colored::*;
+    
+    let uri = format!("spice+unix://{}", spice_socket_path(&info.name).display());
+    let mut cmd = Command::new("remote-viewer");
+    if let Some(folder) = &info.folder_sharing {
+        cmd.arg(format!("--spice-shared-dir={}", folder.display()));
+    }
+    cmd.arg(&uri).stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    
+    if cmd.spawn().is_err() {
+        println!("{} remote-viewer not found; connect a SPICE client to {}", "[!]".yellow(), uri);
+    }
+}
+
+/// SPICE server and agent channels. The clipboard rides on the vdagent
+/// port and folders on the WebDAV one, both of which need spice-vdagent
+/// (or spice-webdavd) running in the guest
+fn spice_args(info: &VmInfo) -> Result<Vec<String>> {
+    let sharing = info.clipboard_sharing || info.folder_sharing.is_some();
+    if info.display != DisplayMode::Spice {
+        if sharing {
+            return Err(VmError::InvalidConfig(
+                "Clipboard and folder sharing need the SPICE display (--display spice)".to_string(),
+            ));
+        }
+        return Ok(vec![]);
+    }
+    
+    let socket = spice_socket_path(&info.name).display().to_string().replace(',', ",,");
+    let mut args = vec!["-spice".to_string(), format!("unix=on,addr={},disable-ticketing=on", socket)];
+    if sharing {
+        args.extend(["-device".into(), "virtio-serial-pci".into()]);
+    }
+    if info.clipboard_sharing {
+        args.extend([
+            "-chardev".into(), "spicevmc,id=spicechannel0,name=vdagent".into(),
+            "-device".into(), "virtserialport,chardev=spicechannel0,name=com.redhat.spice.0".into(),
+        ]);
+    }
+    if info.folder_sharing.is_some() {
+        args.extend([
+            "-chardev".into(), "spiceport,id=spicechannel1,name=org.spice-space.webdav.0".into(),
+            "-device".into(), "virtserialport,chardev=spicechannel1,name=org.spice-space.webdav.0".into(),
+        ]);
+    }
+    Ok(args)
+}
+
+/// Run one QMP command against a running VM and return its `return` value
+pub fn qmp_command(name: &str, execute: &str, arguments: Option<serde_json::Value>) -> Result<serde_json::Value> {
+    use std::io::BufReader;
+    use std::os::unix::net::UnixStream;
+    
+    validate_vm_name(name)?;
+    if running_pid(name).is_none() {
+        return Err(VmError::NotRunning(name.to_string()));
+    }
+    
+    let socket = qmp_socket_path(name);
+    let stream = UnixStream::connect(&socket).map_err(|e| {
+        VmError::Qmp(format!("can't connect to {} ({}); restart the VM to enable QMP", socket.display(), e))
+    })?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    
+    // The server greets first and only accepts commands after capabilities negotiation
+    qmp_next(&mut reader)?;
+    qmp_execute(&mut reader, &mut writer, "qmp_capabilities", None)?;
+    qmp_execute(&mut reader, &mut writer, execute, arguments)
+}
+
+fn qmp_execute(
+    reader: &mut impl std::io::BufRead,
+    writer: &mut impl std::io::Write,
+    execute: &str,
+    arguments: Option<serde_json::Value>,
+) -> Result<serde_json::Value> {
+    let mut request = serde_json::json!({ "execute": execute });
+    if let Some(arguments) = arguments {
+        request["arguments"] = arguments;
+    }
+    writeln!(writer, "{}", request)?;
+    
+    let reply = qmp_next(reader)?;
+    if let Some(error) = reply.get("error") {
+        let desc = error["desc"].as_str().unwrap_or("unknown error");
+        return Err(VmError::Qmp(format!("{}: {}", execute, desc)));
+    }
+    
+    Ok(reply.get("return").cloned().unwrap_or(serde_json::Value::Null))
+}
+
+/// Next QMP message that isn't an asynchronous event
+fn qmp_next(reader: &mut impl std::io::BufRead) -> Result<serde_json::Value> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(VmError::Qmp("connection closed".to_string()));
+        }
+        
+        let message: serde_json::Value = serde_json::from_str(&line)
+            .map_err(|e| VmError::Qmp(format!("bad reply: {}", e)))?;
+        if message.get("event").is_none() {
+            return Ok(message);
+        }
+    }
+}
+
+/// Copy a running VM's disk to `target` with a QMP backup job. The guest is
+/// paused only while the job starts, which fixes the point in time copied;
+/// it keeps running while the data is written out
+pub fn backup_running_disk(info: &VmInfo, target: &Path) -> Result<()> {
+    let device = block_device(&info.name, &info.disk_path)?;
+    let job = format!("n01d-backup-{}", std::process::id());
+    
+    qmp_command(&info.name, "stop", None)?;
+    let started = qmp_command(&info.name, "drive-backup", Some(serde_json::json!({
+        "job-id": job,
+        "device": device,
+        "target": target.display().to_string(),
+        "format": info.disk_format,
+        "sync": "full",
+        "auto-dismiss": false,
+    })));
+    if let Err(e) = qmp_command(&info.name, "cont", None) {
+        return Err(VmError::Qmp(format!("left paused, failed to resume: {}", e)));
+    }
+    started?;
+    
+    wait_for_job(&info.name, &job)
+}
+
+/// QEMU's name for the block device backed by `disk`
+fn block_device(name: &str, disk: &Path) -> Result<String> {
+    let devices = qmp_command(name, "query-block", None)?;
+    devices.as_array()
+        .into_iter()
+        .flatten()
+        .find(|device| device["inserted"]["file"].as_str() == Some(&*disk.to_string_lossy()))
+        .and_then(|device| device["device"].as_str())
+        .map(str::to_string)
+        .ok_or_else(|| VmError::Qmp(format!("no block device for {}", disk.display())))
+}
+
+/// Poll a block job until it finishes, then dismiss it
+fn wait_for_job(name: &str, job: &str) -> Result<()> {
+    loop {
+        let jobs = qmp_command(name, "query-jobs", None)?;
+        let status = jobs.as_array()
+            .into_iter()
+            .flatten()
+            .find(|entry| entry["id"].as_str() == Some(job))
+            .ok_or_else(|| VmError::Qmp(format!("job {} disappeared", job)))?;
+        
+        if status["status"] == "concluded" {
+            qmp_command(name, "job-dismiss", Some(serde_json::json!({ "id": job })))?;
+            return match status["error"].as_str() {
+                Some(error) => Err(VmError::Qmp(format!("backup failed: {}", error))),
+                None => Ok(()),
+            };
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// Where hugetlbfs is mounted on systemd hosts
+const HUGEPAGES_MOUNT: &str = "/dev/hugepages";
+
+/// `HugePages_Free`, `HugePages_Total` and `Hugepagesize` (in kB) from /proc/meminfo
+fn parse_hugepages(meminfo: &str) -> Option<(u64, u64, u64)> {
+    let field = |name: &str| -> Option<u64> {
+        meminfo.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?
+            .split_whitespace()
+            .next()?
+            .parse()
+            .ok()
+    };
+    Some((field("HugePages_Free")?, field("HugePages_Total")?, field("Hugepagesize")?))
+}
+
+/// Make sure hugetlbfs is mounted and enough hugepages are free to back all
+/// of the guest's RAM, since -mem-prealloc takes them up front
+fn check_hugepages(info: &VmInfo) -> Result<()> {
+    if !Path::new(HUGEPAGES_MOUNT).is_dir() {
+        return Err(VmError::InvalidConfig(format!("hugetlbfs is not mounted at {}", HUGEPAGES_MOUNT)));
+    }
+    
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let (free, total, page_kb) = parse_hugepages(&meminfo)
+        .ok_or_else(|| VmError::InvalidConfig("this kernel doesn't report hugepages in /proc/meminfo".to_string()))?;
+    let ram_mb = ram_mb(&info.ram)
+        .ok_or_else(|| VmError::InvalidConfig(format!("Can't size hugepages for RAM '{}'", info.ram)))?;
+    
+    let needed = ram_mb.checked_mul(1024)
+        .ok_or_else(|| VmError::InvalidConfig(format!("Can't size hugepages for RAM '{}'", info.ram)))?
+        .div_ceil(page_kb.max(1));
+    if needed > free {
+        return Err(VmError::HugepagesUnavailable { needed, free, suggested: total + needed - free });
+    }
+    Ok(())
+}
+
+/// Where Linux lists the host's NUMA nodes
+const NUMA_SYSFS: &str = "/sys/devices/system/node";
+
+/// The host's NUMA nodes, from the `nodeN` directories in sysfs
+fn numa_nodes() -> Vec<u32> {
+    let mut nodes: Vec<u32> = fs::read_dir(NUMA_SYSFS)
+        .map(|entries| {
+            entries.flatten()
+                .filter_map(|entry| entry.file_name().to_str()?.strip_prefix("node")?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    nodes.sort_unstable();
+    nodes
+}
+
+/// The NUMA node to pin a VM to, if any. Pinning needs Linux's NUMA policy
+/// support, so elsewhere it's dropped
+fn numa_node(info: &VmInfo) -> Option<u32> {
+    info.numa_node.filter(|_| cfg!(target_os = "linux"))
+}
+
+/// Make sure the VM's NUMA node exists and numactl is there to bind its
+/// vCPUs to it
+fn check_numa(info: &VmInfo) -> Result<()> {
+    let Some(node) = info.numa_node else {
+        return Ok(());
+    };
+    if numa_node(info).is_none() {
+        use colored::*;
+        println!("{} NUMA pinning is only supported on Linux, ignoring numa_node = {}", "[!]".yellow(), node);
+        return Ok(());
+    }
+    
+    let nodes = numa_nodes();
+    if !nodes.contains(&node) {
+        let available: Vec<String> = nodes.iter().map(|node| node.to_string()).collect();
+        return Err(VmError::NumaNodeNotFound { node, available: available.join(", ") });
+    }
+    which::which("numactl").map_err(|_| VmError::NumactlMissing)?;
+    Ok(())
+}
+
+/// Host bridge that `bridge` networking attaches VMs to
+const BRIDGE: &str = "br0";
+
+/// Where distributions install QEMU's setuid bridge helper
+const BRIDGE_HELPERS: &[&str] = &[
+    "/usr/lib/qemu/qemu-bridge-helper",
+    "/usr/libexec/qemu-bridge-helper",
+    "/usr/lib/qemu-bridge-helper",
+    "/usr/local/libexec/qemu-bridge-helper",
+];
+
+/// The helper's list of bridges unprivileged users may attach to
+const BRIDGE_CONF: &str = "/etc/qemu/bridge.conf";
+
+/// Make sure QEMU's bridge helper will let this user attach to `bridge`:
+/// the bridge exists, the helper is installed and can create TAP devices,
+/// and bridge.conf allows the bridge. Left to QEMU, each of these fails as
+/// "failed to launch bridge helper" at best
+pub fn check_bridge_setup(bridge: &str) -> Result<()> {
+    let problem = |problem: String| VmError::BridgeSetup { bridge: bridge.to_string(), problem };
+    
+    if !Path::new("/sys/class/net").join(bridge).join("bridge").is_dir() {
+        return Err(problem(format!(
+            "no such bridge; create it with `sudo ip link add {0} type bridge && sudo ip link set {0} up`",
+            bridge
+        )));
+    }
+    
+    let helper = BRIDGE_HELPERS.iter().map(Path::new).find(|path| path.is_file()).ok_or_else(|| {
+        problem("qemu-bridge-helper not found; install QEMU's networking helpers (qemu-system-common on Debian/Ubuntu)".to_string())
+    })?;
+    
+    // Root doesn't need the helper to be privileged, or bridge.conf at all
+    if nix::unistd::geteuid().is_root() {
+        return Ok(());
+    }
+    
+    use std::os::unix::fs::PermissionsExt;
+    let setuid = fs::metadata(helper)?.permissions().mode() & 0o4000 != 0;
+    let has_cap = Command::new("getcap")
+        .arg(helper)
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("cap_net_admin"))
+        .unwrap_or(false);
+    if !setuid && !has_cap {
+        return Err(problem(format!(
+            "{0} can't create TAP devices; run `sudo chmod u+s {0}` or `sudo setcap cap_net_admin+ep {0}`",
+            helper.display()
+        )));
+    }
+    
+    let allow_hint = format!("add `allow {}` to {}", bridge, BRIDGE_CONF);
+    match bridge_acl(Path::new(BRIDGE_CONF), bridge, 0) {
+        Ok((true, false)) => Ok(()),
+        Ok((_, true)) => Err(problem(format!("{} denies it; remove the deny line and {}", BRIDGE_CONF, allow_hint))),
+        Ok((false, false)) => Err(problem(format!("{} doesn't allow it; {}", BRIDGE_CONF, allow_hint))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(problem(format!("{} doesn't exist; create it and {}", BRIDGE_CONF, allow_hint)))
+        }
+        // Usually root:qemu 0640, readable by the helper but not by us
+        Err(_) => {
+            use colored::*;
+            println!("{} Can't read {} to check that it allows {}", "[!]".yellow(), BRIDGE_CONF, bridge);
+            Ok(())
+        }
+    }
+}
+
+/// Whether a bridge.conf, with the files it includes, allows and denies
+/// `bridge`. As in the helper, a deny wins over any allow
+fn bridge_acl(conf: &Path, bridge: &str, depth: u32) -> std::io::Result<(bool, bool)> {
+    let (mut allowed, mut denied) = (false, false);
+    
+    for line in fs::read_to_string(conf)?.lines() {
+        let mut words = line.split('#').next().unwrap_or("").split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("allow"), Some(name)) => allowed |= name == "all" || name == bridge,
+            (Some("deny"), Some(name)) => denied |= name == "all" || name == bridge,
+            // Guard against include loops
+            (Some("include"), Some(path)) if depth < 8 => {
+                let (allow, deny) = bridge_acl(Path::new(path), bridge, depth + 1)?;
+                allowed |= allow;
+                denied |= deny;
+            }
+            _ => {}
+        }
+    }
+    Ok((allowed, denied))
+}
+
+/// Where the kernel lists attached USB devices
+const USB_SYSFS: &str = "/sys/bus/usb/devices";
+
+/// The `/dev/bus/usb` node of an attached USB device, found through sysfs
+fn find_usb_device(id: UsbId) -> Option<PathBuf> {
+    for entry in fs::read_dir(USB_SYSFS).ok()?.flatten() {
+        let dir = entry.path();
+        let read = |file: &str| fs::read_to_string(dir.join(file)).ok().map(|value| value.trim().to_string());
+        let hex = |file: &str| read(file).and_then(|value| u16::from_str_radix(&value, 16).ok());
+        
+        if hex("idVendor") == Some(id.vendor) && hex("idProduct") == Some(id.product) {
+            let bus: u32 = read("busnum")?.parse().ok()?;
+            let dev: u32 = read("devnum")?.parse().ok()?;
+            return Some(PathBuf::from(format!("/dev/bus/usb/{:03}/{:03}", bus, dev)));
+        }
+    }
+    None
+}
+
+/// Make sure every passed-through USB device is plugged in, and warn when
+/// QEMU, running as this user, won't be allowed to open it
+fn check_usb_devices(info: &VmInfo) -> Result<()> {
+    use colored::*;
+    
+    for id in &info.usb_passthrough {
+        let usb: UsbId = id.parse().map_err(VmError::InvalidConfig)?;
+        let node = find_usb_device(usb).ok_or_else(|| VmError::UsbDeviceNotFound(id.clone()))?;
+        
+        #[cfg(unix)]
+        {
+            use nix::unistd::{access, AccessFlags};
+            
+            if access(&node, AccessFlags::R_OK | AccessFlags::W_OK).is_err() {
+                println!(
+                    "{} No read/write access to {} (USB {}); QEMU won't be able to open it without a udev rule or group granting it",
+                    "[!]".yellow(), node.display(), id
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Size in MB of a QEMU memory string like `2G`, `512M` or a bare `1024`
+/// (which QEMU reads as MB)
+pub fn ram_mb(ram: &str) -> Option<u64> {
+    let ram = ram.trim();
+    let (digits, unit) = match ram.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => ram.split_at(i),
+        None => (ram, "M"),
+    };
+    let value: u64 = digits.parse().ok()?;
+    
+    match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "K" => Some(value / 1024),
+        "M" => Some(value),
+        "G" => value.checked_mul(1024),
+        "T" => value.checked_mul(1024 * 1024),
+        _ => None,
+    }
+}
+
+/// Ask a running VM's balloon driver to resize the guest to `target_mb`
+pub fn set_balloon(vm: &str, target_mb: u64) -> Result<()> {
+    let info = load_vm_info(vm)?;
+    if !info.balloon {
+        return Err(VmError::NoBalloon(vm.to_string()));
+    }
+    
+    // The balloon can only give back memory, never add past what QEMU was started with
+    if let Some(max) = ram_mb(&info.ram) {
+        if target_mb == 0 || target_mb > max {
+            return Err(VmError::InvalidConfig(format!("Balloon target must be between 1 and {} MB", max)));
+        }
+    }
+    
+    let bytes = target_mb.checked_mul(1024 * 1024)
+        .ok_or_else(|| VmError::InvalidConfig(format!("Balloon target of {} MB is too large", target_mb)))?;
+    qmp_command(vm, "balloon", Some(serde_json::json!({ "value": bytes })))?;
+    Ok(())
+}
+
+/// Memory the guest currently has in MB, as reported by the balloon driver
+pub fn balloon_actual_mb(vm: &str) -> Result<u64> {
+    let info = load_vm_info(vm)?;
+    if !info.balloon {
+        return Err(VmError::NoBalloon(vm.to_string()));
+    }
+    
+    let reply = qmp_command(vm, "query-balloon", None)?;
+    reply["actual"]
+        .as_u64()
+        .map(|bytes| bytes / (1024 * 1024))
+        .ok_or_else(|| VmError::Qmp("query-balloon reported no size".to_string()))
+}
+
+/// Plug `count` more vCPUs into a running VM started with `maxcpus`,
+/// returning how many it has now. Most guests online new CPUs through udev;
+/// the rest need `echo 1 > /sys/devices/system/cpu/cpuN/online`. Hotplugged
+/// CPUs last until the VM stops
+pub fn hotplug_cpu(vm: &str, count: u32) -> Result<u32> {
+    let info = load_vm_info(vm)?;
+    let headroom = || VmError::NoHotplugHeadroom { vm: vm.to_string(), resource: "vCPUs", flag: "--maxcpus" };
+    if info.maxcpus.is_none() {
+        return Err(headroom());
+    }
+    
+    // Every possible CPU slot, with a qom-path once something is plugged in
+    let slots = qmp_command(vm, "query-hotpluggable-cpus", None)?;
+    let slots = slots.as_array().cloned().unwrap_or_default();
+    let free: Vec<&serde_json::Value> = slots.iter().filter(|slot| slot.get("qom-path").is_none()).collect();
+    if free.len() < count as usize {
+        return Err(headroom());
+    }
+    
+    // query-hotpluggable-cpus lists the highest slots first
+    for slot in free.iter().rev().take(count as usize) {
+        let mut arguments = slot["props"].clone();
+        let ids: Vec<String> = ["socket-id", "core-id", "thread-id"].iter()
+            .map(|key| arguments[key].as_u64().unwrap_or(0).to_string())
+            .collect();
+        arguments["driver"] = slot["type"].clone();
+        arguments["id"] = format!("cpu-{}", ids.join("-")).into();
+        qmp_command(vm, "device_add", Some(arguments))?;
+    }
+    
+    Ok((slots.len() - free.len()) as u32 + count)
+}
+
+/// Plug a `size_mb` DIMM into a running VM started with `maxmem`, returning
+/// the guest's new total in MB. Linux guests need memory auto-onlining
+/// (most distros enable it) or `echo online` on the new memory blocks.
+/// Hotplugged memory lasts until the VM stops
+pub fn hotplug_memory(vm: &str, size_mb: u64) -> Result<u64> {
+    let info = load_vm_info(vm)?;
+    let headroom = || VmError::NoHotplugHeadroom { vm: vm.to_string(), resource: "memory", flag: "--maxmem" };
+    let maxmem = info.maxmem.as_deref().and_then(ram_mb).ok_or_else(headroom)?;
+    if size_mb == 0 {
+        return Err(VmError::InvalidConfig("Memory to add must be more than 0 MB".to_string()));
+    }
+    
+    let summary = qmp_command(vm, "query-memory-size-summary", None)?;
+    let in_use = (summary["base-memory"].as_u64().unwrap_or(0) + summary["plugged-memory"].as_u64().unwrap_or(0)) / (1024 * 1024);
+    let dimms = qmp_command(vm, "query-memory-devices", None)?.as_array().map_or(0, Vec::len);
+    if size_mb > maxmem.saturating_sub(in_use) || dimms >= HOTPLUG_MEM_SLOTS as usize {
+        return Err(headroom());
+    }
+    
+    // The new DIMM's backing follows the boot RAM's hugepages and NUMA binding
+    let id = format!("dimm{}", dimms);
+    let bytes = size_mb.checked_mul(1024 * 1024).ok_or_else(headroom)?;
+    let mut backend = serde_json::json!({ "id": format!("mem-{}", id), "size": bytes });
+    if info.hugepages {
+        backend["qom-type"] = "memory-backend-file".into();
+        backend["mem-path"] = HUGEPAGES_MOUNT.into();
+        backend["prealloc"] = true.into();
+    } else {
+        backend["qom-type"] = "memory-backend-ram".into();
+    }
+    if let Some(node) = numa_node(&info) {
+        backend["host-nodes"] = serde_json::json!([node]);
+        backend["policy"] = "bind".into();
+    }
+    qmp_command(vm, "object-add", Some(backend))?;
+    
+    let dimm = serde_json::json!({ "driver": "pc-dimm", "id": id, "memdev": format!("mem-{}", id) });
+    if let Err(e) = qmp_command(vm, "device_add", Some(dimm)) {
+        let _ = qmp_command(vm, "object-del", Some(serde_json::json!({ "id": format!("mem-{}", id) })));
+        return Err(e);
+    }
+    
+    Ok(in_use + size_mb)
+}
+
+/// Point a VM at a new disk location after its directory was moved or
+/// restored, updating `vm.toml` and the `DISK=` line of `start.sh`
+pub fn relocate_disk(name: &str, disk_path: &Path) -> Result<()> {
+    let vm_dir = get_vm_dir().join(name);
+    
+    let mut info = load_vm_info(name)?;
+    info.disk_path = disk_path.to_path_buf();
+    info.status = VmStatus::Stopped;
+    save_vm_info(&vm_dir.join("vm.toml"), &info)?;
+    
+    let script_path = vm_dir.join("start.sh");
+    if let Ok(script) = fs::read_to_string(&script_path) {
+        let disk_line = format!("DISK={}", sh_quote(&disk_path.display().to_string()));
+        let script: Vec<String> = script.lines()
+            .map(|line| if line.starts_with("DISK=") { disk_line.clone() } else { line.to_string() })
+            .collect();
+        fs::write(&script_path, script.join("\n") + "\n")?;
+    }
+    
+    Ok(())
+}
+
+fn edit_hook(path: &Path) -> Result<Option<PathBuf>> {
+    if path.as_os_str().is_empty() {
+        Ok(None)
+    } else {
+        resolve_hook(path).map(Some)
+    }
+}
+
+/// Absolute path of a hook script, once it's passed `check_hook`
+fn resolve_hook(path: &Path) -> Result<PathBuf> {
+    let resolved = fs::canonicalize(path).map_err(|e| VmError::UnsafeHook {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })?;
+    check_hook(&resolved)?;
+    Ok(resolved)
+}
+
+/// Hooks run with the user's privileges, so anyone who could rewrite one
+/// could run code as them. Only scripts the user owns and nobody else can
+/// write to are run; this is checked again every time a hook fires
+fn check_hook(path: &Path) -> Result<()> {
+    let refuse = |reason: &str| VmError::UnsafeHook {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+    
+    let meta = fs::metadata(path).map_err(|e| refuse(&e.to_string()))?;
+    if !meta.is_file() {
+        return Err(refuse("not a regular file"));
+    }
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        
+        if meta.uid() != nix::unistd::geteuid().as_raw() {
+            return Err(refuse("not owned by the current user"));
+        }
+        if meta.mode() & 0o002 != 0 {
+            return Err(refuse("world-writable"));
+        }
+    }
+    
+    Ok(())
+}
+
+/// Run a lifecycle hook with the VM's name and disk in `N01D_VM`/`N01D_DISK`
+fn run_hook(path: &Path, info: &VmInfo) -> Result<()> {
+    check_hook(path)?;
+    
+    let failed = |reason: String| VmError::HookFailed { path: path.to_path_buf(), reason };
+    let status = Command::new(path)
+        .env("N01D_VM", &info.name)
+        .env("N01D_DISK", &info.disk_path)
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| failed(e.to_string()))?;
+    
+    if !status.success() {
+        return Err(failed(status.to_string()));
+    }
+    Ok(())
+}
+
+/// The VM's disk, QEMU and the host resources it asks for, checked in that
+/// order. QEMU fails opaquely or boots to the firmware shell without its
+/// disk, so a missing one is reported even where QEMU isn't installed
+fn check_host_can_start(info: &VmInfo) -> Result<()> {
+    if !info.disk_path.exists() {
+        return Err(VmError::DiskNotFound(info.disk_path.clone()));
+    }
+    find_qemu(&info.arch)?;
+    if info.hugepages {
+        check_hugepages(info)?;
+    }
+    check_numa(info)
+}
+
+/// Start a VM, on its stored network mode unless `network` overrides it for
+/// this and later runs. A `topology` likewise replaces the stored one (and
+/// the CPU count)
+pub fn start_vm(
+    name: &str,
+    isolated: bool,
+    network: Option<&str>,
+    headless: bool,
+    topology: Option<Topology>,
+    flags: StartFlags,
+) -> Result<()> {
+    validate_vm_name(name)?;
+    
+    let vm_dir = get_vm_dir().join(name);
+    let config_path = vm_dir.join("vm.toml");
+    
+    if !config_path.exists() {
+        return Err(VmError::NotFound(name.to_string()));
+    }
+    // A second QEMU on the same qcow2 would corrupt it. A pidfile left by a
+    // VM that died doesn't count
+    if let Some(pid) = running_pid(name) {
+        return Err(VmError::AlreadyRunning { name: name.to_string(), pid });
+    }
+    
+    let config_str = fs::read_to_string(&config_path)?;
+    let mut info: VmInfo = toml::from_str(&config_str)?;
+    if let Some(topology) = topology {
+        apply_topology(&mut info, topology)?;
+    }
+    flags.apply(&mut info);
+    let network = network.map(str::to_string).unwrap_or_else(|| info.network.clone());
+    
+    check_host_can_start(&info)?;
+    if info.arch == std::env::consts::ARCH && !kvm_available() {
+        use colored::*;
+        println!("{} /dev/kvm is not accessible, falling back to TCG emulation (performance will be degraded)", "[!]".yellow());
+    }
+    if info.nested {
+        check_nested(&info);
+    }
+    check_disk_unlocked(&info.disk_path)?;
+    if forwards_ssh(&network) {
+        ensure_ssh_port(&mut info)?;
+    }
+    if network == "bridge" {
+        check_bridge_setup(BRIDGE)?;
+    }
+    check_shared_folder(&mut info)?;
+    check_usb_devices(&info)?;
+    ensure_varstore(&info)?;
+    
+    let mut cmd = build_launch_command(&info, &network, headless, isolated)?;
+    // QEMU records its own pid; with -daemonize it isn't the one spawned here
+    let pid_path = vm_dir.join("vm.pid");
+    let _ = fs::remove_file(&pid_path);
+    cmd.arg("-pidfile").arg(&pid_path);
+    
+    if let Some(hook) = &info.pre_start {
+        run_hook(hook, &info)?;
+    }
+    // Still marked running with no QEMU left: the guest shut itself down,
+    // so stop_vm never got to revert it. Catch up before it boots again
+    if info.revert_on_stop && info.status == VmStatus::Running {
+        revert_to_autostart(&info);
+    }
+    if info.auto_snapshot {
+        take_autostart_snapshot(&mut info)?;
+        // Recorded now so the snapshot isn't lost track of if the launch fails
+        save_vm_info(&config_path, &info)?;
+    }
+    
+    // Keep QEMU's own output so failed launches can be diagnosed
+    let log = open_qemu_log(&vm_dir)?;
+    cmd.stdout(Stdio::from(log.try_clone()?));
+    cmd.stderr(Stdio::from(log));
+    
+    // Start VM
+    let mut child = cmd.spawn().map_err(VmError::SpawnFailed)?;
+    
+    // QEMU bails out quickly on bad options, missing KVM or a locked disk;
+    // with -daemonize the foreground process exits 0 once the VM is up and
+    // the pidfile written
+    std::thread::sleep(LAUNCH_GRACE);
+    let exited = if headless { Some(child.wait()?) } else { child.try_wait()? };
+    if let Some(status) = exited {
+        if !(headless && status.success()) {
+            return Err(VmError::LaunchFailed(log_tail(&vm_dir.join("qemu.log"), 10)));
+        }
+    }
+    
+    // Update status
+    info.status = VmStatus::Running;
+    info.network = network;
+    info.isolated = isolated;
+    info.last_started = Some(Utc::now());
+    
+    save_vm_info(&config_path, &info)?;
+    
+    // A windowed QEMU is the child itself, and may not have got as far as
+    // writing the pidfile yet
+    if !pid_path.exists() {
+        fs::write(&pid_path, child.id().to_string())?;
+    }
+    
+    if info.display == DisplayMode::Spice && !headless {
+        open_spice_viewer(&info);
+    }
+    
+    // The onion services' Tor lives as long as the VM does
+    if !info.hidden_services.is_empty() {
+        use colored::*;
+        
+        if let Err(e) = crate::network::proxy::start_hidden_services(&info) {
+            println!("{} Onion services not published: {:#}", "[!]".yellow(), e);
+        }
+    }
+    
+    Ok(())
+}
+
+/// The QEMU command `start_vm` would run for a VM, shell-quoted so it can be
+/// copied and tweaked by hand
+pub fn print_launch_command(
+    name: &str,
+    isolated: bool,
+    network: Option<&str>,
+    headless: bool,
+    topology: Option<Topology>,
+    flags: StartFlags,
+) -> Result<String> {
+    let mut info = load_vm_info(name)?;
+    if let Some(topology) = topology {
+        apply_topology(&mut info, topology)?;
+    }
+    flags.apply(&mut info);
+    let network = network.unwrap_or(&info.network).to_string();
+    // The port the first start would pick, though nothing is saved here
+    if forwards_ssh(&network) {
+        ensure_ssh_port(&mut info)?;
+    }
+    let cmd = build_launch_command(&info, &network, headless, isolated)?;
+    
+    let mut words = vec![cmd.get_program().to_string_lossy().to_string()];
+    words.extend(cmd.get_args().map(|arg| arg.to_string_lossy().to_string()));
+    Ok(sh_join(&words))
+}
+
+/// The `-smp` value for a VM: the plain CPU count, or the count with its
+/// topology, which has to multiply out to the same number
+fn smp_arg(info: &VmInfo) -> Result<String> {
+    if info.sockets.is_none() && info.cores.is_none() && info.threads.is_none() {
+        return match info.maxcpus {
+            Some(maxcpus) if maxcpus < info.cpus => Err(VmError::InvalidConfig(format!(
+                "maxcpus = {} is less than cpus = {}", maxcpus, info.cpus
+            ))),
+            Some(maxcpus) => Ok(format!("{},maxcpus={}", info.cpus, maxcpus)),
+            None => Ok(info.cpus.to_string()),
+        };
+    }
+    // QEMU sizes the topology to maxcpus, leaving no way to say how many
+    // of those are plugged at boot
+    if info.maxcpus.is_some() {
+        return Err(VmError::InvalidConfig("CPU hotplug (maxcpus) can't be combined with a topology".to_string()));
+    }
+    
+    let topology = Topology {
+        sockets: info.sockets.unwrap_or(1),
+        cores: info.cores.unwrap_or(1),
+        threads: info.threads.unwrap_or(1),
+    };
+    let Topology { sockets, cores, threads } = topology;
+    if topology.cpus() != Some(info.cpus) {
+        return Err(VmError::InvalidConfig(format!(
+            "Topology {} sockets x {} cores x {} threads doesn't match cpus = {}",
+            sockets, cores, threads, info.cpus
+        )));
+    }
+    
+    Ok(format!("{},sockets={},cores={},threads={}", info.cpus, sockets, cores, threads))
+}
+
+/// Memory slots reserved for `mem add` when a VM has `maxmem`
+const HOTPLUG_MEM_SLOTS: u32 = 8;
+
+/// The `-m` value for a VM: its RAM, plus DIMM slots and a ceiling to
+/// hotplug into when it has `maxmem`
+fn mem_arg(info: &VmInfo) -> Result<String> {
+    let Some(maxmem) = &info.maxmem else {
+        return Ok(info.ram.clone());
+    };
+    
+    match (ram_mb(&info.ram), ram_mb(maxmem)) {
+        (Some(ram), Some(max)) if max >= ram => Ok(format!("{},slots={},maxmem={}M", info.ram, HOTPLUG_MEM_SLOTS, max)),
+        (Some(_), Some(_)) => Err(VmError::InvalidConfig(format!("maxmem = {} is less than ram = {}", maxmem, info.ram))),
+        _ => Err(VmError::InvalidConfig(format!("Can't size maxmem = {} against ram = {}", maxmem, info.ram))),
+    }
+}
+
+/// Store a topology on a VM, deriving its CPU count from it
+fn apply_topology(info: &mut VmInfo, topology: Topology) -> Result<()> {
+    info.cpus = topology.cpus()
+        .ok_or_else(|| VmError::InvalidConfig("Topology has more CPUs than can be counted".to_string()))?;
+    info.sockets = Some(topology.sockets);
+    info.cores = Some(topology.cores);
+    info.threads = Some(topology.threads);
+    Ok(())
+}
+
+/// Extra -drive options for TRIM passthrough. Only qcow2 and raw can punch
+/// holes; the default IDE/AHCI and virtio disks all forward TRIM, so the bus
+/// stays as it is and existing guests keep their device names
+fn discard_options(info: &VmInfo) -> &'static str {
+    if info.discard && matches!(info.disk_format.as_str(), "qcow2" | "raw") {
+        ",discard=unmap,detect-zeroes=unmap"
+    } else {
+        ""
+    }
+}
+
+/// Extra -drive options for the VM's I/O limits. Throttling lives in QEMU's
+/// block layer, so it works whatever the disk's bus; it's only set on the
+/// main disk, never the firmware's pflash drives, which can't be throttled
+fn throttle_options(info: &VmInfo) -> Result<String> {
+    let mut options = String::new();
+    for (option, limit) in [("iops-total", info.iops_limit), ("bps-total", info.bps_limit)] {
+        match limit {
+            // QEMU reads 0 as "no limit", which is never what was meant
+            Some(0) => return Err(VmError::InvalidConfig(format!("throttling.{} must be a positive number", option))),
+            Some(limit) => options.push_str(&format!(",throttling.{}={}", option, limit)),
+            None => {}
+        }
+    }
+    Ok(options)
+}
+
+/// Arguments backing guest RAM. Pinned to a NUMA node, RAM becomes an
+/// explicit memory backend bound to that node, on hugetlbfs if hugepages are
+/// on too; otherwise hugepages just need -mem-path
+fn memory_args(info: &VmInfo) -> Result<Vec<String>> {
+    let Some(node) = numa_node(info) else {
+        return Ok(if info.hugepages {
+            vec!["-mem-path".into(), HUGEPAGES_MOUNT.into(), "-mem-prealloc".into()]
+        } else {
+            vec![]
+        });
+    };
+    
+    // size= is in bytes unless suffixed, unlike -m, so spell it out in MiB
+    let ram_mb = ram_mb(&info.ram)
+        .ok_or_else(|| VmError::InvalidConfig(format!("Can't size the NUMA memory backend for RAM '{}'", info.ram)))?;
+    let backend = if info.hugepages {
+        format!("memory-backend-file,mem-path={},prealloc=on", HUGEPAGES_MOUNT)
+    } else {
+        "memory-backend-ram".to_string()
+    };
+    Ok(vec![
+        "-object".into(),
+        format!("{},id=ram0,size={}M,host-nodes={},policy=bind", backend, ram_mb, node),
+        "-numa".into(),
+        "node,memdev=ram0".into(),
+    ])
+}
+
+/// Assemble the QEMU invocation for a VM; shared by `start_vm` and
+/// `print_launch_command` so a dry run shows exactly what gets spawned
+fn build_launch_command(info: &VmInfo, network: &str, headless: bool, isolated: bool) -> Result<Command> {
+    // numactl execs QEMU, so the pid spawned is still QEMU's
+    let mut cmd = match numa_node(info) {
+        Some(node) => {
+            let mut cmd = Command::new("numactl");
+            cmd.arg(format!("--cpunodebind={}", node)).arg("--").arg(qemu_binary(&info.arch)?);
+            cmd
+        }
+        None => Command::new(qemu_binary(&info.arch)?),
+    };
+    cmd.args(["-m", &mem_arg(info)?]);
+    cmd.args(memory_args(info)?);
+    cmd.args(["-smp", &smp_arg(info)?]);
+    cmd.args(accel_args(info));
+    cmd.args(machine_args(&info.arch));
+    cmd.args(firmware_args(info)?);
+    // QEMU splits -drive on commas, so any in the path have to be doubled
+    let disk = info.disk_path.display().to_string().replace(',', ",,");
+    cmd.args(["-drive", &format!(
+        "file={},format={}{}{}",
+        disk, info.disk_format, discard_options(info), throttle_options(info)?
+    )]);
+    cmd.args(["-name", &info.name]);
+    
+    // Freshly booted guests can stall on crypto without a decent entropy source
+    if info.rng {
+        let backend = if cfg!(windows) {
+            "rng-builtin,id=rng0"
+        } else {
+            "rng-random,filename=/dev/urandom,id=rng0"
+        };
+        cmd.args(["-object", backend]);
+        cmd.args(["-device", "virtio-rng-pci,rng=rng0"]);
+    }
+    
+    if info.balloon {
+        cmd.args(["-device", "virtio-balloon"]);
+    }
+    
+    if let Some(action) = &info.watchdog {
+        if !WATCHDOG_ACTIONS.contains(&action.as_str()) {
+            return Err(VmError::InvalidConfig(format!(
+                "Unknown watchdog action '{}' (expected one of: {})",
+                action, WATCHDOG_ACTIONS.join(", ")
+            )));
+        }
+        cmd.args(["-device", "i6300esb", "-watchdog-action", action]);
+    }
+    
+    // All passed-through devices share one xHCI controller, which takes
+    // USB 1 to 3 devices alike
+    if !info.usb_passthrough.is_empty() {
+        cmd.args(["-device", "qemu-xhci,id=xhci"]);
+        for id in &info.usb_passthrough {
+            let usb: UsbId = id.parse().map_err(VmError::InvalidConfig)?;
+            cmd.args(["-device", &format!(
+                "usb-host,bus=xhci.0,vendorid=0x{:04x},productid=0x{:04x}",
+                usb.vendor, usb.product
+            )]);
+        }
+    }
+    
+    // Isolated mode confines QEMU itself with its seccomp sandbox. That also
+    // sets no_new_privs, which stops the setuid bridge helper from working
+    if isolated {
+        if network == "bridge" {
+            return Err(VmError::InvalidConfig(
+                "Isolated mode can't use bridge networking, the bridge helper needs privileges the sandbox denies".into(),
+            ));
+        }
+        cmd.args(["-sandbox", "on,obsolete=deny,elevateprivileges=deny,resourcecontrol=deny"]);
+    }
+    
+    // Control socket for pausing and other live operations
+    let qmp = qmp_socket_path(&info.name).display().to_string().replace(',', ",,");
+    cmd.args(["-qmp", &format!("unix:{},server=on,wait=off", qmp)]);
+    
+    // Network configuration
+    match network {
+        "none" => {
+            cmd.args(["-nic", "none"]);
+        }
+        "isolated" => {
+            cmd.args(["-nic", "user,restrict=yes"]);
+        }
+        "bridge" => {
+            cmd.args(["-nic", &format!("bridge,br={}", BRIDGE)]);
+        }
+        _ => {
+            // Plain user networking: forward a loopback-only host port to guest
+            // SSH, plus one for each onion service
+            let mut nic = format!("user,hostfwd=tcp:127.0.0.1:{}-:22", ssh_port(info));
+            for (host_port, guest_port) in service_forwards(info) {
+                nic.push_str(&format!(",hostfwd=tcp:127.0.0.1:{}-:{}", host_port, guest_port));
+            }
+            cmd.args(["-nic", &nic]);
+        }
+    }
+    
+    // Display; SPICE clients connect to the server rather than QEMU
+    // opening a window
+    cmd.args(spice_args(info)?);
+    if headless {
+        cmd.args(["-display", "none"]);
+        cmd.arg("-daemonize");
+    } else if info.display == DisplayMode::Spice {
+        cmd.args(["-display", "none"]);
+    } else {
+        cmd.args(["-display", "gtk"]);
+    }
+    
+    // Last, so the user's own arguments win where QEMU lets a later option
+    // override an earlier one
+    cmd.args(&info.extra_args);
+    
+    Ok(cmd)
+}
+
+/// Host port forwarded to a VM's SSH
+pub fn ssh_port(info: &VmInfo) -> u16 {
+    info.ssh_port.unwrap_or(DEFAULT_SSH_PORT)
+}
+
+/// The host port forwarding to a VM's SSH, if its network has the forward
+pub fn forwarded_ssh_port(info: &VmInfo) -> Option<u16> {
+    forwards_ssh(&info.network).then(|| ssh_port(info))
+}
+
+/// Whether a VM started on `network` gets the SSH host forward
+fn forwards_ssh(network: &str) -> bool {
+    !matches!(network, "none" | "isolated" | "bridge")
+}
+
+/// Give a VM without an SSH port its own, so several can have SSH forwarded
+/// at once. The caller saves it
+fn ensure_ssh_port(info: &mut VmInfo) -> Result<u16> {
+    if let Some(port) = info.ssh_port {
+        return Ok(port);
+    }
+    
+    // Ports other VMs forward, whether or not they're running now
+    let claimed: std::collections::HashSet<u16> = scan_vms()?
+        .iter()
+        .filter(|vm| vm.name != info.name)
+        .flat_map(|vm| vm.ssh_port.into_iter().chain(vm.hidden_services.iter().map(|s| s.host_port)))
+        .collect();
+    let port = n01d_common::ports::pick_ssh_port(&info.name, &claimed, n01d_common::ports::loopback_port_free)
+        .ok_or_else(|| VmError::InvalidConfig(format!(
+            "No free host port left for SSH in {}-{}; set ssh_port in vm.toml",
+            SSH_PORT_RANGE.start(), SSH_PORT_RANGE.end()
+        )))?;
+    info.ssh_port = Some(port);
+    Ok(port)
+}
+
+/// Host forwards the VM's onion services need beyond the SSH one, one per
+/// host port
+fn service_forwards(info: &VmInfo) -> Vec<(u16, u16)> {
+    let mut forwards: Vec<(u16, u16)> = info.hidden_services.iter()
+        .filter(|service| service.host_port != ssh_port(info))
+        .map(|service| (service.host_port, service.guest_port))
+        .collect();
+    forwards.sort();
+    forwards.dedup();
+    forwards
+}
+
+/// Publish `guest_port` as `virtual_port` of the VM's onion service, adding
+/// a loopback host forward for it. Replaces any service on the same virtual
+/// port. A running VM gets the forward immediately over QMP
+pub fn add_hidden_service(name: &str, virtual_port: u16, guest_port: u16) -> Result<HiddenService> {
+    use colored::*;
+    
+    let mut info = load_vm_info(name)?;
+    if !forwards_ssh(&info.network) {
+        return Err(VmError::InvalidConfig(format!(
+            "VM '{}' is on '{}' networking, which has no host forwards for an onion service",
+            name, info.network
+        )));
+    }
+    
+    info.hidden_services.retain(|service| service.virtual_port != virtual_port);
+    
+    // Reuse an existing forward to the same guest port
+    let host_port = if guest_port == 22 {
+        ensure_ssh_port(&mut info)?
+    } else if let Some(service) = info.hidden_services.iter().find(|s| s.guest_port == guest_port) {
+        service.host_port
+    } else {
+        std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port()
+    };
+    
+    let is_new_forward = !service_forwards(&info).contains(&(host_port, guest_port));
+    let service = HiddenService { virtual_port, guest_port, host_port };
+    info.hidden_services.push(service.clone());
+    save_vm_info(&get_vm_dir().join(name).join("vm.toml"), &info)?;
+    
+    if is_new_forward && host_port != ssh_port(&info) && running_pid(name).is_some() {
+        let rule = format!("hostfwd_add tcp:127.0.0.1:{}-:{}", host_port, guest_port);
+        let added = qmp_command(name, "human-monitor-command", Some(serde_json::json!({ "command-line": rule })))
+            .map_err(|e| e.to_string())
+            .and_then(|output| match output.as_str().map(str::trim) {
+                // HMP reports failures as output rather than a QMP error
+                Some(msg) if !msg.is_empty() => Err(msg.to_string()),
+                _ => Ok(()),
+            });
+        if let Err(e) = added {
+            println!("{} Couldn't add the forward live ({}); restart '{}' to apply it", "[!]".yellow(), e, name);
+        }
+    }
+    
+    Ok(service)
+}
+
+/// Replace this process with `ssh` into a running VM through its forwarded port
+pub fn ssh_vm(name: &str, user: &str) -> Result<()> {
+    use colored::*;
+    
+    validate_vm_name(name)?;
+    
+    let vms = scan_vms()?;
+    let info = vms.iter()
+        .find(|vm| vm.name == name)
+        .ok_or_else(|| VmError::NotFound(name.to_string()))?;
+    
+    if info.status != VmStatus::Running {
+        return Err(VmError::NotRunning(name.to_string()));
+    }
+    if !forwards_ssh(&info.network) {
+        return Err(VmError::InvalidConfig(format!(
+            "VM '{}' is on '{}' networking, which has no SSH port forward",
+            name, info.network
+        )));
+    }
+    
+    let port = ssh_port(info);
+    
+    // Only one QEMU can bind the port; the others fail to start or lose the race
+    let clashes: Vec<&str> = vms.iter()
+        .filter(|vm| vm.name != name && vm.status == VmStatus::Running)
+        .filter(|vm| forwards_ssh(&vm.network) && ssh_port(vm) == port)
+        .map(|vm| vm.name.as_str())
+        .collect();
+    if !clashes.is_empty() {
+        println!(
+            "{} Port {} is also forwarded for {}; set ssh_port in vm.toml to give each VM its own",
+            "[!]".yellow(), port, clashes.join(", ")
+        );
+    }
+    
+    println!("{} Connecting to '{}' on port {}", "[*]".blue(), name, port);
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-p", &port.to_string()]);
+    // Every VM shows up as 127.0.0.1, so its host key can't be pinned usefully
+    cmd.args(["-o", "StrictHostKeyChecking=no", "-o", "UserKnownHostsFile=/dev/null"]);
+    cmd.arg(format!("{}@127.0.0.1", user));
+    
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        
+        Err(VmError::SshFailed(cmd.exec()))
+    }
+    
+    #[cfg(not(unix))]
+    {
+        cmd.status().map_err(VmError::SshFailed)?;
+        Ok(())
+    }
+}
+
+/// Block until the guest's SSH server answers on a forwarded host port.
+///
+/// A bare TCP connect isn't enough: QEMU's user networking accepts on the host
+/// side before the guest is listening, so this waits for the `SSH-` banner
+pub fn wait_for_ssh(port: u16, timeout: Duration) -> Result<()> {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    
+    const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+    
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let deadline = Instant::now() + timeout;
+    let mut tick = 0;
+    
+    let banner_seen = |mut stream: TcpStream| -> bool {
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).is_ok() && &buf == b"SSH-"
+    };
+    
+    loop {
+        if let Ok(stream) = TcpStream::connect_timeout(&addr, Duration::from_secs(1)) {
+            if banner_seen(stream) {
+                print!("\r   \r");
+                let _ = std::io::stdout().flush();
+                return Ok(());
+            }
+        }
+        
+        if Instant::now() >= deadline {
+            println!();
+            return Err(VmError::SshTimeout { port, secs: timeout.as_secs() });
+        }
+        
+        print!("\r{} ", SPINNER[tick % SPINNER.len()]);
+        let _ = std::io::stdout().flush();
+        tick += 1;
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+pub fn stop_vm(name: &str, force: bool) -> Result<()> {
+    validate_vm_name(name)?;
+    
+    let vm_dir = get_vm_dir().join(name);
+    let pid_path = vm_dir.join("vm.pid");
+    let config_path = vm_dir.join("vm.toml");
+    let mut pid = None;
+    
+    if pid_path.exists() {
+        let pid_str = fs::read_to_string(&pid_path)?;
+        pid = Some(pid_str.trim().parse::<i32>()?);
+    }
+    
+    let mut info: Option<VmInfo> = if config_path.exists() {
+        Some(toml::from_str(&fs::read_to_string(&config_path)?)?)
+    } else {
+        None
+    };
+    // Measured while the process is still there to ask. A VM that already
+    // died can't say when, so its session isn't counted
+    let session = info.as_ref().and_then(uptime_secs);
+    
+    if let Some(pid) = pid {
+        // Send signal
+        #[cfg(unix)]
+        {
+            use nix::sys::signal::{self, Signal};
+            use nix::unistd::Pid;
+            
+            let sig = if force { Signal::SIGKILL } else { Signal::SIGTERM };
+            match signal::kill(Pid::from_raw(pid), sig) {
+                // Already gone is as good as stopped
+                Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+                Err(e) => return Err(VmError::StopFailed { pid, source: e }),
+            }
+        }
+        
+        fs::remove_file(&pid_path)?;
+    }
+    
+    if let Err(e) = crate::network::proxy::stop_hidden_services(&vm_dir) {
+        use colored::*;
+        
+        println!("{} {:#}", "[!]".yellow(), e);
+    }
+    
+    // Update status, only once the VM is really on its way down
+    if let Some(info) = info.as_mut() {
+        info.total_runtime_secs += session.unwrap_or(0);
+        info.last_started = None;
+        info.status = VmStatus::Stopped;
+        save_vm_info(&config_path, info)?;
+    }
+    
+    if let Some(info) = info {
+        use colored::*;
+        
+        if info.revert_on_stop || info.post_stop.is_some() {
+            if let Some(pid) = pid {
+                wait_for_exit(pid, STOP_EXIT_WAIT);
+            }
+        }
+        
+        if info.revert_on_stop {
+            revert_to_autostart(&info);
+        }
+        
+        if let Some(hook) = &info.post_stop {
+            if let Err(e) = run_hook(hook, &info) {
+                println!("{} {}", "[!]".yellow(), e);
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// Roll a VM's disk back to its newest autostart snapshot, reporting how
+/// that went rather than failing
+fn revert_to_autostart(info: &VmInfo) {
+    use colored::*;
+    
+    match info.snapshots.iter().filter(|snap| snap.starts_with(AUTOSTART_PREFIX)).max() {
+        Some(snapshot) => match apply_snapshot(&info.disk_path, snapshot) {
+            Ok(()) => println!("{} Reverted '{}' to {}", "[*]".blue(), info.name, snapshot),
+            Err(e) => println!("{} {}", "[!]".yellow(), e),
+        },
+        None => println!("{} '{}' has no autostart snapshot to revert to", "[!]".yellow(), info.name),
+    }
+}
+
+/// How long `stop_vm` waits for QEMU to let go of the disk before reverting
+/// it or running the post-stop hook anyway
+const STOP_EXIT_WAIT: Duration = Duration::from_secs(10);
+
+/// Wait until `pid` has exited or `timeout` passes
+fn wait_for_exit(pid: i32, timeout: Duration) {
+    #[cfg(unix)]
+    {
+        use nix::unistd::Pid;
+        
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && nix::sys::signal::kill(Pid::from_raw(pid), None).is_ok() {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+/// Stop every VM with a live pid, returning the names that were stopped.
+/// A VM that fails to stop is reported and skipped, so one stuck guest
+/// can't keep the others running
+pub fn stop_all_vms(force: bool) -> Result<Vec<String>> {
+    use colored::*;
+    
+    let mut stopped = Vec::new();
+    for info in scan_vms()? {
+        if running_pid(&info.name).is_none() {
+            continue;
+        }
+        
+        match stop_vm(&info.name, force) {
+            Ok(()) => stopped.push(info.name),
+            Err(e) => println!("{} Failed to stop '{}': {}", "[!]".yellow(), info.name, e),
+        }
+    }
+    
+    Ok(stopped)
+}
+
+/// Start every stopped VM tagged with `group`, returning the names that were
+/// started. Errors are reported per VM and the rest of the group still starts
+pub fn start_group(group: &str, isolated: bool, network: Option<&str>, headless: bool) -> Result<Vec<String>> {
+    use colored::*;
+    
+    let members: Vec<VmInfo> = scan_vms()?
+        .into_iter()
+        .filter(|info| info.group.as_deref() == Some(group))
+        .collect();
+    
+    if members.is_empty() {
+        println!("{} No VMs in group '{}'", "[!]".yellow(), group);
+    }
+    
+    Ok(start_each(members, isolated, network, headless))
+}
+
+fn start_each(members: Vec<VmInfo>, isolated: bool, network: Option<&str>, headless: bool) -> Vec<String> {
+    use colored::*;
+    
+    let mut started = Vec::new();
+    for info in members {
+        if info.status == VmStatus::Running {
+            println!("{} '{}' is already running", "[*]".blue(), info.name);
+            continue;
+        }
+        
+        match start_vm(&info.name, isolated, network, headless, None, StartFlags::default()) {
+            Ok(()) => started.push(info.name),
+            Err(e) => println!("{} Failed to start '{}': {}", "[!]".yellow(), info.name, e),
+        }
+    }
+    
+    started
+}
+
+/// Result of `qemu-img check` on a VM's disk
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DiskCheck {
+    #[serde(default)]
+    pub check_errors: u64,
+    #[serde(default)]
+    pub corruptions: u64,
+    #[serde(default)]
+    pub leaks: u64,
+    #[serde(default)]
+    pub corruptions_fixed: u64,
+    #[serde(default)]
+    pub leaks_fixed: u64,
+    pub image_end_offset: Option<u64>,
+    #[serde(skip)]
+    pub is_clean: bool,
+}
+
+/// Parse the `--output=json` report of `qemu-img check`
+fn parse_disk_check(json: &str) -> Result<DiskCheck> {
+    let mut check: DiskCheck = serde_json::from_str(json)
+        .map_err(|e| VmError::DiskCheckFailed(format!("unexpected qemu-img output: {}", e)))?;
+    check.is_clean = check.check_errors == 0 && check.corruptions == 0 && check.leaks == 0;
+    Ok(check)
+}
+
+/// Run `qemu-img check` on a stopped VM's disk
+pub fn check_disk(vm: &str) -> Result<DiskCheck> {
+    run_disk_check(vm, false)
+}
+
+/// Run `qemu-img check -r all` on a stopped VM's disk, fixing leaks and corruptions
+pub fn repair_disk(vm: &str) -> Result<DiskCheck> {
+    run_disk_check(vm, true)
+}
+
+fn run_disk_check(vm: &str, repair: bool) -> Result<DiskCheck> {
+    validate_vm_name(vm)?;
     
+    let config_path = get_vm_dir().join(vm).join("vm.toml");
     if !config_path.exists() {
-        anyhow::bail!("VM '{}' not found", name);
+        return Err(VmError::NotFound(vm.to_string()));
+    }
+    
+    // Checking an image QEMU has open gives bogus results, repairing one corrupts it
+    if let Some(pid) = running_pid(vm) {
+        return Err(VmError::AlreadyRunning { name: vm.to_string(), pid });
     }
     
     let config_str = fs::read_to_string(&config_path)?;
-    let mut info: VmInfo = toml::from_str(&config_str)?;
+    let info: VmInfo = toml::from_str(&config_str)?;
     
-    // Build QEMU command
-    let mut cmd = Command::new("qemu-system-x86_64");
-    cmd.args(["-m", &info.ram]);
-    cmd.args(["-smp", &info.cpus.to_string()]);
-    cmd.args(["-cpu", "host"]);
-    cmd.arg("-enable-kvm");
-    cmd.args(["-drive", &format!("file={},format=qcow2", info.disk_path.display())]);
-    cmd.args(["-name", name]);
+    if !info.disk_path.exists() {
+        return Err(VmError::DiskNotFound(info.disk_path));
+    }
     
-    // Network configuration
-    match network {
-        "none" => {
-            cmd.args(["-nic", "none"]);
-        }
-        "isolated" => {
-            cmd.args(["-nic", "user,restrict=yes"]);
-        }
-        "nat" => {
-            cmd.args(["-nic", "user"]);
-        }
-        "bridge" => {
-            cmd.args(["-nic", "bridge,br=br0"]);
-        }
-        _ => {
-            cmd.args(["-nic", "user"]);
-        }
+    let mut cmd = Command::new("qemu-img");
+    cmd.args(["check", "--output=json"]);
+    if repair {
+        cmd.args(["-r", "all"]);
     }
+    let output = cmd.arg(&info.disk_path).output()?;
     
-    // Display
-    if headless {
-        cmd.args(["-display", "none"]);
-        cmd.arg("-daemonize");
-    } else {
-        cmd.args(["-display", "gtk"]);
+    // Exit codes 2 and 3 mean corruptions/leaks were found; the report is still valid
+    match output.status.code() {
+        Some(0) | Some(2) | Some(3) => parse_disk_check(&String::from_utf8_lossy(&output.stdout)),
+        _ => Err(VmError::DiskCheckFailed(String::from_utf8_lossy(&output.stderr).to_string())),
     }
+}
+
+/// Rewrite a VM's qcow2 disk with `qemu-img convert` to drop clusters the
+/// guest no longer uses, optionally compressing it. The original is kept as
+/// `<disk>.bak` until the new image passes `qemu-img check`. Returns the
+/// allocated size before and after
+pub fn compact_disk(vm: &str, compress: bool) -> Result<(u64, u64)> {
+    let info = load_vm_info(vm)?;
     
-    // Apply isolation if requested
-    if isolated {
-        // We'll handle this through sandbox module
-        println!("Applying isolation settings...");
+    if let Some(pid) = running_pid(vm) {
+        return Err(VmError::AlreadyRunning { name: vm.to_string(), pid });
     }
     
-    // Start VM
-    let child = cmd.spawn().context("Failed to start VM")?;
+    // Work on the real file so a symlinked disk is compacted where it lives
+    let disk = info.disk_path.canonicalize().map_err(|_| VmError::DiskNotFound(info.disk_path.clone()))?;
     
-    // Update status
-    info.status = VmStatus::Running;
-    info.network = network.to_string();
-    info.isolated = isolated;
+    if info.disk_format != "qcow2" {
+        return Err(VmError::InvalidDisk {
+            path: disk,
+            reason: format!("only qcow2 disks can be compacted, this one is {}", info.disk_format),
+        });
+    }
+    // convert copies only the current state, internal snapshots would be lost
+    if !info.snapshots.is_empty() {
+        return Err(VmError::InvalidDisk {
+            path: disk,
+            reason: "it has snapshots, which compacting would drop".to_string(),
+        });
+    }
     
-    let config_str = toml::to_string_pretty(&info)?;
-    fs::write(&config_path, config_str)?;
+    let (before, _) = disk_sizes(&disk)?;
     
-    // Save PID
-    let pid_path = vm_dir.join("vm.pid");
-    fs::write(&pid_path, child.id().to_string())?;
+    let file_name = disk.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let tmp = disk.with_file_name(format!("{}.compact-tmp", file_name));
+    let bak = disk.with_file_name(format!("{}.bak", file_name));
     
-    Ok(())
+    let mut cmd = Command::new("qemu-img");
+    cmd.args(["convert", "-O", "qcow2"]);
+    if compress {
+        cmd.arg("-c");
+    }
+    let output = cmd.arg(&disk).arg(&tmp).output()?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&tmp);
+        return Err(VmError::QemuImgFailed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    
+    // Hard link first so the rename swaps the images atomically
+    let _ = fs::remove_file(&bak);
+    fs::hard_link(&disk, &bak)?;
+    fs::rename(&tmp, &disk)?;
+    
+    match check_disk(vm) {
+        Ok(check) if check.is_clean => {
+            fs::remove_file(&bak)?;
+        }
+        result => {
+            fs::rename(&bak, &disk)?;
+            let reason = match result {
+                Ok(_) => "compacted image has errors".to_string(),
+                Err(e) => e.to_string(),
+            };
+            return Err(VmError::DiskCheckFailed(format!("{}; original disk restored", reason)));
+        }
+    }
+    
+    let (after, _) = disk_sizes(&disk)?;
+    Ok((before, after))
 }
 
-pub fn stop_vm(name: &str, force: bool) -> Result<()> {
-    let vm_dir = get_vm_dir().join(name);
-    let pid_path = vm_dir.join("vm.pid");
-    let config_path = vm_dir.join("vm.toml");
+/// Pull (allocated bytes, virtual size) out of `qemu-img info --output=json`
+fn parse_disk_sizes(json: &[u8]) -> Result<(u64, u64)> {
+    let info: serde_json::Value = serde_json::from_slice(json)
+        .map_err(|e| VmError::QemuImgFailed(e.to_string()))?;
     
-    if pid_path.exists() {
-        let pid_str = fs::read_to_string(&pid_path)?;
-        let pid: i32 = pid_str.trim().parse()?;
-        
-        // Send signal
-        #[cfg(unix)]
-        {
-            use nix::sys::signal::{self, Signal};
-            use nix::unistd::Pid;
-            
-            let sig = if force { Signal::SIGKILL } else { Signal::SIGTERM };
-            let _ = signal::kill(Pid::from_raw(pid), sig);
-        }
-        
-        fs::remove_file(&pid_path)?;
+    match (info["actual-size"].as_u64(), info["virtual-size"].as_u64()) {
+        (Some(actual), Some(virt)) => Ok((actual, virt)),
+        _ => Err(VmError::QemuImgFailed("qemu-img info reported no sizes".to_string())),
     }
+}
+
+fn disk_sizes(disk: &Path) -> Result<(u64, u64)> {
+    // -U lets us read images a running QEMU holds locked
+    let output = Command::new("qemu-img")
+        .args(["info", "-U", "--output=json"])
+        .arg(disk)
+        .output()?;
     
-    // Update status
-    if config_path.exists() {
-        let config_str = fs::read_to_string(&config_path)?;
-        let mut info: VmInfo = toml::from_str(&config_str)?;
-        info.status = VmStatus::Stopped;
-        let config_str = toml::to_string_pretty(&info)?;
-        fs::write(&config_path, config_str)?;
+    if !output.status.success() {
+        return Err(VmError::QemuImgFailed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
     }
     
-    Ok(())
+    parse_disk_sizes(&output.stdout)
+}
+
+/// (VM name, allocated bytes on disk, virtual size) for every VM, largest
+/// allocation first. VMs whose disk can't be read are reported and left out
+pub fn disk_report() -> Result<Vec<(String, u64, u64)>> {
+    use colored::*;
+    
+    let mut report = Vec::new();
+    for info in scan_vms()? {
+        match disk_sizes(&info.disk_path) {
+            Ok((actual, virt)) => report.push((info.name, actual, virt)),
+            Err(e) => println!("{} {}: {}", "[!]".yellow(), info.name, e),
+        }
+    }
+    
+    report.sort_by_key(|(_, actual, _)| std::cmp::Reverse(*actual));
+    Ok(report)
 }
 
 pub fn create_snapshot(vm: &str, name: &str) -> Result<()> {
+    validate_vm_name(vm)?;
+    
     let vm_dir = get_vm_dir().join(vm);
     let config_path = vm_dir.join("vm.toml");
     
     if !config_path.exists() {
-        anyhow::bail!("VM '{}' not found", vm);
+        return Err(VmError::NotFound(vm.to_string()));
+    }
+    
+    validate_snapshot_name(name)?;
+    
+    // qemu-img would take the snapshot under the running VM's feet
+    if let Some(pid) = running_pid(vm) {
+        return Err(VmError::AlreadyRunning { name: vm.to_string(), pid });
     }
     
     let config_str = fs::read_to_string(&config_path)?;
     let mut info: VmInfo = toml::from_str(&config_str)?;
     
-    // Create snapshot with qemu-img
+    // The disk is the authority; vm.toml may have missed snapshots made by hand
+    let mut existing = n01d_common::snapshot::disk_snapshots(&info.disk_path).ok_or_else(|| {
+        VmError::SnapshotFailed(format!("can't read the snapshots on {}", info.disk_path.display()))
+    })?;
+    existing.extend(info.snapshots.iter().cloned());
+    ensure_new_snapshot(vm, name, &existing)?;
+    
+    take_snapshot(&mut info, name)?;
+    save_vm_info(&config_path, &info)?;
+    
+    Ok(())
+}
+
+/// Check a user-chosen snapshot name against the rules the app shares
+fn validate_snapshot_name(name: &str) -> Result<()> {
+    match n01d_common::snapshot::snapshot_name_problem(name) {
+        Some(reason) => Err(VmError::InvalidSnapshotName { name: name.to_string(), reason }),
+        None => Ok(()),
+    }
+}
+
+fn ensure_new_snapshot(vm: &str, name: &str, existing: &[String]) -> Result<()> {
+    if existing.iter().any(|snap| snap == name) {
+        return Err(VmError::SnapshotExists { vm: vm.to_string(), name: name.to_string() });
+    }
+    Ok(())
+}
+
+/// Create an internal snapshot with qemu-img and record it in `info`
+fn take_snapshot(info: &mut VmInfo, name: &str) -> Result<()> {
     let output = Command::new("qemu-img")
         .args(["snapshot", "-c", name])
         .arg(&info.disk_path)
         .output()?;
     
     if !output.status.success() {
-        anyhow::bail!("Failed to create snapshot: {}", String::from_utf8_lossy(&output.stderr));
+        return Err(VmError::SnapshotFailed(String::from_utf8_lossy(&output.stderr).to_string()));
     }
     
-    // Update config
     info.snapshots.push(name.to_string());
-    let config_str = toml::to_string_pretty(&info)?;
-    fs::write(&config_path, config_str)?;
+    Ok(())
+}
+
+/// Snapshot a VM before it boots and prune autostart snapshots beyond its
+/// `autostart_keep`. Disks without internal snapshots are skipped with a warning
+fn take_autostart_snapshot(info: &mut VmInfo) -> Result<()> {
+    use colored::*;
+    
+    if info.disk_format != "qcow2" {
+        println!("{} {} disks can't snapshot, starting '{}' without one", "[!]".yellow(), info.disk_format, info.name);
+        return Ok(());
+    }
+    
+    // The timestamp sorts chronologically, so the newest snapshot is the max
+    let name = format!("{}{}", AUTOSTART_PREFIX, Utc::now().format("%Y%m%d-%H%M%S"));
+    if !info.snapshots.contains(&name) {
+        take_snapshot(info, &name)?;
+    }
+    
+    let mut autostart: Vec<String> = info.snapshots.iter()
+        .filter(|snap| snap.starts_with(AUTOSTART_PREFIX))
+        .cloned()
+        .collect();
+    autostart.sort();
+    let excess = autostart.len().saturating_sub(info.autostart_keep.max(1) as usize);
+    
+    for old in &autostart[..excess] {
+        let output = Command::new("qemu-img")
+            .args(["snapshot", "-d", old])
+            .arg(&info.disk_path)
+            .output()?;
+        
+        if output.status.success() {
+            info.snapshots.retain(|snap| snap != old);
+        } else {
+            println!("{} Failed to delete old snapshot {}: {}", "[!]".yellow(), old, String::from_utf8_lossy(&output.stderr).trim());
+        }
+    }
     
     Ok(())
 }
 
 pub fn restore_snapshot(vm: &str, snapshot: &str) -> Result<()> {
+    validate_vm_name(vm)?;
+    
     let vm_dir = get_vm_dir().join(vm);
     let config_path = vm_dir.join("vm.toml");
     
     if !config_path.exists() {
-        anyhow::bail!("VM '{}' not found", vm);
+        return Err(VmError::NotFound(vm.to_string()));
     }
     
     let config_str = fs::read_to_string(&config_path)?;
     let info: VmInfo = toml::from_str(&config_str)?;
     
-    // Restore snapshot with qemu-img
+    apply_snapshot(&info.disk_path, snapshot)
+}
+
+/// Roll a disk back to one of its internal snapshots with qemu-img
+fn apply_snapshot(disk: &Path, snapshot: &str) -> Result<()> {
     let output = Command::new("qemu-img")
         .args(["snapshot", "-a", snapshot])
-        .arg(&info.disk_path)
+        .arg(disk)
         .output()?;
     
     if !output.status.success() {
-        anyhow::bail!("Failed to restore snapshot: {}", String::from_utf8_lossy(&output.stderr));
+        return Err(VmError::RestoreFailed(String::from_utf8_lossy(&output.stderr).to_string()));
     }
     
     Ok(())
 }
 
+/// Network mode for new VMs when the config doesn't set `default_network`
+pub const DEFAULT_NETWORK: &str = "nat";
+
+/// Keys `config --set` knows about
+const CONFIG_KEYS: &[&str] = &["default_network"];
+
+/// The CLI's settings file, ~/.config/nullsec-vm/config.toml on Linux
+pub fn config_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("nullsec-vm")
+        .join("config.toml")
+}
+
+/// The config file as a table, empty if there isn't one yet
+fn load_config() -> Result<toml::Table> {
+    let path = config_path();
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+    Ok(toml::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Reject network modes `NetworkMode` doesn't know
+fn validate_network(mode: &str) -> Result<()> {
+    mode.parse::<NetworkMode>()
+        .map(|_| ())
+        .map_err(|e| VmError::InvalidConfig(e.to_string()))
+}
+
+/// The network mode new VMs get: the config's `default_network`, or nat
+fn default_network() -> Result<String> {
+    match load_config()?.get("default_network") {
+        Some(toml::Value::String(mode)) => {
+            validate_network(mode)?;
+            Ok(mode.clone())
+        }
+        Some(_) => Err(VmError::InvalidConfig(format!("default_network in {} must be a string", config_path().display()))),
+        None => Ok(DEFAULT_NETWORK.to_string()),
+    }
+}
+
 pub fn show_config() -> Result<()> {
     use colored::*;
     
-    let config_path = dirs::config_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("nullsec-vm")
-        .join("config.toml");
+    let config_path = config_path();
     
     if config_path.exists() {
         let config = fs::read_to_string(&config_path)?;
@@ -380,23 +3492,510 @@ pub fn show_config() -> Result<()> {
         println!("  Default CPUs: 2");
         println!("  Default Disk: 20G");
         println!("  Default Isolation: medium");
+        println!("  Default Network: {}", DEFAULT_NETWORK);
     }
     
     Ok(())
 }
 
+/// Set one `key=value` in the config file, keeping whatever else is in it
 pub fn set_config(value: &str) -> Result<()> {
-    // Parse key=value
-    let parts: Vec<&str> = value.splitn(2, '=').collect();
-    if parts.len() != 2 {
-        anyhow::bail!("Invalid config format. Use: key=value");
-    }
+    let (key, value) = value.split_once('=')
+        .ok_or_else(|| VmError::InvalidConfig("Invalid config format. Use: key=value".to_string()))?;
+    let (key, value) = (key.trim(), value.trim());
     
-    let _key = parts[0];
-    let _val = parts[1];
+    match key {
+        "default_network" => validate_network(value)?,
+        _ => {
+            return Err(VmError::InvalidConfig(format!(
+                "Unknown config key '{}' (expected one of: {})",
+                key, CONFIG_KEYS.join(", ")
+            )));
+        }
+    }
     
-    // TODO: Implement config setting
-    println!("Config setting not yet implemented");
+    let mut config = load_config()?;
+    config.insert(key.to_string(), toml::Value::String(value.to_string()));
     
+    let path = config_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    write_atomic(&path, toml::to_string(&config)?.as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// A stopped 2-CPU, 2G VM called `lab` with every option at its default
+    fn test_vm_info() -> VmInfo {
+        VmInfo {
+            ram: "2G".into(),
+            cpus: 2,
+            ..VmInfo::new("lab", PathBuf::from("/tmp/lab.qcow2"), "nat".into())
+        }
+    }
+    
+    #[test]
+    fn test_valid_vm_names() {
+        for name in ["nullsec-linux", "kali_2024.1", "a", "VM.2"] {
+            assert!(validate_vm_name(name).is_ok(), "{} should be valid", name);
+        }
+        assert!(validate_vm_name(&"a".repeat(MAX_VM_NAME_LEN)).is_ok());
+    }
+    
+    #[test]
+    fn test_rejects_path_traversal() {
+        for name in ["..", ".", "../../.ssh", "foo/bar", "foo\\bar", "/etc"] {
+            assert!(validate_vm_name(name).is_err(), "{} should be rejected", name);
+        }
+    }
+    
+    #[test]
+    fn test_qemu_binary_per_arch() {
+        assert_eq!(qemu_binary("x86_64").unwrap(), "qemu-system-x86_64");
+        assert_eq!(qemu_binary("aarch64").unwrap(), "qemu-system-aarch64");
+        assert_eq!(qemu_binary("riscv64").unwrap(), "qemu-system-riscv64");
+        assert!(matches!(qemu_binary("sparc"), Err(VmError::UnsupportedArch(_))));
+    }
+    
+    #[test]
+    fn test_machine_args_per_arch() {
+        assert!(machine_args("x86_64").is_empty());
+        assert_eq!(&machine_args("aarch64")[..2], ["-M", "virt"]);
+        assert_eq!(machine_args("riscv64"), ["-M", "virt", "-bios", "default"]);
+    }
+    
+    #[test]
+    fn test_old_vm_toml_defaults_to_x86_64() {
+        let toml = r#"
+            name = "legacy"
+            status = "Stopped"
+            ram = "2G"
+            cpus = 2
+            disk_path = "/tmp/legacy.qcow2"
+            snapshots = []
+            network = "nat"
+            isolated = false
+        "#;
+        let info: VmInfo = toml::from_str(toml).unwrap();
+        assert_eq!(info.arch, "x86_64");
+    }
+    
+    #[test]
+    fn test_parse_disk_check() {
+        let clean = r#"{
+            "image-end-offset": 262144,
+            "total-clusters": 16384,
+            "check-errors": 0,
+            "filename": "vm.qcow2",
+            "format": "qcow2"
+        }"#;
+        let check = parse_disk_check(clean).unwrap();
+        assert!(check.is_clean);
+        assert_eq!(check.image_end_offset, Some(262144));
+        
+        let leaky = r#"{ "image-end-offset": 327680, "check-errors": 0, "leaks": 3, "format": "qcow2" }"#;
+        let check = parse_disk_check(leaky).unwrap();
+        assert!(!check.is_clean);
+        assert_eq!(check.leaks, 3);
+    }
+    
+    #[test]
+    fn test_parse_disk_sizes() {
+        let json = br#"{
+            "virtual-size": 21474836480,
+            "filename": "lab.qcow2",
+            "format": "qcow2",
+            "actual-size": 3221553152,
+            "dirty-flag": false
+        }"#;
+        assert_eq!(parse_disk_sizes(json).unwrap(), (3221553152, 21474836480));
+        assert!(parse_disk_sizes(br#"{ "format": "raw" }"#).is_err());
+    }
+    
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(60 * 7), "7m");
+        assert_eq!(format_duration(2 * 3600 + 14 * 60 + 5), "2h14m");
+        assert_eq!(format_duration(3 * 86400 + 4 * 3600), "3d4h");
+    }
+    
+    #[test]
+    fn test_process_start_time_of_self() {
+        if !Path::new("/proc/self/stat").exists() {
+            return;
+        }
+        let started = process_start_time(std::process::id() as i32).unwrap();
+        assert!(started <= Utc::now());
+    }
+    
+    #[test]
+    fn test_list_filters() {
+        let mut info: VmInfo = toml::from_str(r#"
+            name = "kali-web"
+            status = "Stopped"
+            ram = "2G"
+            cpus = 2
+            disk_path = "/tmp/kali-web.qcow2"
+            snapshots = []
+            network = "nat"
+            isolated = false
+            description = "Burp and ZAP for the Web Lab"
+            tags = ["pentest", "web"]
+        "#).unwrap();
+        
+        assert!(matches_filter(&info, None, None));
+        assert!(matches_filter(&info, Some("web"), Some("web lab")));
+        assert!(matches_filter(&info, None, Some("KALI")));
+        assert!(!matches_filter(&info, Some("malware"), None));
+        assert!(!matches_filter(&info, Some("pentest"), Some("windows")));
+        
+        info.description = None;
+        assert!(!matches_filter(&info, None, Some("burp")));
+        assert!(validate_tag("two words").is_err());
+    }
+    
+    #[test]
+    fn test_manifest_defaults_and_typos() {
+        let manifest: VmManifest = toml::from_str(r#"
+            [[vm]]
+            name = "web-01"
+            
+            [[vm]]
+            name = "db-01"
+            ram = "4G"
+            tags = ["lab"]
+        "#).unwrap();
+        assert_eq!(manifest.vms.len(), 2);
+        assert_eq!(manifest.vms[0].ram, "2G");
+        assert_eq!(manifest.vms[0].cpus, 2);
+        assert_eq!(manifest.vms[1].tags, ["lab"]);
+        
+        let typo = toml::from_str::<VmManifest>(r#"
+            [[vm]]
+            name = "web-01"
+            rma = "4G"
+        "#);
+        assert!(typo.is_err());
+        
+        assert!(valid_size("512M") && valid_size("1.5T") && valid_size("20"));
+        assert!(!valid_size("4GG") && !valid_size("G") && !valid_size("0"));
+    }
+    
+    #[test]
+    fn test_project_paths_are_relative_to_the_file() {
+        let dir = std::env::temp_dir().join(format!("n01d-project-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(PROJECT_FILE);
+        
+        fs::write(&path, r#"
+            [[vm]]
+            name = "dev"
+            iso = "isos/debian.iso"
+        "#).unwrap();
+        let vms = load_project(&path).unwrap();
+        assert_eq!(vms[0].iso, Some(dir.join("isos/debian.iso")));
+        
+        // A cloned repo doesn't get to run scripts
+        fs::write(&path, "[[vm]]\nname = \"dev\"\npost_stop = \"/opt/hooks/stop.sh\"\n").unwrap();
+        let hook = load_project(&path).unwrap_err().to_string();
+        
+        fs::write(&path, "[[vm]]\nname = \"dev\"\nisos = \"debian.iso\"\n").unwrap();
+        let err = load_project(&path).unwrap_err().to_string();
+        
+        fs::write(&path, "vm = []\n").unwrap();
+        let empty = load_project(&path);
+        fs::remove_dir_all(&dir).ok();
+        
+        assert!(hook.contains("hook"), "{}", hook);
+        assert!(err.contains(".n01d.toml") && err.contains("isos"), "{}", err);
+        assert!(empty.is_err());
+        
+        let mut info = VmInfo { project: Some(path.clone()), ..test_vm_info() };
+        assert!(check_project_owns(&path, &info).is_ok());
+        info.project = None;
+        assert!(check_project_owns(&path, &info).is_err());
+    }
+    
+    #[test]
+    fn test_ram_mb() {
+        assert_eq!(ram_mb("2G"), Some(2048));
+        assert_eq!(ram_mb("512M"), Some(512));
+        assert_eq!(ram_mb("1024"), Some(1024));
+        assert_eq!(ram_mb("4GB"), Some(4096));
+        assert_eq!(ram_mb("lots"), None);
+    }
+    
+    #[test]
+    fn test_parse_hugepages() {
+        let meminfo = "MemTotal:       32780528 kB\n\
+                       HugePages_Total:    1024\n\
+                       HugePages_Free:      900\n\
+                       HugePages_Rsvd:        0\n\
+                       Hugepagesize:       2048 kB\n";
+        assert_eq!(parse_hugepages(meminfo), Some((900, 1024, 2048)));
+        assert_eq!(parse_hugepages("MemTotal: 1 kB\n"), None);
+    }
+    
+    #[test]
+    fn test_smp_arg() {
+        let mut info = VmInfo { cpus: 4, ..test_vm_info() };
+        assert_eq!(smp_arg(&info).unwrap(), "4");
+        
+        info.sockets = Some(2);
+        info.cores = Some(4);
+        assert!(smp_arg(&info).is_err());
+        
+        apply_topology(&mut info, "2,4,2".parse().unwrap()).unwrap();
+        assert_eq!(info.cpus, 16);
+        assert_eq!(smp_arg(&info).unwrap(), "16,sockets=2,cores=4,threads=2");
+        
+        info.maxcpus = Some(32);
+        assert!(smp_arg(&info).is_err());
+        (info.sockets, info.cores, info.threads) = (None, None, None);
+        assert_eq!(smp_arg(&info).unwrap(), "16,maxcpus=32");
+        info.maxcpus = Some(8);
+        assert!(smp_arg(&info).is_err());
+        
+        assert!("2,0,1".parse::<Topology>().is_err());
+        assert!("2,4".parse::<Topology>().is_err());
+        assert!("65536,65536,2".parse::<Topology>().is_err());
+        
+        // A hand-edited topology that overflows is a mismatch, not a panic
+        info.maxcpus = None;
+        (info.sockets, info.cores, info.threads) = (Some(65536), Some(65536), Some(1));
+        assert!(smp_arg(&info).is_err());
+    }
+    
+    #[test]
+    fn test_hook_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        
+        let hook = std::env::temp_dir().join(format!("n01d-hook-{}.sh", std::process::id()));
+        fs::write(&hook, "#!/bin/sh\n").unwrap();
+        
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(check_hook(&hook).is_ok());
+        
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(matches!(check_hook(&hook), Err(VmError::UnsafeHook { .. })));
+        
+        fs::remove_file(&hook).unwrap();
+        assert!(check_hook(&hook).is_err());
+        assert!(check_hook(&std::env::temp_dir()).is_err());
+    }
+    
+    #[test]
+    fn test_snapshot_names() {
+        assert!(validate_snapshot_name("pre-update_1.0").is_ok());
+        for name in ["", "my snap", "a/b", "-a", "autostart-20240101-000000"] {
+            assert!(matches!(validate_snapshot_name(name), Err(VmError::InvalidSnapshotName { .. })), "{}", name);
+        }
+        
+        let existing = ["clean".to_string(), "pre-update".to_string()];
+        assert!(matches!(ensure_new_snapshot("lab", "clean", &existing), Err(VmError::SnapshotExists { .. })));
+        assert!(ensure_new_snapshot("lab", "clean-2", &existing).is_ok());
+    }
+    
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_memory_args() {
+        let mut info = test_vm_info();
+        assert!(memory_args(&info).unwrap().is_empty());
+        
+        info.numa_node = Some(1);
+        assert_eq!(memory_args(&info).unwrap(), [
+            "-object", "memory-backend-ram,id=ram0,size=2048M,host-nodes=1,policy=bind",
+            "-numa", "node,memdev=ram0",
+        ]);
+        
+        info.hugepages = true;
+        assert_eq!(
+            memory_args(&info).unwrap()[1],
+            "memory-backend-file,mem-path=/dev/hugepages,prealloc=on,id=ram0,size=2048M,host-nodes=1,policy=bind"
+        );
+    }
+    
+    #[test]
+    fn test_mem_arg() {
+        let mut info = test_vm_info();
+        assert_eq!(mem_arg(&info).unwrap(), "2G");
+        
+        info.maxmem = Some("8G".into());
+        assert_eq!(mem_arg(&info).unwrap(), "2G,slots=8,maxmem=8192M");
+        
+        info.maxmem = Some("1G".into());
+        assert!(mem_arg(&info).is_err());
+    }
+    
+    #[test]
+    fn test_spice_args() {
+        let mut info = test_vm_info();
+        assert!(spice_args(&info).unwrap().is_empty());
+        
+        info.clipboard_sharing = true;
+        assert!(spice_args(&info).is_err());
+        
+        info.display = DisplayMode::Spice;
+        let args = spice_args(&info).unwrap();
+        assert!(args.contains(&"virtserialport,chardev=spicechannel0,name=com.redhat.spice.0".to_string()));
+        assert!(!args.iter().any(|arg| arg.contains("webdav")));
+        
+        info.folder_sharing = Some(PathBuf::from("/srv/share"));
+        let args = spice_args(&info).unwrap();
+        assert_eq!(args.iter().filter(|arg| *arg == "virtio-serial-pci").count(), 1);
+        assert!(args.contains(&"spiceport,id=spicechannel1,name=org.spice-space.webdav.0".to_string()));
+    }
+    
+    #[test]
+    fn test_missing_disk_is_reported_before_qemu() {
+        // No QEMU exists for this arch, so looking for it first would fail
+        // with something other than DiskNotFound
+        let mut info = test_vm_info();
+        info.arch = "no-such-arch".into();
+        info.disk_path = std::env::temp_dir().join(format!("n01d-missing-{}.qcow2", std::process::id()));
+        let config: VmInfo = toml::from_str(&toml::to_string(&info).unwrap()).unwrap();
+        
+        match check_host_can_start(&config) {
+            Err(VmError::DiskNotFound(path)) => assert_eq!(path, info.disk_path),
+            other => panic!("expected DiskNotFound, got {:?}", other),
+        }
+        assert!(check_host_can_start(&VmInfo { disk_path: std::env::temp_dir(), ..config }).is_err());
+    }
+    
+    #[test]
+    fn test_extra_args_come_last() {
+        let mut info = VmInfo {
+            extra_args: vec!["-device".into(), "virtio-tablet-pci".into()],
+            ..test_vm_info()
+        };
+        
+        let cmd = build_launch_command(&info, "nat", true, false).unwrap();
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        assert_eq!(args[args.len() - 2..], info.extra_args[..]);
+        
+        StartFlags { qemu_args: vec!["-vga".into(), "std".into()], ..Default::default() }.apply(&mut info);
+        assert_eq!(info.extra_args, ["-vga", "std"]);
+        
+        // Clearing comes first, so a new value on the same run replaces the old
+        info.nested = true;
+        info.iops_limit = Some(500);
+        let flags = StartFlags {
+            clear: vec![StartSetting::QemuArgs, StartSetting::Nested, StartSetting::Iops],
+            bps_limit: Some(1 << 20),
+            ..Default::default()
+        };
+        flags.apply(&mut info);
+        assert!(info.extra_args.is_empty() && !info.nested);
+        assert_eq!((info.iops_limit, info.bps_limit), (None, Some(1 << 20)));
+    }
+    
+    #[test]
+    fn test_bridge_acl() {
+        let dir = std::env::temp_dir().join(format!("n01d-bridge-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let conf = dir.join("bridge.conf");
+        let extra = dir.join("extra.conf");
+        fs::write(&extra, "allow br1\ndeny lab0 # not this one\n").unwrap();
+        fs::write(&conf, format!("# bridges for n01d\nallow br0\ninclude {}\n", extra.display())).unwrap();
+        
+        assert_eq!(bridge_acl(&conf, "br0", 0).unwrap(), (true, false));
+        assert_eq!(bridge_acl(&conf, "br1", 0).unwrap(), (true, false));
+        assert_eq!(bridge_acl(&conf, "br2", 0).unwrap(), (false, false));
+        assert_eq!(bridge_acl(&conf, "lab0", 0).unwrap(), (false, true));
+        
+        fs::write(&conf, "allow all\ndeny br0\n").unwrap();
+        assert_eq!(bridge_acl(&conf, "br0", 0).unwrap(), (true, true));
+        assert_eq!(bridge_acl(&conf, "virbr0", 0).unwrap(), (true, false));
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn test_throttle_options() {
+        let mut info = test_vm_info();
+        assert_eq!(throttle_options(&info).unwrap(), "");
+        
+        info.iops_limit = Some(500);
+        info.bps_limit = Some(50 * 1024 * 1024);
+        assert_eq!(throttle_options(&info).unwrap(), ",throttling.iops-total=500,throttling.bps-total=52428800");
+        
+        info.iops_limit = Some(0);
+        assert!(throttle_options(&info).is_err());
+    }
+    
+    #[test]
+    fn test_pflash_args() {
+        let varstore = Path::new("/vms/win,11/OVMF_VARS.secboot.fd");
+        let args = pflash_args(Firmware::SecureBoot, "/usr/share/OVMF/OVMF_CODE_4M.secboot.fd", varstore);
+        assert_eq!(args, [
+            "-machine", "q35,smm=on",
+            "-global", "driver=cfi.pflash01,property=secure,value=on",
+            "-drive", "if=pflash,format=raw,unit=0,readonly=on,file=/usr/share/OVMF/OVMF_CODE_4M.secboot.fd",
+            "-drive", "if=pflash,format=raw,unit=1,file=/vms/win,,11/OVMF_VARS.secboot.fd",
+        ]);
+        
+        let args = pflash_args(Firmware::Uefi, "/usr/share/OVMF/OVMF_CODE_4M.fd", varstore);
+        assert_eq!(args.len(), 4);
+        assert!(!args.iter().any(|arg| arg.contains("smm")));
+    }
+    
+    #[test]
+    fn test_rejects_empty_and_malformed_names() {
+        assert!(validate_vm_name("").is_err());
+        assert!(validate_vm_name("-rf").is_err());
+        assert!(validate_vm_name("my vm").is_err());
+        assert!(validate_vm_name(&"a".repeat(MAX_VM_NAME_LEN + 1)).is_err());
+    }
+    
+    #[test]
+    fn test_qmp_execute_skips_events() {
+        let replies = concat!(
+            r#"{"event": "STOP", "timestamp": {"seconds": 1, "microseconds": 0}}"#, "\n",
+            r#"{"return": {"running": false, "status": "paused"}}"#, "\n",
+            r#"{"error": {"class": "GenericError", "desc": "No balloon device has been activated"}}"#, "\n",
+        );
+        let mut reader = std::io::Cursor::new(replies);
+        let mut sent = Vec::new();
+        
+        let status = qmp_execute(&mut reader, &mut sent, "query-status", None).unwrap();
+        assert_eq!(status["status"], "paused");
+        assert_eq!(String::from_utf8(sent).unwrap(), "{\"execute\":\"query-status\"}\n");
+        
+        let err = qmp_execute(&mut reader, &mut Vec::new(), "query-balloon", None).unwrap_err();
+        assert!(err.to_string().contains("No balloon device"));
+    }
+    
+    #[test]
+    fn test_wait_for_ssh_needs_banner() {
+        use std::io::Write;
+        use std::net::TcpListener;
+        
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n");
+            }
+        });
+        assert!(wait_for_ssh(port, Duration::from_secs(5)).is_ok());
+        
+        // Nothing listening any more
+        assert!(matches!(
+            wait_for_ssh(port, Duration::ZERO),
+            Err(VmError::SshTimeout { .. })
+        ));
+    }
+    
+    #[test]
+    fn test_parse_vmdk_extents() {
+        let descriptor = "# Disk DescriptorFile\nversion=1\ncreateType=\"twoGbMaxExtentSparse\"\n\n# Extent description\nRW 4192256 SPARSE \"win-s001.vmdk\"\nRW 2048 SPARSE \"win-s002.vmdk\"\n\nddb.virtualHWVersion = \"4\"\n";
+        assert_eq!(parse_vmdk_extents(descriptor), vec!["win-s001.vmdk", "win-s002.vmdk"]);
+        
+        assert!(parse_vmdk_extents("RW 2048 SPARSE \"x.vmdk\"\n").is_empty());
+    }
+}